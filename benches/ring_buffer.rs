@@ -0,0 +1,148 @@
+//! Measures the ring buffers for a stereo 48 kHz stream processed in 64 sample blocks. Run with
+//! `cargo bench --features bench`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use win_xp_crash::bench::ChannelBuffers;
+
+const SAMPLE_RATE: usize = 48000;
+const BLOCK_SIZE: usize = 64;
+const NUM_CHANNELS: usize = 2;
+/// A small buffer wraps often, which is where the wrap handling matters most
+const BUFFER_SIZE: usize = 129;
+/// The frozen loops are a second long, like a typical freeze
+const FROZEN_BUFFER_SIZE: usize = SAMPLE_RATE + 1;
+const SECONDS: usize = 60;
+
+fn main() {
+    let block: [f32; BLOCK_SIZE] = std::array::from_fn(|i| (i as f32 * 0.1).sin());
+    let num_blocks = SAMPLE_RATE * SECONDS / BLOCK_SIZE;
+
+    // The write head the way `RingBuffer::advance()` used to move it, as a baseline
+    let modulo = measure(num_blocks, || {
+        let mut samples = vec![0f32; BUFFER_SIZE];
+        let mut heads = [0usize; NUM_CHANNELS];
+        // Loop sizes are only known at runtime
+        let size = black_box(BUFFER_SIZE);
+        move || {
+            for head in &mut heads {
+                for &sample in &block {
+                    *head = (*head + 1) % (size - 1);
+                    samples[*head] = sample;
+                }
+            }
+            black_box(&samples);
+        }
+    });
+
+    // And the way it moves now
+    let subtract = measure(num_blocks, || {
+        let mut samples = vec![0f32; BUFFER_SIZE];
+        let mut heads = [0usize; NUM_CHANNELS];
+        // Loop sizes are only known at runtime
+        let size = black_box(BUFFER_SIZE);
+        move || {
+            for head in &mut heads {
+                for &sample in &block {
+                    *head += 1;
+                    if *head >= size - 1 {
+                        *head -= size - 1;
+                    }
+                    samples[*head] = sample;
+                }
+            }
+            black_box(&samples);
+        }
+    });
+
+    let per_sample = measure(num_blocks, || {
+        let mut buffers = ChannelBuffers::new(NUM_CHANNELS, BUFFER_SIZE);
+        move || {
+            for mut buffer in buffers.iter_mut() {
+                for &sample in &block {
+                    black_box(buffer.next_item(sample));
+                }
+            }
+        }
+    });
+
+    let block_based = measure(num_blocks, || {
+        let mut buffers = ChannelBuffers::new(NUM_CHANNELS, BUFFER_SIZE);
+        move || {
+            for mut buffer in buffers.iter_mut() {
+                buffer.record(black_box(&block));
+            }
+        }
+    });
+
+    // The stereo frozen path, reading a loop from both channels
+    let frozen_per_sample = measure(num_blocks, || {
+        let mut buffers = frozen_buffers();
+        move || {
+            for mut buffer in buffers.iter_mut() {
+                for _ in 0..BLOCK_SIZE {
+                    black_box(buffer.next_item(0.));
+                }
+            }
+        }
+    });
+
+    let frozen_block_based = measure(num_blocks, || {
+        let mut buffers = frozen_buffers();
+        let mut output = [0f32; BLOCK_SIZE];
+        move || {
+            for mut buffer in buffers.iter_mut() {
+                buffer.play(&mut output);
+                black_box(&output);
+            }
+        }
+    });
+
+    let num_samples = num_blocks * BLOCK_SIZE * NUM_CHANNELS;
+    for (name, duration) in [
+        ("modulo write head", modulo),
+        ("subtract write head", subtract),
+        ("next_item()", per_sample),
+        ("record()", block_based),
+        ("frozen next_item()", frozen_per_sample),
+        ("frozen play()", frozen_block_based),
+    ] {
+        let ns_per_sample = duration.as_nanos() as f64 / num_samples as f64;
+        println!("{name:>20}: {duration:>10.2?} for {SECONDS} s, {ns_per_sample:.3} ns per sample");
+    }
+}
+
+/// Stereo buffers holding a second of noise, frozen
+fn frozen_buffers() -> ChannelBuffers {
+    let mut buffers = ChannelBuffers::new(NUM_CHANNELS, FROZEN_BUFFER_SIZE);
+    let mut noise = 1u32;
+    let loop_samples: Vec<f32> = (0..FROZEN_BUFFER_SIZE - 1)
+        .map(|_| {
+            noise ^= noise << 13;
+            noise ^= noise >> 17;
+            noise ^= noise << 5;
+            noise as f32 / u32::MAX as f32 * 2. - 1.
+        })
+        .collect();
+    for mut buffer in buffers.iter_mut() {
+        buffer.load(&loop_samples);
+        buffer.freezing = true;
+    }
+
+    buffers
+}
+
+/// Run a block processing function for `num_blocks` blocks after warming it up
+fn measure<F: FnMut()>(num_blocks: usize, setup: impl FnOnce() -> F) -> Duration {
+    let mut process_block = setup();
+    for _ in 0..num_blocks / 10 {
+        process_block();
+    }
+
+    let start = Instant::now();
+    for _ in 0..num_blocks {
+        process_block();
+    }
+    start.elapsed()
+}
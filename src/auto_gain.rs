@@ -0,0 +1,158 @@
+use nih_plug::prelude::*;
+
+use crate::detector::Detector;
+
+/// The window of the input's short-term loudness, in milliseconds
+const LOUDNESS_WINDOW_MS: f32 = 400.;
+/// The most the loop is raised or lowered by, in decibels
+const MAX_COMPENSATION_DB: f32 = 12.;
+/// The most the normalization raises or lowers the loop by, in decibels
+const MAX_NORMALIZATION_DB: f32 = 18.;
+/// How long the gain takes to reach a new compensation, in milliseconds
+const RAMP_MS: f32 = 10.;
+/// Mean squares below this count as silence, which isn't compensated for
+const SILENCE: f32 = 1e-8;
+
+/// Keeps the frozen loop at the level of the input it interrupts. The input's mean square is
+/// followed until the freeze engages and compared to the captured loop's mean square then.
+#[derive(Clone, Debug)]
+pub struct AutoGain {
+    input: Detector,
+    /// The input's mean square over the last `LOUDNESS_WINDOW_MS`
+    input_level: f32,
+    ramp_samples: f32,
+    gain: f32,
+    /// The compensation for the current freeze
+    target: f32,
+    /// How much the gain moves per sample towards its target
+    step: f32,
+}
+
+/// Brings the frozen loop's RMS level to a target. The loop's mean square is measured once when
+/// the freeze engages, so sparse loops with a few loud clicks are raised less than peak
+/// normalization would raise them.
+#[derive(Clone, Debug)]
+pub struct Normalizer {
+    ramp_samples: f32,
+    /// The captured loop's mean square, or zero while nothing is frozen
+    loop_level: f32,
+    gain: f32,
+    /// The gain that brings the loop to the target level
+    target: f32,
+    /// How much the gain moves per sample towards its target
+    step: f32,
+}
+
+impl Default for AutoGain {
+    fn default() -> Self {
+        Self {
+            input: Detector::default(),
+            input_level: 0.,
+            ramp_samples: 1.,
+            gain: 1.,
+            target: 1.,
+            step: 0.,
+        }
+    }
+}
+
+impl AutoGain {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.input
+            .set_times(LOUDNESS_WINDOW_MS, LOUDNESS_WINDOW_MS, sample_rate);
+        self.ramp_samples = (RAMP_MS / 1000. * sample_rate).max(1.);
+    }
+
+    /// Follow the input's loudness with the next sample's mean square over all channels. This
+    /// is fed while nothing is frozen.
+    pub fn measure_input(&mut self, mean_square: f32) {
+        self.input_level = self.input.next_level(mean_square);
+    }
+
+    /// Start compensating for a freeze that captured a loop with `loop_level` as its mean
+    /// square. The gain ramps over from unity, and stays at unity when either side is silent.
+    pub fn engage(&mut self, loop_level: f32) {
+        let max_gain = util::db_to_gain(MAX_COMPENSATION_DB);
+        self.gain = 1.;
+        self.target = if self.input_level > SILENCE && loop_level > SILENCE {
+            (self.input_level / loop_level)
+                .sqrt()
+                .clamp(1. / max_gain, max_gain)
+        } else {
+            1.
+        };
+        self.step = (self.target - 1.).abs() / self.ramp_samples;
+    }
+
+    /// The gain for the next sample of the loop. Disabling Auto Gain ramps back to unity.
+    pub fn next_gain(&mut self, enabled: bool) -> f32 {
+        let target = if enabled { self.target } else { 1. };
+        self.gain += (target - self.gain).clamp(-self.step, self.step);
+
+        self.gain
+    }
+
+    pub fn reset(&mut self) {
+        self.input.reset();
+        self.input_level = 0.;
+        self.gain = 1.;
+        self.target = 1.;
+        self.step = 0.;
+    }
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            ramp_samples: 1.,
+            loop_level: 0.,
+            gain: 1.,
+            target: 1.,
+            step: 0.,
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.ramp_samples = (RAMP_MS / 1000. * sample_rate).max(1.);
+    }
+
+    /// Start normalizing a freeze that captured a loop with `loop_level` as its mean square. The
+    /// gain ramps over from unity once `set_target()` is called.
+    pub fn engage(&mut self, loop_level: f32) {
+        self.loop_level = loop_level;
+        self.gain = 1.;
+        self.target = 1.;
+    }
+
+    /// Set the RMS level the loop is brought to in dBFS. Changing the target while frozen ramps
+    /// the gain over to the new one, and a silent loop stays at unity.
+    pub fn set_target(&mut self, target_db: f32) {
+        let max_gain = util::db_to_gain(MAX_NORMALIZATION_DB);
+        let target = if self.loop_level > SILENCE {
+            (util::db_to_gain(target_db) / self.loop_level.sqrt()).clamp(1. / max_gain, max_gain)
+        } else {
+            1.
+        };
+        if target != self.target {
+            self.target = target;
+            self.step = (target - self.gain).abs() / self.ramp_samples;
+        }
+    }
+
+    /// The gain for the next sample of the loop. Disabling Normalize ramps back to unity.
+    pub fn next_gain(&mut self, enabled: bool) -> f32 {
+        let target = if enabled { self.target } else { 1. };
+        self.gain += (target - self.gain).clamp(-self.step, self.step);
+
+        self.gain
+    }
+
+    pub fn reset(&mut self) {
+        self.loop_level = 0.;
+        self.gain = 1.;
+        self.target = 1.;
+        self.step = 0.;
+    }
+}
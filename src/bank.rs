@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{resample, ChannelBuffers};
+
+/// The number of snapshots that can be stored
+pub const NUM_SNAPSHOTS: usize = 8;
+
+/// A stored copy of every channel's loop. The channels only hold the samples that are actually
+/// used so empty slots don't take up any space in the plugin's state.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    channels: Vec<Vec<f32>>,
+}
+
+impl Snapshot {
+    /// The stored loop for a channel in playback order
+    pub fn channel(&self, channel: usize) -> &[f32] {
+        &self.channels[channel]
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Allocate enough space to capture this many channels without allocating, keeping the
+    /// captured loops. New channels start out silent. This must be called outside of the audio
+    /// thread.
+    pub fn reserve(&mut self, num_channels: usize) {
+        let len = self.len();
+        self.channels.resize_with(num_channels, || vec![0.; len]);
+        for channel in &mut self.channels {
+            channel.reserve_exact(crate::MAX_BUFFER_SIZE - len);
+        }
+    }
+
+    /// Free the space `reserve()` allocated, keeping the captured loops
+    pub fn shrink(&mut self) {
+        for channel in &mut self.channels {
+            channel.shrink_to_fit();
+        }
+    }
+
+    /// Resample the loops by `ratio`, for instance after the sample rate changed. This
+    /// allocates.
+    pub fn resample(&mut self, ratio: f64) {
+        for channel in &mut self.channels {
+            *channel = resample(channel, ratio);
+        }
+    }
+
+    /// Copy the buffers' loops into this snapshot. This does not allocate as long as
+    /// `reserve()` has been called for the current channel count.
+    pub fn capture(&mut self, buffers: &ChannelBuffers) {
+        for (channel, buffer) in self.channels.iter_mut().zip(buffers.iter()) {
+            channel.resize(crate::MAX_BUFFER_SIZE, 0.);
+            let len = buffer.copy_loop(channel);
+            channel.truncate(len);
+        }
+    }
+}
+
+/// A bank of buffer snapshots that frozen loops can be stored into and recalled from. The bank
+/// is saved with the plugin's state. All storage is allocated up front so storing and
+/// recalling can happen on the audio thread.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotBank {
+    slots: Vec<Snapshot>,
+    /// The slot the next store goes into. Stores cycle through the slots.
+    next_slot: usize,
+    /// The sample rate the snapshots were stored at
+    sample_rate: f32,
+}
+
+impl SnapshotBank {
+    /// Allocate the snapshot storage for this many channels, keeping the stored snapshots.
+    /// Snapshots are resampled if they were stored at a different sample rate, and get silent
+    /// channels added when the channel count grows. This must be called outside of the audio
+    /// thread.
+    pub fn prepare(&mut self, num_channels: usize, sample_rate: f32) {
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let resampling = self.sample_rate > 0. && self.sample_rate != sample_rate;
+        self.sample_rate = sample_rate;
+        self.next_slot %= NUM_SNAPSHOTS;
+
+        self.slots.resize_with(NUM_SNAPSHOTS, Snapshot::default);
+        for slot in &mut self.slots {
+            // Restored states may contain slots that don't fit into the buffers
+            let len = slot.len();
+            if len >= crate::MAX_BUFFER_SIZE || slot.channels.iter().any(|c| c.len() != len) {
+                slot.channels.clear();
+            }
+            if resampling && !slot.is_empty() {
+                slot.resample(ratio);
+            }
+            slot.reserve(num_channels);
+        }
+    }
+
+    /// Free the space `prepare()` allocated, keeping the stored snapshots
+    pub fn shrink(&mut self) {
+        self.slots.iter_mut().for_each(Snapshot::shrink);
+    }
+
+    /// Copy the buffers' loops into the next slot. Returns the slot that was written to. This
+    /// does not allocate as long as `prepare()` has been called for the current channel count.
+    pub fn store(&mut self, buffers: &ChannelBuffers) -> Option<usize> {
+        let slot_idx = self.next_slot;
+        self.slots.get_mut(slot_idx)?.capture(buffers);
+
+        self.next_slot = (self.next_slot + 1) % NUM_SNAPSHOTS;
+        Some(slot_idx)
+    }
+
+    /// The snapshot in a slot, or `None` if that slot is empty
+    pub fn get(&self, slot: usize) -> Option<&Snapshot> {
+        self.slots.get(slot).filter(|snapshot| !snapshot.is_empty())
+    }
+}
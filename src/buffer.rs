@@ -1,42 +1,1117 @@
+use std::ops::{Deref, DerefMut, Range};
 
+use crate::formant::FormantCorrector;
+use crate::ftz::flush_denormal;
+use crate::interpolation::{Interpolation, SincTable};
+use crate::oversampling::{Oversampler, Oversampling};
+
+/// The coefficient of the one-pole lowpass filter that smears the frozen loop along its length
+const SMEAR_LOWPASS: f32 = 0.05;
+/// The length of the crossfade at the seam of a reversed loop, in samples
+const REVERSED_SEAM_LEN: f64 = 256.;
+/// The length of the grains that stretch the frozen loop, in samples. A new grain starts every
+/// half grain.
+const GRAIN_LEN: usize = 2048;
+
+/// The state of one channel's ring buffer. The recorded samples live in `ChannelBuffers`, which
+/// holds all channels in a single allocation.
 #[derive(Clone, Debug)]
 pub struct RingBuffer {
-    samples: Vec<f32>,
     size: usize,
     head: usize,
     pub freezing: bool,
+    /// Fractional loop length used while freezing. `None` loops the whole buffer.
+    length: Option<f32>,
+    /// Fractional read position within the loop used while freezing with a loop length or a
+    /// playback rate set
+    position: f64,
+    /// The playback rate of the frozen loop. Values above 1 pitch the loop up.
+    rate: f32,
+    /// Whether the frozen loop plays backwards, starting with its newest sample
+    reversed: bool,
+    /// How much longer the frozen loop takes to play through without changing its pitch. Other
+    /// values than 1 play the loop as overlapping grains.
+    stretch: f32,
+    /// How much of the recorded loop is kept where the whole loop plays while freezing. Anything
+    /// below 1 blends the input into the loop at the write head, so the loop slowly turns into
+    /// what's playing now.
+    hold: f32,
+    /// The read positions of the two overlapping grains while stretching, the newer one first
+    grain_positions: [f64; 2],
+    /// The number of samples since the newer grain started
+    grain_age: usize,
+    /// The part of the loop that's played while freezing, as fractions of the loop starting at
+    /// its oldest sample
+    window_start: f32,
+    window_len: f32,
+    /// The coefficient for the one-pole lowpass filter that tames aliasing when pitching up
+    lowpass_coefficient: f32,
+    lowpass_state: f32,
+    /// The state of the lowpass filter that runs along the loop for `ChannelBuffers::smear()`
+    smear_state: f32,
+    /// How fractional loops are read between samples
+    interpolation: Interpolation,
+    /// An interpolation mode that's applied the next time the playing loop wraps around
+    next_interpolation: Option<Interpolation>,
+    /// The region an engine that reads the buffer directly is playing, see
+    /// `Channel::set_engine_region()`
+    engine_region: Option<PlayRegion>,
+}
+
+/// The ring buffers of all channels. The channels' samples are stored one after another in a
+/// single allocation, `MAX_BUFFER_SIZE` samples per channel.
+#[derive(Clone, Debug, Default)]
+pub struct ChannelBuffers {
+    samples: Vec<f32>,
+    buffers: Vec<RingBuffer>,
+    /// The decimators of the channels' oversampled fractional loops
+    oversamplers: Vec<Oversampler>,
+    /// Keep the formants of the channels' repitched loops in place
+    formant_correctors: Vec<FormantCorrector>,
+    /// Shared by all channels for the sinc interpolation
+    sinc_table: SincTable,
+    /// Where the next `cross_feed()` starts in the loop
+    cross_feed_position: usize,
+    /// Where the next `smear()` starts in the loop
+    smear_position: usize,
+}
+
+/// One channel of `ChannelBuffers`, for recording and playing it back. This dereferences to the
+/// channel's `RingBuffer`.
+pub struct Channel<'a> {
+    buffer: &'a mut RingBuffer,
+    samples: &'a mut [f32],
+    oversampler: &'a mut Oversampler,
+    formant_corrector: &'a mut FormantCorrector,
+    sinc_table: &'a SincTable,
+}
+
+/// One channel of `ChannelBuffers` for reading the recorded audio. This dereferences to the
+/// channel's `RingBuffer`.
+#[derive(Clone, Copy)]
+pub struct ChannelRef<'a> {
+    buffer: &'a RingBuffer,
+    samples: &'a [f32],
+}
+
+/// A range of the recorded loop and the position within it, in samples. These are positions in
+/// the buffer like in `ChannelRef::peak()`, and the range may wrap around the end of the loop.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlayRegion {
+    pub start: f32,
+    pub len: f32,
+    pub position: f32,
+    /// How many samples the position moves per sample
+    pub rate: f32,
+}
+
+impl ChannelBuffers {
+    /// Silent buffers of `size` samples for `num_channels` channels
+    pub fn new(num_channels: usize, size: usize) -> Self {
+        Self {
+            samples: vec![0.; num_channels * crate::MAX_BUFFER_SIZE],
+            buffers: vec![RingBuffer::new(size); num_channels],
+            oversamplers: vec![Oversampler::default(); num_channels],
+            formant_correctors: vec![FormantCorrector::default(); num_channels],
+            sinc_table: SincTable::new(),
+            cross_feed_position: 0,
+            smear_position: 0,
+        }
+    }
+
+    /// Change the number of channels. The remaining channels keep their audio, new channels
+    /// start out silent with a buffer of `size` samples. The first call also computes the sinc
+    /// interpolation's taps. This allocates.
+    pub fn set_num_channels(&mut self, num_channels: usize, size: usize) {
+        self.samples.resize(num_channels * crate::MAX_BUFFER_SIZE, 0.);
+        self.buffers.resize(num_channels, RingBuffer::new(size));
+        self.oversamplers.resize(num_channels, Oversampler::default());
+        self.formant_correctors.resize(num_channels, FormantCorrector::default());
+        if self.sinc_table.is_empty() {
+            self.sinc_table = SincTable::new();
+        }
+    }
+
+    /// The number of channels. How long each channel's loop is is up to the channel, see
+    /// `RingBuffer::loop_len()`.
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn first(&self) -> Option<ChannelRef<'_>> {
+        self.iter().next()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ChannelRef<'_>> {
+        self.buffers
+            .iter()
+            .zip(self.samples.chunks_exact(crate::MAX_BUFFER_SIZE))
+            .map(|(buffer, samples)| ChannelRef { buffer, samples })
+    }
+
+    /// Rotate the next `len` samples of the left and right loops into each other by `angle`. The
+    /// rotated run moves on with every call and wraps around the loop, so every sample is rotated
+    /// once per pass through the loop and the loops slowly trade sides. The rotation keeps the
+    /// pair's energy the same, which keeps feeding them into each other stable at any amount.
+    /// This only does something with exactly two channels.
+    pub fn cross_feed(&mut self, angle: f32, len: usize) {
+        let [left, right] = &self.buffers[..] else {
+            return;
+        };
+        let loop_len = left.loop_len();
+        if right.loop_len() != loop_len {
+            return;
+        }
+
+        let (left_samples, right_samples) = self.samples.split_at_mut(crate::MAX_BUFFER_SIZE);
+        let (sin, cos) = angle.sin_cos();
+        // The loop may have gotten shorter since the last call
+        let mut position = self.cross_feed_position % loop_len;
+        for _ in 0..len.min(loop_len) {
+            let (left, right) = (left_samples[position], right_samples[position]);
+            left_samples[position] = cos * left + sin * right;
+            right_samples[position] = cos * right - sin * left;
+            position += 1;
+            if position >= loop_len {
+                position = 0;
+            }
+        }
+        self.cross_feed_position = position;
+    }
+
+    /// Blend the next `len` samples of every loop towards a lowpass filtered version of
+    /// themselves by `amount`. Like with `cross_feed()` the run moves on with every call, so the
+    /// loops blur a bit more with every pass.
+    pub fn smear(&mut self, amount: f32, len: usize) {
+        let Some(loop_len) = self.buffers.first().map(RingBuffer::loop_len) else {
+            return;
+        };
+
+        let start = self.smear_position % loop_len;
+        let len = len.min(loop_len);
+        for mut channel in self.iter_mut() {
+            channel.smear(start, len, amount);
+        }
+        self.smear_position = (start + len) % loop_len;
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = Channel<'_>> {
+        let sinc_table = &self.sinc_table;
+        self.buffers
+            .iter_mut()
+            .zip(self.samples.chunks_exact_mut(crate::MAX_BUFFER_SIZE))
+            .zip(&mut self.oversamplers)
+            .zip(&mut self.formant_correctors)
+            .map(move |(((buffer, samples), oversampler), formant_corrector)| Channel {
+                buffer,
+                samples,
+                oversampler,
+                formant_corrector,
+                sinc_table,
+            })
+    }
 }
 
 impl RingBuffer {
     pub fn new(size: usize) -> Self {
         Self { 
-            samples: vec![0.; crate::MAX_BUFFER_SIZE],
             size,
             head: 0,
             freezing: false,
+            length: None,
+            position: 0.,
+            rate: 1.,
+            reversed: false,
+            stretch: 1.,
+            hold: 1.,
+            grain_positions: [0.; 2],
+            grain_age: 0,
+            window_start: 0.,
+            window_len: 1.,
+            lowpass_coefficient: 1.,
+            lowpass_state: 0.,
+            smear_state: 0.,
+            interpolation: Interpolation::Linear,
+            next_interpolation: None,
+            engine_region: None,
         }
     }
 
     fn advance(&mut self) {
-        self.head = (self.head + 1) % (self.size - 1);
+        // The head never goes past the wrap point, not even after `resize()`, so a single
+        // subtraction wraps it around
+        self.head += 1;
+        if self.head >= self.size - 1 {
+            self.head -= self.size - 1;
+        }
+    }
+
+    /// The ranges of the buffer the next `len` samples are written to or read from, split where
+    /// the head wraps around, together with the matching ranges of the block
+    fn block_runs(&self, len: usize) -> impl Iterator<Item = (Range<usize>, Range<usize>)> {
+        let wrap = self.size - 1;
+        let mut start = (self.head + 1) % wrap;
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset >= len {
+                return None;
+            }
+
+            let run_len = (len - offset).min(wrap - start);
+            let runs = (start..start + run_len, offset..offset + run_len);
+            start = (start + run_len) % wrap;
+            offset += run_len;
+            Some(runs)
+        })
+    }
+
+    /// Move the head like `len` calls to `advance()` would
+    fn advance_by(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        self.head = (self.head + len) % (self.size - 1);
+        self.restart_loop();
+    }
+
+    /// Move the head to where a fractional loop left off, so playing the whole loop carries on
+    /// from there instead of jumping back to where the fractional playback started, for instance
+    /// after a hitch. The loop holds the same audio either way, only the sample it starts with
+    /// changes.
+    fn resume_whole_loop(&mut self) {
+        // Only the first sample after a fractional loop has anything to resume, which spares
+        // every other sample the divisions
+        if self.position == 0. {
+            return;
+        }
+
+        let wrap = self.size - 1;
+        let offset = (self.position.floor() as i64).rem_euclid(wrap as i64) as usize;
+        self.head = (self.head + offset) % wrap;
+        self.position = 0.;
+    }
+
+    /// Make the next fractional loop start at its first sample
+    fn restart_loop(&mut self) {
+        self.position = 0.;
+        self.grain_positions = [0.; 2];
+        self.grain_age = 0;
+    }
+
+    /// Read the loop at a fractional `position`
+    fn read(&self, samples: &[f32], position: f64, length: f64, sinc_table: &SincTable) -> f32 {
+        let wrap = (self.size - 1) as i64;
+        let loop_len = length.ceil() as i64;
+        let start = self.head as i64 + 1 + self.window().0 - loop_len;
+        let index = position.floor();
+        let t = (position - index) as f32;
+        let index = index as i64;
+
+        // The loop wraps around, so the samples past its end are the ones at its start
+        let sample = |offset: i64| {
+            samples[(start + (index + offset).rem_euclid(loop_len)).rem_euclid(wrap) as usize]
+        };
+        self.interpolation.interpolate(sample, t, sinc_table)
+    }
+
+    /// Read the sample at the fractional position and move the position on by `rate`, wrapping
+    /// around the end of the loop
+    fn read_next(
+        &mut self,
+        samples: &[f32],
+        length: f64,
+        rate: f64,
+        sinc_table: &SincTable,
+    ) -> f32 {
+        let seam_len = REVERSED_SEAM_LEN.min(length / 4.);
+        if !(0. ..length).contains(&self.position) {
+            self.position = if self.reversed && self.position < 0. {
+                // The next reversed pass carries on where the seam's crossfade left off
+                (self.position + length - seam_len).rem_euclid(length)
+            } else {
+                self.position.rem_euclid(length)
+            };
+            if let Some(interpolation) = self.next_interpolation.take() {
+                self.interpolation = interpolation;
+            }
+        }
+
+        let rate = if self.reversed { -rate } else { rate };
+        if self.stretch == 1. {
+            let mut output = self.read(samples, self.position, length, sinc_table);
+            if self.reversed && self.position < seam_len {
+                // The oldest samples fade into the samples leading up to the next pass, which
+                // were last heard right after the freeze engaged
+                let lead_in =
+                    self.read(samples, self.position + length - seam_len, length, sinc_table);
+                output = lead_in + (output - lead_in) * (self.position / seam_len) as f32;
+            }
+            self.position += rate;
+            return output;
+        }
+
+        let output = self.read_grains(samples, length, rate, sinc_table);
+        self.position += rate / self.stretch as f64;
+        output
+    }
+
+    /// Overlap two grains with Hann windows that add up to one. The grains play the loop at the
+    /// playback rate, and a new one starts at the stretched position every half grain.
+    fn read_grains(
+        &mut self,
+        samples: &[f32],
+        length: f64,
+        rate: f64,
+        sinc_table: &SincTable,
+    ) -> f32 {
+        if self.grain_age >= GRAIN_LEN / 2 {
+            self.grain_positions = [self.position, self.grain_positions[0]];
+            self.grain_age = 0;
+        }
+
+        let phase = self.grain_age as f32 / GRAIN_LEN as f32;
+        let newer_gain = 0.5 - 0.5 * (std::f32::consts::TAU * phase).cos();
+        let [newer, older] = self.grain_positions.map(|position| position.rem_euclid(length));
+        let newer_sample = self.read(samples, newer, length, sinc_table);
+        let older_sample = self.read(samples, older, length, sinc_table);
+        self.grain_positions = [newer + rate, older + rate];
+        self.grain_age += 1;
+
+        older_sample + (newer_sample - older_sample) * newer_gain
+    }
+
+    /// Play the frozen loop backwards. Switching to reversed playback starts at the loop's newest
+    /// sample, so right after the freeze engages the most recent audio runs back into the past.
+    pub fn set_reversed(&mut self, reversed: bool) {
+        if reversed == self.reversed {
+            return;
+        }
+
+        self.reversed = reversed;
+        let start = if reversed {
+            (self.play_length() as f64 - 1.).max(0.)
+        } else {
+            0.
+        };
+        self.position = start;
+        self.grain_positions = [start; 2];
+        self.grain_age = 0;
+    }
+
+    /// The buffer position of the first sample of the last complete chunk of `len` samples,
+    /// where `head_phase` is the newest recorded sample's offset from the start of its chunk
+    pub fn last_chunk_start(&self, len: usize, head_phase: usize) -> usize {
+        let wrap = (self.size - 1) as i64;
+        let len = len as i64;
+        // The chunk the head is in only counts once its last sample has been recorded
+        let start = self.head as i64 + 1 - len - (head_phase as i64 + 1) % len;
+        start.rem_euclid(wrap) as usize
+    }
+
+    /// Set how much longer the frozen loop takes to play through, keeping its pitch. Stretching
+    /// plays the loop as overlapping grains, a stretch of 1 reads it directly.
+    pub fn set_stretch(&mut self, stretch: f32) {
+        self.stretch = stretch;
+    }
+
+    /// Set how much of the loop is kept while it plays through as a whole, see `hold`. Fractional
+    /// loops are always held completely, as they don't play back at the write head.
+    pub fn set_hold(&mut self, hold: f32) {
+        self.hold = hold;
+    }
+
+    /// Set how fractional loops are read between samples. While one is playing the change waits
+    /// until the loop wraps around, where switching doesn't click.
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        if self.freezing && self.is_fractional() {
+            self.next_interpolation =
+                Some(interpolation).filter(|&interpolation| interpolation != self.interpolation);
+        } else {
+            self.interpolation = interpolation;
+            self.next_interpolation = None;
+        }
+    }
+
+    /// Set the playback rate used while freezing. Pitching up applies a gentle lowpass filter to
+    /// reduce aliasing.
+    pub fn set_rate(&mut self, rate: f32) {
+        if rate == self.rate {
+            return;
+        }
+
+        self.rate = rate;
+        self.lowpass_coefficient = if rate.abs() > 1. {
+            // Keep the cutoff a bit below the Nyquist frequency of the resampled audio
+            1. - (-std::f32::consts::TAU * 0.45 / rate.abs()).exp()
+        } else {
+            1.
+        };
+    }
+
+    /// Set the loop length used while freezing in samples. This can be fractional for tuned
+    /// loops. Passing `None` loops the whole buffer again.
+    pub fn set_length(&mut self, length: Option<f32>) {
+        self.length = length.map(|length| length.clamp(2., crate::MAX_BUFFER_SIZE as f32));
+    }
+
+    /// Select the part of the loop that's played while freezing, as fractions of the loop
+    /// starting at its oldest sample. The window is kept inside of the loop. Loop lengths set
+    /// with `set_length()` play the end of the window.
+    pub fn set_window(&mut self, start: f32, len: f32) {
+        self.window_len = len.clamp(0., 1.);
+        self.window_start = start.clamp(0., 1. - self.window_len);
+    }
+
+    /// The end of the window as an offset from the oldest sample and its length, in samples
+    fn window(&self) -> (i64, f32) {
+        let wrap = (self.size - 1) as f32;
+        let len = (self.window_len * wrap).max(2.);
+        let end = (self.window_start * wrap + len).round().min(wrap);
+        (end as i64, len)
+    }
+
+    /// Whether the frozen loop is read with `next_fractional()` instead of sample by sample
+    fn is_fractional(&self) -> bool {
+        self.length.is_some()
+            || self.rate != 1.
+            || self.reversed
+            || self.stretch != 1.
+            || self.window_len < 1.
+    }
+
+    /// The length of the loop that's played while freezing
+    fn play_length(&self) -> f32 {
+        let (_, window_len) = self.window();
+        match self.length {
+            // Longer loops used to wrap around the buffer and still do without a window
+            Some(length) if self.window_len < 1. => length.min(window_len),
+            Some(length) => length,
+            None => window_len,
+        }
+    }
+
+    /// The position of the loop's oldest sample in the buffer, which is where the loop window's
+    /// fractions start
+    pub fn oldest(&self) -> usize {
+        (self.head + 1) % (self.size - 1)
+    }
+
+    /// The part of the loop that's being played and where the read or write head currently is
+    pub fn play_region(&self) -> PlayRegion {
+        let wrap = self.loop_len() as f32;
+        if let Some(region) = self.engine_region.filter(|_| self.freezing) {
+            region
+        } else if self.freezing && self.is_fractional() {
+            // See `read()`
+            let len = self.play_length().min(wrap);
+            let end = self.head as f32 + 1. + self.window().0 as f32;
+            let start = (end - len.ceil()).rem_euclid(wrap);
+            let rate = if self.reversed { -self.rate } else { self.rate };
+            PlayRegion {
+                start,
+                len,
+                position: (start + self.position as f32).rem_euclid(wrap),
+                rate: rate / self.stretch,
+            }
+        } else {
+            PlayRegion { start: 0., len: wrap, position: self.head as f32, rate: 1. }
+        }
+    }
+
+    /// The length of the loop that is played while freezing without a loop length set. This is
+    /// one sample less than the buffer's size, the Buffer Size parameter, as the plugin's
+    /// original ring buffer always wrapped around one sample early. Changing that would change
+    /// every saved loop's length, so the size keeps its meaning and this is what's heard.
+    pub fn loop_len(&self) -> usize {
+        self.size - 1
     }
 
+    /// The longest loop any buffer can hold
+    pub fn capacity(&self) -> usize {
+        crate::MAX_BUFFER_SIZE - 1
+    }
+
+    /// The position of the newest recorded sample in the buffer. This is always within the
+    /// loop.
+    pub fn head(&self) -> usize {
+        self.head
+    }
 
+    pub fn is_freezing(&self) -> bool {
+        self.freezing
+    }
+}
+
+impl Channel<'_> {
+    // Without this the per-sample calls from other crates, like the benchmark, aren't inlined
+    #[inline]
     pub fn next_item(&mut self, item: f32) -> f32 {
-        self.advance();
-        if self.freezing {
-            self.samples[self.head]
+        if self.buffer.freezing && self.buffer.is_fractional() {
+            return self.next_fractional(self.buffer.play_length() as f64);
+        }
+
+        if self.buffer.freezing {
+            self.buffer.resume_whole_loop();
+        }
+        self.buffer.advance();
+        self.buffer.restart_loop();
+        if self.buffer.freezing {
+            let stored = &mut self.samples[self.buffer.head];
+            *stored = blend_hold(*stored, item, self.buffer.hold);
+            *stored
         } else {
-            self.samples[self.head] = item;
+            self.samples[self.buffer.head] = item;
             item
         }
     }
 
+    /// Record `items` like `next_item()` does while not freezing. The items are copied in as few
+    /// contiguous runs as possible, split where the write head wraps around.
+    pub fn record(&mut self, items: &[f32]) {
+        for (ring, block) in self.buffer.block_runs(items.len()) {
+            self.samples[ring].copy_from_slice(&items[block]);
+        }
+        self.buffer.advance_by(items.len());
+    }
+
+    /// Play the next samples of the whole loop into `target` like `next_item()` does while
+    /// freezing without a loop length, playback rate, stretch or window set. This copies
+    /// contiguous runs like `record()`.
+    pub fn play(&mut self, target: &mut [f32]) {
+        self.buffer.resume_whole_loop();
+        for (ring, block) in self.buffer.block_runs(target.len()) {
+            target[block].copy_from_slice(&self.samples[ring]);
+        }
+        self.buffer.advance_by(target.len());
+    }
+
+    /// The recorded sample at a buffer position, wrapping around the end of the loop. This
+    /// doesn't move the read head.
+    pub fn sample_at(&self, position: usize) -> f32 {
+        self.samples[position % self.buffer.loop_len()]
+    }
+
+    /// Show `region` as the playing part of the loop while freezing instead of the read head's
+    /// region, for engines that read the buffer with `sample_at()`. `None` goes back to the read
+    /// head.
+    pub fn set_engine_region(&mut self, region: Option<PlayRegion>) {
+        self.buffer.engine_region = region;
+    }
+
+    /// Set how many samples fractional loops are read at per output sample. Changing the factor
+    /// restarts the decimation filters, and the latency changes with it.
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        self.oversampler.set_oversampling(oversampling);
+    }
+
+    /// Keep the formants of repitched loops in place
+    pub fn set_formant_correction(&mut self, enabled: bool) {
+        self.formant_corrector.set_enabled(enabled);
+    }
+
+    /// Read the next sample of a loop with a fractional length at the current playback rate,
+    /// and correct its formants if that's enabled
+    fn next_fractional(&mut self, length: f64) -> f32 {
+        let output = self.read_fractional(length);
+        if !self.formant_corrector.is_enabled() {
+            return output;
+        }
+
+        if self.formant_corrector.needs_analysis() {
+            // The recorded audio around the read position, at its original pitch
+            let buffer = &*self.buffer;
+            let samples = &*self.samples;
+            let sinc_table = self.sinc_table;
+            self.formant_corrector.analyze(|offset| {
+                let position = (buffer.position + offset as f64).rem_euclid(length);
+                buffer.read(samples, position, length, sinc_table)
+            });
+        }
+        self.formant_corrector.process(output)
+    }
+
+    /// Read the next sample of a loop with a fractional length at the current playback rate,
+    /// interpolating between the closest samples. The loop covers the most recent audio in the
+    /// window, which is the audio before the write head unless a window is set.
+    fn read_fractional(&mut self, length: f64) -> f32 {
+        let buffer = &mut *self.buffer;
+        if self.oversampler.oversampling() != Oversampling::Off {
+            // The decimation filters take care of the aliasing instead of the lowpass filter
+            let rate = buffer.rate as f64 / self.oversampler.oversampling().factor() as f64;
+            let samples = &*self.samples;
+            let output = self
+                .oversampler
+                .process(|| buffer.read_next(samples, length, rate, self.sinc_table));
+            buffer.lowpass_state = output;
+            return output;
+        }
+
+        let output = buffer.read_next(self.samples, length, buffer.rate as f64, self.sinc_table);
+        if buffer.lowpass_coefficient < 1. {
+            let lowpass_state = buffer.lowpass_state;
+            buffer.lowpass_state = flush_denormal(
+                lowpass_state + (output - lowpass_state) * buffer.lowpass_coefficient,
+            );
+        } else {
+            buffer.lowpass_state = output;
+        }
+        buffer.lowpass_state
+    }
+
+    /// Replace the recorded loop with `loop_samples`, in the same order as `copy_loop()`. The
+    /// buffer's size changes to fit the new loop. An empty loop loads as a single silent sample,
+    /// since the buffer can't be any shorter than that.
+    pub fn load(&mut self, loop_samples: &[f32]) {
+        let loop_samples = if loop_samples.is_empty() { &[0.][..] } else { loop_samples };
+        let len = loop_samples.len();
+        self.samples[..len].copy_from_slice(loop_samples);
+        self.samples[len..].iter_mut().for_each(|s| *s = 0.);
+
+        self.buffer.size = len + 1;
+        self.buffer.head = len - 1;
+        self.buffer.restart_loop();
+    }
+
+    /// Silence any NaN or infinite samples that made it into the recorded audio, as they would
+    /// otherwise play back forever once frozen
+    pub fn sanitize(&mut self) {
+        self.samples.iter_mut().for_each(|s| *s = sanitize(*s));
+        if !self.buffer.lowpass_state.is_finite() {
+            self.buffer.lowpass_state = 0.;
+        }
+    }
+
+    /// Silence the recorded audio and move the heads back to the start
+    pub fn clear(&mut self) {
+        self.samples.iter_mut().for_each(|s| *s = 0.);
+        self.buffer.head = 0;
+        self.buffer.restart_loop();
+        self.buffer.lowpass_state = 0.;
+    }
+
+    /// Resample the recorded loop by `ratio`, for instance after a sample rate change. The buffer's
+    /// size changes to fit the resampled loop. This allocates and must be called outside of the
+    /// audio thread.
+    pub fn resample(&mut self, ratio: f64) {
+        let mut loop_samples = vec![0.; crate::MAX_BUFFER_SIZE];
+        let len = self.view().copy_loop(&mut loop_samples);
+        self.load(&resample(&loop_samples[..len], ratio));
+    }
+
     pub fn resize(&mut self, size: usize) {
         // Set all samples that are outside the new size to 0
         self.samples.iter_mut().skip(size).for_each(|s| *s = 0.);
         
-        self.head = self.head.min(size - 1);
-        self.size = size;
+        self.resize_window(size);
+    }
+
+    /// Change the size like `resize()`, but keep the samples outside of the new size, so
+    /// growing the buffer again plays them
+    pub fn resize_window(&mut self, size: usize) {
+        // The head keeps its place in the buffer where it can, so growing the buffer again
+        // carries on from the same sample
+        self.buffer.head %= size - 1;
+        self.buffer.size = size;
+    }
+
+    /// See `ChannelBuffers::smear()`. The filter runs along the buffer, which wraps around
+    /// between the loop's newest and oldest samples just like the playback does.
+    fn smear(&mut self, start: usize, len: usize, amount: f32) {
+        let loop_len = self.buffer.loop_len();
+        let (before, after) = self.samples[..loop_len].split_at_mut(start.min(loop_len));
+        let mut state = self.buffer.smear_state;
+        for sample in after.iter_mut().chain(before).take(len) {
+            state = flush_denormal(state + (*sample - state) * SMEAR_LOWPASS);
+            *sample = flush_denormal(*sample + (state - *sample) * amount);
+        }
+        self.buffer.smear_state = state;
+    }
+
+    /// A read-only view of the channel
+    pub fn view(&self) -> ChannelRef<'_> {
+        ChannelRef { buffer: self.buffer, samples: self.samples }
+    }
+}
+
+impl<'a> ChannelRef<'a> {
+    /// The recorded loop in buffer order, see `loop_samples()` for playback order
+    pub fn contents(&self) -> &'a [f32] {
+        &self.samples[..self.loop_len()]
+    }
+
+    /// The recorded loop in playback order, starting with the oldest sample like `copy_loop()`
+    pub fn loop_samples(&self) -> impl Iterator<Item = f32> + 'a {
+        let (newer, older) = self.contents().split_at(self.oldest());
+        older.iter().chain(newer).copied()
+    }
+
+    /// The lowest and highest sample in a range of the recorded loop, indexed by their position
+    /// in the buffer rather than in playback order so the range doesn't move while the loop
+    /// plays. Both include zero, so an empty range is `(0, 0)`.
+    pub fn peak(&self, range: std::ops::Range<usize>) -> (f32, f32) {
+        self.samples[range.start.min(self.loop_len())..range.end.min(self.loop_len())]
+            .iter()
+            .fold((0f32, 0f32), |(min, max), &s| (min.min(s), max.max(s)))
     }
-}
\ No newline at end of file
+
+    /// The mean square of the whole recorded loop
+    pub fn mean_square(&self) -> f32 {
+        let samples = &self.samples[..self.loop_len()];
+        let sum = samples.iter().map(|&sample| sample as f64 * sample as f64).sum::<f64>();
+        (sum / samples.len().max(1) as f64) as f32
+    }
+
+    /// Copy the recorded loop into `target` in playback order, starting with the oldest sample.
+    /// Returns the number of samples written.
+    pub fn copy_loop(&self, target: &mut [f32]) -> usize {
+        let wrap = self.size - 1;
+        let start = (self.head + 1) % wrap;
+        let (newer, older) = self.samples[..wrap].split_at(start);
+        target[..older.len()].copy_from_slice(older);
+        target[older.len()..wrap].copy_from_slice(newer);
+
+        wrap
+    }
+}
+
+impl Deref for Channel<'_> {
+    type Target = RingBuffer;
+
+    fn deref(&self) -> &RingBuffer {
+        self.buffer
+    }
+}
+
+impl DerefMut for Channel<'_> {
+    fn deref_mut(&mut self) -> &mut RingBuffer {
+        self.buffer
+    }
+}
+
+impl Deref for ChannelRef<'_> {
+    type Target = RingBuffer;
+
+    fn deref(&self) -> &RingBuffer {
+        self.buffer
+    }
+}
+
+/// Replace NaN and infinite input samples with silence
+#[inline]
+pub fn sanitize(sample: f32) -> f32 {
+    if sample.is_finite() {
+        sample
+    } else {
+        0.
+    }
+}
+
+/// Blend the input `item` into a recorded sample of a loop that's held by `hold`. Fully held
+/// loops keep their samples bit for bit, and loops that aren't held at all take the input as is.
+#[inline]
+fn blend_hold(stored: f32, item: f32, hold: f32) -> f32 {
+    if hold >= 1. {
+        stored
+    } else if hold <= 0. {
+        item
+    } else {
+        stored + (item - stored) * (1. - hold)
+    }
+}
+
+/// Linearly resample a loop by `ratio`. The result always fits into a `RingBuffer`.
+pub fn resample(samples: &[f32], ratio: f64) -> Vec<f32> {
+    if samples.is_empty() {
+        return vec![];
+    }
+
+    let len = (samples.len() as f64 * ratio).round() as usize;
+    let len = len.clamp(1, crate::MAX_BUFFER_SIZE - 1);
+    (0..len)
+        .map(|i| {
+            let position = i as f64 / ratio;
+            let index = position.floor() as usize;
+            let t = (position - index as f64) as f32;
+            // The loop wraps around, so the last sample interpolates towards the first one
+            let current = samples[index % samples.len()];
+            let next = samples[(index + 1) % samples.len()];
+            current + (next - current) * t
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn loop_of(buffers: &ChannelBuffers) -> Vec<f32> {
+        buffers.first().unwrap().loop_samples().collect()
+    }
+
+    #[test]
+    fn recording_wraps_around_the_loop() {
+        let mut buffers = ChannelBuffers::new(1, 5);
+        {
+            let mut channel = buffers.iter_mut().next().unwrap();
+            channel.record(&[1., 2., 3.]);
+            channel.record(&[4., 5., 6.]);
+            assert_eq!(channel.head(), 2);
+        }
+
+        assert_eq!(loop_of(&buffers), [3., 4., 5., 6.]);
+    }
+
+    #[test]
+    fn next_item_matches_record() {
+        let mut recorded = ChannelBuffers::new(1, 7);
+        let mut per_sample = ChannelBuffers::new(1, 7);
+        let items: Vec<f32> = (0..17).map(|i| i as f32).collect();
+        recorded.iter_mut().next().unwrap().record(&items);
+        let mut channel = per_sample.iter_mut().next().unwrap();
+        items.iter().for_each(|&item| {
+            channel.next_item(item);
+        });
+
+        assert_eq!(loop_of(&recorded), loop_of(&per_sample));
+    }
+
+    #[test]
+    fn frozen_next_item_matches_play() {
+        let items: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        let mut buffers = ChannelBuffers::new(2, 7);
+        let mut played = Vec::new();
+        let mut per_sample = Vec::new();
+        for (i, mut channel) in buffers.iter_mut().enumerate() {
+            channel.record(&items);
+            channel.freezing = true;
+            if i == 0 {
+                played = vec![0.; 15];
+                channel.play(&mut played);
+            } else {
+                per_sample = (0..15).map(|_| channel.next_item(0.)).collect();
+            }
+        }
+
+        assert_eq!(played, per_sample);
+        assert_eq!(played[..6], [4., 5., 6., 7., 8., 9.]);
+    }
+
+    #[test]
+    fn resize_window_wraps_the_head() {
+        let items: Vec<f32> = (0..300).map(|i| i as f32).collect();
+        let mut buffers = ChannelBuffers::new(1, 201);
+        let mut channel = buffers.iter_mut().next().unwrap();
+        channel.record(&items);
+        assert_eq!(channel.head(), 100);
+
+        // The head is outside of the smaller loop, so it wraps into it
+        channel.freezing = true;
+        channel.resize_window(51);
+        assert_eq!(channel.head(), 0);
+        let mut shrunk = [0.; 50];
+        channel.play(&mut shrunk);
+        assert_eq!(shrunk[..49], items[200..249]);
+        assert_eq!(shrunk[49], 199.);
+        assert_eq!(channel.head(), 0);
+
+        // Growing again plays the samples that were kept, carrying on from the same sample
+        channel.resize_window(201);
+        let mut grown = [0.; 200];
+        channel.play(&mut grown);
+        assert_eq!(grown[..100], items[200..]);
+        assert_eq!(grown[100..], items[100..200]);
+    }
+
+    #[test]
+    fn load_keeps_the_loop_order() {
+        let mut buffers = ChannelBuffers::new(1, 129);
+        buffers.iter_mut().next().unwrap().load(&[1., 2., 3.]);
+
+        assert_eq!(buffers.first().unwrap().loop_len(), 3);
+        assert_eq!(loop_of(&buffers), [1., 2., 3.]);
+    }
+
+    #[test]
+    fn load_empty_loop() {
+        let mut buffers = ChannelBuffers::new(1, 129);
+        {
+            let mut channel = buffers.iter_mut().next().unwrap();
+            channel.record(&[1.; 64]);
+            channel.load(&[]);
+            assert_eq!(channel.next_item(0.5), 0.5);
+        }
+
+        assert_eq!(loop_of(&buffers), [0.5]);
+    }
+
+    #[test]
+    fn resample_clamps_the_length() {
+        assert!(resample(&[], 2.).is_empty());
+        assert_eq!(resample(&[1., 2.], 0.01).len(), 1);
+        assert_eq!(resample(&[1., 2., 3., 4.], 0.5).len(), 2);
+        assert_eq!(resample(&[0.; 40000], 2.).len(), crate::MAX_BUFFER_SIZE - 1);
+    }
+
+    /// Ten periods of a 100 Hz sine at `sample_rate`
+    fn sine(sample_rate: f64) -> Vec<f32> {
+        let len = (sample_rate / 10.) as usize;
+        (0..len)
+            .map(|i| (i as f64 * 100. * std::f64::consts::TAU / sample_rate).sin() as f32)
+            .collect()
+    }
+
+    fn assert_close(resampled: &[f32], expected: &[f32]) {
+        assert_eq!(resampled.len(), expected.len());
+        for (sample, expected) in resampled.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1e-3, "{sample} != {expected}");
+        }
+    }
+
+    #[test]
+    fn resample_sine_up() {
+        let mut buffers = ChannelBuffers::new(1, crate::MAX_BUFFER_SIZE);
+        {
+            let mut channel = buffers.iter_mut().next().unwrap();
+            channel.load(&sine(44100.));
+            channel.resample(48000. / 44100.);
+        }
+
+        assert_close(&loop_of(&buffers), &sine(48000.));
+    }
+
+    #[test]
+    fn resample_sine_down() {
+        assert_close(&resample(&sine(48000.), 44100. / 48000.), &sine(44100.));
+    }
+
+    #[test]
+    fn smeared_loop_decays_without_subnormals() {
+        // A quiet tail that keeps getting quieter with every pass
+        let quiet: Vec<f32> = (0..64).map(|i| if i % 2 == 0 { 1e-18 } else { -1e-18 }).collect();
+        let mut buffers = ChannelBuffers::new(1, 129);
+        buffers.iter_mut().next().unwrap().load(&quiet);
+        for _ in 0..200 {
+            buffers.smear(0.5, 64);
+            assert!(loop_of(&buffers).iter().all(|sample| !sample.is_subnormal()));
+        }
+
+        assert!(loop_of(&buffers).iter().all(|&sample| sample == 0.));
+    }
+    /// A buffer size for the random operations, small enough to wrap around often
+    fn buffer_size() -> impl Strategy<Value = usize> {
+        2..66usize
+    }
+
+    fn block() -> impl Strategy<Value = Vec<f32>> {
+        prop::collection::vec(-1f32..1., 0..100)
+    }
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        ToggleFreeze,
+        Resize(usize),
+        ResizeWindow(usize),
+        /// Record or play a whole block, depending on whether the buffer is freezing
+        Block(Vec<f32>),
+        NextItem(f32),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            1 => Just(Operation::ToggleFreeze),
+            1 => buffer_size().prop_map(Operation::Resize),
+            1 => buffer_size().prop_map(Operation::ResizeWindow),
+            1 => block().prop_map(Operation::Block),
+            4 => (-1f32..1.).prop_map(Operation::NextItem),
+        ]
+    }
+
+    proptest! {
+        /// Random sequences of recording, freezing and resizing, checked against the ring
+        /// buffer's invariants after every step
+        #[test]
+        fn random_operations_keep_the_invariants(
+            size in buffer_size(),
+            operations in prop::collection::vec(operation(), 0..500),
+        ) {
+            let mut buffers = ChannelBuffers::new(1, size);
+            let mut channel = buffers.iter_mut().next().unwrap();
+            // What's been played since the freeze engaged or the buffer was last resized, and
+            // the loop as it was then
+            let mut frozen_run = Vec::new();
+            let mut frozen_loop: Vec<f32> = Vec::new();
+            for operation in operations {
+                if frozen_run.is_empty() {
+                    frozen_loop = channel.view().loop_samples().collect();
+                }
+                match operation {
+                    Operation::ToggleFreeze => channel.freezing = !channel.freezing,
+                    Operation::Resize(size) => channel.resize(size),
+                    Operation::ResizeWindow(size) => channel.resize_window(size),
+                    Operation::Block(mut block) => {
+                        if channel.freezing {
+                            channel.play(&mut block);
+                            frozen_run.extend(block);
+                        } else {
+                            channel.record(&block);
+                        }
+                    },
+                    Operation::NextItem(item) => {
+                        let output = channel.next_item(item);
+                        if channel.freezing {
+                            frozen_run.push(output);
+                        } else {
+                            // Unfrozen buffers pass their input through
+                            prop_assert_eq!(output, item);
+                        }
+                    },
+                }
+
+                let loop_len = channel.loop_len();
+                prop_assert!(channel.head() < loop_len);
+                prop_assert!(loop_len < channel.capacity());
+                prop_assert_eq!(channel.view().contents().len(), loop_len);
+                if !channel.freezing || loop_len != frozen_loop.len() {
+                    frozen_run.clear();
+                }
+                // Frozen buffers play their loop over and over, starting with the oldest sample
+                for (i, &sample) in frozen_run.iter().enumerate() {
+                    prop_assert_eq!(sample, frozen_loop[i % loop_len]);
+                }
+            }
+        }
+
+        /// Recording and playing blocks of any length does the same as going through them one
+        /// sample at a time, regardless of where the blocks wrap around the loop
+        #[test]
+        fn blocks_match_single_items(
+            size in buffer_size(),
+            recorded in block(),
+            played in prop::collection::vec(0..100usize, 0..8),
+        ) {
+            let mut buffers = ChannelBuffers::new(2, size);
+            let mut channels = buffers.iter_mut();
+            let (mut blocks, mut items) = (channels.next().unwrap(), channels.next().unwrap());
+            blocks.record(&recorded);
+            recorded.iter().for_each(|&item| {
+                items.next_item(item);
+            });
+            blocks.freezing = true;
+            items.freezing = true;
+            for len in played {
+                let mut block = vec![0.; len];
+                blocks.play(&mut block);
+                let single: Vec<f32> = (0..len).map(|_| items.next_item(0.)).collect();
+                prop_assert_eq!(block, single);
+                prop_assert_eq!(blocks.head(), items.head());
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! The CLAP entry point. This does what `nih_export_clap!()` does, and additionally serves the
+//! preset discovery factory and the preset load extension so hosts can browse and load the
+//! factory presets, and the track info extension so the editor can show the track's name and
+//! color. Everything else is passed through to nih-plug's wrapper untouched.
+
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::ext::preset_load::*;
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::ext::track_info::*;
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::factory::preset_discovery::{
+    clap_preset_discovery_location_kind, CLAP_PRESET_DISCOVERY_FACTORY_ID,
+    CLAP_PRESET_DISCOVERY_FACTORY_ID_COMPAT,
+};
+use clap_sys::host::clap_host;
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::stream::clap_istream;
+use clap_sys::version::CLAP_VERSION;
+use nih_plug::wrapper::clap::{PluginDescriptor, Wrapper};
+use nih_plug::wrapper::setup_logger;
+use std::ffi::{c_char, c_void, CStr};
+use std::mem::MaybeUninit;
+use std::sync::{Arc, OnceLock};
+
+use crate::presets::Preset;
+use crate::track_info::{self, SharedTrackInfo, TrackInfo};
+use crate::{preset_discovery, WinXpCrash, WinXpCrashParams};
+
+#[no_mangle]
+#[used]
+#[allow(non_upper_case_globals)]
+pub static clap_entry: clap_plugin_entry = clap_plugin_entry {
+    clap_version: CLAP_VERSION,
+    init: Some(init),
+    deinit: Some(deinit),
+    get_factory: Some(get_factory),
+};
+
+static PLUGIN_FACTORY: clap_plugin_factory = clap_plugin_factory {
+    get_plugin_count: Some(get_plugin_count),
+    get_plugin_descriptor: Some(get_plugin_descriptor),
+    create_plugin: Some(create_plugin),
+};
+
+static PRESET_LOAD: clap_plugin_preset_load = clap_plugin_preset_load {
+    from_location: Some(from_location),
+};
+
+static TRACK_INFO: clap_plugin_track_info = clap_plugin_track_info {
+    changed: Some(track_info_changed),
+};
+
+static PLUGIN_DESCRIPTOR: OnceLock<PluginDescriptor> = OnceLock::new();
+
+/// nih-plug's plugin with the preset load and track info extensions added on top. nih-plug's
+/// functions only look at `plugin_data`, so they work the same when they're called through the
+/// copy.
+#[repr(C)]
+struct WrappedPlugin {
+    /// The host only ever sees this, the struct starts with it so the pointer can be cast back
+    plugin: clap_plugin,
+    /// The plugin nih-plug created, which still owns the wrapper
+    inner: *const clap_plugin,
+    host: *const clap_host,
+    /// Shared with the plugin's parameters
+    track_info: SharedTrackInfo,
+}
+
+fn plugin_descriptor() -> &'static PluginDescriptor {
+    PLUGIN_DESCRIPTOR.get_or_init(PluginDescriptor::for_plugin::<WinXpCrash>)
+}
+
+/// The preset's state as nih-plug's state extension reads it: the JSON prefixed with its length
+fn serialize_state(preset: &Preset) -> Option<Vec<u8>> {
+    let state = serde_json::to_vec(&preset.state(&WinXpCrashParams::default())).ok()?;
+    let mut serialized = (state.len() as u64).to_le_bytes().to_vec();
+    serialized.extend(state);
+
+    Some(serialized)
+}
+
+unsafe extern "C" fn init(_plugin_path: *const c_char) -> bool {
+    setup_logger();
+    true
+}
+
+unsafe extern "C" fn deinit() {}
+
+unsafe extern "C" fn get_factory(factory_id: *const c_char) -> *const c_void {
+    if factory_id.is_null() {
+        return std::ptr::null();
+    }
+
+    let factory_id = CStr::from_ptr(factory_id);
+    if factory_id == CLAP_PLUGIN_FACTORY_ID {
+        std::ptr::from_ref(&PLUGIN_FACTORY).cast()
+    } else if factory_id == CLAP_PRESET_DISCOVERY_FACTORY_ID
+        || factory_id == CLAP_PRESET_DISCOVERY_FACTORY_ID_COMPAT
+    {
+        std::ptr::from_ref(&preset_discovery::FACTORY).cast()
+    } else {
+        std::ptr::null()
+    }
+}
+
+unsafe extern "C" fn get_plugin_count(_factory: *const clap_plugin_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn get_plugin_descriptor(
+    _factory: *const clap_plugin_factory,
+    index: u32,
+) -> *const clap_plugin_descriptor {
+    match index {
+        0 => std::ptr::from_ref(plugin_descriptor().clap_plugin_descriptor()).cast(),
+        _ => std::ptr::null(),
+    }
+}
+
+unsafe extern "C" fn create_plugin(
+    _factory: *const clap_plugin_factory,
+    host: *const clap_host,
+    plugin_id: *const c_char,
+) -> *const clap_plugin {
+    if host.is_null()
+        || plugin_id.is_null()
+        || CStr::from_ptr(plugin_id) != plugin_descriptor().clap_id()
+    {
+        return std::ptr::null();
+    }
+
+    // The wrapper releases this reference itself when the inner plugin is destroyed
+    let track_info = SharedTrackInfo::default();
+    let wrapper = track_info::create_with(track_info.clone(), || {
+        Arc::into_raw(Wrapper::<WinXpCrash>::new(host.cast()))
+    });
+    let inner: *const clap_plugin = (*wrapper).clap_plugin.as_ptr().cast_const().cast();
+    let plugin = Box::new(WrappedPlugin {
+        plugin: clap_plugin {
+            init: Some(plugin_init),
+            get_extension: Some(get_extension),
+            destroy: Some(destroy),
+            ..*inner
+        },
+        inner,
+        host,
+        track_info,
+    });
+
+    Box::into_raw(plugin).cast()
+}
+
+unsafe extern "C" fn plugin_init(plugin: *const clap_plugin) -> bool {
+    let plugin = &*plugin.cast::<WrappedPlugin>();
+    let initialized = (*plugin.inner).init.is_some_and(|init| init(plugin.inner));
+    // Host extensions can be queried from here on
+    if initialized {
+        update_track_info(plugin);
+    }
+
+    initialized
+}
+
+unsafe extern "C" fn destroy(plugin: *const clap_plugin) {
+    let plugin = Box::from_raw(plugin.cast_mut().cast::<WrappedPlugin>());
+    if let Some(destroy) = (*plugin.inner).destroy {
+        destroy(plugin.inner);
+    }
+}
+
+unsafe extern "C" fn get_extension(plugin: *const clap_plugin, id: *const c_char) -> *const c_void {
+    let plugin = &*plugin.cast::<WrappedPlugin>();
+    if !id.is_null() {
+        let id = CStr::from_ptr(id);
+        if id == CLAP_EXT_PRESET_LOAD || id == CLAP_EXT_PRESET_LOAD_COMPAT {
+            return std::ptr::from_ref(&PRESET_LOAD).cast();
+        }
+        if id == CLAP_EXT_TRACK_INFO || id == CLAP_EXT_TRACK_INFO_COMPAT {
+            return std::ptr::from_ref(&TRACK_INFO).cast();
+        }
+    }
+
+    match (*plugin.inner).get_extension {
+        Some(get_extension) => get_extension(plugin.inner, id),
+        None => std::ptr::null(),
+    }
+}
+
+unsafe extern "C" fn from_location(
+    plugin: *const clap_plugin,
+    location_kind: clap_preset_discovery_location_kind,
+    location: *const c_char,
+    load_key: *const c_char,
+) -> bool {
+    let plugin = &*plugin.cast::<WrappedPlugin>();
+    let ids = [CLAP_EXT_PRESET_LOAD, CLAP_EXT_PRESET_LOAD_COMPAT];
+    let host_preset_load = host_extension::<clap_host_preset_load>(plugin.host, ids);
+    let loaded = load_preset(plugin.inner, location_kind, load_key);
+    match (host_preset_load, loaded) {
+        (Some(host_preset_load), true) => {
+            if let Some(on_loaded) = host_preset_load.loaded {
+                on_loaded(plugin.host, location_kind, location, load_key);
+            }
+        },
+        (Some(host_preset_load), false) => {
+            if let Some(on_error) = host_preset_load.on_error {
+                let message = c"There's no factory preset with this load key";
+                on_error(plugin.host, location_kind, location, load_key, 0, message.as_ptr());
+            }
+        },
+        (None, _) => (),
+    }
+
+    loaded
+}
+
+/// Load the factory preset with the load key through the inner plugin's state extension, which
+/// sets the parameters and tells the host about the new values
+unsafe fn load_preset(
+    inner: *const clap_plugin,
+    location_kind: clap_preset_discovery_location_kind,
+    load_key: *const c_char,
+) -> bool {
+    let Some(state) = preset_discovery::preset(location_kind, load_key).and_then(serialize_state)
+    else {
+        return false;
+    };
+    let Some(get_extension) = (*inner).get_extension else {
+        return false;
+    };
+    let plugin_state = get_extension(inner, CLAP_EXT_STATE.as_ptr()).cast::<clap_plugin_state>();
+    let Some(load) = plugin_state.as_ref().and_then(|plugin_state| plugin_state.load) else {
+        return false;
+    };
+
+    let mut stream = SliceStream { data: &state };
+    let stream = clap_istream {
+        ctx: std::ptr::from_mut(&mut stream).cast(),
+        read: Some(read),
+    };
+    load(inner, &stream)
+}
+
+/// The host's extension with the ID or its draft's ID, if the host supports either
+unsafe fn host_extension<T>(host: *const clap_host, ids: [&CStr; 2]) -> Option<&'static T> {
+    let get_extension = (*host).get_extension?;
+    ids.into_iter()
+        .find_map(|id| get_extension(host, id.as_ptr()).cast::<T>().as_ref())
+}
+
+unsafe extern "C" fn track_info_changed(plugin: *const clap_plugin) {
+    update_track_info(&*plugin.cast::<WrappedPlugin>());
+}
+
+/// Ask the host for the track's name and color again. Hosts without the track info extension
+/// leave it empty.
+unsafe fn update_track_info(plugin: &WrappedPlugin) {
+    let ids = [CLAP_EXT_TRACK_INFO, CLAP_EXT_TRACK_INFO_COMPAT];
+    let host_track_info = host_extension::<clap_host_track_info>(plugin.host, ids);
+    let track_info = host_track_info.and_then(|host_track_info| {
+        let mut info = MaybeUninit::<clap_track_info>::zeroed();
+        let filled = host_track_info.get?(plugin.host, info.as_mut_ptr());
+        filled.then(|| read_track_info(info.assume_init_ref()))
+    });
+
+    if let Ok(mut shared) = plugin.track_info.lock() {
+        *shared = track_info;
+    }
+}
+
+/// The parts of the host's track info the editor uses
+fn read_track_info(info: &clap_track_info) -> TrackInfo {
+    let name = (info.flags & CLAP_TRACK_INFO_HAS_TRACK_NAME != 0).then(|| {
+        // The name is NUL terminated unless the host filled the whole array
+        let name: Vec<u8> = info.name.iter().map(|&c| c as u8).take_while(|&c| c != 0).collect();
+        String::from_utf8_lossy(&name).into_owned()
+    });
+    let color = info.color;
+    let color = (info.flags & CLAP_TRACK_INFO_HAS_TRACK_COLOR != 0)
+        .then_some([color.red, color.green, color.blue]);
+
+    TrackInfo { name, color }
+}
+
+/// The rest of a byte slice, read through a `clap_istream`
+struct SliceStream<'a> {
+    data: &'a [u8],
+}
+
+unsafe extern "C" fn read(stream: *const clap_istream, buffer: *mut c_void, size: u64) -> i64 {
+    let stream = &mut *(*stream).ctx.cast::<SliceStream>();
+    let len = stream.data.len().min(size as usize);
+    let (read, rest) = stream.data.split_at(len);
+    std::ptr::copy_nonoverlapping(read.as_ptr(), buffer.cast::<u8>(), len);
+    stream.data = rest;
+
+    len as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::wrapper::state::{ParamValue, PluginState};
+
+    use super::*;
+    use crate::presets::FACTORY_PRESETS;
+
+    #[test]
+    fn serves_the_factories() {
+        let get_factory = clap_entry.get_factory.unwrap();
+        // SAFETY: The IDs are valid C strings
+        unsafe {
+            assert!(!get_factory(CLAP_PLUGIN_FACTORY_ID.as_ptr()).is_null());
+            let discovery = std::ptr::from_ref(&preset_discovery::FACTORY).cast();
+            assert_eq!(get_factory(CLAP_PRESET_DISCOVERY_FACTORY_ID.as_ptr()), discovery);
+            assert_eq!(get_factory(CLAP_PRESET_DISCOVERY_FACTORY_ID_COMPAT.as_ptr()), discovery);
+            assert!(get_factory(c"clap.unknown-factory".as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn reads_the_track_name_and_color() {
+        // SAFETY: All zeros is a valid empty track info
+        let mut info: clap_track_info = unsafe { std::mem::zeroed() };
+        assert_eq!(read_track_info(&info), TrackInfo::default());
+
+        info.flags = CLAP_TRACK_INFO_HAS_TRACK_NAME | CLAP_TRACK_INFO_HAS_TRACK_COLOR;
+        for (c, byte) in info.name.iter_mut().zip(b"Drums") {
+            *c = *byte as c_char;
+        }
+        info.color.red = 200;
+        info.color.blue = 40;
+        let expected = TrackInfo {
+            name: Some("Drums".to_owned()),
+            color: Some([200, 0, 40]),
+        };
+        assert_eq!(read_track_info(&info), expected);
+
+        // A name that fills the whole array has no NUL at the end
+        info.name.fill(b'a' as c_char);
+        assert_eq!(read_track_info(&info).name.map(|name| name.len()), Some(info.name.len()));
+    }
+
+    #[test]
+    fn reads_the_preset_state_in_pieces() {
+        let preset = &FACTORY_PRESETS[1];
+        let serialized = serialize_state(preset).unwrap();
+        let mut stream = SliceStream { data: &serialized };
+        let stream = clap_istream {
+            ctx: std::ptr::from_mut(&mut stream).cast(),
+            read: Some(read),
+        };
+
+        let mut read_back = Vec::new();
+        let mut buffer = [0u8; 7];
+        loop {
+            // SAFETY: The buffer holds as many bytes as are asked for
+            let len = unsafe { read(&stream, buffer.as_mut_ptr().cast(), buffer.len() as u64) };
+            if len == 0 {
+                break;
+            }
+            read_back.extend(&buffer[..len as usize]);
+        }
+        assert_eq!(read_back, serialized);
+
+        let (len, json) = read_back.split_at(8);
+        assert_eq!(u64::from_le_bytes(len.try_into().unwrap()) as usize, json.len());
+        let state: PluginState = serde_json::from_slice(json).unwrap();
+        let stored = |id: &str| state.params.get(id).cloned();
+        assert!(matches!(stored("buffer_size"), Some(ParamValue::I32(4096))));
+        assert!(matches!(stored("release"), Some(ParamValue::F32(_))));
+        assert!(stored("freeze").is_none());
+        let version = state.fields.get(crate::state::STATE_VERSION_KEY);
+        assert_eq!(version, Some(&crate::state::STATE_VERSION.to_string()));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::ftz::flush_denormal;
+use crate::rng::Rng;
+
+/// The fewest clicks per second the Crackle parameter produces above zero
+const MIN_DENSITY: f32 = 2.;
+/// The most clicks per second, where they blur into a constant frying sound
+const MAX_DENSITY: f32 = 20000.;
+/// The level of the loudest click
+const MAX_LEVEL: f32 = 0.5;
+/// The longest click in samples
+const MAX_CLICK_LEN: u32 = 4;
+/// The coefficient of the one-pole lowpass filter that turns clicks into softer pops
+const SOFT_LOWPASS: f32 = 0.25;
+
+/// Sparse clicks and pops of random length, level and polarity for one channel
+#[derive(Clone, Debug)]
+pub struct Crackle {
+    rng: Rng,
+    /// The number of samples left of the current click
+    remaining: u32,
+    /// The current click's level including its polarity
+    level: f32,
+    lowpass_state: f32,
+}
+
+impl Crackle {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            remaining: 0,
+            level: 0.,
+            lowpass_state: 0.,
+        }
+    }
+
+    /// The chance of a click starting on any sample for the Crackle parameter's `amount`. The
+    /// density rises exponentially from a few clicks per second, and zero means no clicks at all.
+    pub fn chance(amount: f32, sample_rate: f32) -> f32 {
+        if amount <= 0. {
+            return 0.;
+        }
+
+        MIN_DENSITY * (MAX_DENSITY / MIN_DENSITY).powf(amount) / sample_rate
+    }
+
+    /// The next sample, with a new click starting at `chance`. Soft crackle runs the clicks
+    /// through a lowpass filter so they sound more like dull pops.
+    pub fn next(&mut self, chance: f32, soft: bool) -> f32 {
+        if self.remaining == 0 && self.rng.next_f32() < chance {
+            self.remaining = 1 + self.rng.next_u32() % MAX_CLICK_LEN;
+            // Most clicks are quiet, with the occasional loud one
+            let level = self.rng.next_f32();
+            let polarity = if self.rng.next_u32() & 1 == 0 {
+                1.
+            } else {
+                -1.
+            };
+            self.level = polarity * level * level * MAX_LEVEL;
+        }
+
+        let click = if self.remaining > 0 {
+            self.remaining -= 1;
+            self.level
+        } else {
+            0.
+        };
+        if soft {
+            self.lowpass_state =
+                flush_denormal(self.lowpass_state + (click - self.lowpass_state) * SOFT_LOWPASS);
+            self.lowpass_state
+        } else {
+            click
+        }
+    }
+}
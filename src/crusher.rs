@@ -0,0 +1,53 @@
+use crate::rng::Rng;
+
+/// The Bit Depth parameter's highest value, which leaves the signal alone
+pub const BYPASS_BIT_DEPTH: i32 = 24;
+
+/// Reduces the bit depth of one channel's wet signal, optionally with dither and noise shaping
+/// so quiet tails fade out smoothly instead of gating
+#[derive(Clone, Debug)]
+pub struct Crusher {
+    rng: Rng,
+    /// The previous sample's quantization error, for the noise shaping
+    error: f32,
+}
+
+impl Crusher {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            error: 0.,
+        }
+    }
+
+    /// The quantization step for a bit depth between -1 and 1, or zero when the bit depth leaves
+    /// the signal alone
+    pub fn step(bit_depth: i32) -> f32 {
+        if bit_depth >= BYPASS_BIT_DEPTH {
+            0.
+        } else {
+            2. / (1u32 << bit_depth.clamp(1, BYPASS_BIT_DEPTH - 1)) as f32
+        }
+    }
+
+    /// Quantize the next sample to multiples of `step`. Dither adds triangular noise of up to
+    /// one step before rounding, which has no DC offset. Noise shaping feeds the last sample's
+    /// error back so the noise moves up to the high frequencies.
+    pub fn process(&mut self, sample: f32, step: f32, dither: bool, noise_shaping: bool) -> f32 {
+        let shaped = if noise_shaping {
+            sample - self.error
+        } else {
+            sample
+        };
+        let noise = if dither {
+            (self.rng.next_f32() - self.rng.next_f32()) * step
+        } else {
+            0.
+        };
+        let output = ((shaped + noise) / step).round() * step;
+        // The error stays within one and a half steps, so feeding it back can't build up
+        self.error = if noise_shaping { output - shaped } else { 0. };
+
+        output
+    }
+}
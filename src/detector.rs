@@ -0,0 +1,84 @@
+use crate::ftz::flush_denormal;
+
+/// A peak envelope follower that decides when the sidechain signal triggers the freeze
+#[derive(Clone, Debug, Default)]
+pub struct Detector {
+    envelope: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+}
+
+impl Detector {
+    /// Set the attack and release times in milliseconds
+    pub fn set_times(&mut self, attack_ms: f32, release_ms: f32, sample_rate: f32) {
+        self.attack_coefficient = coefficient(attack_ms, sample_rate);
+        self.release_coefficient = coefficient(release_ms, sample_rate);
+    }
+
+    /// Feed the next sample's peak level into the envelope follower and return the envelope
+    pub fn next_level(&mut self, level: f32) -> f32 {
+        let coefficient = if level > self.envelope {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.envelope = flush_denormal(level + (self.envelope - level) * coefficient);
+
+        self.envelope
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.;
+    }
+}
+
+/// A high-pass and a low-pass for one channel of the signal feeding a detector, so the trigger
+/// only responds to part of the spectrum. Each filter is two one-pole stages for 12 dB per
+/// octave.
+#[derive(Clone, Debug, Default)]
+pub struct DetectorFilter {
+    highpass: [f32; 2],
+    lowpass: [f32; 2],
+}
+
+impl DetectorFilter {
+    /// Filter the next sample with coefficients from `filter_coefficient()`
+    pub fn process(
+        &mut self,
+        sample: f32,
+        highpass_coefficient: f32,
+        lowpass_coefficient: f32,
+    ) -> f32 {
+        let mut sample = sample;
+        for state in &mut self.highpass {
+            *state = flush_denormal(*state + (sample - *state) * highpass_coefficient);
+            sample -= *state;
+        }
+        for state in &mut self.lowpass {
+            *state = flush_denormal(*state + (sample - *state) * lowpass_coefficient);
+            sample = *state;
+        }
+
+        sample
+    }
+
+    pub fn reset(&mut self) {
+        self.highpass = [0.; 2];
+        self.lowpass = [0.; 2];
+    }
+}
+
+/// The coefficient of a one-pole stage with its cutoff at `frequency` Hz
+pub fn filter_coefficient(frequency: f32, sample_rate: f32) -> f32 {
+    1. - (-std::f32::consts::TAU * frequency / sample_rate).exp()
+}
+
+/// The one-pole coefficient that covers 99% of the distance within `ms` milliseconds
+fn coefficient(ms: f32, sample_rate: f32) -> f32 {
+    let samples = ms / 1000. * sample_rate;
+    if samples < 1. {
+        0.
+    } else {
+        (-(100f32.ln()) / samples).exp()
+    }
+}
@@ -0,0 +1,437 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{self, vec2, RichText};
+use nih_plug_egui::resizable_window::ResizableWindow;
+use nih_plug_egui::{create_egui_editor, widgets, EguiState};
+
+use crate::presets::{self, AbSlot};
+use crate::{Task, WinXpCrash, WinXpCrashParams};
+
+mod bsod;
+mod meter_view;
+mod preset_browser;
+mod randomizer_panel;
+mod skin;
+mod spectrum_view;
+mod waveform_view;
+
+pub use skin::Theme;
+
+/// The editor's size when it's opened for the first time
+const EDITOR_SIZE: (u32, u32) = (360, 640);
+/// The editor can't be made smaller than this. It's enough for the compact layout.
+const MIN_SIZE: (f32, f32) = (240., 200.);
+/// Windows with less room than this below the title bar only show the Freeze button and the
+/// buffer size
+const COMPACT_HEIGHT: f32 = 380.;
+/// Hold this key to freeze while the editor has focus
+const FREEZE_KEY: egui::Key = egui::Key::Space;
+/// How long a failed import's error stays visible
+const IMPORT_ERROR_DURATION: Duration = Duration::from_secs(5);
+/// The size of the Freeze button
+const FREEZE_BUTTON_SIZE: (f32, f32) = (320., 80.);
+
+pub fn default_state() -> Arc<EguiState> {
+    EguiState::from_size(EDITOR_SIZE.0, EDITOR_SIZE.1)
+}
+
+/// The editor's own state, which lives on the GUI thread
+#[derive(Default)]
+struct EditorState {
+    /// Loaded when the editor opens
+    textures: Option<skin::Textures>,
+    waveform: waveform_view::WaveformView,
+    spectrum: spectrum_view::SpectrumView,
+    meters: meter_view::MeterView,
+    presets: preset_browser::PresetBrowser,
+    randomizer: randomizer_panel::RandomizerPanel,
+    bsod: bsod::Bsod,
+    momentary_freeze: MomentaryFreeze,
+}
+
+/// The state of a freeze that lasts while the freeze key or the Freeze button is held
+#[derive(Default)]
+struct MomentaryFreeze {
+    held: bool,
+    /// Whether holding the key or the button turned the freeze on. The parameter gesture stays
+    /// open until it's let go.
+    engaged: bool,
+}
+
+/// The editor only reads the parameters and the atomics the audio thread publishes, and changes
+/// parameters through the `ParamSetter` so the host can record automation.
+pub fn create(
+    params: Arc<WinXpCrashParams>,
+    async_executor: AsyncExecutor<WinXpCrash>,
+) -> Option<Box<dyn Editor>> {
+    create_egui_editor(
+        params.editor_state.clone(),
+        EditorState::default(),
+        |egui_ctx, state| state.textures = Some(skin::Textures::load(egui_ctx)),
+        move |egui_ctx, setter, state| {
+            // The audio thread updates this when the freeze engages or releases
+            let frozen = params.frozen.load(Ordering::Relaxed);
+            let theme = params.theme.read().map_or(Theme::default(), |theme| *theme);
+            egui_ctx.set_visuals(skin::visuals(theme, frozen));
+            // The plain themes don't pretend to be a window that stopped responding
+            let ghosted = frozen && theme == Theme::Xp;
+            // Only CLAP hosts with the track info extension report the track
+            let track = params.track_info.lock().ok().and_then(|track| track.clone());
+
+            if theme == Theme::Xp {
+                egui::TopBottomPanel::top("title-bar")
+                    .exact_height(skin::TITLE_BAR_HEIGHT)
+                    .frame(egui::Frame::none())
+                    .show(egui_ctx, |ui| {
+                        let (rect, _) =
+                            ui.allocate_exact_size(ui.available_size(), egui::Sense::hover());
+                        if let Some(textures) = &state.textures {
+                            skin::title_bar(ui, rect, textures, frozen, track.as_ref());
+                        }
+                    });
+            } else if let Some(track) = &track {
+                egui::TopBottomPanel::top("track-header")
+                    .exact_height(skin::TRACK_HEADER_HEIGHT)
+                    .frame(egui::Frame::none())
+                    .show(egui_ctx, |ui| {
+                        let (rect, _) =
+                            ui.allocate_exact_size(ui.available_size(), egui::Sense::hover());
+                        skin::track_header(ui, rect, track);
+                    });
+            }
+
+            // The size is stored in `editor_state`, which is persisted with the plugin's state
+            let window = ResizableWindow::new("editor").min_size(vec2(MIN_SIZE.0, MIN_SIZE.1));
+            window.show(egui_ctx, &params.editor_state, |ui| {
+                let compact = ui.available_height() < COMPACT_HEIGHT;
+                ui.vertical_centered(|ui| {
+                    freeze_button(
+                        ui,
+                        &params.freezing.freeze,
+                        setter,
+                        &mut state.momentary_freeze,
+                    );
+                    ui.add_space(8.);
+                });
+
+                if compact {
+                    ui.label("Buffer Size");
+                    ui.add(widgets::ParamSlider::for_param(
+                        &params.capture.buffer_size,
+                        setter,
+                    ));
+                    ui.label(buffer_size_text(&params));
+                    if ghosted {
+                        skin::ghost(ui, ui.max_rect());
+                    }
+                    return;
+                }
+
+                state.presets.show(ui, &params, setter, &async_executor);
+                ab_compare(ui, &params, setter);
+                state.randomizer.show(ui, &params, setter);
+                ui.add_space(8.);
+
+                state.waveform.show(ui, &params, setter, frozen);
+                import_controls(ui, &params, &async_executor);
+                ui.add_space(8.);
+                state.meters.show(ui, &params.meters);
+                ui.add_space(8.);
+
+                // The audio thread only feeds the analyzer while it's shown
+                let show_spectrum = ui
+                    .horizontal(|ui| {
+                        setting_checkbox(ui, &params.show_spectrum, "Spectrum");
+                        setting_checkbox(ui, &params.show_bsod, "Blue Screen");
+                        theme_selector(ui, &params, theme);
+                        params.show_spectrum.load(Ordering::Relaxed)
+                    })
+                    .inner;
+                if show_spectrum {
+                    let sample_rate = f32::from_bits(params.sample_rate.load(Ordering::Relaxed));
+                    state
+                        .spectrum
+                        .show(ui, &params.spectrum, sample_rate, frozen);
+                }
+                ui.add_space(8.);
+
+                // The controls follow the parameter groups the host sees
+                group_heading(ui, "Capture");
+                ui.label("Buffer Size");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.capture.buffer_size,
+                    setter,
+                ));
+                ui.label(buffer_size_text(&params));
+
+                ui.label("Size Unit");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.capture.size_unit,
+                    setter,
+                ));
+
+                ui.label("Resize While Frozen");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.capture.resize_while_frozen,
+                    setter,
+                ));
+
+                ui.label("Division");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.capture.division,
+                    setter,
+                ));
+
+                group_heading(ui, "Freeze");
+                ui.label("Attack");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.freezing.attack,
+                    setter,
+                ));
+
+                ui.label("Decay");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.freezing.freeze_decay,
+                    setter,
+                ));
+
+                ui.label("Sustain");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.freezing.freeze_sustain,
+                    setter,
+                ));
+
+                ui.label("Release");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.freezing.release,
+                    setter,
+                ));
+
+                ui.label("Hold");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.freezing.hold_amount,
+                    setter,
+                ));
+
+                ui.label("Freeze Amount");
+                ui.add(widgets::ParamSlider::for_param(
+                    &params.freezing.freeze_amount,
+                    setter,
+                ));
+
+                group_heading(ui, "Mix");
+                ui.label("Spread");
+                ui.add(widgets::ParamSlider::for_param(&params.mix.spread, setter));
+
+                // The controls keep working while ghosted, otherwise there'd be no way to unfreeze
+                if ghosted {
+                    skin::ghost(ui, ui.max_rect());
+                }
+            });
+
+            let show_bsod = params.show_bsod.load(Ordering::Relaxed);
+            state.bsod.show(egui_ctx, frozen, show_bsod);
+        },
+    )
+}
+
+/// Flip between the two A/B compare slots. Only the parameters change, the frozen loops keep
+/// playing.
+fn ab_compare(ui: &mut egui::Ui, params: &WinXpCrashParams, setter: &ParamSetter) {
+    // The lock isn't held while changing parameters, since hosts may read the plugin's state in
+    // response to that
+    let Some(mut ab_compare) = params.ab_compare.read().ok().map(|slots| slots.clone()) else {
+        return;
+    };
+
+    let changed = ui
+        .horizontal(|ui| {
+            let mut changed = false;
+            for slot in [AbSlot::A, AbSlot::B] {
+                let text = match slot {
+                    AbSlot::A => "A",
+                    AbSlot::B => "B",
+                };
+                let active = ab_compare.active == slot;
+                if ui.selectable_label(active, text).clicked() && !active {
+                    ab_compare.switch(setter.raw_context, params);
+                    changed = true;
+                }
+            }
+            if ui.button("Copy A→B").clicked() {
+                ab_compare.copy_a_to_b(setter.raw_context, params);
+                changed = true;
+            }
+
+            changed
+        })
+        .inner;
+
+    if changed {
+        if let Ok(mut slots) = params.ab_compare.write() {
+            *slots = ab_compare;
+        }
+    }
+}
+
+/// Switch between the editor's themes. The new theme is used from the next frame on.
+fn theme_selector(ui: &mut egui::Ui, params: &WinXpCrashParams, current: Theme) {
+    let mut selected = current;
+    egui::ComboBox::from_id_source("theme")
+        .selected_text(current.name())
+        .show_ui(ui, |ui| {
+            for theme in Theme::ALL {
+                ui.selectable_value(&mut selected, theme, theme.name());
+            }
+        });
+
+    if selected != current {
+        if let Ok(mut theme) = params.theme.write() {
+            *theme = selected;
+        }
+        ui.ctx().request_repaint();
+    }
+}
+
+/// The heading of a parameter group's controls
+fn group_heading(ui: &mut egui::Ui, name: &str) {
+    ui.add_space(4.);
+    ui.label(RichText::new(name).strong());
+}
+
+/// A checkbox for one of the editor's persisted settings
+fn setting_checkbox(ui: &mut egui::Ui, setting: &AtomicBool, text: &str) {
+    let mut value = setting.load(Ordering::Relaxed);
+    if ui.checkbox(&mut value, text).changed() {
+        setting.store(value, Ordering::Relaxed);
+    }
+}
+
+/// A big button for the Freeze parameter. Holding the button or the freeze key freezes until
+/// it's let go, Shift+clicking latches the freeze on or off.
+fn freeze_button(
+    ui: &mut egui::Ui,
+    freeze: &BoolParam,
+    setter: &ParamSetter,
+    momentary: &mut MomentaryFreeze,
+) {
+    let frozen = freeze.value();
+    let text = RichText::new(if frozen { "FROZEN" } else { "Freeze" })
+        .size(32.)
+        .strong();
+    // The button shrinks with the window
+    let width = FREEZE_BUTTON_SIZE.0.min(ui.available_width());
+    let button = egui::Button::new(text)
+        .min_size(vec2(width, FREEZE_BUTTON_SIZE.1))
+        .sense(egui::Sense::click_and_drag());
+    let response = ui.add(button);
+
+    let egui_ctx = ui.ctx();
+    let shift = egui_ctx.input(|input| input.modifiers.shift);
+    if response.clicked() && shift {
+        setter.begin_set_parameter(freeze);
+        setter.set_parameter(freeze, !frozen);
+        setter.end_set_parameter(freeze);
+    }
+
+    // Only the key's state is looked at and not its key presses, so key repeats while holding
+    // it don't do anything. The editor only receives keys while the plugin window has focus, and
+    // text fields get to keep theirs.
+    let key_held = !egui_ctx.wants_keyboard_input()
+        && egui_ctx.input(|input| input.key_down(FREEZE_KEY) && !input.modifiers.command);
+    let button_held = response.is_pointer_button_down_on() && !shift;
+    let held = key_held || button_held;
+    if held && !momentary.held {
+        momentary.held = true;
+        // An already latched freeze stays latched after letting go
+        momentary.engaged = !frozen;
+        if momentary.engaged {
+            setter.begin_set_parameter(freeze);
+            setter.set_parameter(freeze, true);
+        }
+    } else if !held && momentary.held {
+        momentary.held = false;
+        if momentary.engaged {
+            momentary.engaged = false;
+            setter.set_parameter(freeze, false);
+            setter.end_set_parameter(freeze);
+        }
+    }
+}
+
+/// Load WAV files that are dropped onto the editor, and offer to reload the last imported file
+fn import_controls(
+    ui: &mut egui::Ui,
+    params: &WinXpCrashParams,
+    async_executor: &AsyncExecutor<WinXpCrash>,
+) {
+    let egui_ctx = ui.ctx().clone();
+    let (hovering, dropped) = egui_ctx.input(|input| {
+        (
+            !input.raw.hovered_files.is_empty(),
+            input
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .find(|path| !presets::is_preset_file(path)),
+        )
+    });
+    if let Some(path) = dropped {
+        start_import(params, async_executor, path);
+    }
+
+    if hovering {
+        ui.label("Drop a WAV file to freeze it or a preset to load it");
+    } else if let Some((message, time)) = params
+        .import_error
+        .lock()
+        .ok()
+        .and_then(|error| error.clone())
+        .filter(|(_, time)| time.elapsed() < IMPORT_ERROR_DURATION)
+    {
+        ui.label(RichText::new(message).color(skin::ERROR));
+        // Make sure the message disappears again without any input
+        egui_ctx.request_repaint_after(IMPORT_ERROR_DURATION.saturating_sub(time.elapsed()));
+    } else if let Some(path) = params.import_path.read().ok().and_then(|path| path.clone()) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if ui.button(format!("Reload {name}")).clicked() {
+            start_import(params, async_executor, path);
+        }
+    }
+}
+
+/// Decode a WAV file on a background thread. The audio thread swaps it into the buffers at the
+/// start of the next block and freezes it.
+fn start_import(
+    params: &WinXpCrashParams,
+    async_executor: &AsyncExecutor<WinXpCrash>,
+    path: PathBuf,
+) {
+    let sample_rate = f32::from_bits(params.sample_rate.load(Ordering::Relaxed));
+    if sample_rate <= 0. {
+        crate::set_import_error(params, "The plugin isn't active".to_owned());
+        return;
+    }
+
+    if let Ok(mut import_path) = params.import_path.write() {
+        *import_path = Some(path);
+    }
+    async_executor.execute_background(Task::ImportWav { sample_rate });
+}
+
+/// The buffer size in samples and in milliseconds at the current sample rate
+fn buffer_size_text(params: &WinXpCrashParams) -> String {
+    let samples = params.capture.buffer_size.value();
+    let sample_rate = f32::from_bits(params.sample_rate.load(Ordering::Relaxed));
+    if sample_rate > 0. {
+        let ms = samples as f32 / sample_rate * 1000.;
+        format!("{samples} samples — {ms:.1} ms")
+    } else {
+        format!("{samples} samples")
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+use nih_plug_egui::egui::{self, pos2, vec2, Align2, Color32, FontId, Id, LayerId, Order, Rect};
+
+/// How long the blue screen flickers before it stays up
+const FLICKER_DURATION: Duration = Duration::from_millis(400);
+/// How long the blue screen is on and off while flickering
+const FLICKER_PERIOD_MS: u128 = 60;
+/// How long the "reboot" takes after releasing the freeze
+const REBOOT_DURATION: Duration = Duration::from_millis(900);
+
+const BSOD_BLUE: Color32 = Color32::from_rgb(0, 0, 170);
+const BSOD_TEXT: &str = "A problem has been detected and Windows XP Crash has been shut\n\
+down to prevent damage to your mix.\n\n\
+FROZEN_BUFFER_NOT_LESS_OR_EQUAL\n\n\
+If this is the first time you've seen this stop error screen,\n\
+release the freeze. If this screen appears again, freeze harder.\n\n\
+*** STOP: 0x0000007E (0xC0000005, 0x00000000, 0x00000000)\n\n\
+Click anywhere to dismiss.";
+
+#[derive(Default)]
+enum Phase {
+    #[default]
+    Hidden,
+    Crashing(Instant),
+    Crashed,
+    /// Dismissed by clicking it, stays hidden until the freeze is released
+    Dismissed,
+    Rebooting(Instant),
+}
+
+/// A blue screen that flickers in when the freeze engages and "reboots" when it's released.
+/// This runs entirely on the GUI thread from the freeze state the audio thread publishes.
+#[derive(Default)]
+pub struct Bsod {
+    phase: Phase,
+    was_frozen: bool,
+}
+
+impl Bsod {
+    pub fn show(&mut self, egui_ctx: &egui::Context, frozen: bool, enabled: bool) {
+        let now = Instant::now();
+        if !enabled {
+            self.phase = Phase::Hidden;
+        } else if frozen && !self.was_frozen {
+            self.phase = Phase::Crashing(now);
+        } else if !frozen && self.was_frozen {
+            self.phase = match self.phase {
+                Phase::Hidden | Phase::Dismissed => Phase::Hidden,
+                _ => Phase::Rebooting(now),
+            };
+        }
+        self.was_frozen = frozen;
+
+        let clicked = egui_ctx.input(|input| input.pointer.any_click());
+        let rect = egui_ctx.screen_rect();
+        let painter = egui_ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("bsod")));
+        match self.phase {
+            Phase::Hidden | Phase::Dismissed => return,
+            Phase::Crashing(start) => {
+                let elapsed = now - start;
+                if elapsed >= FLICKER_DURATION {
+                    self.phase = Phase::Crashed;
+                } else if (elapsed.as_millis() / FLICKER_PERIOD_MS) & 1 == 0 {
+                    // Every other period
+                    blue_screen(&painter, rect);
+                }
+            }
+            Phase::Crashed if clicked => {
+                self.phase = Phase::Dismissed;
+                return;
+            }
+            Phase::Crashed => blue_screen(&painter, rect),
+            Phase::Rebooting(start) => {
+                let progress = (now - start).as_secs_f32() / REBOOT_DURATION.as_secs_f32();
+                if progress >= 1. {
+                    self.phase = Phase::Hidden;
+                    return;
+                }
+                reboot_screen(&painter, rect, progress);
+            }
+        }
+
+        // Keep the animation going without any input
+        egui_ctx.request_repaint();
+    }
+}
+
+fn blue_screen(painter: &egui::Painter, rect: Rect) {
+    painter.rect_filled(rect, 0., BSOD_BLUE);
+    painter.text(
+        rect.left_top() + vec2(12., 12.),
+        Align2::LEFT_TOP,
+        BSOD_TEXT,
+        FontId::monospace(10.),
+        Color32::WHITE,
+    );
+}
+
+/// A black screen with the boot progress bar's blocks moving through it
+fn reboot_screen(painter: &egui::Painter, rect: Rect, progress: f32) {
+    painter.rect_filled(rect, 0., Color32::BLACK);
+    painter.text(
+        pos2(rect.center().x, rect.center().y - 20.),
+        Align2::CENTER_CENTER,
+        "Windows XP Crash",
+        FontId::proportional(20.),
+        Color32::WHITE,
+    );
+
+    let bar = Rect::from_min_size(
+        pos2(rect.center().x - 60., rect.center().y + 10.),
+        vec2(120., 12.),
+    );
+    painter.rect_stroke(bar, 2., egui::Stroke::new(1., Color32::GRAY));
+    let block_x = bar.left() + (progress * 3. % 1.) * (bar.width() + 30.) - 30.;
+    for i in 0..3 {
+        let x = block_x + i as f32 * 9.;
+        if x >= bar.left() && x + 7. <= bar.right() {
+            let block = Rect::from_min_size(pos2(x, bar.top() + 2.), vec2(7., bar.height() - 4.));
+            painter.rect_filled(block, 1., Color32::from_rgb(40, 90, 220));
+        }
+    }
+}
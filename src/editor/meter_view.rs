@@ -0,0 +1,107 @@
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use nih_plug::prelude::util;
+use nih_plug_egui::egui::{self, pos2, vec2, Rect};
+
+use super::skin;
+use crate::meters::{ChannelLevels, Meters, MAX_METER_CHANNELS};
+
+/// The lowest level the meters show
+const MIN_DB: f32 = -60.;
+/// How fast the peak meters fall, in decibels per second
+const PEAK_FALL_DB_PER_SECOND: f32 = 20.;
+/// The RMS meters' integration time
+const RMS_TIME_SECONDS: f32 = 0.3;
+/// The height of a single channel's bar
+const BAR_HEIGHT: f32 = 6.;
+
+/// The meters' displayed levels in decibels, with the ballistics applied on the GUI thread
+pub struct MeterView {
+    input: [(f32, f32); MAX_METER_CHANNELS],
+    output: [(f32, f32); MAX_METER_CHANNELS],
+    last_frame: Option<Instant>,
+}
+
+impl Default for MeterView {
+    fn default() -> Self {
+        Self {
+            input: [(MIN_DB, MIN_DB); MAX_METER_CHANNELS],
+            output: [(MIN_DB, MIN_DB); MAX_METER_CHANNELS],
+            last_frame: None,
+        }
+    }
+}
+
+impl MeterView {
+    /// Draw peak and RMS meters for every input and output channel, with a clip indicator that
+    /// stays lit until it's clicked
+    pub fn show(&mut self, ui: &mut egui::Ui, meters: &Meters) {
+        let now = Instant::now();
+        let seconds = self
+            .last_frame
+            .map_or(0., |last_frame| (now - last_frame).as_secs_f32());
+        self.last_frame = Some(now);
+        apply_ballistics(&mut self.input, meters.input(), seconds);
+        apply_ballistics(&mut self.output, meters.output(), seconds);
+
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                ui.label("In");
+                bars(ui, &self.input[..meters.input().len()]);
+                ui.label("Out");
+                bars(ui, &self.output[..meters.output().len()]);
+            });
+
+            let clipped = meters.clipped.load(Ordering::Relaxed);
+            let color = if clipped { skin::CLIP } else { skin::METER_OFF };
+            let clip_button = egui::Button::new("CLIP").fill(color);
+            if ui
+                .add(clip_button)
+                .on_hover_text("Click to reset")
+                .clicked()
+            {
+                meters.clipped.store(false, Ordering::Relaxed);
+            }
+        });
+
+        // The meters keep falling without any input
+        ui.ctx().request_repaint();
+    }
+}
+
+/// Peaks jump up instantly and fall at a constant rate, the RMS level follows with a one-pole
+/// filter
+fn apply_ballistics(display: &mut [(f32, f32)], levels: &[ChannelLevels], seconds: f32) {
+    let rms_coefficient = (-seconds / RMS_TIME_SECONDS).exp();
+    for ((peak_db, rms_db), level) in display.iter_mut().zip(levels) {
+        let new_peak_db = util::gain_to_db(level.peak()).max(MIN_DB);
+        *peak_db = new_peak_db.max(*peak_db - PEAK_FALL_DB_PER_SECOND * seconds);
+
+        let new_rms_db = util::gain_to_db(level.rms()).max(MIN_DB);
+        *rms_db = new_rms_db + (*rms_db - new_rms_db) * rms_coefficient;
+    }
+}
+
+fn bars(ui: &mut egui::Ui, levels: &[(f32, f32)]) {
+    let width = ui.available_width().min(240.);
+    for &(peak_db, rms_db) in levels {
+        let (rect, _) = ui.allocate_exact_size(vec2(width, BAR_HEIGHT), egui::Sense::hover());
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0., skin::WAVEFORM_BACKGROUND);
+
+        let to_x = |db: f32| rect.left() + (1. - db / MIN_DB).clamp(0., 1.) * rect.width();
+        let rms_rect = Rect::from_min_max(rect.min, pos2(to_x(rms_db), rect.bottom()));
+        painter.rect_filled(rms_rect, 0., skin::WAVEFORM);
+        let peak_x = to_x(peak_db);
+        let peak_color = if peak_db >= 0. {
+            skin::CLIP
+        } else {
+            skin::SPECTRUM_FROZEN
+        };
+        painter.line_segment(
+            [pos2(peak_x, rect.top()), pos2(peak_x, rect.bottom())],
+            egui::Stroke::new(2., peak_color),
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{self, RichText};
+
+use super::skin;
+use crate::presets::{self, UserPreset, FACTORY_PRESETS};
+use crate::state::BufferState;
+use crate::wav;
+use crate::{Task, WinXpCrash, WinXpCrashParams};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    Factory(usize),
+    User(usize),
+}
+
+/// Lists the factory presets and the user presets, and saves and deletes user presets. Presets
+/// can also be exported to share them, and preset files dropped onto the editor are loaded.
+/// The preset files are read and written on the GUI thread.
+#[derive(Default)]
+pub struct PresetBrowser {
+    /// The user presets and their files, read when the editor opens and after every change.
+    /// The directory may not exist or be unreadable, in which case this is empty.
+    user_presets: Option<Vec<(PathBuf, UserPreset)>>,
+    selected: Option<Selection>,
+    /// The name for Save As
+    new_name: String,
+    /// Whether saved presets contain the frozen loops
+    include_buffer: bool,
+    /// Why the last preset operation failed
+    error: Option<String>,
+    /// The file the last export was written to
+    exported: Option<PathBuf>,
+}
+
+impl PresetBrowser {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        params: &WinXpCrashParams,
+        setter: &ParamSetter,
+        async_executor: &AsyncExecutor<WinXpCrash>,
+    ) {
+        let dropped = ui.ctx().input(|input| {
+            input
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .find(|path| presets::is_preset_file(path))
+        });
+        if let Some(path) = dropped {
+            self.import(&path, params, setter, async_executor);
+        }
+
+        let directory = presets::user_directory();
+        if self.user_presets.is_none() {
+            self.refresh(directory.as_deref());
+        }
+        let user_presets = self.user_presets.as_deref().unwrap_or_default();
+
+        let selected_name = match self.selected {
+            Some(Selection::Factory(index)) => FACTORY_PRESETS[index].name,
+            Some(Selection::User(index)) => &user_presets[index].1.name,
+            None => "Presets",
+        };
+        let mut clicked = None;
+        egui::ComboBox::from_id_source("presets")
+            .selected_text(selected_name)
+            .width(ui.available_width())
+            .show_ui(ui, |ui| {
+                let mut item = |ui: &mut egui::Ui, selection, name: &str| {
+                    if ui
+                        .selectable_label(self.selected == Some(selection), name)
+                        .clicked()
+                    {
+                        clicked = Some(selection);
+                    }
+                };
+                for (index, preset) in FACTORY_PRESETS.iter().enumerate() {
+                    item(ui, Selection::Factory(index), preset.name);
+                }
+                if !user_presets.is_empty() {
+                    ui.separator();
+                }
+                for (index, (_, preset)) in user_presets.iter().enumerate() {
+                    item(ui, Selection::User(index), &preset.name);
+                }
+            });
+        if let Some(selection) = clicked {
+            self.load(selection, params, setter, async_executor);
+        }
+
+        match directory {
+            Some(directory) => self.user_preset_controls(ui, &directory, params),
+            None => {
+                ui.label(RichText::new("User presets aren't available").color(skin::ERROR));
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Export").clicked() {
+                self.export(params);
+            }
+            ui.checkbox(&mut self.include_buffer, "Include Buffer");
+        });
+
+        if let Some(error) = &self.error {
+            ui.label(RichText::new(error).color(skin::ERROR));
+        } else if let Some(path) = &self.exported {
+            ui.label(format!("Exported to {}", path.display()));
+        }
+    }
+
+    /// Save As, Save and Delete for the presets in the user preset directory
+    fn user_preset_controls(
+        &mut self,
+        ui: &mut egui::Ui,
+        directory: &Path,
+        params: &WinXpCrashParams,
+    ) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_name);
+            let save_as = egui::Button::new("Save As");
+            if ui
+                .add_enabled(!self.new_name.trim().is_empty(), save_as)
+                .clicked()
+            {
+                let name = self.new_name.trim().to_owned();
+                let path = presets::preset_path(directory, &name);
+                self.save(directory, path, name, params);
+            }
+        });
+        ui.horizontal(|ui| {
+            let user_preset = match self.selected {
+                Some(Selection::User(index)) => self
+                    .user_presets
+                    .as_ref()
+                    .and_then(|user_presets| user_presets.get(index))
+                    .cloned(),
+                _ => None,
+            };
+            let enabled = user_preset.is_some();
+            let save = ui.add_enabled(enabled, egui::Button::new("Save")).clicked();
+            let delete = ui
+                .add_enabled(enabled, egui::Button::new("Delete"))
+                .clicked();
+
+            if let Some((path, preset)) = user_preset {
+                if save {
+                    self.save(directory, path, preset.name, params);
+                } else if delete {
+                    self.delete(directory, &path);
+                }
+            }
+        });
+    }
+
+    /// Read the user presets again after they changed on disk
+    fn refresh(&mut self, directory: Option<&Path>) {
+        let user_presets = match directory.map(presets::load_user_presets) {
+            Some(Ok(user_presets)) => user_presets,
+            Some(Err(err)) => {
+                self.error = Some(format!("Could not read the user presets: {err}"));
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        self.user_presets = Some(user_presets);
+    }
+
+    /// Apply a preset's values through parameter gestures, and freeze the loops it contains
+    fn load(
+        &mut self,
+        selection: Selection,
+        params: &WinXpCrashParams,
+        setter: &ParamSetter,
+        async_executor: &AsyncExecutor<WinXpCrash>,
+    ) {
+        self.selected = Some(selection);
+        self.error = None;
+        self.exported = None;
+        let user_preset = match selection {
+            Selection::Factory(index) => {
+                FACTORY_PRESETS[index].apply(setter.raw_context, params);
+                return;
+            }
+            Selection::User(index) => self
+                .user_presets
+                .as_ref()
+                .and_then(|user_presets| user_presets.get(index)),
+        };
+        let Some((_, preset)) = user_preset else {
+            return;
+        };
+
+        self.new_name = preset.name.clone();
+        self.error = apply_user_preset(preset, params, setter, async_executor).err();
+    }
+
+    /// Load a preset file that was dropped onto the editor, for instance one that was exported
+    /// by another instance of the plugin
+    fn import(
+        &mut self,
+        path: &Path,
+        params: &WinXpCrashParams,
+        setter: &ParamSetter,
+        async_executor: &AsyncExecutor<WinXpCrash>,
+    ) {
+        self.selected = None;
+        self.exported = None;
+        let sample_rate = f32::from_bits(params.sample_rate.load(Ordering::Relaxed));
+        self.error = match UserPreset::import(path, sample_rate) {
+            Ok(preset) => {
+                self.new_name = preset.name.clone();
+                apply_user_preset(&preset, params, setter, async_executor).err()
+            }
+            Err(err) => Some(format!("Could not import the preset: {err}")),
+        };
+    }
+
+    /// The frozen loops to store in a preset, if they should be stored
+    fn buffer_to_store(&self, params: &WinXpCrashParams) -> Option<BufferState> {
+        if !self.include_buffer {
+            return None;
+        }
+
+        // The state only contains loops while they're frozen
+        params
+            .buffer_state
+            .read()
+            .ok()
+            .filter(|buffer| buffer.frozen)
+            .map(|buffer| buffer.clone())
+    }
+
+    /// Write the current parameter values to `path` and select the saved preset
+    fn save(&mut self, directory: &Path, path: PathBuf, name: String, params: &WinXpCrashParams) {
+        self.error = None;
+        self.exported = None;
+        let preset = UserPreset::capture(name, params, self.buffer_to_store(params));
+        if let Err(err) = preset.save(&path) {
+            self.error = Some(format!("Could not save the preset: {err}"));
+            return;
+        }
+
+        self.refresh(Some(directory));
+        self.selected = self
+            .user_presets
+            .as_ref()
+            .and_then(|user_presets| user_presets.iter().position(|(p, _)| *p == path))
+            .map(Selection::User);
+    }
+
+    /// Write the current parameter values to a preset file in the export directory, named after
+    /// the name that's been entered
+    fn export(&mut self, params: &WinXpCrashParams) {
+        let name = match self.new_name.trim() {
+            "" => "Windows XP Crash",
+            name => name,
+        };
+        let directory = params
+            .export_dir
+            .read()
+            .ok()
+            .and_then(|directory| directory.clone())
+            .unwrap_or_else(wav::default_directory);
+        let path = presets::preset_path(&directory, name);
+
+        let preset = UserPreset::capture(name.to_owned(), params, self.buffer_to_store(params));
+        match preset.export(&path) {
+            Ok(()) => {
+                self.error = None;
+                self.exported = Some(path);
+            }
+            Err(err) => {
+                self.error = Some(format!("Could not export the preset: {err}"));
+                self.exported = None;
+            }
+        }
+    }
+
+    fn delete(&mut self, directory: &Path, path: &Path) {
+        self.error = None;
+        self.exported = None;
+        if let Err(err) = std::fs::remove_file(path) {
+            self.error = Some(format!("Could not delete the preset: {err}"));
+        }
+
+        self.refresh(Some(directory));
+        self.selected = None;
+    }
+}
+
+/// Apply a user preset's values through parameter gestures, and freeze the loops it contains.
+/// Returns an error message if the loops couldn't be loaded.
+fn apply_user_preset(
+    preset: &UserPreset,
+    params: &WinXpCrashParams,
+    setter: &ParamSetter,
+    async_executor: &AsyncExecutor<WinXpCrash>,
+) -> Result<(), String> {
+    preset.apply(setter.raw_context, params);
+    let Some(buffer) = preset.buffer.clone().filter(|buffer| buffer.frozen) else {
+        return Ok(());
+    };
+
+    let sample_rate = f32::from_bits(params.sample_rate.load(Ordering::Relaxed));
+    if sample_rate <= 0. {
+        return Err("The plugin isn't active, the buffer was not loaded".to_owned());
+    }
+    if let Ok(mut preset_buffer) = params.preset_buffer.lock() {
+        *preset_buffer = Some(buffer);
+    }
+    async_executor.execute_background(Task::LoadPresetBuffer { sample_rate });
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nih_plug::prelude::*;
+use nih_plug_egui::egui;
+
+use crate::presets;
+use crate::rng::Rng;
+use crate::WinXpCrashParams;
+
+/// Randomizes the parameters that aren't locked, and undoes the last randomization
+pub struct RandomizerPanel {
+    rng: Rng,
+    /// The values the last randomization replaced
+    undo: Option<BTreeMap<String, f32>>,
+}
+
+impl Default for RandomizerPanel {
+    fn default() -> Self {
+        // Every editor session gets different results
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+
+        Self {
+            rng: Rng::new(seed),
+            undo: None,
+        }
+    }
+}
+
+impl RandomizerPanel {
+    pub fn show(&mut self, ui: &mut egui::Ui, params: &WinXpCrashParams, setter: &ParamSetter) {
+        ui.horizontal(|ui| {
+            if ui.button("Randomize").clicked() {
+                // The locks aren't held while changing parameters, see `ab_compare()`
+                let locked = params
+                    .randomizer_locks
+                    .read()
+                    .map(|locked| locked.clone())
+                    .unwrap_or_default();
+                self.undo = Some(presets::randomize(
+                    setter.raw_context,
+                    params,
+                    &locked,
+                    &mut self.rng,
+                ));
+            }
+
+            let undo = egui::Button::new("Undo Randomize");
+            if ui.add_enabled(self.undo.is_some(), undo).clicked() {
+                if let Some(values) = self.undo.take() {
+                    presets::restore_values(setter.raw_context, params, &values);
+                }
+            }
+        });
+
+        ui.collapsing("Randomizer Locks", |ui| {
+            let Ok(mut locked) = params.randomizer_locks.write() else {
+                return;
+            };
+
+            for (id, name) in presets::randomizable_params(params) {
+                let mut is_locked = locked.contains(&id);
+                if ui.checkbox(&mut is_locked, name).changed() {
+                    if is_locked {
+                        locked.insert(id);
+                    } else {
+                        locked.remove(&id);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,218 @@
+use nih_plug_egui::egui::{
+    self, pos2, vec2, Align2, Color32, ColorImage, FontId, Rect, Stroke, TextureHandle,
+    TextureOptions, Visuals,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::track_info::TrackInfo;
+
+/// The height of the fake title bar
+pub const TITLE_BAR_HEIGHT: f32 = 30.;
+/// The height of the plain themes' track header
+pub const TRACK_HEADER_HEIGHT: f32 = 22.;
+
+const LUNA_BLUE: Color32 = Color32::from_rgb(0, 84, 227);
+const LUNA_BLUE_LIGHT: Color32 = Color32::from_rgb(61, 149, 255);
+/// The window background of the classic XP dialogs
+const XP_BEIGE: Color32 = Color32::from_rgb(236, 233, 216);
+const XP_BUTTON_BORDER: Color32 = Color32::from_rgb(0, 60, 116);
+const XP_SELECTION: Color32 = Color32::from_rgb(49, 106, 197);
+/// The waveform display looks like the visualizations of the era's media players
+pub const WAVEFORM_BACKGROUND: Color32 = Color32::BLACK;
+pub const WAVEFORM: Color32 = Color32::from_rgb(0, 224, 64);
+/// The clip indicator while lit and while off
+pub const CLIP: Color32 = Color32::from_rgb(230, 0, 0);
+pub const METER_OFF: Color32 = Color32::from_rgb(120, 120, 120);
+/// Error messages
+pub const ERROR: Color32 = Color32::from_rgb(200, 0, 0);
+/// The edges of the part of the loop that's played, and the playhead
+pub const WAVEFORM_MARKER: Color32 = Color32::from_rgb(0, 140, 255);
+pub const PLAYHEAD: Color32 = Color32::WHITE;
+/// The spectrum of frozen audio, to tell it apart from the live input
+pub const SPECTRUM_FROZEN: Color32 = Color32::from_rgb(255, 185, 0);
+/// The part of the loop that's played while freezing
+pub const WAVEFORM_ACTIVE: Color32 = Color32::from_rgb(0, 48, 96);
+/// The washed out title bar of a window that stopped responding
+const GHOST_TITLE: Color32 = Color32::from_rgb(150, 170, 205);
+/// Drawn over the whole window while frozen, like the ghost window Windows shows for hung
+/// applications
+const GHOST_OVERLAY: Color32 = Color32::from_rgba_premultiplied(96, 96, 96, 96);
+
+const WINDOW_ICON: (&[u8], [usize; 2]) =
+    (include_bytes!("../../assets/window_icon.rgba"), [16, 16]);
+const CLOSE_BUTTON: (&[u8], [usize; 2]) =
+    (include_bytes!("../../assets/close_button.rgba"), [21, 21]);
+
+/// The skin's images. These are uploaded when the editor opens.
+pub struct Textures {
+    window_icon: TextureHandle,
+    close_button: TextureHandle,
+}
+
+impl Textures {
+    pub fn load(egui_ctx: &egui::Context) -> Self {
+        let load = |name: &str, (rgba, size): (&[u8], [usize; 2])| {
+            let image = ColorImage::from_rgba_unmultiplied(size, rgba);
+            egui_ctx.load_texture(name, image, TextureOptions::NEAREST)
+        };
+
+        Self {
+            window_icon: load("window-icon", WINDOW_ICON),
+            close_button: load("close-button", CLOSE_BUTTON),
+        }
+    }
+}
+
+/// The editor's look. This is picked in the editor and saved with the plugin's state.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Theme {
+    /// Windows XP's Luna theme, with a title bar and a window that stop responding while frozen
+    #[default]
+    Xp,
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Xp, Theme::Dark, Theme::Light];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Xp => "Windows XP",
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+}
+
+/// The widget styling for a theme. Only the XP theme changes while frozen, the plain themes
+/// show the freeze through the Freeze button.
+pub fn visuals(theme: Theme, frozen: bool) -> Visuals {
+    match theme {
+        Theme::Xp => xp_visuals(frozen),
+        Theme::Dark => Visuals::dark(),
+        Theme::Light => Visuals::light(),
+    }
+}
+
+/// XP-era widget styling. A frozen plugin looks like a window that stopped responding.
+fn xp_visuals(frozen: bool) -> Visuals {
+    let mut visuals = Visuals::light();
+    visuals.panel_fill = XP_BEIGE;
+    visuals.window_fill = XP_BEIGE;
+    visuals.extreme_bg_color = Color32::WHITE;
+    visuals.selection.bg_fill = if frozen { Color32::GRAY } else { XP_SELECTION };
+
+    let border_color = if frozen {
+        Color32::GRAY
+    } else {
+        XP_BUTTON_BORDER
+    };
+    let border = Stroke::new(1., border_color);
+    for widget in [
+        &mut visuals.widgets.inactive,
+        &mut visuals.widgets.hovered,
+        &mut visuals.widgets.active,
+    ] {
+        widget.bg_stroke = border;
+        widget.rounding = 3.0.into();
+    }
+    visuals.widgets.inactive.weak_bg_fill = Color32::from_rgb(245, 244, 234);
+    visuals.widgets.hovered.weak_bg_fill = Color32::from_rgb(253, 216, 137);
+    visuals.widgets.active.weak_bg_fill = Color32::from_rgb(226, 223, 207);
+
+    visuals
+}
+
+/// Paint the Luna title bar into `rect`. While frozen the title says the plugin isn't
+/// responding and the bar is ghosted. The bar names the track and takes on its color when the
+/// host reports them.
+pub fn title_bar(
+    ui: &egui::Ui,
+    rect: Rect,
+    textures: &Textures,
+    frozen: bool,
+    track: Option<&TrackInfo>,
+) {
+    let painter = ui.painter();
+    let (fill, highlight) = match track.and_then(track_color) {
+        _ if frozen => (GHOST_TITLE, GHOST_TITLE),
+        Some(color) => (color, lighten(color)),
+        None => (LUNA_BLUE, LUNA_BLUE_LIGHT),
+    };
+    painter.rect_filled(rect, 0., fill);
+    // A lighter band along the top edge stands in for Luna's gradient
+    painter.rect_filled(
+        Rect::from_min_size(rect.min, vec2(rect.width(), rect.height() / 3.)),
+        0.,
+        highlight,
+    );
+
+    let icon_rect =
+        Rect::from_min_size(pos2(rect.left() + 7., rect.center_y() - 8.), vec2(16., 16.));
+    let uv = Rect::from_min_max(pos2(0., 0.), pos2(1., 1.));
+    painter.image(textures.window_icon.id(), icon_rect, uv, Color32::WHITE);
+
+    // Like a document window, the track comes before the application
+    let mut title = match track.and_then(|track| track.name.as_deref()) {
+        Some(name) => format!("{name} - Windows XP Crash"),
+        None => String::from("Windows XP Crash"),
+    };
+    if frozen {
+        title.push_str(" (Not Responding)");
+    }
+    painter.text(
+        pos2(icon_rect.right() + 6., rect.center_y()),
+        Align2::LEFT_CENTER,
+        title,
+        FontId::proportional(14.),
+        text_color(fill),
+    );
+
+    let close_rect = Rect::from_min_size(
+        pos2(rect.right() - 26., rect.center_y() - 10.5),
+        vec2(21., 21.),
+    );
+    painter.image(textures.close_button.id(), close_rect, uv, Color32::WHITE);
+}
+
+/// The plain themes' header: the track's name on a strip in the track's color
+pub fn track_header(ui: &egui::Ui, rect: Rect, track: &TrackInfo) {
+    let painter = ui.painter();
+    let fill = track_color(track).unwrap_or(ui.visuals().faint_bg_color);
+    painter.rect_filled(rect, 0., fill);
+    if let Some(name) = &track.name {
+        painter.text(
+            pos2(rect.left() + 8., rect.center_y()),
+            Align2::LEFT_CENTER,
+            name,
+            FontId::proportional(14.),
+            text_color(fill),
+        );
+    }
+}
+
+fn track_color(track: &TrackInfo) -> Option<Color32> {
+    track.color.map(|[r, g, b]| Color32::from_rgb(r, g, b))
+}
+
+/// The title bar's highlight for a track color, halfway to white
+fn lighten(color: Color32) -> Color32 {
+    let lighten = |channel: u8| channel + (255 - channel) / 2;
+    Color32::from_rgb(lighten(color.r()), lighten(color.g()), lighten(color.b()))
+}
+
+/// White text on dark backgrounds and black text on light ones
+fn text_color(background: Color32) -> Color32 {
+    let [r, g, b, _] = background.to_array().map(f32::from);
+    if 0.299 * r + 0.587 * g + 0.114 * b > 160. {
+        Color32::BLACK
+    } else {
+        Color32::WHITE
+    }
+}
+
+/// Gray out everything painted so far in `rect`
+pub fn ghost(ui: &egui::Ui, rect: Rect) {
+    ui.painter().rect_filled(rect, 0., GHOST_OVERLAY);
+}
@@ -0,0 +1,136 @@
+use std::f32::consts::TAU;
+
+use nih_plug_egui::egui::{self, pos2, vec2, Color32, Stroke};
+
+use super::skin;
+use crate::spectrum::SpectrumFifo;
+
+/// The FFT size. At 44.1 kHz this resolves about 21 Hz per bin.
+const FFT_SIZE: usize = 2048;
+/// How much of the previous magnitude is kept every frame, for a calmer display
+const AVERAGING: f32 = 0.8;
+/// The frequency range and the magnitude range the plot covers
+const MIN_FREQUENCY: f32 = 20.;
+const MAX_FREQUENCY: f32 = 20_000.;
+const MIN_DB: f32 = -90.;
+const MAX_DB: f32 = 0.;
+/// The number of points along the frequency axis
+const PLOT_POINTS: usize = 160;
+/// The height of the plot
+const HEIGHT: f32 = 80.;
+
+/// The editor side of the spectrum analyzer. The FFT runs on the GUI thread.
+pub struct SpectrumView {
+    window: Vec<f32>,
+    samples: Vec<f32>,
+    spectrum: Vec<(f32, f32)>,
+    /// The averaged magnitude per bin in decibels
+    magnitudes: Vec<f32>,
+    /// The FIFO's write count at the last analysis, to skip frames without new audio
+    last_written: usize,
+}
+
+impl Default for SpectrumView {
+    fn default() -> Self {
+        // A Hann window to keep the leakage between bins down
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (TAU * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        Self {
+            window,
+            samples: vec![0.; FFT_SIZE],
+            spectrum: vec![(0., 0.); FFT_SIZE],
+            magnitudes: vec![MIN_DB; FFT_SIZE / 2],
+            last_written: 0,
+        }
+    }
+}
+
+impl SpectrumView {
+    /// Analyze the latest output and plot it. Frozen audio is drawn in a different color than
+    /// the live input.
+    pub fn show(&mut self, ui: &mut egui::Ui, fifo: &SpectrumFifo, sample_rate: f32, frozen: bool) {
+        let written = fifo.read_latest(&mut self.samples);
+        if written != self.last_written {
+            self.last_written = written;
+            self.analyze();
+        }
+
+        let (rect, _) =
+            ui.allocate_exact_size(vec2(ui.available_width(), HEIGHT), egui::Sense::hover());
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0., skin::WAVEFORM_BACKGROUND);
+        if sample_rate <= 0. {
+            return;
+        }
+
+        let bin_width = sample_rate / FFT_SIZE as f32;
+        let points = (0..PLOT_POINTS)
+            .map(|i| {
+                let t = i as f32 / (PLOT_POINTS - 1) as f32;
+                let frequency = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(t);
+                let bin = ((frequency / bin_width) as usize).min(self.magnitudes.len() - 1);
+                let level = (self.magnitudes[bin] - MIN_DB) / (MAX_DB - MIN_DB);
+                pos2(
+                    rect.left() + t * rect.width(),
+                    rect.bottom() - level.clamp(0., 1.) * rect.height(),
+                )
+            })
+            .collect();
+        let color: Color32 = if frozen {
+            skin::SPECTRUM_FROZEN
+        } else {
+            skin::WAVEFORM
+        };
+        painter.add(egui::Shape::line(points, Stroke::new(1., color)));
+    }
+
+    fn analyze(&mut self) {
+        for ((bin, sample), window) in self
+            .spectrum
+            .iter_mut()
+            .zip(&self.samples)
+            .zip(&self.window)
+        {
+            *bin = (sample * window, 0.);
+        }
+        fft(&mut self.spectrum);
+
+        // The window halves the amplitude, and a full scale sine ends up split over both halves
+        // of the spectrum
+        let scale = 4. / FFT_SIZE as f32;
+        for (magnitude, (re, im)) in self.magnitudes.iter_mut().zip(&self.spectrum) {
+            let db = 20. * ((re * re + im * im).sqrt() * scale).max(1e-9).log10();
+            *magnitude = *magnitude * AVERAGING + db * (1. - AVERAGING);
+        }
+    }
+}
+
+/// An in-place iterative radix-2 FFT. The length must be a power of two.
+fn fft(data: &mut [(f32, f32)]) {
+    let n = data.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            data.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a_re, a_im) = data[start + k];
+                let (b_re, b_im) = data[start + k + len / 2];
+                let (t_re, t_im) = (b_re * cos - b_im * sin, b_re * sin + b_im * cos);
+                data[start + k] = (a_re + t_re, a_im + t_im);
+                data[start + k + len / 2] = (a_re - t_re, a_im - t_im);
+            }
+        }
+        len *= 2;
+    }
+}
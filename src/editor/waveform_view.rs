@@ -0,0 +1,197 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{self, pos2, vec2, Rect, Stroke};
+
+use super::skin;
+use crate::WinXpCrashParams;
+
+/// The height of the waveform display
+const HEIGHT: f32 = 80.;
+/// The playhead stops moving if the audio thread hasn't published anything for this long, for
+/// instance because the host stopped processing
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(250);
+/// The shortest window that can be selected, as a fraction of the loop. This is the Loop
+/// Length parameter's minimum.
+const MIN_WINDOW: f32 = 0.01;
+
+/// Draws the recorded loop with the part that's being played and a playhead. The playhead's
+/// position is only published once per block, so it's interpolated in between to keep it
+/// moving smoothly with large block sizes.
+///
+/// Dragging over the waveform selects the loop window, dragging inside of the window moves it,
+/// and double clicking plays the whole loop again.
+#[derive(Default)]
+pub struct WaveformView {
+    /// The number of the last publish and when the editor first saw it
+    last_publish: Option<(u32, Instant)>,
+    drag: Option<Drag>,
+}
+
+enum Drag {
+    /// Selecting a new window starting at this fraction of the loop
+    Select { anchor: f32 },
+    /// Moving the window, grabbed this far from its start
+    Move { grab_offset: f32 },
+}
+
+impl WaveformView {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        params: &WinXpCrashParams,
+        setter: &ParamSetter,
+        frozen: bool,
+    ) {
+        let waveform = &params.waveform;
+        let sample_rate = f32::from_bits(params.sample_rate.load(Ordering::Relaxed));
+        let (rect, response) = ui.allocate_exact_size(
+            vec2(ui.available_width(), HEIGHT),
+            egui::Sense::click_and_drag(),
+        );
+
+        let loop_len = waveform.loop_len() as f32;
+        let oldest = waveform.oldest() as f32;
+        // The window's fractions start at the loop's oldest sample, the waveform is drawn from
+        // the start of the buffer
+        let to_fraction = |x: f32| {
+            let position = (x - rect.left()) / rect.width() * loop_len;
+            ((position - oldest).rem_euclid(loop_len) / loop_len).clamp(0., 1.)
+        };
+        if loop_len > 0. {
+            self.edit_window(&response, params, setter, to_fraction);
+        }
+
+        let painter = ui.painter();
+        painter.rect_filled(rect, 0., skin::WAVEFORM_BACKGROUND);
+        let (mut region, publishes) = waveform.play_region();
+        let (window_start, window_len) = (
+            params.capture.loop_start.value(),
+            params.capture.loop_length.value(),
+        );
+        if !frozen && window_len < 1. {
+            // The audio thread only uses the window while freezing
+            region.start = (oldest + window_start * loop_len) % loop_len;
+            region.len = window_len * loop_len;
+        }
+        let to_x = |position: f32| rect.left() + position / loop_len * rect.width();
+        if loop_len > 0. && region.len < loop_len {
+            // The region may wrap around the end of the loop
+            let end = region.start + region.len;
+            for (start, end) in [
+                (region.start, end.min(loop_len)),
+                (0., (end - loop_len).max(0.)),
+            ] {
+                let region_rect = Rect::from_min_max(
+                    pos2(to_x(start), rect.top()),
+                    pos2(to_x(end), rect.bottom()),
+                );
+                painter.rect_filled(region_rect, 0., skin::WAVEFORM_ACTIVE);
+            }
+            for marker in [region.start, end % loop_len] {
+                painter.line_segment(
+                    [
+                        pos2(to_x(marker), rect.top()),
+                        pos2(to_x(marker), rect.bottom()),
+                    ],
+                    Stroke::new(1., skin::WAVEFORM_MARKER),
+                );
+            }
+        }
+
+        let stroke = Stroke::new(1., skin::WAVEFORM);
+        let point_width = rect.width() / waveform.resolution() as f32;
+        let to_y = |sample: f32| rect.center_y() - sample.clamp(-1., 1.) * rect.height() / 2.;
+        for (i, (min, max)) in waveform.points().enumerate() {
+            let x = rect.left() + (i as f32 + 0.5) * point_width;
+            painter.line_segment([pos2(x, to_y(max)), pos2(x, to_y(min))], stroke);
+        }
+
+        let now = Instant::now();
+        let published_at = match self.last_publish {
+            Some((last_publishes, time)) if last_publishes == publishes => time,
+            _ => {
+                self.last_publish = Some((publishes, now));
+                now
+            }
+        };
+        if !frozen || loop_len <= 0. || region.len <= 0. {
+            return;
+        }
+
+        let elapsed = (now - published_at).min(MAX_EXTRAPOLATION).as_secs_f32();
+        let offset = (region.position - region.start).rem_euclid(loop_len)
+            + elapsed * sample_rate * region.rate;
+        let playhead = (region.start + offset.rem_euclid(region.len)) % loop_len;
+        painter.line_segment(
+            [
+                pos2(to_x(playhead), rect.top()),
+                pos2(to_x(playhead), rect.bottom()),
+            ],
+            Stroke::new(2., skin::PLAYHEAD),
+        );
+        ui.ctx().request_repaint();
+    }
+
+    /// Change the Loop Start and Loop Length parameters by dragging over the waveform.
+    /// `to_fraction` converts a pointer position to a fraction of the loop.
+    fn edit_window(
+        &mut self,
+        response: &egui::Response,
+        params: &WinXpCrashParams,
+        setter: &ParamSetter,
+        to_fraction: impl Fn(f32) -> f32,
+    ) {
+        let (loop_start, loop_length) = (&params.capture.loop_start, &params.capture.loop_length);
+        if response.double_clicked() {
+            for (param, value) in [(loop_start, 0.), (loop_length, 1.)] {
+                setter.begin_set_parameter(param);
+                setter.set_parameter(param, value);
+                setter.end_set_parameter(param);
+            }
+            return;
+        }
+
+        let pointer = response
+            .interact_pointer_pos()
+            .map(|pos| to_fraction(pos.x));
+        if response.drag_started() {
+            let Some(fraction) = pointer else {
+                return;
+            };
+            let (start, len) = (loop_start.value(), loop_length.value());
+            self.drag = Some(if len < 1. && (start..start + len).contains(&fraction) {
+                Drag::Move {
+                    grab_offset: fraction - start,
+                }
+            } else {
+                Drag::Select { anchor: fraction }
+            });
+            setter.begin_set_parameter(loop_start);
+            setter.begin_set_parameter(loop_length);
+        }
+
+        match (&self.drag, pointer) {
+            (Some(Drag::Select { anchor }), Some(fraction)) if response.dragged() => {
+                let start = anchor.min(fraction);
+                // The window snaps to the ends of the loop if it would be too short otherwise
+                let len = (anchor.max(fraction) - start).max(MIN_WINDOW);
+                let start = start.min(1. - len);
+                setter.set_parameter(loop_start, start);
+                setter.set_parameter(loop_length, len);
+            }
+            (Some(Drag::Move { grab_offset }), Some(fraction)) if response.dragged() => {
+                let len = loop_length.value();
+                let start = (fraction - grab_offset).clamp(0., 1. - len);
+                setter.set_parameter(loop_start, start);
+            }
+            _ => (),
+        }
+
+        if response.drag_released() && self.drag.take().is_some() {
+            setter.end_set_parameter(loop_start);
+            setter.end_set_parameter(loop_length);
+        }
+    }
+}
@@ -0,0 +1,626 @@
+use crate::buffer::{Channel, ChannelBuffers, PlayRegion};
+use crate::oversampling::Oversampling;
+use crate::freeze::SampleControls;
+use crate::{ChunkSize, Mode};
+
+/// How the frozen audio is played back. Every mode is its own engine, and all of them play from
+/// the same recorded buffers, so switching modes keeps the captured audio. The buffers record
+/// the input on their own, engines only run for the samples where something is played back.
+pub trait Engine {
+    /// Allocate the engine's state for `num_channels` channels. This is called from
+    /// `initialize()`, so it may allocate.
+    fn prepare(&mut self, num_channels: usize);
+
+    /// Play a run of one channel's samples into `output`. `dry` holds the channel's input, which
+    /// the buffers record where nothing is frozen. `fade_buffer` holds the loop from before the
+    /// last snapshot recall.
+    fn process_block(
+        &mut self,
+        channel: usize,
+        channel_buffer: &mut Channel<'_>,
+        fade_buffer: &mut Channel<'_>,
+        controls: &[SampleControls],
+        dry: &[f32],
+        output: &mut [f32],
+    );
+
+    /// Called when the freeze engages or is released
+    fn set_freeze(&mut self, engaged: bool);
+
+    /// Forget everything about the current freeze
+    fn reset(&mut self);
+
+    /// How long a fade meant to take `ms` milliseconds takes in this mode. The attack, the
+    /// decay, the release, the stop and the crossfade after a snapshot recall all go through
+    /// this.
+    fn fade_time(&self, ms: f32) -> f32 {
+        ms
+    }
+
+    /// Whether whole loops at their original rate can be copied straight out of the buffer
+    /// instead of going through `process_block()`
+    fn plays_buffer_directly(&self) -> bool {
+        true
+    }
+
+    /// The length of one pass through the loop in samples and how far it moves per sample,
+    /// for a loop of `loop_len` samples played at `rate` and stretched by `stretch`
+    fn pass(&self, loop_len: f32, rate: f32, stretch: f32) -> (f32, f32) {
+        (loop_len, rate.abs() / stretch)
+    }
+
+    /// The buffer size this engine needs to play loops of `size` samples
+    fn buffer_size(&self, size: usize) -> usize {
+        size
+    }
+
+    /// Wear the frozen loops down a bit for a block of `num_samples` samples, see
+    /// `ChannelBuffers::smear()`
+    fn smear(&self, channel_buffers: &mut ChannelBuffers, amount: f32, num_samples: usize) {
+        channel_buffers.smear(amount, num_samples);
+    }
+
+    /// The latency the engine adds on top of the dry signal, in samples
+    fn latency(&self, oversampling: Oversampling) -> usize {
+        oversampling.latency()
+    }
+}
+
+/// The musical mode, which plays the loop through the ring buffer's read head with all of its
+/// loop length, rate, window, stretch and interpolation controls
+#[derive(Debug, Default)]
+pub struct LoopEngine;
+
+/// The authentic mode, which repeats the last complete hardware chunk of a free-running sample
+/// counter with raw splices, like a soundcard whose driver crashed
+#[derive(Debug)]
+pub struct ChunkEngine {
+    chunk_size: ChunkSize,
+    /// The sample counter of the newest recorded sample when the chunks were first played
+    /// after the freeze engaged. The head stays there while the chunks play.
+    head_at: Option<u64>,
+    /// Every channel's chunk as the buffer position of its first sample and its length. This
+    /// is found when the channel first plays after the freeze engages, and found again when
+    /// the chunk size changes.
+    chunks: Vec<Option<(usize, usize)>>,
+}
+
+/// All engines, allocated up front so switching modes never allocates. Switching while the
+/// loop is audible crossfades from the previous engine to the new one.
+#[derive(Debug)]
+pub struct Engines {
+    looper: LoopEngine,
+    chunk: ChunkEngine,
+    mode: Mode,
+    /// The engine that's fading out after a switch
+    previous: Option<Mode>,
+    /// The previous engine's output during the crossfade
+    scratch: Vec<f32>,
+}
+
+impl Engine for LoopEngine {
+    fn prepare(&mut self, _num_channels: usize) {}
+
+    fn process_block(
+        &mut self,
+        channel: usize,
+        channel_buffer: &mut Channel<'_>,
+        fade_buffer: &mut Channel<'_>,
+        controls: &[SampleControls],
+        dry: &[f32],
+        output: &mut [f32],
+    ) {
+        let loop_len = channel_buffer.loop_len();
+        for ((output, &dry), controls) in output.iter_mut().zip(dry).zip(controls) {
+            let length = controls.channel_length(channel, loop_len);
+            // The loop keeps playing while it fades out after the release, and a refresh
+            // records while the old loop plays from the fade buffer
+            channel_buffer.freezing = controls.wet > 0. && !controls.refreshing;
+            channel_buffer.set_length(length);
+            channel_buffer.set_rate(controls.rate);
+            channel_buffer.set_stretch(controls.stretch);
+            channel_buffer.set_window(controls.window_start, controls.window_len);
+            channel_buffer.set_reversed(controls.reversed);
+            channel_buffer.set_hold(controls.hold);
+            let mut frozen = channel_buffer.next_item(dry);
+            if controls.crossfade < 1. {
+                fade_buffer.freezing = true;
+                fade_buffer.set_length(length);
+                fade_buffer.set_rate(controls.rate);
+                fade_buffer.set_stretch(controls.stretch);
+                fade_buffer.set_window(controls.window_start, controls.window_len);
+                fade_buffer.set_reversed(controls.reversed);
+                // The loop that's fading out doesn't take in the input anymore
+                fade_buffer.set_hold(1.);
+                let faded = fade_buffer.next_item(dry);
+                frozen = faded + (frozen - faded) * controls.crossfade;
+            }
+            *output = frozen;
+        }
+    }
+
+    fn set_freeze(&mut self, _engaged: bool) {}
+
+    fn reset(&mut self) {}
+}
+
+impl Default for ChunkEngine {
+    fn default() -> Self {
+        Self {
+            chunk_size: ChunkSize::Samples1024,
+            head_at: None,
+            chunks: Vec::new(),
+        }
+    }
+}
+
+impl ChunkEngine {
+    pub fn set_chunk_size(&mut self, chunk_size: ChunkSize) {
+        self.chunk_size = chunk_size;
+    }
+}
+
+impl Engine for ChunkEngine {
+    fn prepare(&mut self, num_channels: usize) {
+        self.chunks.resize(num_channels, None);
+    }
+
+    fn process_block(
+        &mut self,
+        channel: usize,
+        channel_buffer: &mut Channel<'_>,
+        _fade_buffer: &mut Channel<'_>,
+        controls: &[SampleControls],
+        dry: &[f32],
+        output: &mut [f32],
+    ) {
+        let Some(chunk) = self.chunks.get_mut(channel) else {
+            return;
+        };
+        let len = self.chunk_size.samples();
+        for ((output, &dry), controls) in output.iter_mut().zip(dry).zip(controls) {
+            // Nothing is smoothed, so there's nothing to play once the loop is inaudible, and
+            // a recalled snapshot is cut to right away. A refresh just passes the input while
+            // it records.
+            if controls.wet <= 0. || controls.refreshing {
+                channel_buffer.freezing = false;
+                *output = channel_buffer.next_item(dry);
+                continue;
+            }
+
+            channel_buffer.freezing = true;
+            let start = match *chunk {
+                Some((start, chunk_len)) if chunk_len == len => start,
+                _ => {
+                    // The chunks are counted from the first processed sample, not from the freeze
+                    let head_at = *self
+                        .head_at
+                        .get_or_insert(controls.position.saturating_sub(1));
+                    let start =
+                        channel_buffer.last_chunk_start(len, (head_at % len as u64) as usize);
+                    *chunk = Some((start, len));
+                    start
+                }
+            };
+            // The chunk repeats in step with the counter, not from the moment of the freeze
+            let position = start + (controls.position % len as u64) as usize;
+            *output = channel_buffer.sample_at(position);
+            channel_buffer.set_engine_region(Some(PlayRegion {
+                start: start as f32,
+                len: len as f32,
+                position: (position % channel_buffer.loop_len()) as f32,
+                rate: 1.,
+            }));
+        }
+    }
+
+    fn set_freeze(&mut self, _engaged: bool) {
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.head_at = None;
+        self.chunks.iter_mut().for_each(|chunk| *chunk = None);
+    }
+
+    /// Nothing is smoothed, so the loop cuts in and out right away
+    fn fade_time(&self, _ms: f32) -> f32 {
+        0.
+    }
+
+    fn plays_buffer_directly(&self) -> bool {
+        false
+    }
+
+    /// The chunks always play at their own rate, regardless of the loop controls
+    fn pass(&self, _loop_len: f32, _rate: f32, _stretch: f32) -> (f32, f32) {
+        (self.chunk_size.samples() as f32, 1.)
+    }
+
+    /// The last complete chunk starts up to two chunks before the write head
+    fn buffer_size(&self, size: usize) -> usize {
+        size.max(2 * self.chunk_size.samples())
+    }
+
+    /// The chunks are raw splices of the recording, which the smear would only blur
+    fn smear(&self, _channel_buffers: &mut ChannelBuffers, _amount: f32, _num_samples: usize) {}
+
+    /// The chunks are read directly, so the oversampling doesn't apply
+    fn latency(&self, _oversampling: Oversampling) -> usize {
+        0
+    }
+}
+
+impl Default for Engines {
+    fn default() -> Self {
+        Self {
+            looper: LoopEngine,
+            chunk: ChunkEngine::default(),
+            mode: Mode::Musical,
+            previous: None,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl Engines {
+    /// Allocate every engine's state and the crossfade's scratch space. This allocates.
+    pub fn prepare(&mut self, num_channels: usize, max_block_size: usize) {
+        self.looper.prepare(num_channels);
+        self.chunk.prepare(num_channels);
+        self.scratch.resize(max_block_size, 0.);
+    }
+
+    /// The engine for the current mode
+    pub fn current(&self) -> &dyn Engine {
+        match self.mode {
+            Mode::Musical => &self.looper,
+            Mode::Authentic => &self.chunk,
+        }
+    }
+
+    /// The chunk size the authentic mode plays, regardless of the current mode
+    pub fn set_chunk_size(&mut self, chunk_size: ChunkSize) {
+        self.chunk.set_chunk_size(chunk_size);
+    }
+
+    /// Switch to another mode. Returns whether the previous engine needs to be crossfaded out,
+    /// which only happens when `audible` is set. Otherwise the switch is immediate.
+    pub fn set_mode(&mut self, mode: Mode, audible: bool) -> bool {
+        if mode == self.mode {
+            return false;
+        }
+
+        self.previous = audible.then_some(self.mode);
+        self.mode = mode;
+        self.previous.is_some()
+    }
+
+    /// Stop playing the previous engine once the crossfade is done
+    pub fn finish_crossfade(&mut self) {
+        self.previous = None;
+    }
+
+    /// Whether runs that play the whole loop can be copied straight out of the buffer. Both
+    /// engines go through `process_block()` during a crossfade.
+    pub fn plays_buffer_directly(&self) -> bool {
+        self.previous.is_none() && self.current().plays_buffer_directly()
+    }
+
+    pub fn set_freeze(&mut self, engaged: bool) {
+        self.looper.set_freeze(engaged);
+        self.chunk.set_freeze(engaged);
+    }
+
+    pub fn reset(&mut self) {
+        self.looper.reset();
+        self.chunk.reset();
+        self.previous = None;
+    }
+
+    /// Play a run of one channel's samples with the current engine, see
+    /// `Engine::process_block()`. During a crossfade the previous engine plays the samples
+    /// where the loop is audible as well, and fades out by `SampleControls::engine_crossfade`.
+    pub fn process_block(
+        &mut self,
+        channel: usize,
+        channel_buffer: &mut Channel<'_>,
+        fade_buffer: &mut Channel<'_>,
+        controls: &[SampleControls],
+        dry: &[f32],
+        output: &mut [f32],
+    ) {
+        // Engines that don't play through the read head show their own region instead
+        channel_buffer.set_engine_region(None);
+        let Some(previous) = self.previous else {
+            engine(&mut self.looper, &mut self.chunk, self.mode).process_block(
+                channel,
+                channel_buffer,
+                fade_buffer,
+                controls,
+                dry,
+                output,
+            );
+            return;
+        };
+
+        // Where nothing is frozen the previous engine would record the input a second time, so
+        // the run is split where the loop becomes audible or inaudible. Both engines share the
+        // buffers, so every part is played by both before moving on to the next one.
+        let audible = |controls: &SampleControls| controls.wet > 0. && !controls.refreshing;
+        let mut start = 0;
+        while start < controls.len() {
+            let is_audible = audible(&controls[start]);
+            let len = controls[start..]
+                .iter()
+                .position(|controls| audible(controls) != is_audible)
+                .unwrap_or(controls.len() - start);
+            let part = start..start + len;
+            start = part.end;
+
+            let output = &mut output[part.clone()];
+            engine(&mut self.looper, &mut self.chunk, self.mode).process_block(
+                channel,
+                channel_buffer,
+                fade_buffer,
+                &controls[part.clone()],
+                &dry[part.clone()],
+                output,
+            );
+            if !is_audible {
+                continue;
+            }
+
+            let scratch = &mut self.scratch[part.clone()];
+            engine(&mut self.looper, &mut self.chunk, previous).process_block(
+                channel,
+                channel_buffer,
+                fade_buffer,
+                &controls[part.clone()],
+                &dry[part.clone()],
+                scratch,
+            );
+            for ((output, &faded), controls) in
+                output.iter_mut().zip(&*scratch).zip(&controls[part])
+            {
+                *output = faded + (*output - faded) * controls.engine_crossfade;
+            }
+        }
+    }
+}
+
+fn engine<'a>(
+    looper: &'a mut LoopEngine,
+    chunk: &'a mut ChunkEngine,
+    mode: Mode,
+) -> &'a mut dyn Engine {
+    match mode {
+        Mode::Musical => looper,
+        Mode::Authentic => chunk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::ChannelBuffers;
+
+    /// The controls for a sample that plays the whole loop as is, or records while `wet` is 0
+    fn controls(wet: f32, position: u64) -> SampleControls {
+        SampleControls {
+            rate: 1.,
+            stretch: 1.,
+            hold: 1.,
+            window_len: 1.,
+            gains: [1.; 3],
+            wet,
+            crossfade: 1.,
+            engine_crossfade: 1.,
+            position,
+            ..Default::default()
+        }
+    }
+
+    /// `Engine::process_block()` for both the engines and `Engines`
+    trait ProcessBlock {
+        fn process_block(
+            &mut self,
+            channel: usize,
+            channel_buffer: &mut Channel<'_>,
+            fade_buffer: &mut Channel<'_>,
+            controls: &[SampleControls],
+            dry: &[f32],
+            output: &mut [f32],
+        );
+    }
+
+    impl<E: Engine> ProcessBlock for E {
+        fn process_block(
+            &mut self,
+            channel: usize,
+            channel_buffer: &mut Channel<'_>,
+            fade_buffer: &mut Channel<'_>,
+            controls: &[SampleControls],
+            dry: &[f32],
+            output: &mut [f32],
+        ) {
+            Engine::process_block(
+                self,
+                channel,
+                channel_buffer,
+                fade_buffer,
+                controls,
+                dry,
+                output,
+            )
+        }
+    }
+
+    impl ProcessBlock for Engines {
+        fn process_block(
+            &mut self,
+            channel: usize,
+            channel_buffer: &mut Channel<'_>,
+            fade_buffer: &mut Channel<'_>,
+            controls: &[SampleControls],
+            dry: &[f32],
+            output: &mut [f32],
+        ) {
+            Engines::process_block(
+                self,
+                channel,
+                channel_buffer,
+                fade_buffer,
+                controls,
+                dry,
+                output,
+            )
+        }
+    }
+
+    /// Run the first channel's `positions` through `engine`, where every sample's input is its
+    /// position
+    fn process(
+        engine: &mut impl ProcessBlock,
+        buffers: &mut [ChannelBuffers; 2],
+        positions: std::ops::Range<u64>,
+        wet: f32,
+    ) -> Vec<f32> {
+        let controls: Vec<_> = positions.map(|position| controls(wet, position)).collect();
+        process_controls(engine, buffers, &controls)
+    }
+
+    /// Run the first channel through `engine` with the controls for every sample, where every
+    /// sample's input is its position
+    fn process_controls(
+        engine: &mut impl ProcessBlock,
+        buffers: &mut [ChannelBuffers; 2],
+        controls: &[SampleControls],
+    ) -> Vec<f32> {
+        let dry: Vec<f32> = controls.iter().map(|controls| controls.position as f32).collect();
+        let mut output = vec![0.; dry.len()];
+        let [channel_buffers, fade_buffers] = buffers;
+        let mut channel_buffer = channel_buffers.iter_mut().next().unwrap();
+        let mut fade_buffer = fade_buffers.iter_mut().next().unwrap();
+        engine.process_block(
+            0,
+            &mut channel_buffer,
+            &mut fade_buffer,
+            controls,
+            &dry,
+            &mut output,
+        );
+
+        output
+    }
+
+    /// Buffers holding a loop of 1024 samples
+    fn buffers() -> [ChannelBuffers; 2] {
+        [ChannelBuffers::new(1, 1025), ChannelBuffers::new(1, 1025)]
+    }
+
+    fn positions(output: &[f32]) -> Vec<u64> {
+        output.iter().map(|&sample| sample as u64).collect()
+    }
+
+    #[test]
+    fn loop_engine_records_and_plays_the_loop() {
+        let mut engine = LoopEngine;
+        let mut buffers = buffers();
+        let recorded = process(&mut engine, &mut buffers, 0..3000, 0.);
+        assert_eq!(positions(&recorded), (0..3000).collect::<Vec<_>>());
+
+        // The newest 1024 samples repeat, oldest first
+        let frozen = process(&mut engine, &mut buffers, 3000..5048, 1.);
+        let expected: Vec<u64> = (1976..3000).cycle().take(2048).collect();
+        assert_eq!(positions(&frozen), expected);
+    }
+
+    #[test]
+    fn chunk_engine_repeats_the_last_complete_chunk() {
+        let mut engine = ChunkEngine::default();
+        engine.prepare(1);
+        engine.set_chunk_size(ChunkSize::Samples256);
+        let mut buffers = buffers();
+        process(&mut engine, &mut buffers, 0..3000, 0.);
+
+        // The chunk from 2816 to 3072 isn't complete yet, so the one before it repeats in step
+        // with the sample counter
+        let frozen = process(&mut engine, &mut buffers, 3000..3600, 1.);
+        let expected: Vec<u64> = (3000..3600).map(|position| 2560 + position % 256).collect();
+        assert_eq!(positions(&frozen), expected);
+    }
+
+    #[test]
+    fn chunk_engine_finds_the_chunk_again_after_a_reset() {
+        let mut engine = ChunkEngine::default();
+        engine.prepare(1);
+        engine.set_chunk_size(ChunkSize::Samples256);
+        let mut buffers = buffers();
+        for (positions, wet) in [(0..3000, 0.), (3000..3100, 1.), (3100..4000, 0.)] {
+            process(&mut engine, &mut buffers, positions, wet);
+        }
+        engine.set_freeze(true);
+        let frozen = process(&mut engine, &mut buffers, 4000..4100, 1.);
+        let expected: Vec<u64> = (4000..4100).map(|position| 3584 + position % 256).collect();
+        assert_eq!(positions(&frozen), expected);
+    }
+
+    #[test]
+    fn switching_modes_crossfades_audible_loops() {
+        let mut engines = Engines::default();
+        engines.prepare(1, 4096);
+        assert!(!engines.set_mode(Mode::Musical, true));
+        assert!(!engines.set_mode(Mode::Authentic, false));
+        assert!(!engines.plays_buffer_directly());
+        assert!(!engines.set_mode(Mode::Musical, false));
+        assert!(engines.plays_buffer_directly());
+
+        engines.set_chunk_size(ChunkSize::Samples256);
+        let mut buffers = buffers();
+        process(&mut engines, &mut buffers, 0..3000, 0.);
+        let looped = process(&mut engines, &mut buffers, 3000..3100, 1.);
+        assert_eq!(positions(&looped), (1976..2076).collect::<Vec<_>>());
+
+        // Fully faded in, only the new engine is heard. Its chunks are counted from the first
+        // sample it plays.
+        assert!(engines.set_mode(Mode::Authentic, true));
+        let chunks = process(&mut engines, &mut buffers, 3100..3200, 1.);
+        let expected: Vec<u64> = (3100..3200).map(|position| 2816 + position % 256).collect();
+        assert_eq!(positions(&chunks), expected);
+        assert!(!engines.plays_buffer_directly());
+    }
+
+    #[test]
+    fn crossfades_mix_both_engines_where_the_loop_is_audible() {
+        let mut engines = Engines::default();
+        engines.prepare(1, 4096);
+        engines.set_chunk_size(ChunkSize::Samples256);
+        let mut buffers = buffers();
+        process(&mut engines, &mut buffers, 0..3000, 0.);
+        process(&mut engines, &mut buffers, 3000..3100, 1.);
+
+        // Halfway through the crossfade the loop is released, from there on the input is
+        // recorded and passed through while the previous engine stays silent
+        assert!(engines.set_mode(Mode::Authentic, true));
+        let controls: Vec<_> = (3100..3200)
+            .map(|position| SampleControls {
+                engine_crossfade: 0.25,
+                ..controls(if position < 3150 { 1. } else { 0. }, position)
+            })
+            .collect();
+        let output = process_controls(&mut engines, &mut buffers, &controls);
+        let expected: Vec<f32> = (3100..3200u64)
+            .map(|position| {
+                if position >= 3150 {
+                    return position as f32;
+                }
+
+                let looped = (2076 + position - 3100) as f32;
+                let chunk = (2816 + position % 256) as f32;
+                looped + (chunk - looped) * 0.25
+            })
+            .collect();
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,184 @@
+/// The number of LPC coefficients that describe a spectral envelope
+const ORDER: usize = 12;
+/// The length of the frames the envelopes are analyzed from, in samples
+const FRAME_LEN: usize = 512;
+/// How many samples pass between two analyses. Each analysis covers two overlapping frames, so
+/// its cost is spread out over this many samples.
+const HOP: usize = 256;
+
+/// Keeps a repitched loop's formants where they were in the recorded audio. The repitched audio is
+/// whitened with its own spectral envelope and then shaped with the envelope of the recorded
+/// audio at the read position. The envelopes are 12 pole LPC fits that are computed every few
+/// hundred samples, which is good enough for glitching vocals. Everything is stored in fixed
+/// size arrays, so this never allocates.
+#[derive(Clone, Debug)]
+pub struct FormantCorrector {
+    enabled: bool,
+    /// The last repitched samples, with the oldest one at `history_position`
+    history: [f32; FRAME_LEN],
+    history_position: usize,
+    /// The number of samples until the envelopes are analyzed again
+    countdown: usize,
+    /// A Hann window for the analysis frames
+    window: [f32; FRAME_LEN],
+    /// Scratch space for the windowed analysis frame
+    frame: [f32; FRAME_LEN],
+    /// The repitched audio's envelope, which is removed
+    whiten: [f32; ORDER],
+    /// The recorded audio's envelope, which is applied instead
+    color: [f32; ORDER],
+    /// The last inputs of the whitening filter and the last outputs of the coloring filter, the
+    /// newest one first
+    whiten_state: [f32; ORDER],
+    color_state: [f32; ORDER],
+    /// Keeps the corrected audio at the repitched audio's level. This changes together with the
+    /// envelopes, as the two only match each other.
+    gain: f32,
+}
+
+impl Default for FormantCorrector {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            history: [0.; FRAME_LEN],
+            history_position: 0,
+            countdown: 0,
+            window: std::array::from_fn(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / (FRAME_LEN - 1) as f32;
+                0.5 - 0.5 * phase.cos()
+            }),
+            frame: [0.; FRAME_LEN],
+            whiten: [0.; ORDER],
+            color: [0.; ORDER],
+            whiten_state: [0.; ORDER],
+            color_state: [0.; ORDER],
+            gain: 1.,
+        }
+    }
+}
+
+impl FormantCorrector {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turn the correction on or off. It starts over from flat envelopes when it's turned on.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled != self.enabled {
+            *self = Self {
+                enabled,
+                ..Self::default()
+            };
+        }
+    }
+
+    /// Whether `analyze()` needs to be called before the next sample is processed
+    pub fn needs_analysis(&self) -> bool {
+        self.countdown == 0
+    }
+
+    /// Analyze the envelopes again. `source` returns the recorded audio around the read position
+    /// at its original pitch, by its offset from the read position.
+    pub fn analyze(&mut self, source: impl Fn(i64) -> f32) {
+        self.countdown = HOP;
+
+        let first_offset = -(FRAME_LEN as i64 / 2);
+        for ((sample, window), offset) in
+            self.frame.iter_mut().zip(&self.window).zip(first_offset..)
+        {
+            *sample = source(offset) * window;
+        }
+        let color = lpc(&self.frame);
+
+        let (newest, oldest) = self.history.split_at(self.history_position);
+        for ((sample, window), &history) in self
+            .frame
+            .iter_mut()
+            .zip(&self.window)
+            .zip(oldest.iter().chain(newest))
+        {
+            *sample = history * window;
+        }
+        let whiten = lpc(&self.frame);
+
+        // Both envelopes are needed, a frame that's silent leaves the audio as it is
+        if let (Some((color, color_error)), Some((whiten, whiten_error))) = (color, whiten) {
+            self.color = color;
+            self.whiten = whiten;
+            // The whitened audio has the repitched audio's prediction error, and the coloring
+            // filter boosts it by the inverse of the recorded audio's
+            self.gain = (color_error / whiten_error).sqrt().min(4.);
+        } else {
+            self.color = [0.; ORDER];
+            self.whiten = [0.; ORDER];
+            self.gain = 1.;
+        }
+    }
+
+    /// Correct the next repitched sample
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.history[self.history_position] = sample;
+        self.history_position = (self.history_position + 1) % FRAME_LEN;
+        self.countdown = self.countdown.saturating_sub(1);
+
+        let residual = self
+            .whiten
+            .iter()
+            .zip(&self.whiten_state)
+            .fold(sample, |residual, (a, past)| residual + a * past);
+        self.whiten_state.copy_within(..ORDER - 1, 1);
+        self.whiten_state[0] = sample;
+
+        let output = self
+            .color
+            .iter()
+            .zip(&self.color_state)
+            .fold(residual * self.gain, |output, (a, past)| output - a * past);
+        let output = if output.is_finite() { output } else { 0. };
+        self.color_state.copy_within(..ORDER - 1, 1);
+        self.color_state[0] = output;
+
+        output
+    }
+}
+
+/// Fit an all-pole envelope to a windowed frame with the autocorrelation method. Returns the
+/// coefficients of the prediction error filter after its leading 1, and the prediction error
+/// relative to the frame's power. Silent frames don't have an envelope.
+fn lpc(frame: &[f32]) -> Option<([f32; ORDER], f32)> {
+    let mut autocorrelation = [0f64; ORDER + 1];
+    for (lag, value) in autocorrelation.iter_mut().enumerate() {
+        *value = frame[lag..]
+            .iter()
+            .zip(frame)
+            .map(|(&a, &b)| a as f64 * b as f64)
+            .sum();
+    }
+    if autocorrelation[0] < 1e-9 {
+        return None;
+    }
+    // A tiny noise floor keeps the recursion well conditioned for very pure tones
+    autocorrelation[0] *= 1. + 1e-4;
+
+    // Levinson-Durbin recursion
+    let mut coefficients = [0f64; ORDER + 1];
+    coefficients[0] = 1.;
+    let mut error = autocorrelation[0];
+    for i in 1..=ORDER {
+        let correlation: f64 = (0..i)
+            .map(|j| coefficients[j] * autocorrelation[i - j])
+            .sum();
+        let reflection = -correlation / error;
+        let previous = coefficients;
+        for j in 1..i {
+            coefficients[j] = previous[j] + reflection * previous[i - j];
+        }
+        coefficients[i] = reflection;
+        error *= 1. - reflection * reflection;
+    }
+
+    Some((
+        std::array::from_fn(|i| coefficients[i + 1] as f32),
+        (error / autocorrelation[0]).max(1e-6) as f32,
+    ))
+}
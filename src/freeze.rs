@@ -0,0 +1,3602 @@
+//! The buffer freeze itself, without the plugin around it. `FreezeEngine` records its channels,
+//! freezes them and plays the frozen loops through every mode, effect and envelope the plugin
+//! has. The plugin in `lib.rs` only maps its parameters onto `Settings` and its host's events
+//! onto `Event`, so a command line tool or another plugin framework can run the exact same
+//! processing.
+//!
+//! Nothing in here talks to nih-plug. The host is whatever implements `Host`, or nothing at all
+//! with `FreezeEngine::process()`.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::auto_gain::{AutoGain, Normalizer};
+use crate::bank::{Snapshot, SnapshotBank, NUM_SNAPSHOTS};
+use crate::buffer::{sanitize, ChannelBuffers};
+use crate::crackle::Crackle;
+use crate::crusher::{Crusher, BYPASS_BIT_DEPTH};
+use crate::detector::{filter_coefficient, Detector, DetectorFilter};
+use crate::engine::Engines;
+use crate::ftz::ScopedFtz;
+use crate::hitch::Hitch;
+use crate::link::FreezeLink;
+use crate::meters::BlockLevels;
+use crate::oversampling::LatencyDelay;
+use crate::pitch::PitchDetector;
+use crate::rng::Rng;
+use crate::size_unit::SizeDisplay;
+use crate::state::BufferState;
+use crate::sysex::{BufferDump, BufferLoad};
+use crate::tap::TapTempo;
+use crate::tilt::Tilt;
+use crate::waveform::{Waveform, WAVEFORM_POINTS};
+
+pub use crate::buffer::{ChannelRef, RingBuffer};
+pub use crate::interpolation::Interpolation;
+pub use crate::link::LinkGroup;
+pub use crate::oversampling::Oversampling;
+pub use crate::size_unit::SizeUnit;
+pub use crate::sysex::SysEx;
+pub use crate::{
+    ChannelRotate, ChunkSize, DecayShape, Division, Mode, NoteBehavior, Quantize,
+    ResizeWhileFrozen, StopCurve,
+};
+
+/// The shortest and the longest buffer size in samples
+pub const MIN_BUFFER_SIZE: usize = 128;
+pub const MAX_BUFFER_SIZE: usize = 65536;
+/// The tempo used for synced lengths when the host doesn't report one
+pub(crate) const DEFAULT_TEMPO: f64 = 120.;
+/// Repitching the frozen loop with notes is limited to two octaves in either direction
+pub(crate) const MAX_TRANSPOSE_SEMITONES: f32 = 24.;
+/// The number of steps in the pitch sequence
+pub const SEQUENCE_STEPS: usize = 8;
+/// The exponent of the hardest velocity curve, the softest one uses its inverse
+const MAX_VELOCITY_EXPONENT: f32 = 4.;
+/// Freeze Amount engages the freeze from this value up
+const FREEZE_AMOUNT_THRESHOLD: f32 = 0.5;
+/// The crossfade time when recalling a snapshot
+const RECALL_CROSSFADE_MS: f32 = 20.;
+/// How long Channel Rotate takes to move the wet signals on to the next channel
+const ROTATE_CROSSFADE_MS: f32 = 5.;
+/// The crossfade time when switching modes while the loop is heard
+const ENGINE_CROSSFADE_MS: f32 = 20.;
+/// How long a stolen voice takes to fade out
+const STEAL_FADE_MS: f32 = 5.;
+/// Taps further apart than this start a new tap tempo sequence
+const TAP_TIMEOUT_SECONDS: f32 = 2.;
+/// The number of voices reported to CLAP hosts for polyphonic modulation, and the upper limit
+/// for the Voice Count parameter
+pub const MAX_VOICES: u32 = 8;
+/// How much shorter the right channel's loop gets at full spread
+const MAX_SPREAD_DETUNE: f32 = 0.02;
+/// The seed for the random voice pan, so sessions sound the same every time they're played back
+const PAN_SEED: u32 = 0x5eed_1e55;
+/// The seed for the first channel's crackle, the other channels count up from there
+const CRACKLE_SEED: u32 = 0xc2ac_c1e5;
+/// The seed for the first channel's dither
+const DITHER_SEED: u32 = 0xd1ce_d1ce;
+/// The seed for the hitches
+const HITCH_SEED: u32 = 0x5107_1e55;
+/// Decay gains below this are treated as silence, about -120 dB
+pub(crate) const DECAY_SILENCE: f32 = 1e-6;
+/// How long the gated decay shape takes to fade out after its hold
+const GATE_FADE_MS: f32 = 10.;
+/// How quickly the refresh detector follows rising and falling input levels, in milliseconds
+const REFRESH_ATTACK_MS: f32 = 1.;
+const REFRESH_RELEASE_MS: f32 = 100.;
+
+/// The part of the host the engine talks to while processing. The plugin reaches its host
+/// through `crate::host`, and `FreezeEngine::process()` runs without one.
+pub trait Host {
+    /// The transport at the start of the block
+    fn transport(&self) -> TransportInfo;
+    /// The block's next event, in the order of their timing
+    fn next_event(&mut self) -> Option<Event>;
+    fn send_event(&mut self, event: OutputEvent);
+    /// Run `task` away from the audio thread
+    fn execute_background(&self, task: Task);
+    fn set_latency_samples(&self, samples: u32);
+    /// The number of voices the engine plays at most, see `Settings::voice_count`
+    fn set_current_voice_capacity(&self, capacity: u32);
+}
+
+/// What the engine needs to know about the host's transport
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransportInfo {
+    pub playing: bool,
+    pub tempo: Option<f64>,
+    pub time_sig_numerator: Option<i32>,
+    pub time_sig_denominator: Option<i32>,
+    pub pos_beats: Option<f64>,
+    pub bar_start_pos_beats: Option<f64>,
+}
+
+/// An event for the engine, timed in samples from the start of the block. Hosts that don't
+/// identify voices leave `voice_id` empty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    NoteOn {
+        timing: u32,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        velocity: f32,
+    },
+    NoteOff {
+        timing: u32,
+        note: u8,
+    },
+    /// A held voice's offset to `Settings::buffer_size_normalized`
+    BufferSizeModulation {
+        timing: u32,
+        voice_id: i32,
+        normalized_offset: f32,
+    },
+    /// The voice's tuning in semitones
+    PolyTuning {
+        timing: u32,
+        voice_id: Option<i32>,
+        note: u8,
+        tuning: f32,
+    },
+    /// The voice's volume as a linear gain
+    PolyVolume {
+        timing: u32,
+        voice_id: Option<i32>,
+        note: u8,
+        gain: f32,
+    },
+    /// The voice's pan, from -1 for hard left to 1 for hard right
+    PolyPan {
+        timing: u32,
+        voice_id: Option<i32>,
+        note: u8,
+        pan: f32,
+    },
+    SysEx {
+        timing: u32,
+        message: SysEx,
+    },
+}
+
+impl Event {
+    pub fn timing(&self) -> u32 {
+        match *self {
+            Event::NoteOn { timing, .. }
+            | Event::NoteOff { timing, .. }
+            | Event::BufferSizeModulation { timing, .. }
+            | Event::PolyTuning { timing, .. }
+            | Event::PolyVolume { timing, .. }
+            | Event::PolyPan { timing, .. }
+            | Event::SysEx { timing, .. } => timing,
+        }
+    }
+}
+
+/// An event the engine sends to the host, timed like `Event`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputEvent {
+    /// The frozen loop's pitch for Pitch to MIDI
+    NoteOn { timing: u32, note: u8, velocity: f32 },
+    NoteOff { timing: u32, note: u8 },
+    /// A note's voice has ended, so the host can free its per-voice modulation
+    VoiceTerminated {
+        timing: u32,
+        voice_id: i32,
+        channel: u8,
+        note: u8,
+    },
+    /// A chunk of a buffer dump
+    SysEx { timing: u32, message: SysEx },
+}
+
+/// Work that is done on a background thread
+#[derive(Debug, Clone, Copy)]
+pub enum Task {
+    /// Write the loops in `Shared::export_buffer` to a WAV file
+    ExportWav,
+    /// Decode the WAV file at the import path into `Shared::import_buffer`
+    ImportWav { sample_rate: f32 },
+    /// Move the loops in `WinXpCrashParams::preset_buffer` into `Shared::import_buffer`,
+    /// resampling them to `sample_rate`
+    LoadPresetBuffer { sample_rate: f32 },
+}
+
+/// The channels `FreezeEngine::prepare_layout()` sets the engine up for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// Every channel gets its own buffer
+    pub channels: usize,
+    /// Whether the input only arrives on the first channel, which is then recorded into every
+    /// channel's buffer
+    pub mono_input: bool,
+    pub sidechain_channels: usize,
+}
+
+/// Everything the engine can be set to, with the plugin's parameter defaults. The plugin copies
+/// its parameters in here before every block. The fields are named after those parameters, see
+/// `WinXpCrashParams` for what they do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// The buffer size in samples, and the same normalized over its range. The voices' buffer
+    /// size modulation is added to the normalized value, see `Settings::set_buffer_size()`.
+    pub buffer_size: i32,
+    pub buffer_size_normalized: f32,
+    pub size_unit: SizeUnit,
+    pub glide_buffer_size: bool,
+    pub resize_while_frozen: ResizeWhileFrozen,
+    pub division: Division,
+    pub velocity_division: f32,
+    pub capture_reversed: bool,
+    pub loop_start: f32,
+    pub loop_length: f32,
+
+    pub freeze: bool,
+    pub freeze_amount: f32,
+    pub trigger_one_loop: bool,
+    pub trigger_repeats: i32,
+    pub mode: Mode,
+    pub chunk_size: ChunkSize,
+    /// The envelope's and the stop's times in milliseconds
+    pub attack: f32,
+    pub freeze_decay: f32,
+    pub freeze_sustain: f32,
+    pub release: f32,
+    pub hold_amount: f32,
+    pub stop_time: f32,
+    pub stop_curve: StopCurve,
+    /// The Decay parameter's level drop per repeat in decibels
+    pub decay: f32,
+    pub decay_shape: DecayShape,
+    pub gate_hold: i32,
+    pub midi_trigger: bool,
+    pub velocity_curve: f32,
+    pub voice_count: i32,
+    pub retrigger: bool,
+    pub tap_tempo: bool,
+    pub tap_note: i32,
+    pub snapshot_keys: bool,
+    pub store_note: i32,
+    pub recall_note: i32,
+    pub restore_last: bool,
+    pub size_keys: bool,
+    pub halve_note: i32,
+    pub double_note: i32,
+    pub quantize_trigger: Quantize,
+    pub quantize_release: bool,
+    pub freeze_on_stop: bool,
+    pub link_group: LinkGroup,
+    pub sidechain_trigger: bool,
+    /// The sidechain's and the refresh's thresholds as linear gains
+    pub sidechain_threshold: f32,
+    pub sidechain_attack: f32,
+    pub sidechain_release: f32,
+    pub sidechain_highpass: f32,
+    pub sidechain_lowpass: f32,
+    pub sidechain_listen: bool,
+    pub refresh: bool,
+    pub refresh_threshold: f32,
+
+    pub key_tracking: bool,
+    pub note_behavior: NoteBehavior,
+    pub root_note: i32,
+    pub formant: bool,
+    pub glide: f32,
+    pub interpolation: Interpolation,
+    pub oversampling: Oversampling,
+    pub half_time: bool,
+    pub stretch: f32,
+    pub sequence: bool,
+    pub sequence_length: i32,
+    /// Every pitch sequence step's interval in semitones
+    pub sequence_intervals: [i32; SEQUENCE_STEPS],
+    pub pitch_output: bool,
+    pub pitch_output_transpose: i32,
+
+    pub smear: f32,
+    pub crackle: f32,
+    pub soft_crackle: bool,
+    pub bit_depth: i32,
+    pub dither: bool,
+    pub noise_shaping: bool,
+    /// The wet signal's tilt in decibels
+    pub tilt: f32,
+
+    pub auto_gain: bool,
+    pub normalize: bool,
+    pub normalize_target: f32,
+    pub key_pan: f32,
+    pub random_pan: bool,
+    pub spread: f32,
+    pub cross_feed: f32,
+    pub channel_rotate: ChannelRotate,
+
+    pub hitch_rate: f32,
+    pub hitch_depth: f32,
+    pub vibrato_rate: f32,
+    pub vibrato_depth: f32,
+
+    pub export_wav: bool,
+    pub import_wav: bool,
+    /// Whether the block's levels are measured for `FreezeEngine::levels()`
+    pub metering: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let mut settings = Self {
+            buffer_size: 0,
+            buffer_size_normalized: 0.,
+            size_unit: SizeUnit::Samples,
+            glide_buffer_size: false,
+            resize_while_frozen: ResizeWhileFrozen::Window,
+            division: Division::Off,
+            velocity_division: 0.,
+            capture_reversed: false,
+            loop_start: 0.,
+            loop_length: 1.,
+
+            freeze: false,
+            freeze_amount: 0.,
+            trigger_one_loop: false,
+            trigger_repeats: 1,
+            mode: Mode::Musical,
+            chunk_size: ChunkSize::Samples1024,
+            attack: 0.,
+            freeze_decay: 200.,
+            freeze_sustain: 1.,
+            release: 0.,
+            hold_amount: 1.,
+            stop_time: 0.,
+            stop_curve: StopCurve::Tape,
+            decay: 0.,
+            decay_shape: DecayShape::Exponential,
+            gate_hold: 4,
+            midi_trigger: true,
+            velocity_curve: 0.,
+            voice_count: MAX_VOICES as i32,
+            retrigger: false,
+            tap_tempo: false,
+            tap_note: 24,
+            snapshot_keys: false,
+            store_note: 12,
+            recall_note: 14,
+            restore_last: false,
+            size_keys: false,
+            halve_note: 0,
+            double_note: 2,
+            quantize_trigger: Quantize::Off,
+            quantize_release: false,
+            freeze_on_stop: false,
+            link_group: LinkGroup::Off,
+            sidechain_trigger: false,
+            sidechain_threshold: 0.1,
+            sidechain_attack: 1.,
+            sidechain_release: 100.,
+            sidechain_highpass: 20.,
+            sidechain_lowpass: 20000.,
+            sidechain_listen: false,
+            refresh: false,
+            refresh_threshold: 0.1,
+
+            key_tracking: false,
+            note_behavior: NoteBehavior::LengthTuned,
+            root_note: 60,
+            formant: false,
+            glide: 0.,
+            interpolation: Interpolation::Linear,
+            oversampling: Oversampling::Off,
+            half_time: false,
+            stretch: 1.,
+            sequence: false,
+            sequence_length: SEQUENCE_STEPS as i32,
+            sequence_intervals: [0; SEQUENCE_STEPS],
+            pitch_output: false,
+            pitch_output_transpose: 0,
+
+            smear: 0.,
+            crackle: 0.,
+            soft_crackle: false,
+            bit_depth: BYPASS_BIT_DEPTH,
+            dither: false,
+            noise_shaping: false,
+            tilt: 0.,
+
+            auto_gain: false,
+            normalize: false,
+            normalize_target: -18.,
+            key_pan: 0.,
+            random_pan: false,
+            spread: 0.,
+            cross_feed: 0.,
+            channel_rotate: ChannelRotate::Off,
+
+            hitch_rate: 0.,
+            hitch_depth: 0.5,
+            vibrato_rate: 5.,
+            vibrato_depth: 0.,
+
+            export_wav: false,
+            import_wav: false,
+            metering: false,
+        };
+        settings.set_buffer_size(1024);
+        settings
+    }
+}
+
+impl Settings {
+    /// Set the buffer size in samples together with its normalized value
+    pub fn set_buffer_size(&mut self, samples: i32) {
+        let samples = samples.clamp(MIN_BUFFER_SIZE as i32, MAX_BUFFER_SIZE as i32);
+        self.buffer_size = samples;
+        self.buffer_size_normalized = (samples - MIN_BUFFER_SIZE as i32) as f32
+            / (MAX_BUFFER_SIZE - MIN_BUFFER_SIZE) as f32;
+    }
+}
+
+/// Every sample's value for the settings that are smoothed, for the samples of the next block.
+/// Hosts that smooth these fill them in before `FreezeEngine::process_with()`, and
+/// `FreezeEngine::process()` holds them at their settings. `FreezeEngine::prepare()` sizes them
+/// for the longest block.
+#[derive(Debug, Clone, Default)]
+pub struct Ramps {
+    pub loop_start: Vec<f32>,
+    pub loop_length: Vec<f32>,
+    pub stretch: Vec<f32>,
+    pub hold_amount: Vec<f32>,
+    pub tilt: Vec<f32>,
+    pub freeze_amount: Vec<f32>,
+}
+
+impl Ramps {
+    fn resize(&mut self, max_len: usize) {
+        for ramp in self.ramps_mut() {
+            ramp.resize(max_len, 0.);
+        }
+    }
+
+    /// Hold every ramp at its setting for the first `len` samples
+    fn hold(&mut self, settings: &Settings, len: usize) {
+        let values = [
+            settings.loop_start,
+            settings.loop_length,
+            settings.stretch,
+            settings.hold_amount,
+            settings.tilt,
+            settings.freeze_amount,
+        ];
+        for (ramp, value) in self.ramps_mut().into_iter().zip(values) {
+            ramp[..len].fill(value);
+        }
+    }
+
+    fn ramps_mut(&mut self) -> [&mut Vec<f32>; 6] {
+        [
+            &mut self.loop_start,
+            &mut self.loop_length,
+            &mut self.stretch,
+            &mut self.hold_amount,
+            &mut self.tilt,
+            &mut self.freeze_amount,
+        ]
+    }
+}
+
+/// What the engine shares with other threads: the plugin's saved state and editor, and the
+/// background tasks
+#[derive(Clone, Default)]
+pub(crate) struct Shared {
+    pub buffer_state: Arc<RwLock<BufferState>>,
+    pub snapshot_bank: Arc<RwLock<SnapshotBank>>,
+    pub waveform: Arc<Waveform>,
+    /// Whether the freeze is engaged
+    pub frozen: Arc<AtomicBool>,
+    pub size_display: Arc<SizeDisplay>,
+    /// The loops captured for the WAV export, written to disk by `Task::ExportWav`
+    pub export_buffer: Arc<Mutex<BufferState>>,
+    /// The loops decoded by `Task::ImportWav`, waiting to be copied into the buffers
+    pub import_buffer: Arc<Mutex<BufferState>>,
+    /// Set by the background task when `import_buffer` contains a new import
+    pub import_ready: Arc<AtomicBool>,
+}
+
+/// The freeze for any number of channels. `prepare()` allocates everything it needs, processing
+/// is allocation free and safe on an audio thread.
+pub struct FreezeEngine {
+    settings: Settings,
+    ramps: Ramps,
+    shared: Shared,
+    /// The levels of the last block, while `Settings::metering` is enabled
+    levels: [BlockLevels; 2],
+
+    channel_buffers: ChannelBuffers,
+
+    note_freezing: bool,
+    /// Bitmask of the currently held MIDI notes
+    held_notes: u128,
+    /// Bitmask of the released notes whose voices are still fading out, see
+    /// `next_voice_level()`
+    releasing_notes: u128,
+    /// The level of the loudest voice that was stolen. The wet gain fades down from this over
+    /// `STEAL_FADE_MS` instead of dropping to the remaining voices' level right away.
+    stolen_level: f32,
+    /// Bitmask of the notes whose voices `reset()` dropped. `reset()` can't send events, so
+    /// their voices are terminated at the start of the next `process()` call.
+    dropped_notes: u128,
+    /// The note that currently sets the loop length when key tracking is enabled
+    active_note: Option<u8>,
+    /// Per note voice information for the held notes, used for CLAP polyphonic modulation
+    note_voices: [NoteVoice; 128],
+    /// The loop length the buffers are currently gliding towards or sitting at, in samples
+    glide_length: Option<f32>,
+    /// The playback rate the buffers are currently gliding towards or sitting at when notes
+    /// repitch the loop
+    glide_rate: f32,
+    /// How many steps faster than the Division parameter the current freeze stutters. This is
+    /// latched from the velocity of the note that started the freeze.
+    division_offset: usize,
+    /// How much of the frozen loop is audible. This is 1 while freezing and fades out to 0 over
+    /// the release time after the freeze has been released.
+    wet_gain: f32,
+    /// The level the wet gain fades to while the freeze is engaged. Freeze Amount lowers this
+    /// when nothing else requests the freeze.
+    wet_target: f32,
+    /// Whether the attack has reached `wet_target` and the wet gain falls to the sustain level.
+    /// This is cleared when the freeze engages and by every note played during the freeze.
+    decaying: bool,
+    /// How far the stop after the release has gotten, from 0 to 1. This is `None` while the
+    /// freeze is engaged or when it was released without a stop.
+    stop_progress: Option<f32>,
+    /// Slows the frozen loop down for a moment every now and then
+    hitch: Hitch,
+    /// The vibrato's phase from 0 to 1. This starts over whenever the freeze engages.
+    vibrato_phase: f32,
+    /// How many times the frozen loop has played through since the freeze engaged, for the
+    /// Decay parameter and for switching Half-Time and the pitch sequence on the loop's seam
+    repeats: f64,
+    /// Whether the loop currently plays at half speed. This follows the Half-Time parameter
+    /// whenever the loop starts another pass.
+    half_time: bool,
+    /// The pitch sequence's current step and its factor for the playback rate. The rate is
+    /// only picked up again when the loop starts another pass, so it never changes mid-pass.
+    sequence_step: usize,
+    sequence_rate: f32,
+    /// How many channels Channel Rotate has moved the wet signals by, see
+    /// `SampleControls::rotation`. This moves towards `rotation_target` after every rotation.
+    rotation: f32,
+    rotation_target: f32,
+    /// Set for blocks where Channel Rotate routes the wet signals. They're written to
+    /// `wet_scratch` then, and routed to their channels after all channels are processed.
+    rotate_wet: bool,
+    /// Every channel's wet signal for Channel Rotate
+    wet_scratch: Vec<Vec<f32>>,
+    /// How many times the size keys doubled the frozen loop's length, negative when they halved
+    /// it. Presses go to `pending_size_steps` and apply on the loop's next pass like Half-Time.
+    size_steps: i32,
+    pending_size_steps: i32,
+    /// The loop length before the size keys and the longest loop they can make, as of the last
+    /// sample
+    unscaled_loop_len: f32,
+    max_loop_len: f32,
+    /// Whether the current freeze plays backwards. This is latched from the Capture Reversed
+    /// parameter when the freeze engages and cleared once the release has faded out.
+    reversed_freeze: bool,
+    /// Whether the freeze is currently engaged. This follows `freeze_requested()`, but may lag
+    /// behind it until the next grid line when the trigger is quantized.
+    freeze_engaged: bool,
+
+    tap_tempo: TapTempo,
+    /// The buffer size set by tapping the tap note or by loading audio over SysEx. This
+    /// overrides the Buffer Size parameter until that parameter changes.
+    buffer_size_override: Option<f32>,
+    /// The Buffer Size parameter's value when the override was set
+    buffer_size_override_param: i32,
+    /// The Size Unit parameter's value in the previous block
+    size_unit: SizeUnit,
+    /// The tempo when the sync unit was selected, which the buffer size is relative to
+    sync_tempo: f64,
+    /// The factor for the Buffer Size parameter's length in the sync unit, from the tempo change
+    /// since the unit was selected. This is 1 in the other units.
+    size_scale: f64,
+    /// The size the channel buffers were last resized to. This is `None` after audio was loaded
+    /// into the buffers, so they're resized again at the end of the next block.
+    applied_buffer_size: Option<usize>,
+    /// Whether the buffers were resized with `ResizeWhileFrozen::Window` and still hold samples
+    /// outside of their size, which are cleared once the loop is released
+    window_resized: bool,
+    /// The number of samples processed so far, used to time the taps
+    sample_position: u64,
+
+    /// Sends the buffer contents over SysEx when requested
+    buffer_dump: BufferDump,
+    /// Collects buffer contents sent over SysEx until they can be loaded into the buffers
+    buffer_load: BufferLoad,
+    /// Set when audio loaded over SysEx or recalled from the snapshot bank should be frozen.
+    /// This is cleared again when the freeze is released through the Freeze parameter or MIDI
+    /// notes.
+    latched_freezing: bool,
+    /// The Freeze parameter's value in the previous block, used to detect it being switched
+    last_freeze_param: bool,
+    /// Whether Freeze Amount was above the threshold in the previous block, for the same reason
+    last_amount_param: bool,
+    /// Whether Freeze Amount is currently above the threshold, following its smoothed value
+    amount_freezing: bool,
+    /// Set when the transport stopped with Freeze on Stop enabled. This is cleared again when
+    /// playback resumes or when the freeze is released manually.
+    transport_freezing: bool,
+    /// The Trigger One Loop parameter's value in the previous block, used to detect it being
+    /// switched on
+    last_trigger_param: bool,
+    /// Set by Trigger One Loop, and cleared again once the loop has played its repeats
+    trigger_freezing: bool,
+    /// Set by `set_freeze()`
+    offline_freezing: bool,
+    /// The repeat count when Trigger One Loop was last switched on, which its repeats are
+    /// counted from
+    trigger_start: f64,
+    sidechain_detector: Detector,
+    /// Filter every sidechain channel before it reaches the detector
+    sidechain_filters: Vec<DetectorFilter>,
+    /// Every sidechain channel as the detector hears it, for Sidechain Listen
+    detector_signal: Vec<Vec<f32>>,
+    /// Compensates the level difference between the input and the frozen loop
+    auto_gain: AutoGain,
+    /// Brings the frozen loop to the Normalize Target's level
+    normalizer: Normalizer,
+    /// Whether the sidechain detector is currently above the threshold
+    sidechain_freezing: bool,
+    /// Follows the input's level for the freeze's refresh
+    refresh_detector: Detector,
+    /// The number of samples that are still recorded while the freeze is refreshed, or `None`
+    /// when it isn't. The old loop keeps playing from the fade buffers in the meantime.
+    refresh_remaining: Option<u32>,
+    /// Set when a note played with Retrigger enabled, until the refresh it starts can begin
+    retrigger_pending: bool,
+    /// Shares the freeze with the other instances in the same link group
+    freeze_link: FreezeLink,
+    /// Set when another instance in the link group froze, and cleared when one released
+    link_freezing: bool,
+    /// Whether the transport was playing in the previous block, used to only react to the
+    /// transport starting or stopping
+    last_playing: bool,
+
+    /// The buffers that were playing before recalling a snapshot. These are faded out while the
+    /// recalled snapshot fades in.
+    fade_buffers: ChannelBuffers,
+    /// The progress of the crossfade from `fade_buffers` to `channel_buffers`. This is 1 when
+    /// there's no crossfade going on.
+    crossfade: f32,
+    /// The engine for every mode, see `Mode`
+    engines: Engines,
+    /// The progress of the crossfade from the previous mode's engine to the current one, like
+    /// `crossfade`
+    engine_crossfade: f32,
+
+    /// Picks the random voice pans
+    pan_rng: Rng,
+    /// The voice count last reported to the host for CLAP's voice info extension
+    voice_capacity: u32,
+    /// Estimates the frozen loop's pitch for Pitch to MIDI
+    pitch_detector: PitchDetector,
+    /// The note Pitch to MIDI is holding on the MIDI output
+    pitch_output_note: Option<u8>,
+
+    sample_rate: f32,
+    /// Whether a mono input feeds several output channels
+    mono_input: bool,
+    /// The controls for every sample of the block, computed before the channels are processed
+    sample_controls: Vec<SampleControls>,
+    /// The first channel's input while a mono input feeds several channels, since that channel
+    /// is overwritten before the others are processed
+    mono_dry: Vec<f32>,
+    /// Process every sample through all channels in turn and through the engines, like the
+    /// plugin did before it processed the channels slice by slice. The tests compare the two.
+    #[cfg(test)]
+    per_sample_reference: bool,
+    /// Delay every channel's input by the oversampling's latency
+    dry_delays: Vec<LatencyDelay>,
+    /// One channel's frozen samples as the engines play them, before the effects and gains
+    frozen: Vec<f32>,
+    /// Every channel's own crackle, so the clicks differ between channels
+    crackles: Vec<Crackle>,
+    /// The bit reduction for every channel's wet signal
+    crushers: Vec<Crusher>,
+    /// The tilt EQ for every channel's wet signal
+    tilts: Vec<Tilt>,
+    /// The latency last reported to the host
+    reported_latency: u32,
+    /// Set when the frozen loops changed and need to be copied into the plugin's state
+    state_dirty: bool,
+
+    /// The Export WAV parameter's value in the previous block, used to export once per switch
+    last_export_param: bool,
+    /// The Import WAV parameter's value in the previous block
+    last_import_param: bool,
+    /// The loop that was frozen before the current capture, for Restore Last
+    undo: Snapshot,
+    /// The Restore Last parameter's value in the previous block
+    last_restore_param: bool,
+}
+
+impl Default for FreezeEngine {
+    fn default() -> Self {
+        Self::with_shared(Shared::default())
+    }
+}
+
+impl FreezeEngine {
+    /// A new engine sharing its state with the plugin through `shared`
+    pub(crate) fn with_shared(shared: Shared) -> Self {
+        Self {
+            settings: Settings::default(),
+            ramps: Ramps::default(),
+            shared,
+            levels: [BlockLevels::default(); 2],
+            channel_buffers: ChannelBuffers::default(),
+            note_freezing: false,
+            held_notes: 0,
+            releasing_notes: 0,
+            stolen_level: 0.,
+            dropped_notes: 0,
+            active_note: None,
+            note_voices: [NoteVoice::new(0, 0); 128],
+            glide_length: None,
+            glide_rate: 1.,
+            division_offset: 0,
+            wet_gain: 0.,
+            wet_target: 1.,
+            decaying: false,
+            stop_progress: None,
+            hitch: Hitch::new(HITCH_SEED),
+            vibrato_phase: 0.,
+            repeats: 0.,
+            half_time: false,
+            sequence_step: 0,
+            sequence_rate: 1.,
+            rotation: 0.,
+            rotation_target: 0.,
+            rotate_wet: false,
+            wet_scratch: Vec::new(),
+            size_steps: 0,
+            pending_size_steps: 0,
+            unscaled_loop_len: 0.,
+            max_loop_len: 0.,
+            reversed_freeze: false,
+            freeze_engaged: false,
+            tap_tempo: TapTempo::default(),
+            buffer_size_override: None,
+            buffer_size_override_param: 0,
+            size_unit: SizeUnit::Samples,
+            sync_tempo: DEFAULT_TEMPO,
+            size_scale: 1.,
+            applied_buffer_size: None,
+            window_resized: false,
+            sample_position: 0,
+            buffer_dump: BufferDump::default(),
+            buffer_load: BufferLoad::default(),
+            latched_freezing: false,
+            last_freeze_param: false,
+            last_amount_param: false,
+            amount_freezing: false,
+            transport_freezing: false,
+            last_trigger_param: false,
+            trigger_freezing: false,
+            offline_freezing: false,
+            trigger_start: 0.,
+            last_playing: false,
+            sidechain_detector: Detector::default(),
+            sidechain_filters: Vec::new(),
+            detector_signal: Vec::new(),
+            auto_gain: AutoGain::default(),
+            normalizer: Normalizer::default(),
+            sidechain_freezing: false,
+            refresh_detector: Detector::default(),
+            refresh_remaining: None,
+            retrigger_pending: false,
+            freeze_link: FreezeLink::default(),
+            link_freezing: false,
+            fade_buffers: ChannelBuffers::default(),
+            crossfade: 1.,
+            engines: Engines::default(),
+            engine_crossfade: 1.,
+            pan_rng: Rng::new(PAN_SEED),
+            voice_capacity: MAX_VOICES,
+            pitch_detector: PitchDetector::default(),
+            pitch_output_note: None,
+            sample_rate: 44100.,
+            mono_input: false,
+            sample_controls: Vec::new(),
+            mono_dry: Vec::new(),
+            #[cfg(test)]
+            per_sample_reference: false,
+            dry_delays: Vec::new(),
+            frozen: Vec::new(),
+            crackles: Vec::new(),
+            crushers: Vec::new(),
+            tilts: Vec::new(),
+            reported_latency: 0,
+            state_dirty: false,
+            last_export_param: false,
+            last_import_param: false,
+            undo: Snapshot::default(),
+            last_restore_param: false,
+        }
+    }
+
+    /// Prepare the engine for `channels` channels at `sample_rate`, processing at most
+    /// `max_len` samples at a time. Every channel records its own input. This allocates, and
+    /// it may be called again at any time. The captured audio is kept for the channels that
+    /// still exist.
+    pub fn prepare(&mut self, sample_rate: f32, max_len: usize, channels: usize) {
+        self.prepare_layout(
+            sample_rate,
+            max_len,
+            Layout {
+                channels,
+                ..Layout::default()
+            },
+        );
+    }
+
+    /// The settings the next block is processed with
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// The smoothed settings for `process_with()`
+    pub fn ramps_mut(&mut self) -> &mut Ramps {
+        &mut self.ramps
+    }
+
+    /// Freeze the buffers like `Settings::freeze` does. This holds the freeze on top of the
+    /// settings, so either of them keeps the loop frozen.
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.offline_freezing = freeze;
+    }
+
+    /// Use `length` as the buffer size in samples until `Settings::buffer_size` changes, like a
+    /// tapped length does
+    pub fn set_length(&mut self, length: f32) {
+        self.buffer_size_override =
+            Some(length.clamp(MIN_BUFFER_SIZE as f32, MAX_BUFFER_SIZE as f32));
+        self.buffer_size_override_param = self.settings.buffer_size;
+    }
+
+    /// Whether the freeze is engaged right now
+    pub fn is_frozen(&self) -> bool {
+        self.freeze_engaged
+    }
+
+    /// The length of the first channel's loop in samples as it's recorded right now, see
+    /// `RingBuffer::loop_len()`
+    pub fn loop_len(&self) -> usize {
+        self.channel_buffers.first().map_or(0, |buffer| buffer.loop_len())
+    }
+
+    /// A view of a channel's buffer as it's recorded right now, `None` past the last channel
+    pub fn channel(&self, index: usize) -> Option<ChannelRef<'_>> {
+        self.channel_buffers.iter().nth(index)
+    }
+
+    /// The number of voices the host should reserve for the notes
+    pub fn voice_capacity(&self) -> u32 {
+        self.voice_capacity
+    }
+
+    /// The latency the wet path adds on top of the dry signal. Only the oversampling's
+    /// decimation filters delay the playback, the input is delayed by the same amount in
+    /// `process()`. Engines that look ahead need to report their delay in `Engine::latency()`.
+    pub fn latency_samples(&self) -> u32 {
+        self.engines.current().latency(self.settings.oversampling) as u32
+    }
+
+    /// The input and output levels of the last block, while `Settings::metering` is enabled
+    pub(crate) fn levels(&self) -> &[BlockLevels; 2] {
+        &self.levels
+    }
+
+    pub(crate) fn shared(&self) -> &Shared {
+        &self.shared
+    }
+
+    /// Process the next block of samples in place, with the smoothed settings held at their
+    /// values and without a host. Returns whether the frozen loop is still heard, in which case
+    /// the engine needs to keep processing even when the input is silent.
+    pub fn process(&mut self, channels: &mut [&mut [f32]]) -> bool {
+        let num_samples = channels.first().map_or(0, |channel| channel.len());
+        self.ramps.hold(&self.settings, num_samples);
+        self.process_with(channels, None, None, &mut NoHost)
+    }
+
+    /// Process the next block of samples in place like a plugin host would: with the
+    /// `sidechain`'s channels, an optional output for just the wet signal, the ramps filled in
+    /// through `ramps_mut()` and the `host`'s events. Returns the same as `process()`.
+    pub fn process_with(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        sidechain: Option<&[&mut [f32]]>,
+        mut wet_output: Option<&mut [&mut [f32]]>,
+        host: &mut impl Host,
+    ) -> bool {
+        let _ftz = ScopedFtz::enable();
+        self.end_dropped_voices(host);
+        let transport = host.transport();
+        let tempo = transport.tempo.unwrap_or(DEFAULT_TEMPO);
+        self.update_size_unit(tempo);
+        // Switching modes while the loop is heard crossfades from the previous engine
+        if self.engines.set_mode(self.settings.mode, self.wet_gain > 0.) {
+            self.engine_crossfade = 0.;
+        }
+        self.engines.set_chunk_size(self.settings.chunk_size);
+        let block = self.block_controls(&transport, tempo);
+        self.begin_block(&transport, host);
+
+        // The editor's displays are only fed while it's open
+        let metering = self.settings.metering;
+        let mut levels = [BlockLevels::default(); 2];
+        let num_samples = channels.first().map_or(0, |channel| channel.len());
+        // Hosts never process more samples than the maximum block size `prepare()` was called
+        // with, which is what the scratch buffers are sized for
+        debug_assert!(num_samples <= self.sample_controls.len());
+        self.delay_dry_signal(channels, host);
+        self.update_pitch_output(host);
+
+        // The block is processed in segments that end at the next event, so events see the
+        // buffers exactly as they were at their position in the block. Each segment first
+        // computes the controls for all of its samples and then runs every channel through its
+        // buffer in one go.
+        let mut next_event = host.next_event();
+        let mut segment_start = 0;
+        while segment_start < num_samples {
+            // Handle the events at their exact position in the block so the glide starts on the
+            // right sample
+            while let Some(event) = next_event {
+                if event.timing() > segment_start as u32 {
+                    break;
+                }
+
+                self.handle_event(event, host);
+                next_event = host.next_event();
+            }
+
+            let max_segment_end =
+                next_event.map_or(num_samples, |event| (event.timing() as usize).min(num_samples));
+            let mut segment_end = segment_start;
+            let mut boundary = None;
+            while segment_end < max_segment_end && boundary.is_none() {
+                let (controls, sample_boundary) =
+                    self.next_sample_controls(&block, channels, sidechain, segment_end, host);
+                self.sample_controls[segment_end] = controls;
+                boundary = sample_boundary;
+                segment_end += 1;
+            }
+
+            let meter_levels = metering.then_some(&mut levels);
+            self.process_segment(
+                channels,
+                wet_output.as_deref_mut(),
+                segment_start..segment_end,
+                boundary,
+                meter_levels,
+            );
+            segment_start = segment_end;
+        }
+        if self.engine_crossfade >= 1. {
+            self.engines.finish_crossfade();
+        }
+
+        let meter_levels = metering.then_some(&mut levels);
+        self.route_wet_signals(channels, sidechain, wet_output, num_samples, meter_levels);
+        self.finish_block(num_samples, host);
+        self.levels = levels;
+
+        self.is_playing_buffer()
+    }
+
+    /// Prepare the engine for the channels in `layout`, like `prepare()`. The latency and the
+    /// voice capacity the host needs to know about are in `latency_samples()` and
+    /// `voice_capacity()` afterwards.
+    pub fn prepare_layout(&mut self, sample_rate: f32, max_len: usize, layout: Layout) {
+        // Nothing is playing yet, so there's nothing to crossfade
+        self.engines.set_mode(self.settings.mode, false);
+        self.engines.set_chunk_size(self.settings.chunk_size);
+        self.reported_latency = self.latency_samples();
+        self.voice_capacity = self.settings.voice_count as u32;
+        let num_channels = layout.channels;
+
+        // The captured audio would otherwise play back at the wrong pitch
+        if sample_rate != self.sample_rate && !self.channel_buffers.is_empty() {
+            let ratio = sample_rate as f64 / self.sample_rate as f64;
+            for mut buffer in self.channel_buffers.iter_mut().chain(self.fade_buffers.iter_mut()) {
+                buffer.resample(ratio);
+            }
+            self.undo.resample(ratio);
+
+            // Keep the loop's duration rather than its length in samples
+            let loop_len = self.channel_buffers.first().map_or(0, |buffer| buffer.loop_len());
+            self.buffer_size_override = Some((loop_len + 1).max(MIN_BUFFER_SIZE) as f32);
+            self.buffer_size_override_param = self.settings.buffer_size;
+            self.glide_length = None;
+        }
+
+        self.sample_rate = sample_rate;
+        self.mono_input = layout.mono_input;
+        self.applied_buffer_size = None;
+        let max_block_size = max_len;
+        self.ramps.resize(max_block_size);
+        self.sample_controls.resize(max_block_size, SampleControls::default());
+        self.mono_dry.resize(max_block_size, 0.);
+        self.frozen.resize(max_block_size, 0.);
+        self.wet_scratch = vec![vec![0.; max_block_size]; num_channels];
+        let num_sidechain_channels = layout.sidechain_channels;
+        self.sidechain_filters = vec![DetectorFilter::default(); num_sidechain_channels];
+        self.detector_signal = vec![vec![0.; max_block_size]; num_sidechain_channels];
+        self.dry_delays.resize(num_channels, LatencyDelay::default());
+        self.crackles = (0..num_channels)
+            .map(|channel| Crackle::new(CRACKLE_SEED.wrapping_add(channel as u32)))
+            .collect();
+        self.crushers = (0..num_channels)
+            .map(|channel| Crusher::new(DITHER_SEED.wrapping_add(channel as u32)))
+            .collect();
+        self.engines.prepare(num_channels, max_block_size);
+        self.shared.waveform.prepare(WAVEFORM_POINTS, num_channels);
+        // The filters depend on the sample rate
+        self.tilts = vec![Tilt::new(self.sample_rate); num_channels];
+        self.auto_gain.set_sample_rate(self.sample_rate);
+        self.normalizer.set_sample_rate(self.sample_rate);
+        self.refresh_detector.set_times(REFRESH_ATTACK_MS, REFRESH_RELEASE_MS, self.sample_rate);
+        self.pitch_detector.prepare(self.sample_rate);
+
+        // Hosts may initialize the plugin again at any time. The captured audio is kept for the
+        // channels that still exist, new channels start out silent.
+        if self.channel_buffers.len() != num_channels {
+            let buffer_size = self.settings.buffer_size as usize;
+            self.channel_buffers.set_num_channels(num_channels, buffer_size);
+            self.fade_buffers.set_num_channels(num_channels, buffer_size);
+            self.buffer_dump = BufferDump::new(num_channels);
+            self.buffer_load = BufferLoad::new(num_channels);
+        }
+
+        if let Ok(mut export_buffer) = self.shared.export_buffer.lock() {
+            export_buffer.reserve(num_channels);
+        }
+
+        // The snapshots are resampled here if needed, and restored banks need their storage
+        // allocated again
+        if let Ok(mut snapshot_bank) = self.shared.snapshot_bank.write() {
+            snapshot_bank.prepare(num_channels, self.sample_rate);
+        }
+        self.undo.reserve(num_channels);
+
+        // Hosts initialize the plugin again after restoring a state, so this is where a saved
+        // freeze is copied back into the buffers without racing the audio thread
+        if let Ok(mut state) = self.shared.buffer_state.write() {
+            if let Some(len) = state.restore(&mut self.channel_buffers, self.sample_rate) {
+                self.buffer_size_override = Some((len + 1).max(MIN_BUFFER_SIZE) as f32);
+                self.buffer_size_override_param = self.settings.buffer_size;
+                self.latched_freezing = true;
+            }
+            state.reserve(num_channels);
+            state.capture(&self.channel_buffers, self.sample_rate);
+        }
+    }
+
+    /// Free the buffers while the engine isn't used
+    pub fn deactivate(&mut self) {
+        // Deactivated instances shouldn't hold on to the full size buffers. A freeze is kept in
+        // the plugin's state so `initialize()` restores it when the plugin gets activated again.
+        if let Ok(mut state) = self.shared.buffer_state.write() {
+            state.frozen = self.freeze_engaged || self.latched_freezing || self.freeze_param_held();
+            if state.frozen {
+                state.capture(&self.channel_buffers, self.sample_rate);
+            }
+            state.shrink();
+        }
+        self.state_dirty = false;
+
+        if let Ok(mut export_buffer) = self.shared.export_buffer.lock() {
+            export_buffer.shrink();
+        }
+        if let Ok(mut snapshot_bank) = self.shared.snapshot_bank.write() {
+            snapshot_bank.shrink();
+        }
+        self.undo.shrink();
+
+        self.channel_buffers = ChannelBuffers::default();
+        self.fade_buffers = ChannelBuffers::default();
+        self.applied_buffer_size = None;
+        self.shared.waveform.clear();
+        self.buffer_dump = BufferDump::new(0);
+        self.buffer_load = BufferLoad::new(0);
+    }
+
+    /// Forget the audio and the notes from before the host rewound. Audio that's held by the
+    /// freeze settings or a load is kept.
+    pub fn reset(&mut self) {
+        // Stale audio from before the host rewound would otherwise end up in the next freeze.
+        // Hosts also reset the plugin after initializing it again, so audio that is being held
+        // by the Freeze parameter or a load is kept.
+        if !self.freeze_param_held() && !self.latched_freezing {
+            for mut buffer in self.channel_buffers.iter_mut().chain(self.fade_buffers.iter_mut()) {
+                buffer.clear();
+            }
+            self.shared.waveform.clear();
+        }
+        self.crossfade = 1.;
+        self.engines.reset();
+        self.engine_crossfade = 1.;
+
+        self.note_freezing = false;
+        // The voices are kept until the host has been told they ended
+        self.dropped_notes |= self.held_notes | self.releasing_notes;
+        self.held_notes = 0;
+        self.releasing_notes = 0;
+        self.stolen_level = 0.;
+        self.active_note = None;
+        self.glide_length = None;
+        self.glide_rate = 1.;
+        self.division_offset = 0;
+        self.wet_gain = 0.;
+        self.wet_target = 1.;
+        self.decaying = false;
+        self.stop_progress = None;
+        self.hitch = Hitch::new(HITCH_SEED);
+        self.vibrato_phase = 0.;
+        self.repeats = 0.;
+        self.half_time = false;
+        self.sequence_step = 0;
+        self.sequence_rate = 1.;
+        self.rotation = 0.;
+        self.rotation_target = 0.;
+        self.size_steps = 0;
+        self.pending_size_steps = 0;
+        self.reversed_freeze = false;
+        self.freeze_engaged = false;
+        self.shared.frozen.store(false, Ordering::Relaxed);
+        self.transport_freezing = false;
+        self.trigger_freezing = false;
+        self.sidechain_detector.reset();
+        self.sidechain_filters.iter_mut().for_each(DetectorFilter::reset);
+        self.auto_gain.reset();
+        self.normalizer.reset();
+        self.sidechain_freezing = false;
+        self.amount_freezing = false;
+        self.refresh_detector.reset();
+        self.refresh_remaining = None;
+        self.retrigger_pending = false;
+        self.pitch_detector.reset();
+        self.pitch_output_note = None;
+        self.link_freezing = false;
+        self.tap_tempo = TapTempo::default();
+        self.pan_rng = Rng::new(PAN_SEED);
+        for (channel, crackle) in self.crackles.iter_mut().enumerate() {
+            *crackle = Crackle::new(CRACKLE_SEED.wrapping_add(channel as u32));
+        }
+        for (channel, crusher) in self.crushers.iter_mut().enumerate() {
+            *crusher = Crusher::new(DITHER_SEED.wrapping_add(channel as u32));
+        }
+    }
+
+    /// The values every sample's controls are computed from that stay the same for the whole
+    /// block. These are taken before anything is loaded into the buffers at the start of the
+    /// block.
+    fn block_controls(&mut self, transport: &TransportInfo, tempo: f64) -> BlockControls {
+        self.normalizer.set_target(self.settings.normalize_target);
+        // A rotation that's still applied when Channel Rotate is switched off stays until the
+        // loop has faded out
+        self.rotate_wet = self.channel_buffers.len() > 1
+            && (self.settings.channel_rotate != ChannelRotate::Off
+                || self.rotation != 0.);
+        self.sidechain_detector.set_times(
+            self.settings.sidechain_attack,
+            self.settings.sidechain_release,
+            self.sample_rate,
+        );
+
+        BlockControls {
+            glide_coefficient: self.glide_coefficient(),
+            tempo,
+            engine_crossfade_step: 1. / (ENGINE_CROSSFADE_MS / 1000. * self.sample_rate),
+            attack_step: self.fade_step(self.settings.attack),
+            decay_step: self.fade_step(self.settings.freeze_decay),
+            release_step: self.fade_step(self.settings.release),
+            steal_step: self.fade_step(STEAL_FADE_MS),
+            stop_step: self.stop_step(),
+            // Engines without smoothing cut straight to a recalled snapshot
+            crossfade_step: self.fade_step(RECALL_CROSSFADE_MS),
+            spread: self.settings.spread,
+            auto_gain_enabled: self.settings.auto_gain,
+            normalize_enabled: self.settings.normalize,
+            crackle: Crackle::chance(self.settings.crackle, self.sample_rate),
+            hitch_chance: self.settings.hitch_rate / self.sample_rate,
+            hitch_depth: self.settings.hitch_depth,
+            vibrato_step: self.settings.vibrato_rate / self.sample_rate,
+            crush_step: Crusher::step(self.settings.bit_depth),
+            rotate_step: 1. / (ROTATE_CROSSFADE_MS / 1000. * self.sample_rate).max(1.),
+            whole_loop_len: self.channel_buffers.first().map_or(0, |buffer| buffer.loop_len())
+                as f32,
+            quantize_grid: self.quantize_grid(transport, tempo),
+            highpass_coefficient: filter_coefficient(
+                self.settings.sidechain_highpass,
+                self.sample_rate,
+            ),
+            lowpass_coefficient: filter_coefficient(
+                self.settings.sidechain_lowpass,
+                self.sample_rate,
+            ),
+        }
+    }
+
+    /// Follow the parameters and the sources that can only change between blocks: the voices,
+    /// loaded audio, the buttons, the freeze sources and the link group
+    fn begin_block(&mut self, transport: &TransportInfo, context: &mut impl Host) {
+        // Notes that were held when MIDI triggering got disabled would otherwise keep the buffer
+        // frozen forever
+        if !self.settings.midi_trigger {
+            self.release_all_notes();
+        }
+        // Lowering the voice count steals the notes that don't fit anymore
+        let voice_count = self.settings.voice_count as u32;
+        self.steal_voices(0, voice_count, context);
+        if voice_count != self.voice_capacity {
+            self.voice_capacity = voice_count;
+            context.set_current_voice_capacity(voice_count);
+        }
+
+        // Loaded audio replaces the buffer contents at block boundaries so a block never contains
+        // a mix of old and new audio
+        if let Some((len, freeze)) = self.buffer_load.apply(&mut self.channel_buffers) {
+            self.buffer_size_override = Some((len + 1).max(MIN_BUFFER_SIZE) as f32);
+            self.buffer_size_override_param = self.settings.buffer_size;
+            self.applied_buffer_size = None;
+            self.latched_freezing |= freeze;
+            self.state_dirty = true;
+        }
+
+        let export_param = self.settings.export_wav;
+        if export_param && !self.last_export_param {
+            self.start_export(context);
+        }
+        self.last_export_param = export_param;
+
+        let import_param = self.settings.import_wav;
+        if import_param && !self.last_import_param {
+            context.execute_background(Task::ImportWav {
+                sample_rate: self.sample_rate,
+            });
+        }
+        self.last_import_param = import_param;
+
+        let restore_param = self.settings.restore_last;
+        if restore_param && !self.last_restore_param {
+            self.restore_last();
+        }
+        self.last_restore_param = restore_param;
+        // The parameter's changes split the block, so this starts the freeze on the exact sample
+        let trigger_param = self.settings.trigger_one_loop;
+        if trigger_param && !self.last_trigger_param {
+            // Repeats are counted from zero again when the freeze engages
+            self.trigger_start = if self.freeze_engaged { self.repeats } else { 0. };
+            self.trigger_freezing = true;
+        }
+        self.last_trigger_param = trigger_param;
+        if self.shared.import_ready.load(Ordering::Acquire) {
+            self.apply_import();
+        }
+
+        let freeze_param = self.settings.freeze;
+        let amount_param = self.settings.freeze_amount >= FREEZE_AMOUNT_THRESHOLD;
+        if freeze_param != self.last_freeze_param || amount_param != self.last_amount_param {
+            self.last_freeze_param = freeze_param;
+            self.last_amount_param = amount_param;
+            self.latched_freezing = false;
+            self.transport_freezing = false;
+        }
+
+        if transport.playing != self.last_playing {
+            self.last_playing = transport.playing;
+            // A manual freeze that's already active takes precedence, so releasing it by hand
+            // isn't overridden by the stopped transport
+            self.transport_freezing = !transport.playing
+                && self.settings.freeze_on_stop
+                && !self.freeze_requested();
+        }
+
+        // Freezes and releases are shared at block boundaries, so linked instances follow each
+        // other within a block
+        if self.freeze_link.set_group(self.settings.link_group) {
+            // Whatever the old group requested no longer applies
+            self.link_freezing = false;
+        }
+        if let Some(frozen) = self.freeze_link.sync(self.own_freeze_requested()) {
+            self.link_freezing = frozen;
+        }
+
+        // Touching the Buffer Size parameter takes over from the tapped or loaded length again
+        if self.settings.buffer_size != self.buffer_size_override_param {
+            self.buffer_size_override = None;
+        }
+    }
+
+    /// Delay the input by the latency the wet path adds, and keep a mono input's channel around
+    /// before it's overwritten. The oversampling can be changed at any time, the host is told
+    /// about the new latency right away.
+    fn delay_dry_signal(&mut self, channels: &mut [&mut [f32]], context: &mut impl Host) {
+        let latency = self.latency_samples();
+        if latency != self.reported_latency {
+            context.set_latency_samples(latency);
+            self.reported_latency = latency;
+        }
+        for (channel, dry_delay) in channels.iter_mut().zip(&mut self.dry_delays) {
+            dry_delay.set_len(latency as usize);
+            dry_delay.process(channel);
+        }
+        if self.mono_input {
+            if let Some(first_channel) = channels.first() {
+                self.mono_dry[..first_channel.len()].copy_from_slice(first_channel);
+            }
+        }
+    }
+
+    /// Compute the controls for the sample at `sample_id` and move every envelope, glide and
+    /// freeze source on to the next sample. `channels` still hold the input here. Returns the
+    /// boundary the segment ends at after this sample, if any.
+    fn next_sample_controls(
+        &mut self,
+        block: &BlockControls,
+        channels: &[&mut [f32]],
+        sidechain: Option<&[&mut [f32]]>,
+        sample_id: usize,
+        context: &mut impl Host,
+    ) -> (SampleControls, Option<SegmentBoundary>) {
+        let length = self.next_loop_length(block.glide_coefficient, block.tempo);
+        let length = self.scale_loop_length(length, block.whole_loop_len);
+        let hitch = if self.freeze_engaged {
+            self.hitch.next_rate(block.hitch_chance, block.hitch_depth, self.sample_rate)
+        } else {
+            self.hitch.cancel();
+            1.
+        };
+        let half_time = if self.half_time { 0.5 } else { 1. };
+        let rate = self.next_playback_rate(block.glide_coefficient)
+            * self.next_stop_rate(block.stop_step)
+            * hitch
+            * half_time
+            * self.sequence_rate
+            * self.next_vibrato_rate(block.vibrato_step);
+        // Moving the window is smoothed so the loop scrubs to its new position
+        let window_start = self.ramps.loop_start[sample_id];
+        let window_len = self.ramps.loop_length[sample_id];
+        let stretch = self.ramps.stretch[sample_id];
+        let hold = self.ramps.hold_amount[sample_id];
+        let tilt = self.ramps.tilt[sample_id];
+        let (left_gain, right_gain, volume) = self.expression_gains();
+        let sidechain_level = sidechain.map_or(0., |channels| {
+            channels
+                .iter()
+                .zip(&mut self.sidechain_filters)
+                .zip(&mut self.detector_signal)
+                .filter_map(|((channel, filter), signal)| {
+                    let filtered = filter.process(
+                        *channel.get(sample_id)?,
+                        block.highpass_coefficient,
+                        block.lowpass_coefficient,
+                    );
+                    signal[sample_id] = filtered;
+                    Some(filtered)
+                })
+                .fold(0., |level: f32, sample| level.max(sample.abs()))
+        });
+        self.update_sidechain_freezing(sidechain_level);
+        let freeze_amount = self.ramps.freeze_amount[sample_id];
+        self.amount_freezing = freeze_amount >= FREEZE_AMOUNT_THRESHOLD;
+        self.wet_target = if self.full_freeze_requested() { 1. } else { freeze_amount };
+        // A mono input only arrives on the first channel
+        let input_channels = if self.mono_input { channels.len().min(1) } else { channels.len() };
+        let inputs = &channels[..input_channels];
+        let input_level =
+            inputs.iter().fold(0., |level: f32, channel| level.max(channel[sample_id].abs()));
+        if !self.freeze_engaged || self.refresh_remaining.is_some() {
+            let mean_square = inputs
+                .iter()
+                .map(|channel| channel[sample_id] * channel[sample_id])
+                .sum::<f32>()
+                / input_channels.max(1) as f32;
+            self.auto_gain.measure_input(mean_square);
+        }
+        // Engaging the freeze sanitizes the buffers before this sample is played, so the
+        // segment ends after it
+        let mut engaged = self.update_freeze_engaged(block.quantize_grid, sample_id);
+        if !self.freeze_engaged {
+            self.end_pitch_output(sample_id as u32, context);
+        }
+        let voice_level = self.next_voice_level(block, sample_id as u32, context);
+        let wet = self.next_wet_gain(
+            block.attack_step,
+            block.decay_step,
+            block.release_step,
+            voice_level,
+        );
+        // Starting a refresh swaps the buffers before this sample is played, and finishing one
+        // freezes the new loop like engaging the freeze does
+        let was_refreshing = self.refresh_remaining.is_some();
+        self.update_refresh(input_level, block.whole_loop_len, !engaged);
+        let refreshing = self.refresh_remaining.is_some();
+        let refresh_started = refreshing && !was_refreshing;
+        engaged |= was_refreshing && !refreshing && self.freeze_engaged;
+        let crossfade = self.crossfade;
+        self.crossfade = (self.crossfade + block.crossfade_step).min(1.);
+        let engine_crossfade = self.engine_crossfade;
+        self.engine_crossfade = (self.engine_crossfade + block.engine_crossfade_step).min(1.);
+        let loop_len = length.unwrap_or(block.whole_loop_len * window_len);
+        let (pass_len, speed) = self.engines.current().pass(loop_len, rate, stretch);
+        self.advance_repeats(pass_len, speed);
+        let trigger_repeats = self.settings.trigger_repeats as f64;
+        if self.freeze_engaged && self.repeats - self.trigger_start >= trigger_repeats {
+            self.trigger_freezing = false;
+        }
+        self.rotation = (self.rotation + block.rotate_step).min(self.rotation_target);
+        // The engaging sample is still played at the previous freeze's level, the new
+        // compensation is measured once the buffers are sanitized
+        let auto_gain = if engaged {
+            1.
+        } else {
+            self.auto_gain.next_gain(block.auto_gain_enabled)
+                * self.normalizer.next_gain(block.normalize_enabled)
+        };
+        let level = self.decay_gain(pass_len) * auto_gain;
+
+        let controls = SampleControls {
+            length,
+            rate,
+            stretch,
+            hold,
+            reversed: self.reversed_freeze,
+            window_start,
+            window_len,
+            gains: [left_gain * level, right_gain * level, volume * level],
+            wet,
+            crossfade,
+            refreshing,
+            engine_crossfade,
+            rotation: self.rotation,
+            position: self.sample_position + sample_id as u64,
+            spread: block.spread,
+            crackle: block.crackle,
+            crush_step: block.crush_step,
+            tilt,
+        };
+        let boundary = if refresh_started {
+            Some(SegmentBoundary::RefreshStarted)
+        } else if engaged {
+            Some(SegmentBoundary::Engaged)
+        } else {
+            None
+        };
+
+        (controls, boundary)
+    }
+
+    /// Run the channels through their buffers for a segment whose sample controls have been
+    /// computed. When the segment ends at a boundary, its last sample is played after the
+    /// buffers were swapped or sanitized.
+    fn process_segment(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        mut wet_output: Option<&mut [&mut [f32]]>,
+        segment: Range<usize>,
+        boundary: Option<SegmentBoundary>,
+        mut levels: Option<&mut [BlockLevels; 2]>,
+    ) {
+        let split_at = if boundary.is_some() { segment.end - 1 } else { segment.end };
+        self.process_channels(
+            channels,
+            wet_output.as_deref_mut(),
+            segment.start..split_at,
+            levels.as_deref_mut(),
+        );
+        match boundary {
+            // The old loop keeps playing from the fade buffers, so the refresh records over
+            // whatever they held before. Those may still have an older buffer size.
+            Some(SegmentBoundary::RefreshStarted) => {
+                self.undo.capture(&self.channel_buffers);
+                std::mem::swap(&mut self.channel_buffers, &mut self.fade_buffers);
+                self.applied_buffer_size = None;
+            },
+            Some(SegmentBoundary::Engaged) => {
+                for mut buffer in self.channel_buffers.iter_mut() {
+                    buffer.sanitize();
+                }
+                let loop_level =
+                    self.channel_buffers.iter().map(|buffer| buffer.mean_square()).sum::<f32>()
+                        / self.channel_buffers.len().max(1) as f32;
+                self.auto_gain.engage(loop_level);
+                self.normalizer.engage(loop_level);
+                self.normalizer.set_target(self.settings.normalize_target);
+            },
+            None => return,
+        }
+        self.process_channels(channels, wet_output, split_at..segment.end, levels);
+    }
+
+    /// Route the wet signals to where Channel Rotate moves them, and replace the output with
+    /// the sidechain while Sidechain Listen is on. A mono sidechain is heard on every channel,
+    /// and a missing one as silence.
+    fn route_wet_signals(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        sidechain: Option<&[&mut [f32]]>,
+        wet_output: Option<&mut [&mut [f32]]>,
+        num_samples: usize,
+        levels: Option<&mut [BlockLevels; 2]>,
+    ) {
+        if self.rotate_wet {
+            self.rotate_wet_signals(channels, wet_output, num_samples, levels);
+        }
+
+        if self.settings.sidechain_listen {
+            let num_signals = sidechain.map_or(0, |channels| channels.len());
+            let signals = &self.detector_signal[..num_signals.min(self.detector_signal.len())];
+            for (i, channel) in channels.iter_mut().enumerate() {
+                match signals.get(i).or(signals.last()) {
+                    Some(signal) => channel.copy_from_slice(&signal[..num_samples]),
+                    None => channel.fill(0.),
+                }
+            }
+        }
+    }
+
+    /// Everything that happens once the block has been played: the loops are worn down, the
+    /// displays and the state are updated, and buffer size changes are applied
+    fn finish_block(&mut self, num_samples: usize, context: &mut impl Host) {
+        // These change the frozen loops a bit with every block. After the release and during a
+        // refresh the buffers record the input again.
+        if self.freeze_engaged && self.refresh_remaining.is_none() {
+            let smear = self.settings.smear;
+            if smear > 0. {
+                self.engines.current().smear(&mut self.channel_buffers, smear, num_samples);
+            }
+
+            // A mono input records the same audio into both channels, so cross feeding would
+            // only change the loops' levels
+            let cross_feed = self.settings.cross_feed;
+            if cross_feed > 0. && !self.mono_input {
+                // At 100% the loops swap sides on every pass
+                let angle = cross_feed * std::f32::consts::FRAC_PI_2;
+                self.channel_buffers.cross_feed(angle, num_samples);
+            }
+        }
+
+        self.sample_position += num_samples as u64;
+        self.buffer_dump.send_chunks(context);
+        if self.settings.metering || self.shared.waveform.is_enabled() {
+            self.shared.waveform.publish(&self.channel_buffers, self.freeze_engaged);
+        }
+
+        // Once the loop is released, the samples a window resize kept are cleared like a
+        // destructive resize would have, so they don't come back when the buffer grows
+        let playing = self.is_playing_buffer();
+        if self.window_resized && !playing {
+            if let Some(size) = self.applied_buffer_size {
+                for mut channel_buffer in self.channel_buffers.iter_mut() {
+                    channel_buffer.resize(size);
+                }
+            }
+            self.window_resized = false;
+        }
+
+        // Buffer size changes are applied here at the end of the block, and only when the size
+        // actually changed. Loaded audio always resizes the buffers, changes of the parameter
+        // follow Resize While Frozen while the loop is held.
+        let buffer_size = self.buffer_size();
+        if self.applied_buffer_size != Some(buffer_size) {
+            let policy = if playing && self.applied_buffer_size.is_some() {
+                self.settings.resize_while_frozen
+            } else {
+                ResizeWhileFrozen::Destructive
+            };
+            self.resize_buffers(buffer_size, policy);
+        }
+
+        if self.state_dirty {
+            self.save_buffer_state();
+        }
+    }
+
+    fn resize_buffers(&mut self, buffer_size: usize, policy: ResizeWhileFrozen) {
+        match policy {
+            // The size is applied by a later block once nothing is played anymore
+            ResizeWhileFrozen::Defer => (),
+            ResizeWhileFrozen::Window => {
+                for mut channel_buffer in self.channel_buffers.iter_mut() {
+                    channel_buffer.resize_window(buffer_size);
+                }
+                self.applied_buffer_size = Some(buffer_size);
+                self.window_resized = true;
+            }
+            ResizeWhileFrozen::Destructive => {
+                for mut channel_buffer in self.channel_buffers.iter_mut() {
+                    channel_buffer.resize(buffer_size);
+                }
+                self.applied_buffer_size = Some(buffer_size);
+                self.window_resized = false;
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event, context: &mut impl Host) {
+        match event {
+            Event::NoteOn { note, .. }
+                if matches!(self.snapshot_key(note), Some(SnapshotKey::Store)) =>
+            {
+                // The host may be saving the bank right now, in which case the store is skipped
+                if let Ok(mut snapshot_bank) = self.shared.snapshot_bank.try_write() {
+                    snapshot_bank.store(&self.channel_buffers);
+                }
+            },
+            Event::NoteOn { note, .. } if self.snapshot_key(note).is_some() => {
+                if let Some(SnapshotKey::Recall(slot)) = self.snapshot_key(note) {
+                    self.recall_snapshot(slot);
+                }
+            },
+            Event::NoteOn { note, .. } if self.size_key(note).is_some() => {
+                if let Some(step) = self.size_key(note) {
+                    self.step_loop_size(step);
+                }
+            },
+            Event::NoteOn { timing, note, .. }
+                if self.settings.tap_tempo && note as i32 == self.settings.tap_note =>
+            {
+                self.tap(timing);
+            },
+            Event::NoteOn { timing, note, velocity, voice_id, channel }
+                if self.settings.midi_trigger =>
+            {
+                // Retriggering a note that's still sounding replaces its voice, and its envelope
+                // starts over from the current level. New notes may need to steal a voice.
+                let level = if (self.held_notes | self.releasing_notes) & (1 << note) != 0 {
+                    self.releasing_notes &= !(1 << note);
+                    self.end_voice(timing, note, context);
+                    self.note_voices[note as usize].level
+                } else {
+                    let max_voices = self.settings.voice_count as u32 - 1;
+                    self.steal_voices(timing, max_voices, context);
+                    0.
+                };
+                if !self.note_freezing {
+                    let velocity = self.curve_velocity(velocity);
+                    self.division_offset = self.velocity_division_steps(velocity);
+                } else if self.settings.retrigger {
+                    self.retrigger_pending = true;
+                }
+                self.decaying = false;
+                self.held_notes |= 1 << note;
+                self.active_note = Some(note);
+                self.note_freezing = true;
+                self.note_voices[note as usize] = NoteVoice {
+                    voice_pan: self.next_voice_pan(note),
+                    level,
+                    ..NoteVoice::new(
+                        voice_id.unwrap_or_else(|| fallback_voice_id(note, channel)),
+                        channel,
+                    )
+                };
+                self.note_voices[note as usize].started = self.sample_position + timing as u64;
+            },
+            Event::NoteOn { .. } => (),
+            Event::NoteOff { note, .. } => {
+                self.release_note(note);
+            },
+            Event::BufferSizeModulation { voice_id, normalized_offset, .. } => {
+                if let Some(voice) = self.held_voice_mut(|_, voice| voice.voice_id == voice_id) {
+                    voice.buffer_size_offset = normalized_offset;
+                }
+            },
+            // Note expressions for notes that aren't held anymore are simply ignored
+            Event::PolyTuning { voice_id, note, tuning, .. } => {
+                if let Some(voice) = self.held_voice_mut(expression_target(voice_id, note)) {
+                    voice.tuning = tuning;
+                }
+            },
+            Event::PolyVolume { voice_id, note, gain, .. } => {
+                if let Some(voice) = self.held_voice_mut(expression_target(voice_id, note)) {
+                    voice.gain = gain;
+                }
+            },
+            Event::PolyPan { voice_id, note, pan, .. } => {
+                if let Some(voice) = self.held_voice_mut(expression_target(voice_id, note)) {
+                    voice.pan = pan;
+                }
+            },
+            Event::SysEx { message: SysEx::DumpRequest, .. } => {
+                self.buffer_dump.start(&self.channel_buffers);
+            },
+            Event::SysEx { message: message @ SysEx::SampleData { .. }, .. } => {
+                self.buffer_load.receive(&message);
+            },
+        }
+    }
+
+    /// Run the channels in `range` through their buffers using the sample controls computed for
+    /// that range. Stretches of samples that are only recorded are written to the buffers in a
+    /// single copy, everything else is processed sample by sample. Channels without a buffer
+    /// are passed through.
+    fn process_channels(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        mut wet_output: Option<&mut [&mut [f32]]>,
+        range: Range<usize>,
+        mut levels: Option<&mut [BlockLevels; 2]>,
+    ) {
+        #[cfg(test)]
+        if self.per_sample_reference {
+            return self.process_channels_per_sample(channels, wet_output, range, levels);
+        }
+
+        let controls = &self.sample_controls[range.clone()];
+        let interpolation = self.settings.interpolation;
+        let oversampling = self.settings.oversampling;
+        let formant = self.settings.formant;
+        let soft_crackle = self.settings.soft_crackle;
+        let dither = self.settings.dither;
+        let noise_shaping = self.settings.noise_shaping;
+        let plays_directly = self.engines.plays_buffer_directly();
+        for (i, (((((channel, mut channel_buffer), mut fade_buffer), crackle), crusher), tilt)) in
+            channels
+                .iter_mut()
+                .zip(self.channel_buffers.iter_mut())
+                .zip(self.fade_buffers.iter_mut())
+                .zip(&mut self.crackles)
+                .zip(&mut self.crushers)
+                .zip(&mut self.tilts)
+                .enumerate()
+        {
+            let samples = &mut channel[range.clone()];
+            // With a mono input every channel records the first channel, the other channels
+            // don't receive any input. A single NaN from upstream would otherwise get frozen
+            // and never go away.
+            if self.mono_input {
+                for (sample, &dry) in samples.iter_mut().zip(&self.mono_dry[range.clone()]) {
+                    *sample = sanitize(dry);
+                }
+            } else {
+                samples.iter_mut().for_each(|sample| *sample = sanitize(*sample));
+            }
+            if let Some(levels) = levels.as_deref_mut() {
+                samples.iter().for_each(|&dry| levels[0].add(i, dry));
+            }
+
+            channel_buffer.set_interpolation(interpolation);
+            fade_buffer.set_interpolation(interpolation);
+            channel_buffer.set_oversampling(oversampling);
+            fade_buffer.set_oversampling(oversampling);
+            channel_buffer.set_formant_correction(formant);
+            fade_buffer.set_formant_correction(formant);
+
+            let loop_len = channel_buffer.loop_len();
+            let length = |controls: &SampleControls| controls.channel_length(i, loop_len);
+            let gain_index = i.min(2);
+            // The wet signals are routed to their channels later while they're rotated, and the
+            // output is metered then as well
+            let mut wet_channel = if self.rotate_wet {
+                self.wet_scratch.get_mut(i).map(|channel| &mut channel[..])
+            } else {
+                wet_output
+                    .as_deref_mut()
+                    .and_then(|channels| channels.get_mut(i))
+                    .map(|channel| &mut channel[..])
+            };
+            let mut output_levels = levels.as_deref_mut().filter(|_| !self.rotate_wet);
+            let run_kind = |controls: &SampleControls| {
+                if controls.is_recording() {
+                    RunKind::Record
+                } else if plays_directly
+                    && controls.plays_whole_loop()
+                    && length(controls).is_none()
+                {
+                    RunKind::Play
+                } else {
+                    RunKind::PerSample
+                }
+            };
+            let mut run_start = 0;
+            while run_start < controls.len() {
+                let kind = run_kind(&controls[run_start]);
+                let run_len = controls[run_start..]
+                    .iter()
+                    .position(|controls| run_kind(controls) != kind)
+                    .unwrap_or(controls.len() - run_start);
+                let mut run = run_start..run_start + run_len;
+                run_start = run.end;
+
+                // Only the last sample's settings matter for a buffer that's recording or
+                // playing the whole loop
+                let last = &controls[run.end - 1];
+                match kind {
+                    RunKind::Record => {
+                        channel_buffer.freezing = false;
+                        channel_buffer.set_length(length(last));
+                        channel_buffer.set_rate(last.rate);
+                        channel_buffer.set_stretch(last.stretch);
+                        channel_buffer.set_window(last.window_start, last.window_len);
+                        channel_buffer.set_reversed(last.reversed);
+                        channel_buffer.record(&samples[run.clone()]);
+                    },
+                    RunKind::Play => {
+                        channel_buffer.freezing = true;
+                        channel_buffer.set_length(None);
+                        channel_buffer.set_rate(last.rate);
+                        channel_buffer.set_stretch(last.stretch);
+                        channel_buffer.set_window(last.window_start, last.window_len);
+                        channel_buffer.set_reversed(last.reversed);
+                        channel_buffer.set_engine_region(None);
+                        channel_buffer.play(&mut samples[run.clone()]);
+                    },
+                    RunKind::PerSample => self.engines.process_block(
+                        i,
+                        &mut channel_buffer,
+                        &mut fade_buffer,
+                        &controls[run.clone()],
+                        &samples[run.clone()],
+                        &mut self.frozen[run.clone()],
+                    ),
+                }
+
+                // Recorded and played runs are mixed in vectorized chunks, the rest of the run
+                // goes through the scalar loop below
+                if kind != RunKind::PerSample {
+                    let block_run = range.start + run.start..range.start + run.end;
+                    let wet_run =
+                        wet_channel.as_deref_mut().map(|channel| channel.get_mut(block_run));
+                    let mixed = match wet_run {
+                        Some(None) => 0,
+                        wet_run => mix_chunks(
+                            &mut samples[run.clone()],
+                            &controls[run.clone()],
+                            gain_index,
+                            wet_run.flatten(),
+                        ),
+                    };
+                    if let Some(levels) = output_levels.as_deref_mut() {
+                        samples[run.start..run.start + mixed]
+                            .iter()
+                            .for_each(|&sample| levels[1].add(i, sample));
+                    }
+                    run.start += mixed;
+                }
+
+                for (sample_id, (sample, controls)) in
+                    run.clone().zip(samples[run.clone()].iter_mut().zip(&controls[run]))
+                {
+                    // Recorded samples are played back as they are, and played runs already hold
+                    // the loop's samples. Everything else was played by the engines above.
+                    let dry = *sample;
+                    let mut frozen = if kind != RunKind::PerSample {
+                        dry
+                    } else {
+                        self.frozen[sample_id]
+                    };
+                    // Without any crackle the random number generator isn't even touched
+                    if controls.crackle > 0. && controls.wet > 0. {
+                        frozen += crackle.next(controls.crackle, soft_crackle);
+                    }
+                    // Neither the crusher nor its dither touch the signal while it's bypassed
+                    if controls.crush_step > 0. && controls.wet > 0. {
+                        frozen =
+                            crusher.process(frozen, controls.crush_step, dither, noise_shaping);
+                    }
+                    // The tilt shapes everything above, and is bypassed completely at 0 dB
+                    if controls.tilt != 0. && controls.wet > 0. {
+                        frozen = tilt.process(frozen, controls.tilt);
+                    }
+                    let (mixed, wet) = mix_sample(dry, frozen, controls, gain_index);
+                    *sample = mixed;
+                    if let Some(levels) = output_levels.as_deref_mut() {
+                        levels[1].add(i, *sample);
+                    }
+
+                    // The wet output is silent while nothing is frozen
+                    if let Some(wet_sample) = wet_channel
+                        .as_deref_mut()
+                        .and_then(|channel| channel.get_mut(range.start + sample_id))
+                    {
+                        *wet_sample = wet;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The same as `process_channels()`, but one sample at a time through every channel and
+    /// only ever through the engines, without recorded or played runs and without the chunked
+    /// mix
+    #[cfg(test)]
+    fn process_channels_per_sample(
+        &mut self,
+        channels: &mut [&mut [f32]],
+        mut wet_output: Option<&mut [&mut [f32]]>,
+        range: Range<usize>,
+        mut levels: Option<&mut [BlockLevels; 2]>,
+    ) {
+        let interpolation = self.settings.interpolation;
+        let oversampling = self.settings.oversampling;
+        let formant = self.settings.formant;
+        let soft_crackle = self.settings.soft_crackle;
+        let dither = self.settings.dither;
+        let noise_shaping = self.settings.noise_shaping;
+        for sample_id in range.clone() {
+            let controls = &self.sample_controls[sample_id];
+            let effects = self
+                .crackles
+                .iter_mut()
+                .zip(&mut self.crushers)
+                .zip(&mut self.tilts);
+            let buffers = self.channel_buffers.iter_mut().zip(self.fade_buffers.iter_mut());
+            for (i, ((channel, buffers), ((crackle, crusher), tilt))) in
+                channels.iter_mut().zip(buffers).zip(effects).enumerate()
+            {
+                let (mut channel_buffer, mut fade_buffer) = buffers;
+                let sample = &mut channel[sample_id];
+                let input = if self.mono_input { self.mono_dry[sample_id] } else { *sample };
+                *sample = sanitize(input);
+                let dry = *sample;
+                if let Some(levels) = levels.as_deref_mut() {
+                    levels[0].add(i, dry);
+                }
+
+                channel_buffer.set_interpolation(interpolation);
+                fade_buffer.set_interpolation(interpolation);
+                channel_buffer.set_oversampling(oversampling);
+                fade_buffer.set_oversampling(oversampling);
+                channel_buffer.set_formant_correction(formant);
+                fade_buffer.set_formant_correction(formant);
+                self.engines.process_block(
+                    i,
+                    &mut channel_buffer,
+                    &mut fade_buffer,
+                    std::slice::from_ref(controls),
+                    &[dry],
+                    &mut self.frozen[sample_id..sample_id + 1],
+                );
+
+                let mut frozen = self.frozen[sample_id];
+                if controls.crackle > 0. && controls.wet > 0. {
+                    frozen += crackle.next(controls.crackle, soft_crackle);
+                }
+                if controls.crush_step > 0. && controls.wet > 0. {
+                    frozen = crusher.process(frozen, controls.crush_step, dither, noise_shaping);
+                }
+                if controls.tilt != 0. && controls.wet > 0. {
+                    frozen = tilt.process(frozen, controls.tilt);
+                }
+                let (mixed, wet) = mix_sample(dry, frozen, controls, i.min(2));
+                *sample = mixed;
+                if let Some(levels) = levels.as_deref_mut().filter(|_| !self.rotate_wet) {
+                    levels[1].add(i, *sample);
+                }
+
+                let wet_channel = if self.rotate_wet {
+                    self.wet_scratch.get_mut(i).map(|channel| &mut channel[..])
+                } else {
+                    wet_output
+                        .as_deref_mut()
+                        .and_then(|channels| channels.get_mut(i))
+                        .map(|channel| &mut channel[..])
+                };
+                if let Some(wet_sample) =
+                    wet_channel.and_then(|channel| channel.get_mut(sample_id))
+                {
+                    *wet_sample = wet;
+                }
+            }
+        }
+    }
+
+    /// Route every channel's wet signal from `wet_scratch` to the channel Channel Rotate moves
+    /// it to, see `SampleControls::rotation`. The channels already hold the dry and wet signals
+    /// mixed on their own channel, so only the difference is added.
+    fn rotate_wet_signals(
+        &self,
+        channels: &mut [&mut [f32]],
+        mut wet_output: Option<&mut [&mut [f32]]>,
+        num_samples: usize,
+        mut levels: Option<&mut [BlockLevels; 2]>,
+    ) {
+        let num_channels = channels.len().min(self.wet_scratch.len());
+        for (sample_id, controls) in self.sample_controls[..num_samples].iter().enumerate() {
+            let offset = controls.rotation as usize;
+            let fade = controls.rotation.fract();
+            for (i, channel) in channels[..num_channels].iter_mut().enumerate() {
+                let from = self.wet_scratch[(i + offset) % num_channels][sample_id];
+                let to = self.wet_scratch[(i + offset + 1) % num_channels][sample_id];
+                let wet = from + (to - from) * fade;
+                channel[sample_id] += wet - self.wet_scratch[i][sample_id];
+                if let Some(levels) = levels.as_deref_mut() {
+                    levels[1].add(i, channel[sample_id]);
+                }
+
+                if let Some(wet_sample) = wet_output
+                    .as_deref_mut()
+                    .and_then(|channels| channels.get_mut(i))
+                    .and_then(|channel| channel.get_mut(sample_id))
+                {
+                    *wet_sample = wet;
+                }
+            }
+        }
+    }
+
+    /// Copy the loops and write them to a WAV file on a background thread. This is skipped if
+    /// the previous export is still being written.
+    fn start_export(&mut self, context: &mut impl Host) {
+        let Ok(mut export_buffer) = self.shared.export_buffer.try_lock() else {
+            return;
+        };
+
+        export_buffer.capture(&self.channel_buffers, self.sample_rate);
+        drop(export_buffer);
+        context.execute_background(Task::ExportWav);
+    }
+
+    /// Copy an imported WAV file into the buffers and freeze it. This is retried in the next
+    /// block if the background task is still holding on to the import.
+    fn apply_import(&mut self) {
+        let Ok(import_buffer) = self.shared.import_buffer.try_lock() else {
+            return;
+        };
+        self.shared.import_ready.store(false, Ordering::Relaxed);
+
+        // Resampling would allocate, imports from before a sample rate change are dropped
+        if import_buffer.sample_rate != self.sample_rate {
+            return;
+        }
+        if let Some(len) = import_buffer.restore(&mut self.channel_buffers, self.sample_rate) {
+            self.buffer_size_override = Some((len + 1).max(MIN_BUFFER_SIZE) as f32);
+            self.buffer_size_override_param = self.settings.buffer_size;
+            self.applied_buffer_size = None;
+            self.latched_freezing = true;
+            self.state_dirty = true;
+        }
+    }
+
+    /// Copy the frozen loops into the plugin's state. This is retried in the next block if the
+    /// host is reading the state right now.
+    fn save_buffer_state(&mut self) {
+        let Ok(mut state) = self.shared.buffer_state.try_write() else {
+            return;
+        };
+
+        state.frozen = self.freeze_engaged;
+        if self.freeze_engaged {
+            state.capture(&self.channel_buffers, self.sample_rate);
+        }
+        self.state_dirty = false;
+    }
+
+    /// Whether a note is the halve or the double key, as the number of doublings it adds
+    fn size_key(&self, note: u8) -> Option<i32> {
+        if !self.settings.size_keys {
+            return None;
+        }
+
+        if note as i32 == self.settings.halve_note {
+            Some(-1)
+        } else if note as i32 == self.settings.double_note {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    /// Halve or double the loop length from the size keys on the loop's next pass. Presses add
+    /// up, and the ones that would make the loop shorter than two samples or longer than the
+    /// recorded loop are ignored. The keys do nothing while nothing is frozen.
+    fn step_loop_size(&mut self, step: i32) {
+        if !self.freeze_engaged && self.wet_gain <= 0. {
+            return;
+        }
+
+        let steps = self.pending_size_steps + step;
+        let length = self.unscaled_loop_len * 2f32.powi(steps);
+        if (2. ..=self.max_loop_len).contains(&length) {
+            self.pending_size_steps = steps;
+        }
+    }
+
+    /// Apply the size keys to the loop length for the next sample. `whole_loop_len` is the length
+    /// that's played without a loop length.
+    fn scale_loop_length(&mut self, length: Option<f32>, whole_loop_len: f32) -> Option<f32> {
+        self.unscaled_loop_len = length.unwrap_or(whole_loop_len);
+        self.max_loop_len = whole_loop_len;
+        if self.size_steps == 0 {
+            return length;
+        }
+
+        let scaled = self.unscaled_loop_len * 2f32.powi(self.size_steps);
+        Some(scaled.clamp(2., whole_loop_len.max(2.)))
+    }
+
+    fn snapshot_key(&self, note: u8) -> Option<SnapshotKey> {
+        if !self.settings.snapshot_keys {
+            return None;
+        }
+
+        let recall_note = self.settings.recall_note;
+        if note as i32 == self.settings.store_note {
+            Some(SnapshotKey::Store)
+        } else if (recall_note..recall_note + NUM_SNAPSHOTS as i32).contains(&(note as i32)) {
+            Some(SnapshotKey::Recall((note as i32 - recall_note) as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Swap a snapshot into the buffers and freeze it, crossfading from the audio that was
+    /// playing before. Empty slots are ignored.
+    fn recall_snapshot(&mut self, slot: usize) {
+        let Ok(snapshot_bank) = self.shared.snapshot_bank.try_read() else {
+            return;
+        };
+        let Some(snapshot) = snapshot_bank.get(slot) else {
+            return;
+        };
+
+        // The old buffers keep playing from the fade buffers, so no audio needs to be copied
+        // besides the snapshot itself
+        std::mem::swap(&mut self.channel_buffers, &mut self.fade_buffers);
+        for (channel, mut channel_buffer) in self.channel_buffers.iter_mut().enumerate() {
+            channel_buffer.load(snapshot.channel(channel));
+        }
+
+        self.crossfade = 0.;
+        self.buffer_size_override = Some((snapshot.len() + 1).max(MIN_BUFFER_SIZE) as f32);
+        self.buffer_size_override_param = self.settings.buffer_size;
+        self.applied_buffer_size = None;
+        self.latched_freezing = true;
+        self.state_dirty = true;
+    }
+
+    /// Swap the loop that was frozen before the current capture back into the buffers and
+    /// freeze it, crossfading like a snapshot recall. A loop that's frozen right now takes its
+    /// place in the undo slot.
+    fn restore_last(&mut self) {
+        if self.undo.is_empty() {
+            return;
+        }
+
+        // During a refresh the loop that's playing is in the fade buffers already
+        if self.refresh_remaining.take().is_none() {
+            std::mem::swap(&mut self.channel_buffers, &mut self.fade_buffers);
+        }
+        for (channel, mut channel_buffer) in self.channel_buffers.iter_mut().enumerate() {
+            channel_buffer.load(self.undo.channel(channel));
+        }
+        let len = self.undo.len();
+        if self.freeze_engaged {
+            self.undo.capture(&self.fade_buffers);
+        }
+
+        self.crossfade = 0.;
+        self.buffer_size_override = Some((len + 1).max(MIN_BUFFER_SIZE) as f32);
+        self.buffer_size_override_param = self.settings.buffer_size;
+        self.applied_buffer_size = None;
+        self.latched_freezing = true;
+        self.state_dirty = true;
+    }
+
+    fn tap(&mut self, timing: u32) {
+        let position = self.sample_position + timing as u64;
+        let timeout = (TAP_TIMEOUT_SECONDS * self.sample_rate) as u64;
+        if let Some(interval) = self.tap_tempo.tap(position, timeout) {
+            self.buffer_size_override =
+                Some(interval.clamp(MIN_BUFFER_SIZE as f32, MAX_BUFFER_SIZE as f32));
+            self.buffer_size_override_param = self.settings.buffer_size;
+        }
+    }
+
+    fn release_note(&mut self, note: u8) {
+        if self.held_notes & (1 << note) == 0 {
+            return;
+        }
+
+        self.held_notes &= !(1 << note);
+        self.note_freezing = self.held_notes != 0;
+        if !self.note_freezing {
+            self.division_offset = 0;
+            self.retrigger_pending = false;
+            self.latched_freezing = false;
+            self.transport_freezing = false;
+        }
+        if self.active_note == Some(note) {
+            // Fall back to the highest note that is still being held
+            self.active_note = self.highest_held_note();
+        }
+
+        // The voice ends once its envelope has faded out
+        self.releasing_notes |= 1 << note;
+    }
+
+    /// Analyze the frozen loop a bit further, and send its pitch as a note once it's known.
+    /// Everything starts over with the next freeze.
+    fn update_pitch_output(&mut self, context: &mut impl Host) {
+        if !self.settings.pitch_output {
+            self.end_pitch_output(0, context);
+            return;
+        }
+        if !self.freeze_engaged {
+            return;
+        }
+
+        if self.pitch_detector.is_idle() {
+            let Some(buffer) = self.channel_buffers.first() else {
+                return;
+            };
+            self.pitch_detector.start(&buffer);
+        }
+        let Some(frequency) = self.pitch_detector.step() else {
+            return;
+        };
+        let note = freq_to_midi_note(frequency).round() as i32
+            + self.settings.pitch_output_transpose;
+        let note = note.clamp(0, 127) as u8;
+        context.send_event(OutputEvent::NoteOn { timing: 0, note, velocity: 1. });
+        self.pitch_output_note = Some(note);
+    }
+
+    /// Send the note off for Pitch to MIDI's note and stop analyzing the loop
+    fn end_pitch_output(&mut self, timing: u32, context: &mut impl Host) {
+        self.pitch_detector.reset();
+        let Some(note) = self.pitch_output_note.take() else {
+            return;
+        };
+        context.send_event(OutputEvent::NoteOff { timing, note });
+    }
+
+    /// Tell the host a note's voice has ended so it can free its per-voice modulators
+    fn end_voice(&self, timing: u32, note: u8, context: &mut impl Host) {
+        let voice = self.note_voices[note as usize];
+        context.send_event(OutputEvent::VoiceTerminated {
+            timing,
+            voice_id: voice.voice_id,
+            channel: voice.channel,
+            note,
+        });
+    }
+
+    /// Terminate the voices of the notes `reset()` dropped
+    fn end_dropped_voices(&mut self, context: &mut impl Host) {
+        let dropped_notes = std::mem::take(&mut self.dropped_notes);
+        for note in (0..128u8).filter(|&note| dropped_notes & (1 << note) != 0) {
+            self.end_voice(0, note, context);
+        }
+    }
+
+    /// End the oldest voices until at most `max_voices` are left, whether their notes are held
+    /// or already fading out. Stolen voices end right away instead of going through the release,
+    /// and the loop fades out quickly if it was the stolen voice that set its level.
+    fn steal_voices(&mut self, timing: u32, max_voices: u32, context: &mut impl Host) {
+        while (self.held_notes | self.releasing_notes).count_ones() > max_voices {
+            let sounding_notes = self.held_notes | self.releasing_notes;
+            let oldest = (0..128u8)
+                .filter(|&note| sounding_notes & (1 << note) != 0)
+                .min_by_key(|&note| self.note_voices[note as usize].started);
+            let Some(note) = oldest else {
+                break;
+            };
+            self.release_note(note);
+            self.releasing_notes &= !(1 << note);
+            self.stolen_level = self.stolen_level.max(self.note_voices[note as usize].level);
+            self.end_voice(timing, note, context);
+        }
+    }
+
+    fn release_all_notes(&mut self) {
+        while let Some(note) = self.highest_held_note() {
+            self.release_note(note);
+        }
+    }
+
+    fn held_voice_mut(
+        &mut self,
+        mut predicate: impl FnMut(u8, &NoteVoice) -> bool,
+    ) -> Option<&mut NoteVoice> {
+        let held_notes = self.held_notes;
+        self.note_voices
+            .iter_mut()
+            .enumerate()
+            .filter(|(note, _)| held_notes & (1 << note) != 0)
+            .find(|(note, voice)| predicate(*note as u8, voice))
+            .map(|(_, voice)| voice)
+    }
+
+    /// The gains for the left and right channels from the volume and pan expressions of the note
+    /// driving the freeze, combined with the voice's own pan, followed by the volume by itself.
+    /// Panning only applies to the first channel pair, mono outputs and any channels beyond the
+    /// first pair only get the volume.
+    fn expression_gains(&self) -> (f32, f32, f32) {
+        match self.active_note {
+            Some(note) if self.note_freezing => {
+                let voice = &self.note_voices[note as usize];
+                if self.channel_buffers.len() == 1 {
+                    return (voice.gain, voice.gain, voice.gain);
+                }
+
+                // Constant power panning, normalized so a centered voice keeps its level
+                let pan = (voice.voice_pan + voice.pan).clamp(-1., 1.);
+                let angle = (pan + 1.) * std::f32::consts::FRAC_PI_4;
+                let left = voice.gain * angle.cos() * std::f32::consts::SQRT_2;
+                let right = voice.gain * angle.sin() * std::f32::consts::SQRT_2;
+                (left, right, voice.gain)
+            }
+            _ => (1., 1., 1.),
+        }
+    }
+
+    /// The stereo position of a new voice for this note, from -1 for hard left to 1 for hard
+    /// right
+    fn next_voice_pan(&mut self, note: u8) -> f32 {
+        let depth = self.settings.key_pan;
+        if self.settings.random_pan {
+            (self.pan_rng.next_f32() * 2. - 1.) * depth
+        } else {
+            ((note as f32 - 63.5) / 63.5) * depth
+        }
+    }
+
+    /// The buffer size in samples including the polyphonic modulation of the note that is
+    /// currently driving the freeze, with the room the current engine needs on top of that
+    fn buffer_size(&self) -> usize {
+        self.engines.current().buffer_size(self.regular_buffer_size())
+    }
+
+    fn regular_buffer_size(&self) -> usize {
+        if let Some(buffer_size_override) = self.buffer_size_override {
+            return buffer_size_override as usize;
+        }
+
+        let samples = match self.active_note {
+            Some(note) if self.note_freezing => {
+                let offset = self.note_voices[note as usize].buffer_size_offset;
+                plain_buffer_size(self.settings.buffer_size_normalized + offset)
+            }
+            _ => self.settings.buffer_size,
+        };
+        ((samples as f64 * self.size_scale) as usize).clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE)
+    }
+
+    /// Follow the Size Unit parameter. Lengths in the sync unit follow the tempo from when the
+    /// unit was selected. Switching to another unit after the tempo changed keeps the current
+    /// length as an override until Buffer Size is touched, so the loop doesn't jump.
+    fn update_size_unit(&mut self, tempo: f64) {
+        let unit = self.settings.size_unit;
+        if unit != self.size_unit {
+            if unit == SizeUnit::Sync {
+                self.sync_tempo = tempo;
+            } else if self.size_scale != 1. && self.buffer_size_override.is_none() {
+                self.buffer_size_override = Some(self.regular_buffer_size() as f32);
+                self.buffer_size_override_param = self.settings.buffer_size;
+            }
+            self.size_unit = unit;
+        }
+
+        self.size_scale = if unit == SizeUnit::Sync {
+            self.sync_tempo / tempo
+        } else {
+            1.
+        };
+        self.shared.size_display.set(unit, self.sample_rate, self.sync_tempo);
+    }
+
+    /// Whether the Freeze or the Freeze Amount parameter holds the freeze
+    fn freeze_param_held(&self) -> bool {
+        self.settings.freeze
+            || self.settings.freeze_amount >= FREEZE_AMOUNT_THRESHOLD
+    }
+
+    fn freeze_requested(&self) -> bool {
+        self.own_freeze_requested() || self.link_freezing
+    }
+
+    /// Whether something in this instance rather than its link group requests a freeze
+    fn own_freeze_requested(&self) -> bool {
+        self.amount_freezing || self.own_full_freeze_requested()
+    }
+
+    /// Whether something other than Freeze Amount requests a freeze, which is then heard at
+    /// its full level
+    fn full_freeze_requested(&self) -> bool {
+        self.own_full_freeze_requested() || self.link_freezing
+    }
+
+    fn own_full_freeze_requested(&self) -> bool {
+        self.note_freezing || self.own_non_note_freeze_requested()
+    }
+
+    /// Whether something in this instance other than the notes and Freeze Amount requests a
+    /// freeze
+    fn own_non_note_freeze_requested(&self) -> bool {
+        self.settings.freeze
+            || self.latched_freezing
+            || self.transport_freezing
+            || self.trigger_freezing
+            || self.offline_freezing
+            || self.sidechain_freezing
+    }
+
+    /// The grid the freeze snaps to in this block, or `None` if the freeze should engage
+    /// immediately because quantizing is disabled or the transport isn't playing
+    fn quantize_grid(&self, transport: &TransportInfo, tempo: f64) -> Option<QuantizeGrid> {
+        let pos_beats = transport.pos_beats.filter(|_| transport.playing)?;
+        let bar_beats = match (transport.time_sig_numerator, transport.time_sig_denominator) {
+            (Some(numerator), Some(denominator)) => numerator as f64 * 4. / denominator as f64,
+            _ => 4.,
+        };
+        let grid_beats = self.settings.quantize_trigger.beats(bar_beats)?;
+        let bar_start = transport.bar_start_pos_beats.unwrap_or(0.);
+
+        Some(QuantizeGrid {
+            start: (pos_beats - bar_start) / grid_beats,
+            step: tempo / 60. / self.sample_rate as f64 / grid_beats,
+        })
+    }
+
+    fn update_sidechain_freezing(&mut self, level: f32) {
+        let envelope = self.sidechain_detector.next_level(level);
+        self.sidechain_freezing = self.settings.sidechain_trigger
+            && envelope >= self.settings.sidechain_threshold;
+    }
+
+    /// Start, count down or finish the refresh for a sample with the input level `level`. A
+    /// refresh records `loop_len` samples while the freeze is engaged and the attack is done,
+    /// either when the input gets loud enough or when Retrigger captures a new note's loop.
+    /// When the freeze is released during a refresh, the refresh keeps recording until the old
+    /// loop has faded out.
+    fn update_refresh(&mut self, level: f32, loop_len: f32, can_start: bool) {
+        let envelope = self.refresh_detector.next_level(level);
+        match self.refresh_remaining {
+            Some(_) if !self.freeze_engaged && self.wet_gain <= 0. => {
+                self.refresh_remaining = None;
+                self.crossfade = 1.;
+            },
+            Some(remaining) if self.freeze_engaged && remaining <= 1 => {
+                // The new loop fades in from here on, and the chunks are found in it again
+                self.refresh_remaining = None;
+                self.crossfade = 0.;
+                self.engines.set_freeze(true);
+            },
+            Some(remaining) => {
+                self.refresh_remaining = Some(remaining.saturating_sub(1));
+                self.crossfade = 0.;
+            },
+            None => {
+                let triggered = self.retrigger_pending
+                    || (self.settings.refresh
+                        && envelope >= self.settings.refresh_threshold);
+                let starts = can_start
+                    && triggered
+                    && self.freeze_engaged
+                    && self.decaying
+                    && self.wet_gain <= self.sustain_level()
+                    && self.crossfade >= 1.
+                    && loop_len >= 1.;
+                if starts {
+                    self.retrigger_pending = false;
+                    self.refresh_remaining = Some(loop_len as u32);
+                    self.crossfade = 0.;
+                }
+            },
+        }
+    }
+
+    /// Engage or release the freeze when requested, waiting for the next grid line if needed.
+    /// Returns whether the freeze just engaged, in which case the buffers need to be sanitized
+    /// before the sample is played.
+    fn update_freeze_engaged(
+        &mut self,
+        quantize_grid: Option<QuantizeGrid>,
+        sample_id: usize,
+    ) -> bool {
+        let requested = self.freeze_requested();
+        if requested == self.freeze_engaged {
+            return false;
+        }
+
+        let quantized = requested || self.settings.quantize_release;
+        match quantize_grid {
+            Some(grid) if quantized && !grid.is_grid_line(sample_id) => false,
+            _ => {
+                self.freeze_engaged = requested;
+                // Engines without smoothing cut off right away
+                let stop_time = self.settings.stop_time;
+                let stops = self.engines.current().fade_time(stop_time) > 0.;
+                self.stop_progress = (!requested && stops).then_some(0.);
+                if requested {
+                    self.reversed_freeze = self.settings.capture_reversed;
+                    self.decaying = false;
+                    self.repeats = 0.;
+                    self.vibrato_phase = 0.;
+                    self.sequence_step = 0;
+                    self.sequence_rate = self.sequence_step_rate(0);
+                } else {
+                    // The buffers record over the loop once it has faded out. During a refresh
+                    // it plays from the fade buffers.
+                    let frozen_buffers = if self.refresh_remaining.is_some() {
+                        &self.fade_buffers
+                    } else {
+                        &self.channel_buffers
+                    };
+                    self.undo.capture(frozen_buffers);
+                }
+                self.engines.set_freeze(requested);
+                self.shared.frozen.store(requested, Ordering::Relaxed);
+                self.state_dirty = true;
+                requested
+            },
+        }
+    }
+
+    /// Whether the output currently comes from the stored audio rather than just the input. Every
+    /// freeze source ends up in `freeze_requested()`, and the release and recall fades are covered
+    /// here as well.
+    fn is_playing_buffer(&self) -> bool {
+        self.freeze_requested()
+            || self.freeze_engaged
+            || self.wet_gain > 0.
+            || self.crossfade < 1.
+            || self.releasing_notes != 0
+    }
+
+    /// How much the wet gain changes per sample for the attack or release time `ms`
+    fn fade_step(&self, ms: f32) -> f32 {
+        let fade_samples = self.engines.current().fade_time(ms) / 1000. * self.sample_rate;
+        if fade_samples < 1. {
+            1.
+        } else {
+            1. / fade_samples
+        }
+    }
+
+    /// How far the stop after the release progresses per sample
+    fn stop_step(&self) -> f32 {
+        let stop_samples = self.settings.stop_time / 1000. * self.sample_rate;
+        if stop_samples < 1. {
+            1.
+        } else {
+            1. / stop_samples
+        }
+    }
+
+    /// The factor for the playback rate while the loop stops after the release. Once the rate
+    /// has reached zero the loop fades out over the release time, the same for every curve.
+    fn next_stop_rate(&mut self, stop_step: f32) -> f32 {
+        let Some(progress) = self.stop_progress else {
+            return 1.;
+        };
+
+        let progress = (progress + stop_step).min(1.);
+        self.stop_progress = Some(progress);
+        self.settings.stop_curve.rate(progress)
+    }
+
+    /// The vibrato's factor for the playback rate, which moves the pitch up and down along a
+    /// sine. This is exactly 1 at zero depth and while nothing is frozen.
+    fn next_vibrato_rate(&mut self, step: f32) -> f32 {
+        let depth_cents = self.settings.vibrato_depth;
+        if depth_cents <= 0. || (!self.freeze_engaged && self.wet_gain <= 0.) {
+            return 1.;
+        }
+
+        let phase = self.vibrato_phase;
+        self.vibrato_phase = (phase + step).fract();
+        2f32.powf(depth_cents / 1200. * (std::f32::consts::TAU * phase).sin())
+    }
+
+    /// Move the envelopes of the voices that are sounding along by a sample and end the voices
+    /// that have faded out. Returns the loudest voice's level including the stolen voices' fade,
+    /// or `None` without any voices. Held
+    /// notes wait for a quantized freeze to engage, and released notes wait for a quantized
+    /// release and for the stop like the freeze's envelope does.
+    fn next_voice_level(
+        &mut self,
+        block: &BlockControls,
+        timing: u32,
+        context: &mut impl Host,
+    ) -> Option<f32> {
+        let sounding_notes = self.held_notes | self.releasing_notes;
+        if sounding_notes == 0 && self.stolen_level <= 0. {
+            return None;
+        }
+
+        let stopping = self.stop_progress.is_some_and(|progress| progress < 1.);
+        let release_pending = self.freeze_engaged && !self.freeze_requested();
+        let releasing = !stopping && !release_pending;
+        let sustain = self.settings.freeze_sustain.max(DECAY_SILENCE);
+        self.stolen_level = (self.stolen_level - block.steal_step).max(0.);
+        let mut loudest = self.stolen_level;
+        let mut notes = sounding_notes;
+        while notes != 0 {
+            let note = notes.trailing_zeros() as u8;
+            notes &= notes - 1;
+
+            let held = self.held_notes & (1 << note) != 0;
+            let voice = &mut self.note_voices[note as usize];
+            voice.level = if !held {
+                if releasing {
+                    (voice.level - block.release_step).max(0.)
+                } else {
+                    voice.level
+                }
+            } else if !self.freeze_engaged {
+                voice.level
+            } else if !voice.decaying {
+                let level = (voice.level + block.attack_step).min(1.);
+                voice.decaying = level >= 1.;
+                level
+            } else if voice.level > sustain {
+                (voice.level - block.decay_step).max(sustain)
+            } else {
+                (voice.level + block.attack_step).min(sustain)
+            };
+            loudest = loudest.max(voice.level);
+
+            if !held && voice.level <= 0. {
+                self.releasing_notes &= !(1 << note);
+                self.end_voice(timing, note, context);
+            }
+        }
+
+        Some(loudest)
+    }
+
+    /// The level the freeze's envelope holds after the decay. Even a sustain of zero keeps the
+    /// loop barely above silence, so the buffers don't start recording while it's frozen.
+    fn sustain_level(&self) -> f32 {
+        (self.wet_target * self.settings.freeze_sustain).max(DECAY_SILENCE)
+    }
+
+    /// The freeze's envelope: the attack rises to `wet_target`, the decay falls to the sustain
+    /// level from there and the release fades out after the freeze is released. While the notes
+    /// are all that's freezing, the loudest of their voices' own envelopes in `voice_level` sets
+    /// the level instead.
+    fn next_wet_gain(
+        &mut self,
+        attack_step: f32,
+        decay_step: f32,
+        release_step: f32,
+        voice_level: Option<f32>,
+    ) -> f32 {
+        // The loop doesn't fade out until it has come to a halt
+        let stopping = self.stop_progress.is_some_and(|progress| progress < 1.);
+        let sustain = self.sustain_level();
+        let only_notes = !self.amount_freezing
+            && !self.link_freezing
+            && !self.own_non_note_freeze_requested();
+        self.wet_gain = if let Some(level) = voice_level.filter(|_| only_notes) {
+            level * self.wet_target
+        } else if self.freeze_engaged && !self.decaying {
+            // Engaging again during the release never jumps down to the peak
+            let wet = (self.wet_gain + attack_step).min(self.wet_target.max(self.wet_gain));
+            self.decaying = wet >= self.wet_target;
+            wet
+        } else if self.freeze_engaged && self.wet_gain > sustain {
+            // Lowering Freeze Amount or the sustain fades down like the decay
+            (self.wet_gain - decay_step).max(sustain)
+        } else if self.freeze_engaged {
+            (self.wet_gain + attack_step).min(sustain)
+        } else if stopping {
+            self.wet_gain
+        } else {
+            (self.wet_gain - release_step).max(0.)
+        };
+        if self.wet_gain <= 0. {
+            self.stop_progress = None;
+            self.reversed_freeze = false;
+        }
+
+        self.wet_gain
+    }
+
+    /// Count the passes through the loop that are played with this sample. `pass_len` is the
+    /// loop's length in samples and `speed` how far the read position moves per sample.
+    /// Half-Time follows its parameter, the size keys' presses apply and the pitch sequence
+    /// moves on to its next step whenever a new pass starts. While nothing is playing Half-Time
+    /// follows right away, and the presses and the sequence are reset.
+    fn advance_repeats(&mut self, pass_len: f32, speed: f32) {
+        if !self.freeze_engaged && self.wet_gain <= 0. {
+            self.half_time = self.settings.half_time;
+            self.size_steps = 0;
+            self.pending_size_steps = 0;
+            self.sequence_step = 0;
+            self.sequence_rate = self.sequence_step_rate(0);
+            self.rotation = 0.;
+            self.rotation_target = 0.;
+            return;
+        }
+
+        let previous = self.repeats;
+        self.repeats += (speed / pass_len.max(1.)) as f64;
+        if self.repeats.floor() != previous.floor() {
+            self.half_time = self.settings.half_time;
+            self.size_steps = self.pending_size_steps;
+            let steps = self.settings.sequence_length as usize;
+            self.sequence_step = (self.sequence_step + 1) % steps.max(1);
+            self.sequence_rate = self.sequence_step_rate(self.sequence_step);
+
+            let num_channels = self.channel_buffers.len() as f32;
+            let repeat = self.repeats.floor() as u64;
+            if num_channels > 1. && self.settings.channel_rotate.rotates_on(repeat) {
+                // A crossfade that's still running is cut short so the next one starts right on
+                // the seam
+                self.rotation = self.rotation_target % num_channels;
+                self.rotation_target = self.rotation + 1.;
+            }
+        }
+    }
+
+    /// The factor for the playback rate of a pitch sequence step, which is 1 while the
+    /// sequence is bypassed
+    fn sequence_step_rate(&self, step: usize) -> f32 {
+        if !self.settings.sequence {
+            return 1.;
+        }
+
+        let semitones = self.settings.sequence_intervals[step];
+        2f32.powf(semitones as f32 / 12.)
+    }
+
+    /// The decay's gain for the current repeat
+    fn decay_gain(&self, pass_len: f32) -> f32 {
+        let decay_db = self.settings.decay;
+        if decay_db <= 0. || (!self.freeze_engaged && self.wet_gain <= 0.) {
+            return 1.;
+        }
+
+        let fade_repeats = GATE_FADE_MS / 1000. * self.sample_rate / pass_len.max(1.);
+        self.settings.decay_shape.gain(
+            self.repeats as f32,
+            decay_db,
+            self.settings.gate_hold as f32,
+            fade_repeats,
+        )
+    }
+
+    fn highest_held_note(&self) -> Option<u8> {
+        match self.held_notes {
+            0 => None,
+            notes => Some(127 - notes.leading_zeros() as u8),
+        }
+    }
+
+    /// The per-sample coefficient for the exponential glide. Zero means the loop length jumps
+    /// to the new value instantly.
+    fn glide_coefficient(&self) -> f32 {
+        let glide_samples = self.settings.glide / 1000. * self.sample_rate;
+        if glide_samples < 1. {
+            0.
+        } else {
+            // Cover 99% of the distance within the glide time
+            (-(100f32.ln()) / glide_samples).exp()
+        }
+    }
+
+    /// Apply the Velocity Curve parameter to a note's velocity. The curve is a power with an
+    /// exponent between 1/4 and 4, and a straight line at 0%.
+    fn curve_velocity(&self, velocity: f32) -> f32 {
+        let curve = self.settings.velocity_curve;
+        if curve == 0. {
+            velocity
+        } else {
+            velocity.clamp(0., 1.).powf(MAX_VELOCITY_EXPONENT.powf(curve))
+        }
+    }
+
+    /// The number of divisions a note with this velocity speeds the stutter up by. Soft notes
+    /// keep the Division parameter's value, hard notes go up to 1/32.
+    fn velocity_division_steps(&self, velocity: f32) -> usize {
+        let max_steps = Division::ThirtySecond as usize - Division::Whole as usize;
+        let steps = self.settings.velocity_division * velocity * max_steps as f32;
+        steps.round() as usize
+    }
+
+    /// The length of the selected stutter division in samples
+    fn division_length(&self, tempo: f64) -> Option<f32> {
+        let division = self.settings.division.faster(self.division_offset);
+        let seconds = division.beats()? as f64 * 60. / tempo;
+
+        Some((seconds * self.sample_rate as f64) as f32)
+    }
+
+    /// Compute the loop length for the next sample. Glides between lengths are done in the log
+    /// domain so they sound like a linear pitch slide. `None` means the whole buffer is looped.
+    fn next_loop_length(&mut self, glide_coefficient: f32, tempo: f64) -> Option<f32> {
+        let target = match self.active_note {
+            Some(note)
+                if self.note_tuning_active()
+                    && self.settings.note_behavior == NoteBehavior::LengthTuned =>
+            {
+                let tuning = self.note_voices[note as usize].tuning;
+                self.sample_rate / (midi_note_to_freq(note) * 2f32.powf(tuning / 12.))
+            }
+            // Stutters jump straight to their length
+            _ if self.settings.division != Division::Off => {
+                return self.division_length(tempo);
+            }
+            // Tapped and loaded lengths always glide so they don't click
+            _ if self.settings.glide_buffer_size
+                || self.buffer_size_override.is_some() =>
+            {
+                self.buffer_size() as f32
+            }
+            _ => return None,
+        };
+
+        let length = match self.glide_length {
+            Some(current) if glide_coefficient > 0. => {
+                target * (current / target).powf(glide_coefficient)
+            }
+            _ => target,
+        };
+        self.glide_length = Some(length);
+
+        Some(length)
+    }
+
+    /// Compute the playback rate for the next sample. When repitching, the held note transposes
+    /// the loop relative to the root note, gliding the same way the loop length does.
+    fn next_playback_rate(&mut self, glide_coefficient: f32) -> f32 {
+        let semitones = match self.active_note {
+            Some(note)
+                if self.note_tuning_active()
+                    && self.settings.note_behavior == NoteBehavior::Repitch =>
+            {
+                note as f32 - self.settings.root_note as f32
+                    + self.note_voices[note as usize].tuning
+            }
+            _ => return 1.,
+        };
+
+        let semitones = semitones.clamp(-MAX_TRANSPOSE_SEMITONES, MAX_TRANSPOSE_SEMITONES);
+        let target = 2f32.powf(semitones / 12.);
+        self.glide_rate = if glide_coefficient > 0. {
+            target * (self.glide_rate / target).powf(glide_coefficient)
+        } else {
+            target
+        };
+
+        self.glide_rate
+    }
+
+    fn note_tuning_active(&self) -> bool {
+        self.settings.key_tracking && self.note_freezing
+    }
+}
+
+/// A held note as seen by the host. Every note is its own voice for polyphonic modulation and
+/// note expressions, and the values of the note currently driving the freeze are applied to the
+/// plugin.
+#[derive(Debug, Clone, Copy)]
+struct NoteVoice {
+    voice_id: i32,
+    channel: u8,
+    /// The normalized offset the host's per-voice modulation applies to the buffer size
+    buffer_size_offset: f32,
+    /// Tuning expression in semitones
+    tuning: f32,
+    /// Volume expression as linear gain
+    gain: f32,
+    /// Pan expression, from -1 for hard left to 1 for hard right
+    pan: f32,
+    /// The voice's position in the stereo field from the note number or the random pan, in the
+    /// same range as `pan`
+    voice_pan: f32,
+    /// The sample counter when the note started, so the oldest note is stolen first
+    started: u64,
+    /// The voice's own envelope, from 0 to 1. This uses the freeze's Attack, Decay, Sustain and
+    /// Release times.
+    level: f32,
+    /// Whether the attack has reached the peak and the envelope falls to the sustain level
+    decaying: bool,
+}
+
+impl NoteVoice {
+    fn new(voice_id: i32, channel: u8) -> Self {
+        Self {
+            voice_id,
+            channel,
+            buffer_size_offset: 0.,
+            tuning: 0.,
+            gain: 1.,
+            pan: 0.,
+            voice_pan: 0.,
+            started: 0,
+            level: 0.,
+            decaying: false,
+        }
+    }
+}
+
+/// A note that manages the snapshot bank instead of triggering the freeze
+enum SnapshotKey {
+    Store,
+    Recall(usize),
+}
+
+/// The beat grid the freeze snaps to during the current block
+#[derive(Debug, Clone, Copy)]
+struct QuantizeGrid {
+    /// The position at the start of the block in grid lines since the start of the bar
+    start: f64,
+    /// How many grid lines pass per sample
+    step: f64,
+}
+
+impl QuantizeGrid {
+    /// Whether a grid line falls on this sample of the block
+    fn is_grid_line(&self, sample_id: usize) -> bool {
+        let position = self.start + sample_id as f64 * self.step;
+        position.floor() != (position - self.step).floor()
+    }
+}
+
+/// Match note expressions to a voice by their voice ID, or by their note if the host doesn't send
+/// voice IDs
+fn expression_target(voice_id: Option<i32>, note: u8) -> impl Fn(u8, &NoteVoice) -> bool {
+    move |voice_note, voice| match voice_id {
+        Some(voice_id) => voice.voice_id == voice_id,
+        None => voice_note == note,
+    }
+}
+
+/// The values `FreezeEngine::next_sample_controls()` needs that stay the same for a whole block
+#[derive(Debug, Clone, Copy)]
+struct BlockControls {
+    glide_coefficient: f32,
+    tempo: f64,
+    /// How far the crossfades and envelopes move per sample
+    engine_crossfade_step: f32,
+    attack_step: f32,
+    decay_step: f32,
+    release_step: f32,
+    steal_step: f32,
+    stop_step: f32,
+    crossfade_step: f32,
+    rotate_step: f32,
+    spread: f32,
+    auto_gain_enabled: bool,
+    normalize_enabled: bool,
+    crackle: f32,
+    hitch_chance: f32,
+    hitch_depth: f32,
+    vibrato_step: f32,
+    crush_step: f32,
+    /// The length that's played without a loop length, before anything is loaded this block
+    whole_loop_len: f32,
+    quantize_grid: Option<QuantizeGrid>,
+    highpass_coefficient: f32,
+    lowpass_coefficient: f32,
+}
+
+/// Why a segment of the block ends early. The segment's last sample needs the buffers to be
+/// swapped or sanitized before it's played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SegmentBoundary {
+    /// A refresh started recording into the other buffers
+    RefreshStarted,
+    /// The freeze engaged, or a refresh finished and froze the new loop
+    Engaged,
+}
+
+/// Everything the channels need to process one sample, computed once per sample for all
+/// channels
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SampleControls {
+    pub(crate) length: Option<f32>,
+    pub(crate) rate: f32,
+    pub(crate) stretch: f32,
+    /// How much of the loop is kept where it plays through as a whole
+    pub(crate) hold: f32,
+    pub(crate) reversed: bool,
+    pub(crate) window_start: f32,
+    pub(crate) window_len: f32,
+    /// The gains for the left channel, the right channel and any other channels
+    pub(crate) gains: [f32; 3],
+    pub(crate) wet: f32,
+    pub(crate) crossfade: f32,
+    /// Whether the buffers record this sample for a refresh while the old loop plays from the
+    /// fade buffers
+    pub(crate) refreshing: bool,
+    /// The progress of the crossfade between engines after switching modes
+    pub(crate) engine_crossfade: f32,
+    /// How many channels Channel Rotate moves the wet signals by. The fractional part is the
+    /// progress of the crossfade to the next channel.
+    pub(crate) rotation: f32,
+    /// The sample counter, which runs regardless of the freeze
+    pub(crate) position: u64,
+    pub(crate) spread: f32,
+    /// The chance of a crackle click starting on this sample
+    pub(crate) crackle: f32,
+    /// The bit reduction's quantization step, zero when it's bypassed
+    pub(crate) crush_step: f32,
+    /// The wet signal's tilt in decibels
+    pub(crate) tilt: f32,
+}
+
+impl SampleControls {
+    /// The loop length for a channel with `loop_len` samples, where the spread shortens the
+    /// right channel's loop
+    pub(crate) fn channel_length(&self, channel: usize, loop_len: usize) -> Option<f32> {
+        if channel == 1 {
+            spread_length(self.length, loop_len, self.spread)
+        } else {
+            self.length
+        }
+    }
+
+    /// Whether the buffers only record this sample. Nothing is played back from them then.
+    fn is_recording(&self) -> bool {
+        self.wet <= 0. && self.crossfade >= 1.
+    }
+
+    /// Whether only the frozen loop is heard and it's played sample by sample, as long as the
+    /// channel doesn't set a loop length either
+    fn plays_whole_loop(&self) -> bool {
+        self.wet >= 1.
+            && self.crossfade >= 1.
+            && self.rate == 1.
+            && self.stretch == 1.
+            && self.hold >= 1.
+            && !self.reversed
+            && self.window_len >= 1.
+            && self.crackle <= 0.
+            && self.crush_step <= 0.
+            && self.tilt == 0.
+    }
+}
+
+/// How `FreezeEngine::process_channels()` handles a run of samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunKind {
+    /// The buffer records the samples, see `SampleControls::is_recording()`
+    Record,
+    /// The buffer plays the whole loop, see `SampleControls::plays_whole_loop()`
+    Play,
+    /// Everything else, where the buffers are read one sample at a time
+    PerSample,
+}
+
+/// The number of samples `mix_chunks()` processes at once
+const MIX_LANES: usize = 8;
+
+/// Apply the channel's gain to a `frozen` sample and mix it into the `dry` sample. Returns the
+/// mixed sample and the wet signal on its own.
+fn mix_sample(dry: f32, frozen: f32, controls: &SampleControls, gain_index: usize) -> (f32, f32) {
+    let frozen = frozen * controls.gains[gain_index];
+    let wet = controls.wet;
+    let mixed = if wet >= 1. { frozen } else { dry + (frozen - dry) * wet };
+
+    (mixed, frozen * wet)
+}
+
+/// Apply the gain and the wet mix to the samples from a buffer that's recording or playing the
+/// whole loop, where the buffer's output is also used as the dry signal. This does exactly what
+/// `mix_sample()` does, but in chunks of `MIX_LANES` samples the compiler can vectorize.
+/// Returns the number of samples that were mixed, the remainder is left to the scalar loop.
+fn mix_chunks(
+    samples: &mut [f32],
+    controls: &[SampleControls],
+    gain_index: usize,
+    mut wet_output: Option<&mut [f32]>,
+) -> usize {
+    let mixed = samples.len() / MIX_LANES * MIX_LANES;
+    for (chunk, (sample_chunk, controls)) in samples[..mixed]
+        .chunks_exact_mut(MIX_LANES)
+        .zip(controls.chunks_exact(MIX_LANES))
+        .enumerate()
+    {
+        let gains: [f32; MIX_LANES] = std::array::from_fn(|j| controls[j].gains[gain_index]);
+        let wet: [f32; MIX_LANES] = std::array::from_fn(|j| controls[j].wet);
+        let dry: [f32; MIX_LANES] = std::array::from_fn(|j| sample_chunk[j]);
+        let frozen: [f32; MIX_LANES] = std::array::from_fn(|j| dry[j] * gains[j]);
+        for j in 0..MIX_LANES {
+            sample_chunk[j] = if wet[j] >= 1. {
+                frozen[j]
+            } else {
+                dry[j] + (frozen[j] - dry[j]) * wet[j]
+            };
+        }
+
+        if let Some(wet_output) = wet_output.as_deref_mut() {
+            let wet_chunk = &mut wet_output[chunk * MIX_LANES..(chunk + 1) * MIX_LANES];
+            for j in 0..MIX_LANES {
+                wet_chunk[j] = frozen[j] * wet[j];
+            }
+        }
+    }
+
+    mixed
+}
+
+/// Shorten a loop by the spread amount. A loop covering the whole buffer needs to switch to an
+/// explicit length for that.
+fn spread_length(length: Option<f32>, loop_len: usize, spread: f32) -> Option<f32> {
+    if spread <= 0. {
+        return length;
+    }
+
+    Some(length.unwrap_or(loop_len as f32) * (1. - spread * MAX_SPREAD_DETUNE))
+}
+
+/// The voice ID hosts would use for a note that doesn't come with one
+fn fallback_voice_id(note: u8, channel: u8) -> i32 {
+    note as i32 | ((channel as i32) << 16)
+}
+
+/// The frequency of a MIDI note in Hz, tuned to A4 at 440 Hz
+fn midi_note_to_freq(note: u8) -> f32 {
+    2f32.powf((note as f32 - 69.) / 12.) * 440.
+}
+
+/// The inverse of `midi_note_to_freq()`, which is fractional between the notes
+fn freq_to_midi_note(frequency: f32) -> f32 {
+    ((frequency / 440.).log2() * 12.) + 69.
+}
+
+/// The buffer size in samples for a value of `Settings::buffer_size_normalized`, which may have
+/// a voice's offset added to it
+fn plain_buffer_size(normalized: f32) -> i32 {
+    let range = (MAX_BUFFER_SIZE - MIN_BUFFER_SIZE) as f32;
+    (normalized.clamp(0., 1.) * range).round() as i32 + MIN_BUFFER_SIZE as i32
+}
+
+/// The host for `FreezeEngine::process()`, whose transport never plays. There are no events, and
+/// the engine's own events and tasks are dropped.
+struct NoHost;
+
+impl Host for NoHost {
+    fn transport(&self) -> TransportInfo {
+        TransportInfo::default()
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        None
+    }
+
+    fn send_event(&mut self, _event: OutputEvent) {}
+
+    fn execute_background(&self, _task: Task) {}
+
+    fn set_latency_samples(&self, _samples: u32) {}
+
+    fn set_current_voice_capacity(&self, _capacity: u32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.;
+    const BLOCK_SIZE: usize = 512;
+
+    /// A mono engine that has recorded a noisy signal and then frozen it for a couple of blocks
+    fn frozen_engine() -> FreezeEngine {
+        let mut engine = FreezeEngine::default();
+        engine.prepare(SAMPLE_RATE, BLOCK_SIZE, 1);
+        let mut rng = Rng::new(1);
+        for i in 0..60 {
+            let mut block: Vec<f32> = (0..BLOCK_SIZE).map(|_| rng.next_f32() - 0.5).collect();
+            engine.set_freeze(i >= 20);
+            engine.process(&mut [&mut block]);
+        }
+        assert!(engine.is_frozen());
+
+        engine
+    }
+
+    fn process_blocks(engine: &mut FreezeEngine, num_blocks: usize) {
+        for _ in 0..num_blocks {
+            engine.process(&mut [&mut [0.; BLOCK_SIZE]]);
+        }
+    }
+
+    fn contents(engine: &FreezeEngine) -> Vec<f32> {
+        engine.channel_buffers.first().unwrap().contents().to_vec()
+    }
+
+    #[test]
+    fn passes_the_input_through_until_frozen() {
+        let mut engine = FreezeEngine::default();
+        engine.prepare(SAMPLE_RATE, BLOCK_SIZE, 2);
+        for i in 0..8 {
+            let input: Vec<f32> =
+                (0..BLOCK_SIZE).map(|j| ((i * BLOCK_SIZE + j) as f32).sin()).collect();
+            let (mut left, mut right) = (input.clone(), input.clone());
+            assert!(!engine.process(&mut [&mut left, &mut right]));
+            assert_eq!(left, input);
+            assert_eq!(right, input);
+        }
+        assert!(!engine.is_frozen());
+    }
+
+    #[test]
+    fn freeze_repeats_the_loop() {
+        let mut engine = frozen_engine();
+        let loop_len = engine.loop_len();
+        let mut output = vec![0.; loop_len * 3];
+        for block in output.chunks_mut(BLOCK_SIZE) {
+            // The input is ignored while frozen
+            block.fill(1.);
+            assert!(engine.process(&mut [block]));
+        }
+
+        assert!(output[..loop_len].iter().any(|&sample| sample != 0.));
+        assert_eq!(output[..loop_len], output[loop_len..loop_len * 2]);
+        assert_eq!(output[..loop_len], output[loop_len * 2..]);
+    }
+
+    #[test]
+    fn set_length_overrides_the_buffer_size() {
+        let mut engine = FreezeEngine::default();
+        engine.prepare(SAMPLE_RATE, BLOCK_SIZE, 1);
+        process_blocks(&mut engine, 1);
+        assert_eq!(engine.loop_len(), engine.settings().buffer_size as usize - 1);
+
+        engine.set_length(2000.);
+        process_blocks(&mut engine, 1);
+        assert_eq!(engine.loop_len(), 1999);
+
+        // The override lasts until the buffer size itself changes
+        engine.settings_mut().set_buffer_size(4096);
+        process_blocks(&mut engine, 1);
+        assert_eq!(engine.loop_len(), 4095);
+    }
+
+    #[test]
+    fn window_resize_keeps_the_frozen_loop() {
+        let mut engine = frozen_engine();
+        let frozen = contents(&engine);
+
+        // Buffer Size automated down and back up again while frozen
+        engine.set_length(512.);
+        process_blocks(&mut engine, 4);
+        let buffer = engine.channel_buffers.first().unwrap();
+        assert_eq!(buffer.loop_len(), 511);
+        assert!(buffer.head() < 511);
+        assert_eq!(contents(&engine), frozen[..511]);
+
+        engine.set_length((frozen.len() + 1) as f32);
+        process_blocks(&mut engine, 4);
+        assert_eq!(contents(&engine), frozen);
+        assert!(engine.window_resized);
+    }
+
+    #[test]
+    fn window_resize_is_cleared_after_the_release() {
+        let mut engine = frozen_engine();
+        let frozen_len = contents(&engine).len();
+        engine.set_length(512.);
+        process_blocks(&mut engine, 1);
+        engine.set_freeze(false);
+        while engine.is_playing_buffer() {
+            process_blocks(&mut engine, 1);
+        }
+        process_blocks(&mut engine, 1);
+        assert!(!engine.window_resized);
+
+        engine.set_length((frozen_len + 1) as f32);
+        process_blocks(&mut engine, 1);
+        assert!(contents(&engine)[512..].iter().all(|&sample| sample == 0.));
+    }
+
+    #[test]
+    fn destructive_resize_clears_the_frozen_loop() {
+        let mut engine = frozen_engine();
+        let frozen = contents(&engine);
+        engine.resize_buffers(512, ResizeWhileFrozen::Destructive);
+        engine.resize_buffers(frozen.len() + 1, ResizeWhileFrozen::Destructive);
+
+        let resized = contents(&engine);
+        assert_eq!(resized[..511], frozen[..511]);
+        assert!(resized[512..].iter().all(|&sample| sample == 0.));
+        assert!(!engine.window_resized);
+    }
+
+    #[test]
+    fn deferred_resize_keeps_the_frozen_loop() {
+        let mut engine = frozen_engine();
+        let frozen = contents(&engine);
+        let applied_buffer_size = engine.applied_buffer_size;
+        engine.resize_buffers(512, ResizeWhileFrozen::Defer);
+        engine.resize_buffers(frozen.len() + 1, ResizeWhileFrozen::Defer);
+
+        assert_eq!(contents(&engine), frozen);
+        assert_eq!(engine.applied_buffer_size, applied_buffer_size);
+        assert!(!engine.window_resized);
+    }
+
+    /// Render a stereo sine through `settings` with a random freeze pattern and random notes,
+    /// in blocks of random sizes
+    fn render_randomly(settings: &Settings, per_sample_reference: bool) -> Vec<f32> {
+        let mut engine = FreezeEngine::default();
+        engine.prepare(SAMPLE_RATE, BLOCK_SIZE, 2);
+        *engine.settings_mut() = settings.clone();
+        engine.per_sample_reference = per_sample_reference;
+        let mut pattern = Rng::new(7);
+        let mut blocks = Rng::new(11);
+        let mut output = Vec::new();
+        for _ in 0..48 {
+            engine.set_freeze(pattern.next_f32() < 0.7);
+            let mut remaining = 1 + pattern.next_u32() as usize % 4096;
+            while remaining > 0 {
+                let len = remaining.min(1 + blocks.next_u32() as usize % BLOCK_SIZE);
+                let start = output.len() / 2;
+                let (mut left, mut right): (Vec<f32>, Vec<f32>) = (start..start + len)
+                    .map(|i| {
+                        let sample = (i as f32 * 0.01).sin();
+                        (sample, sample * -0.5)
+                    })
+                    .unzip();
+                engine.process(&mut [&mut left, &mut right]);
+                output.extend(left.into_iter().zip(right).flat_map(|(l, r)| [l, r]));
+                remaining -= len;
+            }
+        }
+
+        output
+    }
+
+    /// Plays the queued events into the engine and keeps the events the engine sends
+    #[derive(Default)]
+    struct NoteHost {
+        events: VecDeque<Event>,
+        sent: Vec<OutputEvent>,
+    }
+
+    impl Host for NoteHost {
+        fn transport(&self) -> TransportInfo {
+            TransportInfo::default()
+        }
+
+        fn next_event(&mut self) -> Option<Event> {
+            self.events.pop_front()
+        }
+
+        fn send_event(&mut self, event: OutputEvent) {
+            self.sent.push(event);
+        }
+
+        fn execute_background(&self, _task: Task) {}
+
+        fn set_latency_samples(&self, _samples: u32) {}
+
+        fn set_current_voice_capacity(&self, _capacity: u32) {}
+    }
+
+    impl NoteHost {
+        /// The notes of the voices the engine has ended so far
+        fn terminated_notes(&self) -> Vec<u8> {
+            let terminated = self.sent.iter().filter_map(|event| match *event {
+                OutputEvent::VoiceTerminated { note, .. } => Some(note),
+                _ => None,
+            });
+            terminated.collect()
+        }
+    }
+
+    /// A mono engine that freezes on MIDI notes and has recorded a noisy signal
+    fn note_engine() -> FreezeEngine {
+        let mut engine = FreezeEngine::default();
+        engine.prepare(SAMPLE_RATE, BLOCK_SIZE, 1);
+        engine.settings_mut().midi_trigger = true;
+        let mut rng = Rng::new(1);
+        for _ in 0..20 {
+            let mut block: Vec<f32> = (0..BLOCK_SIZE).map(|_| rng.next_f32() - 0.5).collect();
+            engine.process(&mut [&mut block]);
+        }
+
+        engine
+    }
+
+    fn note_on(note: u8) -> Event {
+        Event::NoteOn {
+            timing: 0,
+            voice_id: Some(note as i32),
+            channel: 0,
+            note,
+            velocity: 1.,
+        }
+    }
+
+    /// Process a block of silence with the events at its start. Returns the same as
+    /// `process()`.
+    fn process_events(engine: &mut FreezeEngine, host: &mut NoteHost, events: &[Event]) -> bool {
+        host.events.extend(events);
+        engine.ramps.hold(&engine.settings, BLOCK_SIZE);
+        engine.process_with(&mut [&mut [0.; BLOCK_SIZE]], None, None, host)
+    }
+
+    #[test]
+    fn released_voices_end_with_their_release() {
+        let mut engine = note_engine();
+        // A bit over 9 blocks
+        engine.settings_mut().release = 100.;
+        let mut host = NoteHost::default();
+        process_events(&mut engine, &mut host, &[note_on(60)]);
+        process_events(&mut engine, &mut host, &[]);
+        assert!(engine.is_frozen());
+
+        let note_off = Event::NoteOff { timing: 0, note: 60 };
+        let mut ending_block = None;
+        for block in 0..12 {
+            let events = if block == 0 { &[note_off][..] } else { &[] };
+            let keep_alive = process_events(&mut engine, &mut host, events);
+            if !host.terminated_notes().is_empty() {
+                ending_block = Some(block);
+                break;
+            }
+            // The engine keeps running until the voice has faded out
+            assert!(keep_alive);
+        }
+        assert!(matches!(ending_block, Some(9..=10)));
+        assert_eq!(host.terminated_notes(), [60]);
+
+        assert!(!process_events(&mut engine, &mut host, &[]));
+        assert_eq!(host.terminated_notes(), [60]);
+    }
+
+    #[test]
+    fn voices_have_their_own_envelopes() {
+        let mut engine = note_engine();
+        engine.settings_mut().attack = 100.;
+        let mut host = NoteHost::default();
+        process_events(&mut engine, &mut host, &[note_on(60)]);
+        process_events(&mut engine, &mut host, &[]);
+        let level = engine.note_voices[60].level;
+        assert!(level > 0. && level < 1.);
+
+        // Retriggering continues from the current level, while the new note starts from zero
+        process_events(&mut engine, &mut host, &[note_on(60), note_on(64)]);
+        assert_eq!(host.terminated_notes(), [60]);
+        assert!(engine.note_voices[60].level > level);
+        assert!(engine.note_voices[64].level < level);
+        // The loudest voice sets the loop's level
+        assert_eq!(engine.wet_gain, engine.note_voices[60].level);
+    }
+
+    #[test]
+    fn stolen_voices_fade_out() {
+        let mut engine = note_engine();
+        engine.settings_mut().voice_count = 2;
+        let mut host = NoteHost::default();
+        process_events(&mut engine, &mut host, &[note_on(60)]);
+        // The second note is still far from the first one's level when the first one is stolen
+        engine.settings_mut().attack = 100.;
+        process_events(&mut engine, &mut host, &[note_on(64)]);
+        let remaining_level = engine.note_voices[64].level;
+        assert!(remaining_level < 0.5);
+
+        // Lowering the voice count steals the oldest voice, which ends in the host right away
+        engine.settings_mut().voice_count = 1;
+        engine.ramps.hold(&engine.settings, 16);
+        engine.process_with(&mut [&mut [0.; 16]], None, None, &mut host);
+        assert_eq!(host.terminated_notes(), [60]);
+        // 16 samples into the 5 ms fade
+        assert!(engine.wet_gain > 0.9);
+
+        process_events(&mut engine, &mut host, &[]);
+        assert_eq!(engine.wet_gain, engine.note_voices[64].level);
+        assert_eq!(host.terminated_notes(), [60]);
+    }
+
+    #[test]
+    fn slice_processing_matches_the_per_sample_reference() {
+        let default = Settings::default();
+        let variations = [
+            default.clone(),
+            Settings { mode: Mode::Authentic, ..default.clone() },
+            Settings { stretch: 1.5, interpolation: Interpolation::Cubic, ..default.clone() },
+            Settings {
+                crackle: 0.5,
+                bit_depth: 8,
+                dither: true,
+                tilt: 3.,
+                ..default.clone()
+            },
+            Settings {
+                release: 50.,
+                spread: 0.3,
+                oversampling: Oversampling::X2,
+                formant: true,
+                ..default.clone()
+            },
+            Settings { channel_rotate: ChannelRotate::EveryRepeat, ..default },
+        ];
+
+        for settings in &variations {
+            let reference = render_randomly(settings, true);
+            let slices = render_randomly(settings, false);
+            assert_eq!(reference.len(), slices.len());
+            assert!(
+                reference.iter().zip(&slices).all(|(a, b)| a.to_bits() == b.to_bits()),
+                "{settings:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn process_does_not_allocate_while_the_settings_change() {
+        let mut engine = FreezeEngine::default();
+        engine.prepare(SAMPLE_RATE, BLOCK_SIZE, 2);
+        // Every change is made while the loop is frozen, and again while it's recording
+        let changes: [fn(&mut Settings); 12] = [
+            |settings| settings.mode = Mode::Authentic,
+            |settings| settings.oversampling = Oversampling::X4,
+            |settings| settings.interpolation = Interpolation::Sinc,
+            |settings| settings.formant = true,
+            |settings| settings.stretch = 2.,
+            |settings| settings.size_unit = SizeUnit::Sync,
+            |settings| settings.size_unit = SizeUnit::Pitch,
+            |settings| settings.crackle = 0.8,
+            |settings| settings.bit_depth = 4,
+            |settings| settings.normalize = true,
+            |settings| settings.oversampling = Oversampling::X2,
+            |settings| *settings = Settings::default(),
+        ];
+
+        let mut rng = Rng::new(5);
+        let (mut left, mut right) = (vec![0.; BLOCK_SIZE], vec![0.; BLOCK_SIZE]);
+        for block in 0..changes.len() * 8 {
+            left.iter_mut().chain(&mut right).for_each(|sample| *sample = rng.next_f32() - 0.5);
+            engine.set_freeze(block % 8 >= 2 && block % 8 < 6);
+            if block % 4 == 0 {
+                changes[block / 8](engine.settings_mut());
+            }
+
+            // Allocating in here aborts the tests in debug builds
+            assert_no_alloc::assert_no_alloc(|| engine.process(&mut [&mut left, &mut right]));
+        }
+    }
+
+    #[test]
+    fn mix_chunks_matches_the_scalar_mix() {
+        let mut rng = Rng::new(3);
+        let controls: Vec<SampleControls> = (0..61)
+            .map(|_| SampleControls {
+                gains: [rng.next_f32() * 2., rng.next_f32() * 2., 1.],
+                wet: match rng.next_u32() % 3 {
+                    0 => 0.,
+                    1 => 1.,
+                    _ => rng.next_f32(),
+                },
+                ..Default::default()
+            })
+            .collect();
+        let dry: Vec<f32> = (0..controls.len()).map(|_| rng.next_f32() - 0.5).collect();
+
+        for gain_index in 0..2 {
+            let mut samples = dry.clone();
+            let mut wet_output = vec![0.; dry.len()];
+            let mixed = mix_chunks(&mut samples, &controls, gain_index, Some(&mut wet_output));
+            assert_eq!(mixed, 56);
+
+            // A recorded or played buffer's output is its own dry signal
+            for ((&dry, controls), (&sample, &wet_sample)) in
+                dry.iter().zip(&controls).zip(samples.iter().zip(&wet_output)).take(mixed)
+            {
+                let (expected, expected_wet) = mix_sample(dry, dry, controls, gain_index);
+                assert_eq!(sample.to_bits(), expected.to_bits());
+                assert_eq!(wet_sample.to_bits(), expected_wet.to_bits());
+            }
+            // The remainder is left to the scalar loop
+            assert_eq!(samples[mixed..], dry[mixed..]);
+        }
+    }
+}
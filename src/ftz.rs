@@ -0,0 +1,68 @@
+/// Samples below this are snapped to zero in paths that decay towards silence. Quiet tails
+/// would otherwise end up as subnormal floats, which are very slow to process on x86 CPUs.
+pub const DENORMAL_THRESHOLD: f32 = 1e-20;
+
+/// The MXCSR flush-to-zero and denormals-are-zero bits
+const FTZ_DAZ: u32 = 0x8000 | 0x0040;
+
+/// Flush a sample that is about to become subnormal to zero
+#[inline]
+pub fn flush_denormal(sample: f32) -> f32 {
+    if sample.abs() < DENORMAL_THRESHOLD {
+        0.
+    } else {
+        sample
+    }
+}
+
+/// Enables flush-to-zero and denormals-are-zero while it is alive and restores the previous
+/// mode when dropped. This does nothing on platforms other than x86.
+pub struct ScopedFtz {
+    previous_mode: Option<u32>,
+}
+
+impl ScopedFtz {
+    pub fn enable() -> Self {
+        let previous_mode = read_mode();
+        if let Some(mode) = previous_mode {
+            write_mode(mode | FTZ_DAZ);
+        }
+
+        Self { previous_mode }
+    }
+}
+
+impl Drop for ScopedFtz {
+    fn drop(&mut self) {
+        if let Some(mode) = self.previous_mode {
+            write_mode(mode);
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_mode() -> Option<u32> {
+    let mut mode = 0u32;
+    // SAFETY: This only stores the MXCSR register to `mode`
+    unsafe {
+        std::arch::asm!("stmxcsr [{}]", in(reg) &mut mode, options(nostack, preserves_flags));
+    }
+
+    Some(mode)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn write_mode(mode: u32) {
+    // SAFETY: Only the denormal handling bits differ from the mode that was read before
+    unsafe {
+        std::arch::asm!("ldmxcsr [{}]", in(reg) &mode, options(nostack, readonly, preserves_flags));
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn read_mode() -> Option<u32> {
+    None
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn write_mode(_mode: u32) {}
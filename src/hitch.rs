@@ -0,0 +1,61 @@
+use crate::rng::Rng;
+
+/// The shortest and the longest hitch in seconds
+const MIN_HITCH_SECONDS: f32 = 0.05;
+const MAX_HITCH_SECONDS: f32 = 0.3;
+/// How far the playback rate dips at full depth
+const MAX_DIP: f32 = 0.9;
+/// The part of a hitch the rate takes to fall, after that it holds until it snaps back
+const FALL: f32 = 0.25;
+
+/// Random moments where the frozen loop slows down like a machine hitching under load, and then
+/// snaps back to its regular rate. A hitch always runs to its end before the next one can
+/// start, so the rate is back at exactly 1 between hitches.
+#[derive(Clone, Debug)]
+pub struct Hitch {
+    rng: Rng,
+    /// The current hitch's length and how far into it the playback is, in samples
+    len: u32,
+    elapsed: u32,
+    /// How far the current hitch dips the rate
+    dip: f32,
+}
+
+impl Hitch {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            len: 0,
+            elapsed: 0,
+            dip: 0.,
+        }
+    }
+
+    /// Stop the current hitch, for instance when the freeze is released
+    pub fn cancel(&mut self) {
+        self.elapsed = self.len;
+    }
+
+    /// The factor for the next sample's playback rate. A new hitch starts at `chance` when
+    /// there isn't one already, and dips the rate by up to `depth`.
+    pub fn next_rate(&mut self, chance: f32, depth: f32, sample_rate: f32) -> f32 {
+        if self.elapsed >= self.len {
+            if chance <= 0. || self.rng.next_f32() >= chance {
+                return 1.;
+            }
+
+            let seconds =
+                MIN_HITCH_SECONDS + (MAX_HITCH_SECONDS - MIN_HITCH_SECONDS) * self.rng.next_f32();
+            self.len = ((seconds * sample_rate) as u32).max(1);
+            self.elapsed = 0;
+            // Every hitch dips a bit differently
+            self.dip = depth.clamp(0., 1.) * MAX_DIP * (0.5 + 0.5 * self.rng.next_f32());
+        }
+
+        let progress = self.elapsed as f32 / self.len as f32;
+        self.elapsed += 1;
+        // The rate eases into the hitch and snaps back at its end
+        let fall = (progress / FALL).min(1.);
+        1. - self.dip * fall * (2. - fall)
+    }
+}
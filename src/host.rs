@@ -0,0 +1,148 @@
+use nih_plug::prelude::*;
+
+use crate::freeze::{self, Event, OutputEvent, Task, TransportInfo};
+use crate::{WinXpCrash, BUFFER_SIZE_POLY_MOD_ID};
+
+/// The part of the host the plugin talks to while processing. Plugin hosts are reached through
+/// nih-plug's `ProcessContext`, and the offline renderer brings its own implementation.
+pub(crate) trait Host {
+    fn transport(&self) -> TransportInfo;
+    fn next_event(&mut self) -> Option<PluginNoteEvent<WinXpCrash>>;
+    fn send_event(&mut self, event: PluginNoteEvent<WinXpCrash>);
+    fn execute_background(&self, task: Task);
+    fn set_latency_samples(&self, samples: u32);
+    fn set_current_voice_capacity(&self, capacity: u32);
+}
+
+impl<C: ProcessContext<WinXpCrash>> Host for C {
+    fn transport(&self) -> TransportInfo {
+        let transport = ProcessContext::transport(self);
+        TransportInfo {
+            playing: transport.playing,
+            tempo: transport.tempo,
+            time_sig_numerator: transport.time_sig_numerator,
+            time_sig_denominator: transport.time_sig_denominator,
+            pos_beats: transport.pos_beats(),
+            bar_start_pos_beats: transport.bar_start_pos_beats(),
+        }
+    }
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<WinXpCrash>> {
+        ProcessContext::next_event(self)
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<WinXpCrash>) {
+        ProcessContext::send_event(self, event)
+    }
+
+    fn execute_background(&self, task: Task) {
+        ProcessContext::execute_background(self, task)
+    }
+
+    fn set_latency_samples(&self, samples: u32) {
+        ProcessContext::set_latency_samples(self, samples)
+    }
+
+    fn set_current_voice_capacity(&self, capacity: u32) {
+        ProcessContext::set_current_voice_capacity(self, capacity)
+    }
+}
+
+/// The plugin's host as the freeze engine sees it, with nih-plug's note events translated to
+/// the engine's events and back
+pub(crate) struct EngineHost<'a, H>(pub &'a mut H);
+
+impl<H: Host> freeze::Host for EngineHost<'_, H> {
+    fn transport(&self) -> TransportInfo {
+        self.0.transport()
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        // Events the engine doesn't use are skipped
+        while let Some(event) = self.0.next_event() {
+            if let Some(event) = engine_event(event) {
+                return Some(event);
+            }
+        }
+
+        None
+    }
+
+    fn send_event(&mut self, event: OutputEvent) {
+        let event = match event {
+            OutputEvent::NoteOn { timing, note, velocity } => NoteEvent::NoteOn {
+                timing,
+                voice_id: None,
+                channel: 0,
+                note,
+                velocity,
+            },
+            OutputEvent::NoteOff { timing, note } => NoteEvent::NoteOff {
+                timing,
+                voice_id: None,
+                channel: 0,
+                note,
+                velocity: 0.,
+            },
+            OutputEvent::VoiceTerminated { timing, voice_id, channel, note } => {
+                NoteEvent::VoiceTerminated {
+                    timing,
+                    voice_id: Some(voice_id),
+                    channel,
+                    note,
+                }
+            },
+            OutputEvent::SysEx { timing, message } => NoteEvent::MidiSysEx { timing, message },
+        };
+        self.0.send_event(event);
+    }
+
+    fn execute_background(&self, task: Task) {
+        self.0.execute_background(task)
+    }
+
+    fn set_latency_samples(&self, samples: u32) {
+        self.0.set_latency_samples(samples)
+    }
+
+    fn set_current_voice_capacity(&self, capacity: u32) {
+        self.0.set_current_voice_capacity(capacity)
+    }
+}
+
+/// The engine's event for a note event from the host, if it reacts to it at all. Chokes release
+/// the note like a note off. Automation needs no event, as the parameter already holds the new
+/// value, and so does the modulation of any parameter but the buffer size.
+fn engine_event(event: PluginNoteEvent<WinXpCrash>) -> Option<Event> {
+    let event = match event {
+        NoteEvent::NoteOn { timing, voice_id, channel, note, velocity } => Event::NoteOn {
+            timing,
+            voice_id,
+            channel,
+            note,
+            velocity,
+        },
+        NoteEvent::NoteOff { timing, note, .. } | NoteEvent::Choke { timing, note, .. } => {
+            Event::NoteOff { timing, note }
+        },
+        NoteEvent::PolyModulation {
+            timing,
+            voice_id,
+            poly_modulation_id: BUFFER_SIZE_POLY_MOD_ID,
+            normalized_offset,
+        } => Event::BufferSizeModulation { timing, voice_id, normalized_offset },
+        NoteEvent::PolyTuning { timing, voice_id, note, tuning, .. } => {
+            Event::PolyTuning { timing, voice_id, note, tuning }
+        },
+        NoteEvent::PolyVolume { timing, voice_id, note, gain, .. } => {
+            Event::PolyVolume { timing, voice_id, note, gain }
+        },
+        NoteEvent::PolyPan { timing, voice_id, note, pan, .. } => {
+            Event::PolyPan { timing, voice_id, note, pan }
+        },
+        NoteEvent::MidiSysEx { timing, message } => Event::SysEx { timing, message },
+        _ => return None,
+    };
+
+    Some(event)
+}
@@ -0,0 +1,174 @@
+use nih_plug::prelude::*;
+
+/// The number of samples around the read position the windowed sinc filter reads
+const SINC_TAPS: usize = 16;
+/// The offset of the first sample the sinc filter reads, relative to the sample before the read
+/// position
+const SINC_FIRST_TAP: i64 = 1 - SINC_TAPS as i64 / 2;
+/// The number of fractional positions between two samples the sinc filter's taps are computed
+/// for. The taps for positions in between are interpolated linearly.
+const SINC_PHASES: usize = 256;
+
+/// How the frozen loop is read between two samples when it's played at another rate or with a
+/// fractional length
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The closest sample, which aliases when repitching for a lo-fi sound. This is the
+    /// cheapest mode.
+    #[name = "Nearest"]
+    Nearest,
+    #[name = "Linear"]
+    Linear,
+    /// 4-point Hermite interpolation
+    #[name = "Cubic"]
+    Cubic,
+    /// A 16 tap windowed sinc filter. This is the cleanest and the most expensive mode.
+    #[name = "Sinc"]
+    Sinc,
+}
+
+/// The windowed sinc filter's taps for every fractional position, computed once outside of the
+/// audio thread. The default table is empty, and the sinc mode then falls back to cubic
+/// interpolation.
+#[derive(Clone, Debug, Default)]
+pub struct SincTable {
+    taps: Vec<[f32; SINC_TAPS]>,
+}
+
+impl SincTable {
+    /// Compute the taps. This allocates.
+    pub fn new() -> Self {
+        let taps = (0..=SINC_PHASES)
+            .map(|phase| {
+                let t = phase as f64 / SINC_PHASES as f64;
+                let mut taps: [f32; SINC_TAPS] = std::array::from_fn(|tap| {
+                    let x = (SINC_FIRST_TAP + tap as i64) as f64 - t;
+                    (sinc(x) * blackman(x, (SINC_TAPS / 2) as f64)) as f32
+                });
+                // The taps are normalized so the filter doesn't change the loop's DC offset
+                let sum: f32 = taps.iter().sum();
+                taps.iter_mut().for_each(|tap| *tap /= sum);
+                taps
+            })
+            .collect();
+
+        Self { taps }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taps.is_empty()
+    }
+}
+
+impl Interpolation {
+    /// Interpolate between `sample(0)` and `sample(1)` at `t`, which is between 0 and 1.
+    /// `sample` returns the samples around the read position by their offset, which ranges from
+    /// -7 to 8 for the sinc filter.
+    #[inline]
+    pub fn interpolate(self, sample: impl Fn(i64) -> f32, t: f32, sinc_table: &SincTable) -> f32 {
+        match self {
+            Interpolation::Nearest => {
+                if t < 0.5 {
+                    sample(0)
+                } else {
+                    sample(1)
+                }
+            }
+            Interpolation::Linear => {
+                let current = sample(0);
+                let next = sample(1);
+                current + (next - current) * t
+            }
+            Interpolation::Cubic => {
+                let (previous, current, next, after) =
+                    (sample(-1), sample(0), sample(1), sample(2));
+                let c1 = 0.5 * (next - previous);
+                let c2 = previous - 2.5 * current + 2. * next - 0.5 * after;
+                let c3 = 0.5 * (after - previous) + 1.5 * (current - next);
+                ((c3 * t + c2) * t + c1) * t + current
+            }
+            Interpolation::Sinc => {
+                let phase = t * SINC_PHASES as f32;
+                let index = (phase as usize).min(SINC_PHASES - 1);
+                let phase_t = phase - index as f32;
+                match sinc_table.taps.get(index..index + 2) {
+                    Some([taps, next_taps]) => taps
+                        .iter()
+                        .zip(next_taps)
+                        .zip(SINC_FIRST_TAP..)
+                        .map(|((tap, next_tap), offset)| {
+                            (tap + (next_tap - tap) * phase_t) * sample(offset)
+                        })
+                        .sum(),
+                    _ => Interpolation::Cubic.interpolate(sample, t, sinc_table),
+                }
+            }
+        }
+    }
+}
+
+/// The normalized sinc function, `sin(pi * x) / (pi * x)`
+pub fn sinc(x: f64) -> f64 {
+    if x == 0. {
+        1.
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// A Blackman window centered on zero that's `half_width` wide on either side
+pub fn blackman(x: f64, half_width: f64) -> f64 {
+    if x.abs() >= half_width {
+        return 0.;
+    }
+
+    let phase = std::f64::consts::PI * x / half_width;
+    0.42 + 0.5 * phase.cos() + 0.08 * (2. * phase).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The distortion and noise of a sine at `frequency` cycles per sample read at `rate`, as
+    /// the RMS of the difference to the ideal resampled sine relative to its RMS
+    fn thd_n(interpolation: Interpolation, frequency: f64, rate: f64) -> f64 {
+        let sinc_table = SincTable::new();
+        let sine = |position: f64| (position * frequency * std::f64::consts::TAU).sin();
+        let (mut error, mut signal) = (0., 0.);
+        for i in 0..4096 {
+            let position = i as f64 * rate;
+            let index = position.floor();
+            let t = (position - index) as f32;
+            let output = interpolation.interpolate(
+                |offset| sine(index + offset as f64) as f32,
+                t,
+                &sinc_table,
+            );
+            let expected = sine(position);
+            error += (output as f64 - expected).powi(2);
+            signal += expected.powi(2);
+        }
+
+        (error / signal).sqrt()
+    }
+
+    #[test]
+    fn better_interpolation_distorts_less() {
+        // Very low frequencies are read about as well with cubic interpolation as the sinc
+        // filter's window allows, so the modes are compared on higher ones. At 48 kHz these are
+        // 2.4 kHz, 4.8 kHz and 9.6 kHz.
+        for (frequency, rate) in [(0.05, 1.37), (0.1, 0.9), (0.2, 1.01)] {
+            let thd_n = [
+                Interpolation::Nearest,
+                Interpolation::Linear,
+                Interpolation::Cubic,
+                Interpolation::Sinc,
+            ]
+            .map(|interpolation| thd_n(interpolation, frequency, rate));
+            assert!(thd_n.windows(2).all(|pair| pair[0] > pair[1]), "{thd_n:?}");
+            assert!(thd_n[3] < 1e-3, "{thd_n:?}");
+        }
+    }
+}
@@ -1,59 +1,1531 @@
 use nih_plug::prelude::*;
-use std::sync::Arc;
+use nih_plug_egui::EguiState;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
-use crate::buffer::RingBuffer;
+use crate::bank::{SnapshotBank, NUM_SNAPSHOTS};
+use crate::crusher::BYPASS_BIT_DEPTH;
+use crate::editor::Theme;
+use crate::freeze::{
+    FreezeEngine, Layout, Settings, Shared, Task, DECAY_SILENCE, DEFAULT_TEMPO, MAX_BUFFER_SIZE,
+    MAX_TRANSPOSE_SEMITONES, MAX_VOICES, MIN_BUFFER_SIZE, SEQUENCE_STEPS,
+};
+use crate::host::{EngineHost, Host};
+use crate::interpolation::Interpolation;
+use crate::link::LinkGroup;
+use crate::meters::Meters;
+use crate::oversampling::Oversampling;
+use crate::presets::AbCompare;
+use crate::size_unit::{s2v_buffer_size, v2s_buffer_size, SizeDisplay, SizeUnit};
+use crate::spectrum::SpectrumFifo;
+use crate::state::{BufferState, STATE_VERSION};
+use crate::sysex::SysEx;
+use crate::track_info::SharedTrackInfo;
+use crate::waveform::Waveform;
 
+mod auto_gain;
+mod bank;
 mod buffer;
+mod clap_entry;
+mod crackle;
+mod crusher;
+mod detector;
+mod editor;
+mod engine;
+mod formant;
+pub mod freeze;
+mod ftz;
+mod hitch;
+mod host;
+mod interpolation;
+mod link;
+mod meters;
+mod midi_file;
+pub mod osc;
+mod oversampling;
+mod pitch;
+mod preset_discovery;
+pub mod presets;
+pub mod render;
+mod rng;
+mod size_unit;
+mod state;
+mod spectrum;
+mod sysex;
+mod tap;
+mod tilt;
+mod track_info;
+mod wav;
+pub mod waveform;
 
-const MAX_BUFFER_SIZE: usize = 65536;
+/// Internals for the benchmarks in `benches/`
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::buffer::{ChannelBuffers, RingBuffer};
+}
+
+/// The largest interval a pitch sequence step can play either way, in semitones
+const MAX_SEQUENCE_INTERVAL: i32 = 12;
+/// The most passes Trigger One Loop can play
+const MAX_TRIGGER_REPEATS: i32 = 16;
+/// How far the vinyl stop's exponential decay falls before it's scaled to end at zero
+const VINYL_STOP_DECAY: f32 = 4.;
+/// The number of rates the stepped stop falls through on the way to zero
+const STOP_STEPS: f32 = 6.;
 
+/// The poly modulation ID for the buffer size parameter
+const BUFFER_SIZE_POLY_MOD_ID: u32 = 0;
 
 pub struct WinXpCrash {
     params: Arc<WinXpCrashParams>,
-
-    channel_buffers: Vec<RingBuffer>,
-
-    note_freezing: bool,
+    /// The freeze itself, which this plugin maps its parameters and events onto
+    engine: FreezeEngine,
 }
 
 #[derive(Params)]
 struct WinXpCrashParams {
     /// The parameter's ID is used to identify the parameter in the wrappred plugin API. As long as
-    /// these IDs remain constant, you can rename and reorder these fields as you wish. The
-    /// parameters are exposed to the host in the same order they were defined. In this case, this
-    /// gain parameter is stored as linear gain while the values are displayed in decibels.
+    /// these IDs remain constant, you can rename and reorder these fields as you wish, and move
+    /// them between these groups. The parameters are exposed to the host in the same order they
+    /// were defined.
+    #[nested(group = "Capture")]
+    pub capture: CaptureParams,
+
+    #[nested(group = "Freeze")]
+    pub freezing: FreezeParams,
+
+    #[nested(group = "Pitch")]
+    pub pitch: PitchParams,
+
+    #[nested(group = "Character")]
+    pub character: CharacterParams,
+
+    #[nested(group = "Mix")]
+    pub mix: MixParams,
+
+    #[nested(group = "Modulation")]
+    pub modulation: ModulationParams,
+
+    /// The frozen loops, saved with the project
+    #[persist = "buffer-state"]
+    pub buffer_state: Arc<RwLock<BufferState>>,
+
+    #[persist = "snapshot-bank"]
+    pub snapshot_bank: Arc<RwLock<SnapshotBank>>,
+
+    /// Write the current loop to a WAV file when switched on.
+    #[id = "export_wav"]
+    pub export_wav: BoolParam,
+
+    /// The directory WAV exports are written to. Exports go to the home directory if this isn't
+    /// set.
+    #[persist = "export-dir"]
+    pub export_dir: RwLock<Option<PathBuf>>,
+
+    /// Load the WAV file at the import path into the buffer and freeze it when switched on.
+    #[id = "import_wav"]
+    pub import_wav: BoolParam,
+
+    /// The WAV file to import
+    #[persist = "import-path"]
+    pub import_path: RwLock<Option<PathBuf>>,
+    /// Why the last import failed and when, so the editor can show it for a moment
+    pub import_error: Mutex<Option<(String, Instant)>>,
+    /// The parameter values of the A/B compare slot that isn't active
+    #[persist = "ab-compare"]
+    pub ab_compare: RwLock<AbCompare>,
+    /// The loops of a user preset the editor loaded, waiting for `Task::LoadPresetBuffer`
+    pub preset_buffer: Mutex<Option<BufferState>>,
+
+    /// The version of the state this was saved as, see `state::migrate()`
+    #[persist = "state-version"]
+    pub state_version: RwLock<u32>,
+
+    /// The last tempo the host reported as `f64` bits, for displaying the division lengths
+    pub host_tempo: Arc<AtomicU64>,
+    /// What the Buffer Size parameter's formatters show for the selected size unit
+    pub size_display: Arc<SizeDisplay>,
+    /// The current sample rate as `f32` bits, for displaying lengths in the editor
+    pub sample_rate: AtomicU32,
+    /// Whether the freeze is currently engaged, for the editor's skin
+    pub frozen: Arc<AtomicBool>,
+    /// The track the plugin is on, for the editor's title. The CLAP entry point updates this.
+    pub track_info: SharedTrackInfo,
+    /// The recorded loop's peaks and the buffer's state for the editor's waveform display and
+    /// other observers
+    pub waveform: Arc<Waveform>,
+    /// The output for the editor's spectrum analyzer
+    pub spectrum: SpectrumFifo,
+    /// The input and output levels for the editor's meters
+    pub meters: Meters,
+    /// Whether the editor shows the spectrum analyzer. The output is only written to `spectrum`
+    /// while it does.
+    #[persist = "show-spectrum"]
+    pub show_spectrum: AtomicBool,
+    /// Whether the editor shows a blue screen while frozen
+    #[persist = "show-bsod"]
+    pub show_bsod: AtomicBool,
+    /// The IDs of the parameters the editor's randomizer doesn't change
+    #[persist = "randomizer-locks"]
+    pub randomizer_locks: RwLock<BTreeSet<String>>,
+    /// The editor's theme. This isn't a parameter, so hosts can't automate it.
+    #[persist = "theme"]
+    pub theme: RwLock<Theme>,
+
+    #[persist = "editor-state"]
+    editor_state: Arc<EguiState>,
+}
+
+/// The parameters that decide what gets captured and how much of it loops
+#[derive(Params)]
+struct CaptureParams {
     #[id = "buffer_size"]
     pub buffer_size: IntParam,
 
+    /// How the buffer size is shown and entered. This doesn't change the length itself.
+    #[id = "size_unit"]
+    pub size_unit: EnumParam<SizeUnit>,
+
+    /// Also glide when the Buffer Size parameter changes instead of only between notes.
+    #[id = "glide_buffer_size"]
+    pub glide_buffer_size: BoolParam,
+
+    /// What a Buffer Size change does while the loop is frozen or still fading out.
+    #[id = "resize_while_frozen"]
+    pub resize_while_frozen: EnumParam<ResizeWhileFrozen>,
+
+    /// Stutter the frozen audio at a tempo synced note division instead of looping the whole
+    /// buffer. This is the index of a `Division` so the value can show the division's length at
+    /// the host's tempo.
+    #[id = "division"]
+    pub division: IntParam,
+
+    /// How far the velocity of the note starting a freeze speeds up the division.
+    #[id = "velocity_division"]
+    pub velocity_division: FloatParam,
+
+    /// Play the loop backwards from the most recent audio. This is decided when the freeze
+    /// engages, changing it while frozen takes effect with the next freeze.
+    #[id = "capture_reversed"]
+    pub capture_reversed: BoolParam,
+
+    /// Where the part of the frozen loop that's played starts, as a fraction of the loop from
+    /// its oldest sample. Loop lengths set by notes or the division play the end of the window.
+    #[id = "loop_start"]
+    pub loop_start: FloatParam,
+
+    /// How much of the frozen loop is played, as a fraction of the loop.
+    #[id = "loop_length"]
+    pub loop_length: FloatParam,
+}
+
+/// The parameters that decide when the buffer freezes and how the freeze ends
+#[derive(Params)]
+struct FreezeParams {
     #[id = "freeze"]
     pub freeze: BoolParam,
+
+    /// A continuous Freeze for modulators. Below the threshold the input passes through, from
+    /// 50% up the freeze engages and the output crossfades from halfway to fully frozen. The
+    /// Freeze parameter, notes and the other triggers always freeze at 100%.
+    #[id = "freeze_amount"]
+    pub freeze_amount: FloatParam,
+
+    /// Freeze for a single pass through the loop whenever this is switched on, and release
+    /// automatically afterwards no matter how long it stays on. Switching it on again while the
+    /// loop plays starts counting the pass again.
+    #[id = "trigger_one_loop"]
+    pub trigger_one_loop: BoolParam,
+
+    /// How many passes Trigger One Loop plays before it releases.
+    #[id = "trigger_repeats"]
+    pub trigger_repeats: IntParam,
+
+    /// Authentic loops a fixed size hardware chunk like a crashing soundcard driver, without any
+    /// of the smoothing the musical mode does.
+    #[id = "mode"]
+    pub mode: EnumParam<Mode>,
+
+    /// The size of the chunks the authentic mode loops.
+    #[id = "chunk_size"]
+    pub chunk_size: EnumParam<ChunkSize>,
+
+    /// The time it takes to fade from the input to the frozen loop after the freeze engages.
+    /// Engaging again during the release fades in from wherever the release got to.
+    #[id = "attack"]
+    pub attack: FloatParam,
+
+    /// The time it takes the frozen loop to fall to the sustain level once the attack is done.
+    #[id = "freeze_decay"]
+    pub freeze_decay: FloatParam,
+
+    /// The level the frozen loop is held at while the freeze lasts, relative to the level the
+    /// attack reached. Every note runs through its own envelope with these times, and the
+    /// loudest of the notes sets the level of the loop they all play. A retriggered note swells
+    /// back up from wherever its envelope is.
+    #[id = "freeze_sustain"]
+    pub freeze_sustain: FloatParam,
+
+    /// The time it takes to fade from the frozen loop back to the input after releasing the
+    /// freeze. Released notes keep their voices until they've faded out.
+    #[id = "release"]
+    pub release: FloatParam,
+
+    /// How much of the frozen loop is kept as it plays. Below 100% every pass blends in some of
+    /// the input, so the loop slowly morphs into whatever is playing now. At 0% the input passes
+    /// straight through. Repitched, stretched and windowed loops and the authentic mode's chunks
+    /// are always held completely.
+    #[id = "hold_amount"]
+    pub hold_amount: FloatParam,
+
+    /// Slow the frozen loop down to a halt after releasing the freeze, before it fades out over
+    /// the release time. Zero releases without stopping.
+    #[id = "stop_time"]
+    pub stop_time: FloatParam,
+
+    #[id = "stop_curve"]
+    pub stop_curve: EnumParam<StopCurve>,
+
+    /// How much quieter the frozen loop gets with every repeat. Zero keeps it at full level.
+    #[id = "decay"]
+    pub decay: FloatParam,
+
+    #[id = "decay_shape"]
+    pub decay_shape: EnumParam<DecayShape>,
+
+    /// How many repeats the gated decay shape holds the loop at full level before it drops.
+    #[id = "gate_hold"]
+    pub gate_hold: IntParam,
+
+    /// Whether MIDI notes freeze the buffer. When disabled only the Freeze parameter does.
+    #[id = "midi_trigger"]
+    pub midi_trigger: BoolParam,
+
+    /// Shapes the velocities of incoming notes before anything uses them. Negative values reach
+    /// high velocities with softer playing, positive values need harder playing.
+    #[id = "velocity_curve"]
+    pub velocity_curve: FloatParam,
+
+    /// How many notes can sound at once, including the ones still fading out after their
+    /// release. Playing more notes steals the oldest voice, so a single voice plays the last
+    /// note. The notes all hold the same frozen loop rather than playing loops of their own, so
+    /// a stolen voice ends in the host right away, and the loop fades down quickly if the stolen
+    /// voice was the loudest. Lowering the count steals voices the same way.
+    #[id = "voice_count"]
+    pub voice_count: IntParam,
+
+    /// Capture the buffer again for every note played while notes hold the freeze, instead of
+    /// playing on legato. The new loop is recorded while the old one keeps playing and then
+    /// crosses over to it like a refresh.
+    #[id = "retrigger"]
+    pub retrigger: BoolParam,
+
+    /// Set the buffer size by repeatedly tapping the tap note instead of freezing with it.
+    #[id = "tap_tempo"]
+    pub tap_tempo: BoolParam,
+
+    #[id = "tap_note"]
+    pub tap_note: IntParam,
+
+    /// Use the store and recall keyswitches to manage the snapshot bank.
+    #[id = "snapshot_keys"]
+    pub snapshot_keys: BoolParam,
+
+    /// Stores the current loop in the next snapshot slot.
+    #[id = "store_note"]
+    pub store_note: IntParam,
+
+    /// The first of the keys that recall the snapshot slots, one key per slot.
+    #[id = "recall_note"]
+    pub recall_note: IntParam,
+
+    /// Switching this on brings back the loop that was frozen before the current capture and
+    /// freezes it. The loop it replaces can be brought back the same way.
+    #[id = "restore_last"]
+    pub restore_last: BoolParam,
+
+    /// Use the halve and double keyswitches to change the frozen loop's length.
+    #[id = "size_keys"]
+    pub size_keys: BoolParam,
+
+    /// Halves the frozen loop's length, starting with its next pass.
+    #[id = "halve_note"]
+    pub halve_note: IntParam,
+
+    /// Doubles the frozen loop's length, starting with its next pass.
+    #[id = "double_note"]
+    pub double_note: IntParam,
+
+    /// Delay engaging the freeze until the next line of this beat grid while the transport is
+    /// playing, so the captured loop lines up with the grid.
+    #[id = "quantize_trigger"]
+    pub quantize_trigger: EnumParam<Quantize>,
+
+    /// Also delay releasing the freeze until the next grid line.
+    #[id = "quantize_release"]
+    pub quantize_release: BoolParam,
+
+    /// Hold the last buffer when the host's transport stops, and release it again when playback
+    /// resumes.
+    #[id = "freeze_on_stop"]
+    pub freeze_on_stop: BoolParam,
+
+    /// Instances in the same link group freeze and release together.
+    #[id = "link_group"]
+    pub link_group: EnumParam<LinkGroup>,
+
+    /// Freeze while the level of the sidechain input is above the threshold.
+    #[id = "sidechain_trigger"]
+    pub sidechain_trigger: BoolParam,
+
+    #[id = "sidechain_threshold"]
+    pub sidechain_threshold: FloatParam,
+
+    /// How quickly the sidechain detector follows rising levels.
+    #[id = "sidechain_attack"]
+    pub sidechain_attack: FloatParam,
+
+    /// How quickly the sidechain detector follows falling levels. This also sets how long the
+    /// freeze is held after the sidechain drops below the threshold.
+    #[id = "sidechain_release"]
+    pub sidechain_release: FloatParam,
+
+    /// Filters the sidechain before it reaches the detector, so for instance a kick drum
+    /// doesn't trigger the freeze. The audio itself isn't filtered.
+    #[id = "sidechain_highpass"]
+    pub sidechain_highpass: FloatParam,
+
+    #[id = "sidechain_lowpass"]
+    pub sidechain_lowpass: FloatParam,
+
+    /// Replace the output with the filtered sidechain to tune the filter. This is always off
+    /// after loading a state.
+    #[id = "sidechain_listen"]
+    pub sidechain_listen: BoolParam,
+
+    /// While frozen, re-capture one loop length of the input whenever it gets louder than the
+    /// refresh threshold. The old loop keeps playing while the new one is recorded, and then
+    /// crossfades into it.
+    #[id = "refresh"]
+    pub refresh: BoolParam,
+
+    #[id = "refresh_threshold"]
+    pub refresh_threshold: FloatParam,
+}
+
+/// The parameters for the frozen loop's pitch and playback
+#[derive(Params)]
+struct PitchParams {
+    /// When enabled, MIDI notes tune the frozen loop. Depending on the note behavior they either
+    /// set the loop length to one period of the note's pitch or repitch the captured audio.
+    #[id = "key_tracking"]
+    pub key_tracking: BoolParam,
+
+    #[id = "note_behavior"]
+    pub note_behavior: EnumParam<NoteBehavior>,
+
+    /// The note that plays the frozen audio back at its original pitch when repitching.
+    #[id = "root_note"]
+    pub root_note: IntParam,
+
+    /// Keep the formants of repitched audio in place, so vocals don't sound like chipmunks.
+    #[id = "formant"]
+    pub formant: BoolParam,
+
+    /// The time it takes to slide from the previous note's loop length to the next one.
+    #[id = "glide"]
+    pub glide: FloatParam,
+
+    /// How the frozen loop is read between samples when it's repitched or tuned to a note.
+    #[id = "interpolation"]
+    pub interpolation: EnumParam<Interpolation>,
+
+    /// Read repitched and tuned loops at a multiple of the sample rate to reduce aliasing when
+    /// pitching up. This adds latency.
+    #[id = "oversampling"]
+    pub oversampling: EnumParam<Oversampling>,
+
+    /// Play the frozen loop at half speed, an octave down. Switching this while frozen waits
+    /// for the loop's next pass so the groove stays intact.
+    #[id = "half_time"]
+    pub half_time: BoolParam,
+
+    /// How long the frozen loop takes to play through relative to its length, without changing
+    /// its pitch. Anything other than 100% plays the loop as overlapping grains.
+    #[id = "stretch"]
+    pub stretch: FloatParam,
+
+    /// Repitch every pass of the frozen loop by the next step's interval, so a single captured
+    /// hit turns into a melody. The sequence starts over with the first step when the freeze
+    /// engages.
+    #[id = "sequence"]
+    pub sequence: BoolParam,
+
+    /// How many of the steps the sequence plays before it starts over.
+    #[id = "sequence_length"]
+    pub sequence_length: IntParam,
+
+    /// Estimate the pitch of every freeze's loop and hold it as a note on the MIDI output until
+    /// the freeze is released, so a synth can follow the glitches.
+    #[id = "pitch_output"]
+    pub pitch_output: BoolParam,
+
+    /// Shifts the note Pitch to MIDI sends.
+    #[id = "pitch_output_transpose"]
+    pub pitch_output_transpose: IntParam,
+
+    #[nested(array, group = "Sequence Step")]
+    pub sequence_steps: [SequenceStepParams; SEQUENCE_STEPS],
+}
+
+/// One step of the pitch sequence
+#[derive(Params)]
+struct SequenceStepParams {
+    /// The interval this step repitches its pass by.
+    #[id = "sequence_interval"]
+    pub interval: IntParam,
+}
+
+/// The effects that degrade the frozen loop
+#[derive(Params)]
+struct CharacterParams {
+    /// While frozen, blur the loop a bit more on every pass until it washes out into a drone.
+    #[id = "smear"]
+    pub smear: FloatParam,
+
+    /// Mix clicks and pops into the frozen loop, from a few per second up to a constant fry.
+    #[id = "crackle"]
+    pub crackle: FloatParam,
+
+    /// Lowpass filter the crackle into softer pops.
+    #[id = "soft_crackle"]
+    pub soft_crackle: BoolParam,
+
+    /// Reduce the frozen loop's bit depth. The highest value leaves it alone.
+    #[id = "bit_depth"]
+    pub bit_depth: IntParam,
+
+    /// Add triangular dither scaled to the bit depth before reducing it, which trades the
+    /// truncation distortion for noise.
+    #[id = "dither"]
+    pub dither: BoolParam,
+
+    /// Shape the bit reduction's noise towards the high frequencies.
+    #[id = "noise_shaping"]
+    pub noise_shaping: BoolParam,
+
+    /// Darken or brighten the frozen loop around 700 Hz to seat it in a mix.
+    #[id = "tilt"]
+    pub tilt: FloatParam,
+}
+
+/// The parameters for the frozen loop's level and stereo image
+#[derive(Params)]
+struct MixParams {
+    /// Match the frozen loop's level to the input's short-term loudness right before the freeze
+    /// engaged, within 12 dB either way.
+    #[id = "auto_gain"]
+    pub auto_gain: BoolParam,
+
+    /// Bring the frozen loop's RMS level to the target, measured over the whole loop when the
+    /// freeze engages and within 18 dB either way. This adds to Auto Gain when both are enabled.
+    #[id = "normalize"]
+    pub normalize: BoolParam,
+
+    #[id = "normalize_target"]
+    pub normalize_target: FloatParam,
+
+    /// How far each note's voice is panned by its note number, with low notes to the left and
+    /// high notes to the right.
+    #[id = "key_pan"]
+    pub key_pan: FloatParam,
+
+    /// Pan every voice to a random position instead, scaled by the Key Tracking Pan depth.
+    #[id = "random_pan"]
+    pub random_pan: BoolParam,
+
+    /// Shorten the right channel's loop slightly so the two channels drift apart, which widens
+    /// mono sources.
+    #[id = "spread"]
+    pub spread: FloatParam,
+
+    /// While frozen, feed the left and right loops into each other on every pass so they slowly
+    /// swap sides. This only applies to stereo inputs.
+    #[id = "cross_feed"]
+    pub cross_feed: FloatParam,
+
+    /// While frozen, move the wet signals on to the next channel whenever the loop wraps, so
+    /// successive repeats alternate sides. The dry signals stay where they are.
+    #[id = "channel_rotate"]
+    pub channel_rotate: EnumParam<ChannelRotate>,
+}
+
+/// The random and periodic modulation of the frozen loop's playback
+#[derive(Params)]
+struct ModulationParams {
+    /// How many times per second the frozen loop hitches, slowing down for a moment and then
+    /// snapping back like a PC under load.
+    #[id = "hitch_rate"]
+    pub hitch_rate: FloatParam,
+
+    /// How far the playback rate dips during a hitch.
+    #[id = "hitch_depth"]
+    pub hitch_depth: FloatParam,
+
+    #[id = "vibrato_rate"]
+    pub vibrato_rate: FloatParam,
+
+    /// How far the vibrato bends the frozen loop's pitch in either direction. Zero turns the
+    /// vibrato off.
+    #[id = "vibrato_depth"]
+    pub vibrato_depth: FloatParam,
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[name = "Musical"]
+    Musical,
+    /// The last complete hardware chunk of a free-running sample counter repeats with raw
+    /// splices. Loop lengths, repitching, windows, stretching, reversing, the release and stop,
+    /// interpolation, oversampling and the recall crossfade are all bypassed.
+    #[name = "Authentic"]
+    Authentic,
+}
+
+/// The soundcard block sizes the authentic mode can loop
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkSize {
+    #[name = "256"]
+    Samples256,
+    #[name = "512"]
+    Samples512,
+    #[name = "1024"]
+    Samples1024,
+    #[name = "2048"]
+    Samples2048,
+    #[name = "4096"]
+    Samples4096,
+}
+
+impl ChunkSize {
+    pub fn samples(self) -> usize {
+        256 << self.to_index()
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteBehavior {
+    /// Notes set the loop length to one period of their pitch
+    #[name = "Length Tuned"]
+    LengthTuned,
+    /// Notes change the playback rate relative to the root note, like a sampler
+    #[name = "Repitch"]
+    Repitch,
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Division {
+    #[name = "Off"]
+    Off,
+    #[name = "1/1"]
+    Whole,
+    #[name = "1/2"]
+    Half,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/32"]
+    ThirtySecond,
+}
+
+impl Division {
+    /// The length of the division in quarter notes
+    pub fn beats(self) -> Option<f32> {
+        match self {
+            Division::Off => None,
+            Division::Whole => Some(4.),
+            Division::Half => Some(2.),
+            Division::Quarter => Some(1.),
+            Division::Eighth => Some(0.5),
+            Division::Sixteenth => Some(0.25),
+            Division::ThirtySecond => Some(0.125),
+        }
+    }
+
+    /// Step `steps` divisions faster, stopping at the fastest one. `Off` stays off.
+    pub fn faster(self, steps: usize) -> Self {
+        match self {
+            Division::Off => Division::Off,
+            division => Division::from_index(
+                (division.to_index() + steps).min(Division::ThirtySecond.to_index()),
+            ),
+        }
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantize {
+    #[name = "Off"]
+    Off,
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1 Bar"]
+    Bar,
+}
+
+impl Quantize {
+    /// The grid size in quarter notes for a bar of `bar_beats` quarter notes
+    pub fn beats(self, bar_beats: f64) -> Option<f64> {
+        match self {
+            Quantize::Off => None,
+            Quantize::Sixteenth => Some(0.25),
+            Quantize::Eighth => Some(0.5),
+            Quantize::Quarter => Some(1.),
+            Quantize::Bar => Some(bar_beats),
+        }
+    }
+}
+
+/// What happens to the recorded buffers when the Buffer Size changes while the loop is held
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeWhileFrozen {
+    /// Keep the loop as it is and resize the buffers once the release has faded out
+    #[name = "Defer"]
+    Defer,
+    /// Change the loop length right away but keep the samples outside of it, so growing the
+    /// buffer again brings the frozen audio back
+    #[name = "Window"]
+    Window,
+    /// Clear the samples outside of the new size, like a resize while recording
+    #[name = "Destructive"]
+    Destructive,
+}
+
+/// How the playback rate falls to zero when the freeze stops after the release
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCurve {
+    /// The rate falls linearly, like a tape machine that's stopped
+    #[name = "Tape"]
+    Tape,
+    /// The rate falls quickly at first and then slows down, like a turntable that's switched off
+    #[name = "Vinyl"]
+    Vinyl,
+    /// The rate falls in a few discrete steps
+    #[name = "Stepped"]
+    Stepped,
+}
+
+impl StopCurve {
+    /// The playback rate at `progress` through the stop relative to the rate before it. This
+    /// starts out at 1 and is exactly 0 at the end.
+    pub fn rate(self, progress: f32) -> f32 {
+        let progress = progress.clamp(0., 1.);
+        match self {
+            StopCurve::Tape => 1. - progress,
+            StopCurve::Vinyl => {
+                // The decay is shifted and scaled so it reaches zero
+                let end = (-VINYL_STOP_DECAY).exp();
+                ((-VINYL_STOP_DECAY * progress).exp() - end) / (1. - end)
+            },
+            StopCurve::Stepped => ((1. - progress) * STOP_STEPS).ceil() / STOP_STEPS,
+        }
+    }
+}
+
+/// How the frozen loop's level falls from repeat to repeat with the Decay parameter
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecayShape {
+    /// The level falls by the same number of decibels with every repeat, which sounds natural
+    #[name = "Exponential"]
+    Exponential,
+    /// The level falls by the same amount with every repeat, so it dies out quickly
+    #[name = "Linear"]
+    Linear,
+    /// The loop holds its level for the Gate Hold's repeats and then drops out, like a gated
+    /// reverb
+    #[name = "Gated"]
+    Gated,
+}
+
+impl DecayShape {
+    /// The gain after `repeats` passes through the loop, where the first repeat is `decay_db`
+    /// quieter. The gated shape holds for `hold` repeats and fades out over `fade_repeats`.
+    /// Gains that are effectively silent are exactly zero.
+    pub fn gain(self, repeats: f32, decay_db: f32, hold: f32, fade_repeats: f32) -> f32 {
+        if decay_db <= 0. {
+            return 1.;
+        }
+
+        let gain = match self {
+            DecayShape::Exponential => util::db_to_gain(-decay_db * repeats),
+            DecayShape::Linear => 1. - repeats * (1. - util::db_to_gain(-decay_db)),
+            DecayShape::Gated => 1. - (repeats - hold) / fade_repeats.max(f32::EPSILON),
+        };
+        if gain < DECAY_SILENCE {
+            0.
+        } else {
+            gain.min(1.)
+        }
+    }
+}
+
+/// How often Channel Rotate moves the wet signals on to the next channel. With two channels this
+/// swaps left and right.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelRotate {
+    #[name = "Off"]
+    Off,
+    #[name = "Swap Every Repeat"]
+    EveryRepeat,
+    #[name = "Swap Every 2"]
+    EveryTwoRepeats,
+}
+
+impl ChannelRotate {
+    /// Whether the wet signals move on when the loop starts its pass with the number `repeat`,
+    /// counted from zero
+    pub(crate) fn rotates_on(self, repeat: u64) -> bool {
+        match self {
+            ChannelRotate::Off => false,
+            ChannelRotate::EveryRepeat => true,
+            ChannelRotate::EveryTwoRepeats => repeat.is_multiple_of(2),
+        }
+    }
 }
 
 impl Default for WinXpCrash {
     fn default() -> Self {
+        let params = Arc::new(WinXpCrashParams::default());
+        let shared = Shared {
+            buffer_state: params.buffer_state.clone(),
+            snapshot_bank: params.snapshot_bank.clone(),
+            waveform: params.waveform.clone(),
+            frozen: params.frozen.clone(),
+            size_display: params.size_display.clone(),
+            ..Shared::default()
+        };
+
         Self {
-            params: Arc::new(WinXpCrashParams::default()),
-            channel_buffers: vec![],
-            note_freezing: false,
+            params,
+            engine: FreezeEngine::with_shared(shared),
         }
     }
 }
 
 impl Default for WinXpCrashParams {
     fn default() -> Self {
+        let host_tempo = Arc::new(AtomicU64::new(DEFAULT_TEMPO.to_bits()));
+        let size_display = Arc::new(SizeDisplay::default());
+
+        Self {
+            capture: CaptureParams::new(host_tempo.clone(), size_display.clone()),
+            freezing: FreezeParams::default(),
+            pitch: PitchParams::default(),
+            character: CharacterParams::default(),
+            mix: MixParams::default(),
+            modulation: ModulationParams::default(),
+            buffer_state: Arc::new(RwLock::new(BufferState::default())),
+            snapshot_bank: Arc::new(RwLock::new(SnapshotBank::default())),
+            export_wav: BoolParam::new(
+                "Export WAV",
+                false,
+            ),
+            export_dir: RwLock::new(None),
+            import_wav: BoolParam::new(
+                "Import WAV",
+                false,
+            ),
+            import_path: RwLock::new(None),
+            import_error: Mutex::new(None),
+            ab_compare: RwLock::new(AbCompare::default()),
+            preset_buffer: Mutex::new(None),
+            state_version: RwLock::new(STATE_VERSION),
+            host_tempo,
+            size_display,
+            sample_rate: AtomicU32::new(0),
+            frozen: Arc::new(AtomicBool::new(false)),
+            track_info: track_info::take_pending(),
+            waveform: Arc::new(Waveform::default()),
+            spectrum: SpectrumFifo::default(),
+            meters: Meters::default(),
+            show_spectrum: AtomicBool::new(true),
+            show_bsod: AtomicBool::new(true),
+            randomizer_locks: RwLock::new(BTreeSet::new()),
+            theme: RwLock::new(Theme::default()),
+            editor_state: editor::default_state(),
+        }
+    }
+}
+
+impl WinXpCrashParams {
+    /// The engine's settings for the parameters' current values
+    fn settings(&self) -> Settings {
+        let (capture, freezing, pitch) = (&self.capture, &self.freezing, &self.pitch);
+        let (character, mix, modulation) = (&self.character, &self.mix, &self.modulation);
+        Settings {
+            buffer_size: capture.buffer_size.value(),
+            buffer_size_normalized: capture.buffer_size.modulated_normalized_value(),
+            size_unit: capture.size_unit.value(),
+            glide_buffer_size: capture.glide_buffer_size.value(),
+            resize_while_frozen: capture.resize_while_frozen.value(),
+            division: Division::from_index(capture.division.value() as usize),
+            velocity_division: capture.velocity_division.value(),
+            capture_reversed: capture.capture_reversed.value(),
+            loop_start: capture.loop_start.value(),
+            loop_length: capture.loop_length.value(),
+
+            freeze: freezing.freeze.value(),
+            freeze_amount: freezing.freeze_amount.value(),
+            trigger_one_loop: freezing.trigger_one_loop.value(),
+            trigger_repeats: freezing.trigger_repeats.value(),
+            mode: freezing.mode.value(),
+            chunk_size: freezing.chunk_size.value(),
+            attack: freezing.attack.value(),
+            freeze_decay: freezing.freeze_decay.value(),
+            freeze_sustain: freezing.freeze_sustain.value(),
+            release: freezing.release.value(),
+            hold_amount: freezing.hold_amount.value(),
+            stop_time: freezing.stop_time.value(),
+            stop_curve: freezing.stop_curve.value(),
+            decay: freezing.decay.value(),
+            decay_shape: freezing.decay_shape.value(),
+            gate_hold: freezing.gate_hold.value(),
+            midi_trigger: freezing.midi_trigger.value(),
+            velocity_curve: freezing.velocity_curve.value(),
+            voice_count: freezing.voice_count.value(),
+            retrigger: freezing.retrigger.value(),
+            tap_tempo: freezing.tap_tempo.value(),
+            tap_note: freezing.tap_note.value(),
+            snapshot_keys: freezing.snapshot_keys.value(),
+            store_note: freezing.store_note.value(),
+            recall_note: freezing.recall_note.value(),
+            restore_last: freezing.restore_last.value(),
+            size_keys: freezing.size_keys.value(),
+            halve_note: freezing.halve_note.value(),
+            double_note: freezing.double_note.value(),
+            quantize_trigger: freezing.quantize_trigger.value(),
+            quantize_release: freezing.quantize_release.value(),
+            freeze_on_stop: freezing.freeze_on_stop.value(),
+            link_group: freezing.link_group.value(),
+            sidechain_trigger: freezing.sidechain_trigger.value(),
+            sidechain_threshold: freezing.sidechain_threshold.value(),
+            sidechain_attack: freezing.sidechain_attack.value(),
+            sidechain_release: freezing.sidechain_release.value(),
+            sidechain_highpass: freezing.sidechain_highpass.value(),
+            sidechain_lowpass: freezing.sidechain_lowpass.value(),
+            sidechain_listen: freezing.sidechain_listen.value(),
+            refresh: freezing.refresh.value(),
+            refresh_threshold: freezing.refresh_threshold.value(),
+
+            key_tracking: pitch.key_tracking.value(),
+            note_behavior: pitch.note_behavior.value(),
+            root_note: pitch.root_note.value(),
+            formant: pitch.formant.value(),
+            glide: pitch.glide.value(),
+            interpolation: pitch.interpolation.value(),
+            oversampling: pitch.oversampling.value(),
+            half_time: pitch.half_time.value(),
+            stretch: pitch.stretch.value(),
+            sequence: pitch.sequence.value(),
+            sequence_length: pitch.sequence_length.value(),
+            sequence_intervals: std::array::from_fn(|step| {
+                pitch.sequence_steps[step].interval.value()
+            }),
+            pitch_output: pitch.pitch_output.value(),
+            pitch_output_transpose: pitch.pitch_output_transpose.value(),
+
+            smear: character.smear.value(),
+            crackle: character.crackle.value(),
+            soft_crackle: character.soft_crackle.value(),
+            bit_depth: character.bit_depth.value(),
+            dither: character.dither.value(),
+            noise_shaping: character.noise_shaping.value(),
+            tilt: character.tilt.value(),
+
+            auto_gain: mix.auto_gain.value(),
+            normalize: mix.normalize.value(),
+            normalize_target: mix.normalize_target.value(),
+            key_pan: mix.key_pan.value(),
+            random_pan: mix.random_pan.value(),
+            spread: mix.spread.value(),
+            cross_feed: mix.cross_feed.value(),
+            channel_rotate: mix.channel_rotate.value(),
+
+            hitch_rate: modulation.hitch_rate.value(),
+            hitch_depth: modulation.hitch_depth.value(),
+            vibrato_rate: modulation.vibrato_rate.value(),
+            vibrato_depth: modulation.vibrato_depth.value(),
+
+            export_wav: self.export_wav.value(),
+            import_wav: self.import_wav.value(),
+            metering: self.editor_state.is_open(),
+        }
+    }
+}
+
+impl CaptureParams {
+    fn new(host_tempo: Arc<AtomicU64>, size_display: Arc<SizeDisplay>) -> Self {
         Self {
-            // This gain is stored as linear gain. NIH-plug comes with useful conversion functions
-            // to treat these kinds of parameters as if we were dealing with decibels. Storing this
-            // as decibels is easier to work with, but requires a conversion for every sample.
             buffer_size: IntParam::new(
                 "Buffer Size",
                 1024,
-                IntRange::Linear { min: 128, max: MAX_BUFFER_SIZE as i32 }
+                IntRange::Linear { min: MIN_BUFFER_SIZE as i32, max: MAX_BUFFER_SIZE as i32 }
+            )
+            .with_poly_modulation_id(BUFFER_SIZE_POLY_MOD_ID)
+            .with_value_to_string(v2s_buffer_size(size_display.clone()))
+            .with_string_to_value(s2v_buffer_size(size_display)),
+            size_unit: EnumParam::new("Size Unit", SizeUnit::Samples),
+            glide_buffer_size: BoolParam::new(
+                "Glide Buffer Size",
+                false,
             ),
+            resize_while_frozen: EnumParam::new("Resize While Frozen", ResizeWhileFrozen::Window),
+            division: IntParam::new(
+                "Division",
+                Division::Off.to_index() as i32,
+                IntRange::Linear { min: 0, max: Division::ThirtySecond.to_index() as i32 },
+            )
+            .with_value_to_string(v2s_division(host_tempo.clone()))
+            // The length that's displayed after the division's name is ignored
+            .with_string_to_value(Arc::new(|string| {
+                let name = string.split_whitespace().next()?;
+                Division::variants()
+                    .iter()
+                    .position(|variant| variant.eq_ignore_ascii_case(name))
+                    .map(|index| index as i32)
+            })),
+            velocity_division: FloatParam::new(
+                "Velocity → Division",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            capture_reversed: BoolParam::new("Capture Reversed", false),
+            loop_start: FloatParam::new(
+                "Loop Start",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_smoother(SmoothingStyle::Linear(20.))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            loop_length: FloatParam::new(
+                "Loop Length",
+                1.,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 1.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(20.))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+        }
+    }
+}
+
+impl Default for FreezeParams {
+    fn default() -> Self {
+        Self {
             freeze: BoolParam::new(
                 "Freeze",
                 false,
+            ),
+            freeze_amount: FloatParam::new(
+                "Freeze Amount",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_smoother(SmoothingStyle::Linear(10.))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            trigger_one_loop: BoolParam::new(
+                "Trigger One Loop",
+                false,
+            ),
+            trigger_repeats: IntParam::new(
+                "Trigger Repeats",
+                1,
+                IntRange::Linear { min: 1, max: MAX_TRIGGER_REPEATS },
+            ),
+            mode: EnumParam::new("Mode", Mode::Musical),
+            chunk_size: EnumParam::new("Chunk Size", ChunkSize::Samples1024),
+            attack: FloatParam::new(
+                "Freeze Attack",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 500.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            freeze_decay: FloatParam::new(
+                "Freeze Decay",
+                200.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 5000.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            freeze_sustain: FloatParam::new(
+                "Freeze Sustain",
+                1.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            release: FloatParam::new(
+                "Freeze Release",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 2000.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            hold_amount: FloatParam::new(
+                "Hold Amount",
+                1.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            stop_time: FloatParam::new(
+                "Stop Time",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 5000.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            stop_curve: EnumParam::new("Stop Curve", StopCurve::Tape),
+            decay: FloatParam::new(
+                "Decay",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 24.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            decay_shape: EnumParam::new("Decay Shape", DecayShape::Exponential),
+            gate_hold: IntParam::new(
+                "Gate Hold",
+                4,
+                IntRange::Linear { min: 1, max: 32 },
+            ),
+            midi_trigger: BoolParam::new(
+                "MIDI Trigger",
+                true,
+            ),
+            velocity_curve: FloatParam::new(
+                "Velocity Curve",
+                0.,
+                FloatRange::Linear { min: -1., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            voice_count: IntParam::new(
+                "Voice Count",
+                MAX_VOICES as i32,
+                IntRange::Linear { min: 1, max: MAX_VOICES as i32 },
+            ),
+            retrigger: BoolParam::new(
+                "Retrigger",
+                false,
+            ),
+            tap_tempo: BoolParam::new(
+                "Tap Tempo",
+                false,
+            ),
+            tap_note: IntParam::new(
+                "Tap Note",
+                24,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            snapshot_keys: BoolParam::new(
+                "Snapshot Keys",
+                false,
+            ),
+            store_note: IntParam::new(
+                "Store Note",
+                12,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            recall_note: IntParam::new(
+                "Recall Note",
+                14,
+                IntRange::Linear { min: 0, max: 127 - NUM_SNAPSHOTS as i32 + 1 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            restore_last: BoolParam::new("Restore Last", false),
+            size_keys: BoolParam::new(
+                "Size Keys",
+                false,
+            ),
+            halve_note: IntParam::new(
+                "Halve Note",
+                0,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            double_note: IntParam::new(
+                "Double Note",
+                2,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            quantize_trigger: EnumParam::new("Quantize Trigger", Quantize::Off),
+            quantize_release: BoolParam::new(
+                "Quantize Release",
+                false,
+            ),
+            freeze_on_stop: BoolParam::new(
+                "Freeze on Stop",
+                false,
+            ),
+            link_group: EnumParam::new("Link Group", LinkGroup::Off),
+            sidechain_trigger: BoolParam::new(
+                "Sidechain Trigger",
+                false,
+            ),
+            sidechain_threshold: FloatParam::new(
+                "Sidechain Threshold",
+                util::db_to_gain(-20.),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-60.),
+                    max: util::db_to_gain(0.),
+                    factor: FloatRange::gain_skew_factor(-60., 0.),
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            sidechain_attack: FloatParam::new(
+                "Sidechain Attack",
+                1.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 100.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            sidechain_release: FloatParam::new(
+                "Sidechain Release",
+                100.,
+                FloatRange::Skewed {
+                    min: 1.,
+                    max: 2000.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            sidechain_highpass: FloatParam::new(
+                "Sidechain High-Pass",
+                20.,
+                FloatRange::Skewed {
+                    min: 20.,
+                    max: 20000.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            sidechain_lowpass: FloatParam::new(
+                "Sidechain Low-Pass",
+                20000.,
+                FloatRange::Skewed {
+                    min: 20.,
+                    max: 20000.,
+                    factor: FloatRange::skew_factor(-2.),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            sidechain_listen: BoolParam::new("Sidechain Listen", false),
+            refresh: BoolParam::new("Refresh", false),
+            refresh_threshold: FloatParam::new(
+                "Refresh Threshold",
+                util::db_to_gain(-20.),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-60.),
+                    max: util::db_to_gain(0.),
+                    factor: FloatRange::gain_skew_factor(-60., 0.),
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+        }
+    }
+}
+
+impl Default for PitchParams {
+    fn default() -> Self {
+        Self {
+            key_tracking: BoolParam::new(
+                "Key Tracking",
+                false,
+            ),
+            note_behavior: EnumParam::new("Note Behavior", NoteBehavior::LengthTuned),
+            root_note: IntParam::new(
+                "Root Note",
+                60,
+                IntRange::Linear { min: 0, max: 127 },
+            )
+            .with_value_to_string(formatters::v2s_i32_note_formatter())
+            .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            formant: BoolParam::new("Formant", false),
+            glide: FloatParam::new(
+                "Glide",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 2000.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            interpolation: EnumParam::new("Interp", Interpolation::Linear),
+            oversampling: EnumParam::new("Oversampling", Oversampling::Off),
+            half_time: BoolParam::new("Half-Time", false),
+            stretch: FloatParam::new(
+                "Stretch",
+                1.,
+                FloatRange::SymmetricalSkewed {
+                    min: 0.25,
+                    max: 4.,
+                    factor: 1.,
+                    center: 1.,
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            sequence: BoolParam::new("Pitch Sequence", false),
+            sequence_length: IntParam::new(
+                "Sequence Steps",
+                SEQUENCE_STEPS as i32,
+                IntRange::Linear { min: 1, max: SEQUENCE_STEPS as i32 },
+            ),
+            pitch_output: BoolParam::new(
+                "Pitch to MIDI",
+                false,
+            ),
+            pitch_output_transpose: IntParam::new(
+                "Pitch to MIDI Transpose",
+                0,
+                IntRange::Linear {
+                    min: -(MAX_TRANSPOSE_SEMITONES as i32),
+                    max: MAX_TRANSPOSE_SEMITONES as i32,
+                },
+            )
+            .with_unit(" st"),
+            sequence_steps: std::array::from_fn(SequenceStepParams::new),
+        }
+    }
+}
+
+impl SequenceStepParams {
+    fn new(step: usize) -> Self {
+        Self {
+            interval: IntParam::new(
+                format!("Step {} Interval", step + 1),
+                0,
+                IntRange::Linear {
+                    min: -MAX_SEQUENCE_INTERVAL,
+                    max: MAX_SEQUENCE_INTERVAL,
+                },
+            )
+            .with_unit(" st"),
+        }
+    }
+}
+
+impl Default for CharacterParams {
+    fn default() -> Self {
+        Self {
+            smear: FloatParam::new(
+                "Smear",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            crackle: FloatParam::new(
+                "Crackle",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            soft_crackle: BoolParam::new("Soft Crackle", false),
+            bit_depth: IntParam::new(
+                "Bit Depth",
+                BYPASS_BIT_DEPTH,
+                IntRange::Linear { min: 1, max: BYPASS_BIT_DEPTH },
             )
+            .with_value_to_string(Arc::new(|value| {
+                if value >= BYPASS_BIT_DEPTH {
+                    "Off".to_owned()
+                } else {
+                    format!("{value} bit")
+                }
+            }))
+            .with_string_to_value(Arc::new(|string| {
+                let string = string.trim();
+                if string.eq_ignore_ascii_case("off") {
+                    Some(BYPASS_BIT_DEPTH)
+                } else {
+                    string.trim_end_matches("bit").trim().parse().ok()
+                }
+            })),
+            dither: BoolParam::new("Dither", false),
+            noise_shaping: BoolParam::new("Noise Shaping", false),
+            tilt: FloatParam::new(
+                "Tilt",
+                0.,
+                FloatRange::Linear { min: -6., max: 6. },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+        }
+    }
+}
+
+impl Default for MixParams {
+    fn default() -> Self {
+        Self {
+            auto_gain: BoolParam::new("Auto Gain", false),
+            normalize: BoolParam::new("Normalize", false),
+            normalize_target: FloatParam::new(
+                "Normalize Target",
+                -18.,
+                FloatRange::Linear { min: -40., max: 0. },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            key_pan: FloatParam::new(
+                "Key Tracking Pan",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            random_pan: BoolParam::new(
+                "Random Pan",
+                false,
+            ),
+            spread: FloatParam::new(
+                "Spread",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            cross_feed: FloatParam::new(
+                "Cross Feed",
+                0.,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            channel_rotate: EnumParam::new("Channel Rotate", ChannelRotate::Off),
+        }
+    }
+}
+
+impl Default for ModulationParams {
+    fn default() -> Self {
+        Self {
+            hitch_rate: FloatParam::new(
+                "Hitch Rate",
+                0.,
+                FloatRange::Skewed {
+                    min: 0.,
+                    max: 10.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" /s")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            hitch_depth: FloatParam::new(
+                "Hitch Depth",
+                0.5,
+                FloatRange::Linear { min: 0., max: 1. },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            vibrato_rate: FloatParam::new(
+                "Vibrato Rate",
+                5.,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.,
+                    factor: FloatRange::skew_factor(-1.),
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            vibrato_depth: FloatParam::new(
+                "Vibrato Depth",
+                0.,
+                FloatRange::Linear { min: 0., max: 100. },
+            )
+            .with_unit(" cents")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
         }
     }
 }
@@ -72,8 +1544,9 @@ impl Plugin for WinXpCrash {
         main_input_channels: NonZeroU32::new(2),
         main_output_channels: NonZeroU32::new(2),
 
-        aux_input_ports: &[],
-        aux_output_ports: &[],
+        aux_input_ports: &[new_nonzero_u32(2)],
+        // The wet signal on its own
+        aux_output_ports: &[new_nonzero_u32(2)],
 
         // Individual ports and the layout as a whole can be named here. By default these names
         // are generated as needed. This layout will be called 'Stereo', while a layout with
@@ -84,86 +1557,292 @@ impl Plugin for WinXpCrash {
         main_input_channels: NonZeroU32::new(1),
         main_output_channels: NonZeroU32::new(1),
 
-        aux_input_ports: &[],
-        aux_output_ports: &[],
+        aux_input_ports: &[new_nonzero_u32(1)],
+        aux_output_ports: &[new_nonzero_u32(1)],
 
         names: PortNames::const_default(),
     },
+    // The single input is recorded into both channels' buffers, use Spread to widen it
+    AudioIOLayout {
+        main_input_channels: NonZeroU32::new(1),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: &[new_nonzero_u32(1)],
+        aux_output_ports: &[new_nonzero_u32(2)],
+
+        names: PortNames {
+            layout: Some("Mono to Stereo"),
+            ..PortNames::const_default()
+        },
+    },
+    // Every channel gets its own buffer, so surround layouts just work. The stereo sidechain is
+    // enough to trigger the freeze.
+    AudioIOLayout {
+        main_input_channels: NonZeroU32::new(4),
+        main_output_channels: NonZeroU32::new(4),
+
+        aux_input_ports: &[new_nonzero_u32(2)],
+        aux_output_ports: &[new_nonzero_u32(4)],
+
+        names: PortNames {
+            layout: Some("Quad"),
+            ..PortNames::const_default()
+        },
+    },
+    AudioIOLayout {
+        main_input_channels: NonZeroU32::new(6),
+        main_output_channels: NonZeroU32::new(6),
+
+        aux_input_ports: &[new_nonzero_u32(2)],
+        aux_output_ports: &[new_nonzero_u32(6)],
+
+        names: PortNames {
+            layout: Some("5.1"),
+            ..PortNames::const_default()
+        },
+    },
+    AudioIOLayout {
+        main_input_channels: NonZeroU32::new(8),
+        main_output_channels: NonZeroU32::new(8),
+
+        aux_input_ports: &[new_nonzero_u32(2)],
+        aux_output_ports: &[new_nonzero_u32(8)],
+
+        names: PortNames {
+            layout: Some("7.1"),
+            ..PortNames::const_default()
+        },
+    },
     ];
 
 
     const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
-    const MIDI_OUTPUT: MidiConfig = MidiConfig::None;
+    const MIDI_OUTPUT: MidiConfig = MidiConfig::Basic;
 
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     // If the plugin can send or receive SysEx messages, it can define a type to wrap around those
     // messages here. The type implements the `SysExMessage` trait, which allows conversion to and
     // from plain byte buffers.
-    type SysExMessage = ();
+    type SysExMessage = SysEx;
     // More advanced plugins can use this to run expensive background tasks. See the field's
     // documentation for more information. `()` means that the plugin does not have any background
     // tasks.
-    type BackgroundTask = ();
+    type BackgroundTask = Task;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        // OSC messages are applied through the editor's context like any change made in it
+        editor::create(self.params.clone(), async_executor)
+            .map(|editor| osc::attach(editor, self.params.clone()))
+    }
+
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let shared = self.engine.shared();
+        let export_buffer = shared.export_buffer.clone();
+        let import_buffer = shared.import_buffer.clone();
+        let import_ready = shared.import_ready.clone();
+        Box::new(move |task| match task {
+            Task::ExportWav => {
+                let Ok(export_buffer) = export_buffer.lock() else {
+                    return;
+                };
+                let directory = params
+                    .export_dir
+                    .read()
+                    .ok()
+                    .and_then(|directory| directory.clone())
+                    .unwrap_or_else(wav::default_directory);
+
+                match wav::export(&directory, &export_buffer.channels, export_buffer.sample_rate) {
+                    Ok(path) => nih_log!("Exported the buffer to '{}'", path.display()),
+                    Err(err) => nih_error!("Could not export the buffer: {err}"),
+                }
+            },
+            Task::ImportWav { sample_rate } => {
+                let Some(path) = params.import_path.read().ok().and_then(|path| path.clone())
+                else {
+                    nih_warn!("No WAV file to import has been set");
+                    set_import_error(&params, "No WAV file to import has been set".to_owned());
+                    return;
+                };
+
+                match wav::import(&path, sample_rate) {
+                    Ok(channels) => {
+                        let Ok(mut import_buffer) = import_buffer.lock() else {
+                            return;
+                        };
+                        import_buffer.channels = channels;
+                        import_buffer.sample_rate = sample_rate;
+                        import_buffer.frozen = true;
+                        import_ready.store(true, Ordering::Release);
+                        if let Ok(mut import_error) = params.import_error.lock() {
+                            *import_error = None;
+                        }
+                    },
+                    Err(err) => {
+                        nih_error!("Could not import '{}': {err}", path.display());
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        set_import_error(&params, format!("Could not import {name}: {err}"));
+                    },
+                }
+            },
+            Task::LoadPresetBuffer { sample_rate } => {
+                let Some(buffer) = params
+                    .preset_buffer
+                    .lock()
+                    .ok()
+                    .and_then(|mut buffer| buffer.take())
+                else {
+                    return;
+                };
+                if buffer.sample_rate <= 0. {
+                    return;
+                }
+
+                // The audio thread can only restore loops at its own sample rate
+                let ratio = sample_rate as f64 / buffer.sample_rate as f64;
+                let Ok(mut import_buffer) = import_buffer.lock() else {
+                    return;
+                };
+                import_buffer.channels = buffer
+                    .channels
+                    .iter()
+                    .map(|channel| buffer::resample(channel, ratio))
+                    .collect();
+                import_buffer.sample_rate = sample_rate;
+                import_buffer.frozen = buffer.frozen;
+                import_ready.store(true, Ordering::Release);
+            },
+        })
+    }
+
+    fn filter_state(state: &mut PluginState) {
+        state::migrate(state, &WinXpCrashParams::default());
+        state::disable_debug_params(state);
+    }
+
     fn initialize(
         &mut self,
         audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        let default_buffer = RingBuffer::new(self.params.buffer_size.value() as usize);
-        let num_channels = Into::<u32>::into(audio_io_layout.main_input_channels.unwrap());
+        // Every output channel gets its own buffer, even when they're all fed from a mono input
+        let num_input_channels = Into::<u32>::into(audio_io_layout.main_input_channels.unwrap());
+        let num_channels = audio_io_layout
+            .main_output_channels
+            .map_or(num_input_channels, Into::<u32>::into);
+        let layout = Layout {
+            channels: num_channels as usize,
+            mono_input: num_input_channels == 1 && num_channels > 1,
+            sidechain_channels: audio_io_layout
+                .aux_input_ports
+                .first()
+                .map_or(0, |channels| channels.get() as usize),
+        };
 
-        self.channel_buffers = vec![default_buffer; num_channels as usize];
+        self.update_settings();
+        let max_block_size = buffer_config.max_buffer_size as usize;
+        self.engine.prepare_layout(buffer_config.sample_rate, max_block_size, layout);
+        context.set_latency_samples(self.engine.latency_samples());
+        context.set_current_voice_capacity(self.engine.voice_capacity());
+        self.params.sample_rate.store(buffer_config.sample_rate.to_bits(), Ordering::Relaxed);
 
         true
     }
 
+    fn deactivate(&mut self) {
+        self.update_settings();
+        self.engine.deactivate();
+    }
+
+    fn reset(&mut self) {
+        self.update_settings();
+        self.engine.reset();
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        while let Some(event) = context.next_event() {
-            match event {
-                NoteEvent::NoteOn { note, .. } => {
-                    self.note_freezing = true;
-                },
-                NoteEvent::NoteOff { note, .. } => {
-                    self.note_freezing = false;
-                },
-                _ => {},
-            }
-        }
+        self.process_block(buffer, aux, context)
+    }
+}
+
+impl WinXpCrash {
+    /// `process()` for any `Host`, so the offline renderer runs the exact same code
+    fn process_block(
+        &mut self,
+        buffer: &mut Buffer,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl Host,
+    ) -> ProcessStatus {
+        let tempo = context.transport().tempo.unwrap_or(DEFAULT_TEMPO);
+        self.params.host_tempo.store(tempo.to_bits(), Ordering::Relaxed);
+        self.update_settings();
+        let num_samples = buffer.samples();
+        let params = &self.params;
+        let ramps = self.engine.ramps_mut();
+        params.capture.loop_start.smoothed.next_block(&mut ramps.loop_start, num_samples);
+        params.capture.loop_length.smoothed.next_block(&mut ramps.loop_length, num_samples);
+        params.pitch.stretch.smoothed.next_block(&mut ramps.stretch, num_samples);
+        params.freezing.hold_amount.smoothed.next_block(&mut ramps.hold_amount, num_samples);
+        params.character.tilt.smoothed.next_block(&mut ramps.tilt, num_samples);
+        params.freezing.freeze_amount.smoothed.next_block(&mut ramps.freeze_amount, num_samples);
 
-        for mut channel_sample in buffer.iter_samples() {
-            for (i, channel_buffer) in self.channel_buffers.iter_mut().enumerate() {
-                let sample = channel_sample.get_mut(i).expect("More buffers than channels created");
-                *sample = channel_buffer.next_item(*sample);
+        // Hosts that don't connect the sidechain may not provide the buffer at all, which is
+        // treated like silence
+        let sidechain = aux.inputs.first().map(|input| input.as_slice_immutable());
+        let wet_output = aux.outputs.first_mut().map(|output| output.as_slice());
+        let channels = buffer.as_slice();
+        let playing =
+            self.engine.process_with(channels, sidechain, wet_output, &mut EngineHost(context));
+
+        // The editor's displays are only fed while it's open
+        if self.engine.settings().metering {
+            if self.params.show_spectrum.load(Ordering::Relaxed) {
+                let num_channels = channels.len() as f32;
+                for sample_id in 0..num_samples {
+                    let mix = channels.iter().map(|channel| channel[sample_id]).sum::<f32>();
+                    self.params.spectrum.push(mix / num_channels);
+                }
             }
+            let levels = self.engine.levels();
+            self.params.meters.publish(&levels[0], &levels[1]);
         }
 
-        for channel_buffer in self.channel_buffers.iter_mut() {
-            channel_buffer.resize(self.params.buffer_size.value() as usize);
-            channel_buffer.freezing = self.params.freeze.value() || self.note_freezing;
-        } 
+        // The frozen loop is audible regardless of the input, so hosts that suspend plugins on
+        // silent input need to keep calling us until the release has faded out
+        if playing {
+            ProcessStatus::KeepAlive
+        } else {
+            ProcessStatus::Normal
+        }
+    }
 
-        ProcessStatus::Normal
+    /// Copy the parameters into the engine's settings
+    fn update_settings(&mut self) {
+        *self.engine.settings_mut() = self.params.settings();
     }
 }
-
 impl ClapPlugin for WinXpCrash {
     const CLAP_ID: &'static str = "net.fhannenheim.win-xp-crash";
     const CLAP_DESCRIPTION: Option<&'static str> = Some("The sound of a Windows XP PC crashing as audioplugin");
     const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
     const CLAP_SUPPORT_URL: Option<&'static str> = None;
 
+    const CLAP_POLY_MODULATION_CONFIG: Option<PolyModulationConfig> = Some(PolyModulationConfig {
+        max_voice_capacity: MAX_VOICES,
+        supports_overlapping_voices: false,
+    });
+
     // Don't forget to change these features
     const CLAP_FEATURES: &'static [ClapFeature] = &[
         ClapFeature::AudioEffect,
@@ -171,6 +1850,38 @@ impl ClapPlugin for WinXpCrash {
         ClapFeature::Mono,
         ClapFeature::Glitch,
     ];
+
+    fn remote_controls(&self, context: &mut impl RemoteControlsContext) {
+        let params = &self.params;
+        context.add_section("Windows XP Crash", |section| {
+            // There are no dedicated Mix, Output, Chance, Rate Reduce or Crossfade parameters, so
+            // those slots hold the closest ones
+            section.add_page("Main", |page| {
+                page.add_param(&params.freezing.freeze);
+                page.add_param(&params.capture.buffer_size);
+                page.add_param(&params.freezing.freeze_amount);
+                page.add_param(&params.freezing.decay);
+                page.add_param(&params.mix.normalize_target);
+            });
+            section.add_page("Glitch", |page| {
+                page.add_param(&params.capture.division);
+                page.add_param(&params.modulation.hitch_rate);
+                page.add_param(&params.character.bit_depth);
+                page.add_param(&params.modulation.hitch_depth);
+                page.add_param(&params.freezing.attack);
+            });
+            section.add_page("Sidechain", |page| {
+                page.add_param(&params.freezing.sidechain_trigger);
+                page.add_param(&params.freezing.sidechain_threshold);
+                page.add_param(&params.freezing.sidechain_attack);
+                page.add_param(&params.freezing.sidechain_release);
+                page.add_param(&params.freezing.sidechain_highpass);
+                page.add_param(&params.freezing.sidechain_lowpass);
+                page.add_param(&params.freezing.refresh);
+                page.add_param(&params.freezing.refresh_threshold);
+            });
+        });
+    }
 }
 
 impl Vst3Plugin for WinXpCrash {
@@ -181,5 +1892,131 @@ impl Vst3Plugin for WinXpCrash {
         &[Vst3SubCategory::Fx, Vst3SubCategory::Dynamics];
 }
 
-nih_export_clap!(WinXpCrash);
+/// Show why an import failed in the editor
+fn set_import_error(params: &WinXpCrashParams, message: String) {
+    if let Ok(mut import_error) = params.import_error.lock() {
+        *import_error = Some((message, Instant::now()));
+    }
+}
+
+/// Display a division together with its length at the host's tempo, e.g. `1/8 — 176 ms @ 170
+/// BPM`
+fn v2s_division(host_tempo: Arc<AtomicU64>) -> Arc<dyn Fn(i32) -> String + Send + Sync> {
+    Arc::new(move |value| {
+        let division = Division::from_index(value as usize);
+        let name = Division::variants()[division.to_index()];
+        let tempo = f64::from_bits(host_tempo.load(Ordering::Relaxed));
+        match division.beats() {
+            Some(beats) if tempo.is_finite() && tempo > 0. => {
+                let ms = beats as f64 * 60_000. / tempo;
+                format!("{name} — {ms:.0} ms @ {tempo:.0} BPM")
+            },
+            _ => name.to_owned(),
+        }
+    })
+}
+
 nih_export_vst3!(WinXpCrash);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Renderer;
+    use crate::rng::Rng;
+
+    const SAMPLE_RATE: f32 = 48000.;
+    const BLOCK_SIZE: usize = 512;
+
+    #[test]
+    fn default_parameters_map_to_the_default_settings() {
+        let settings = WinXpCrashParams::default().settings();
+        assert_eq!(settings, Settings::default());
+        assert_eq!(settings.sidechain_threshold, util::db_to_gain(-20.));
+    }
+
+    /// A mono plugin that has recorded a noisy signal and then frozen it for a couple of blocks
+    fn frozen_renderer() -> Renderer {
+        let mut renderer = Renderer::new(1, SAMPLE_RATE).unwrap();
+        let mut rng = Rng::new(1);
+        for i in 0..60 {
+            let mut block: Vec<f32> = (0..BLOCK_SIZE).map(|_| rng.next_f32() - 0.5).collect();
+            renderer.plugin.engine.set_freeze(i >= 20);
+            renderer.process(&mut [&mut block]);
+        }
+        assert!(renderer.plugin.engine.is_frozen());
+
+        renderer
+    }
+
+    #[test]
+    fn mismatched_channel_counts() {
+        // A stereo host briefly processing the mono plugin, and the other way around
+        let mut renderer = frozen_renderer();
+        let mut right = [0.25; BLOCK_SIZE];
+        renderer.process(&mut [&mut [0.; BLOCK_SIZE], &mut right]);
+        assert!(renderer.plugin.engine.is_frozen());
+        // The channel without a buffer is passed through
+        assert!(right.iter().all(|&sample| sample == 0.25));
+
+        let mut renderer = Renderer::new(2, SAMPLE_RATE).unwrap();
+        renderer.plugin.engine.set_freeze(true);
+        for _ in 0..4 {
+            let mut block = [0.5; BLOCK_SIZE];
+            renderer.process(&mut [&mut block]);
+            assert!(block.iter().all(|sample| sample.is_finite()));
+        }
+        renderer.process(&mut []);
+    }
+
+    #[test]
+    fn process_does_not_allocate() {
+        let mut renderer = Renderer::new(2, SAMPLE_RATE).unwrap();
+        let (mut left, mut right) = (vec![0.; BLOCK_SIZE], vec![0.; BLOCK_SIZE]);
+        let mut buffer = Buffer::default();
+        // SAFETY: Both channels are `BLOCK_SIZE` samples long and outlive the buffer
+        unsafe {
+            buffer.set_slices(BLOCK_SIZE, |slices| {
+                slices.push(&mut left);
+                slices.push(&mut right);
+            });
+        }
+
+        let mut rng = Rng::new(5);
+        for block in 0..64 {
+            for channel in buffer.as_slice() {
+                channel.iter_mut().for_each(|sample| *sample = rng.next_f32() - 0.5);
+            }
+            renderer.plugin.engine.set_freeze(block / 6 % 2 == 1);
+            match block {
+                16 => renderer.plugin.engine.set_length(1024.),
+                32 => renderer.plugin.engine.set_length(MAX_BUFFER_SIZE as f32),
+                40 => renderer.send_event(NoteEvent::MidiSysEx {
+                    timing: 100,
+                    message: SysEx::DumpRequest,
+                }),
+                _ => (),
+            }
+            if block % 4 == 0 {
+                renderer.send_event(NoteEvent::NoteOn {
+                    timing: block as u32,
+                    voice_id: None,
+                    channel: 0,
+                    note: 60 + block as u8 % 12,
+                    velocity: 0.8,
+                });
+            } else if block % 4 == 2 {
+                renderer.send_event(NoteEvent::NoteOff {
+                    timing: 300,
+                    voice_id: None,
+                    channel: 0,
+                    note: 60 + (block - 2) as u8 % 12,
+                    velocity: 0.,
+                });
+            }
+
+            // Allocating in here aborts the tests in debug builds
+            assert_no_alloc::assert_no_alloc(|| renderer.process_buffer(&mut buffer));
+            renderer.run_tasks();
+        }
+    }
+}
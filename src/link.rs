@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use nih_plug::prelude::*;
+
+/// A group of instances in the same process that freeze and release together
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkGroup {
+    #[name = "Off"]
+    Off,
+    #[name = "A"]
+    A,
+    #[name = "B"]
+    B,
+    #[name = "C"]
+    C,
+    #[name = "D"]
+    D,
+}
+
+impl LinkGroup {
+    fn index(self) -> Option<usize> {
+        match self {
+            LinkGroup::Off => None,
+            LinkGroup::A => Some(0),
+            LinkGroup::B => Some(1),
+            LinkGroup::C => Some(2),
+            LinkGroup::D => Some(3),
+        }
+    }
+}
+
+/// The state every instance in a group shares. All instances are loaded into the same host
+/// process, so a static is enough to reach the other instances.
+struct Group {
+    /// The group's freeze in the lowest bit, and the number of changes to it in the other bits
+    /// so instances can tell a new change from one they've already seen
+    state: AtomicU32,
+    members: AtomicU32,
+}
+
+impl Group {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            members: AtomicU32::new(0),
+        }
+    }
+
+    /// Set the group's freeze and return the new state
+    fn publish(&self, frozen: bool) -> u32 {
+        let next = |state: u32| ((state >> 1).wrapping_add(1) << 1) | frozen as u32;
+        let previous = self
+            .state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+                Some(next(state))
+            })
+            .unwrap_or_else(|state| state);
+        next(previous)
+    }
+}
+
+static GROUPS: [Group; 4] = [Group::new(), Group::new(), Group::new(), Group::new()];
+
+/// An instance's membership in a link group. Changes to the instance's own freeze are shared
+/// with the group, and changes made by the other members are picked up. This never blocks, so
+/// it can be used on the audio thread.
+#[derive(Debug, Default)]
+pub struct FreezeLink {
+    group: Option<usize>,
+    /// The group's state when this instance last looked at it
+    seen_state: u32,
+    /// The instance's own freeze the last time it was shared
+    shared_freeze: bool,
+}
+
+impl FreezeLink {
+    /// Join a group, leaving the group the instance was in before. Returns whether the group
+    /// changed.
+    pub fn set_group(&mut self, group: LinkGroup) -> bool {
+        let index = group.index();
+        if index == self.group {
+            return false;
+        }
+
+        self.leave();
+        if let Some(index) = index {
+            GROUPS[index].members.fetch_add(1, Ordering::AcqRel);
+            // This differs from the group's state, so the next sync adopts the group's freeze
+            self.seen_state = !GROUPS[index].state.load(Ordering::Acquire);
+        }
+        self.group = index;
+
+        true
+    }
+
+    /// Share the instance's own freeze with the group if it changed. Returns the group's freeze
+    /// when this or another member changed it since the last call, or `None` if nothing
+    /// changed or the instance isn't in a group.
+    pub fn sync(&mut self, own_freeze: bool) -> Option<bool> {
+        let group = &GROUPS[self.group?];
+        if own_freeze != self.shared_freeze {
+            self.shared_freeze = own_freeze;
+            self.seen_state = group.publish(own_freeze);
+            return Some(own_freeze);
+        }
+
+        let state = group.state.load(Ordering::Acquire);
+        if state == self.seen_state {
+            return None;
+        }
+        self.seen_state = state;
+        Some(state & 1 != 0)
+    }
+
+    fn leave(&mut self) {
+        let Some(index) = self.group.take() else {
+            return;
+        };
+
+        let group = &GROUPS[index];
+        // The other members would otherwise stay frozen with nothing left to release them
+        if self.shared_freeze {
+            group.publish(false);
+        }
+        self.shared_freeze = false;
+        if group.members.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Nothing of the last member's state carries over to the next instance joining
+            group.state.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for FreezeLink {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
@@ -1,6 +1,50 @@
 use nih_plug::prelude::*;
+use win_xp_crash::osc::OscServer;
+use win_xp_crash::render::{self, RenderOptions};
 use win_xp_crash::WinXpCrash;
 
 fn main() {
-    nih_export_standalone::<WinXpCrash>();
+    let mut args: Vec<String> = std::env::args().collect();
+    // `--input` renders a file offline instead of starting the application
+    match RenderOptions::from_args(&args[1..]) {
+        Ok(Some(options)) => {
+            if let Err(err) = render::render(&options) {
+                eprintln!("Could not render {}: {err}", options.input.display());
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(None) => (),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+
+    // `--osc-port` is handled here, everything else goes to the standalone wrapper
+    let osc_port = match args.iter().position(|arg| arg == "--osc-port") {
+        Some(index) => {
+            let port = args
+                .get(index + 1)
+                .and_then(|port| port.parse::<u16>().ok());
+            let Some(port) = port else {
+                eprintln!("--osc-port needs a port number");
+                std::process::exit(1);
+            };
+            args.drain(index..index + 2);
+            Some(port)
+        }
+        None => None,
+    };
+
+    // The server is stopped when this is dropped after the application exits
+    let _osc_server = osc_port.and_then(|port| match OscServer::start(port) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            eprintln!("Could not start the OSC server on port {port}: {err}");
+            None
+        }
+    });
+
+    nih_export_standalone_with_args::<WinXpCrash, _>(args);
 }
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+/// The number of channels that are metered. This covers every supported layout.
+pub const MAX_METER_CHANNELS: usize = 8;
+
+/// A channel's levels for the last processed block
+#[derive(Default)]
+pub struct ChannelLevels {
+    /// The highest absolute sample value as `f32` bits
+    peak: AtomicU32,
+    /// The block's RMS level as `f32` bits
+    rms: AtomicU32,
+}
+
+impl ChannelLevels {
+    pub fn peak(&self) -> f32 {
+        f32::from_bits(self.peak.load(Ordering::Relaxed))
+    }
+
+    pub fn rms(&self) -> f32 {
+        f32::from_bits(self.rms.load(Ordering::Relaxed))
+    }
+}
+
+/// The input and output levels the audio thread publishes for the editor's meters. The editor
+/// applies the ballistics.
+#[derive(Default)]
+pub struct Meters {
+    input: [ChannelLevels; MAX_METER_CHANNELS],
+    output: [ChannelLevels; MAX_METER_CHANNELS],
+    num_channels: AtomicUsize,
+    /// Set when the output clipped, until the editor clears it
+    pub clipped: AtomicBool,
+}
+
+impl Meters {
+    pub fn input(&self) -> &[ChannelLevels] {
+        &self.input[..self.num_channels.load(Ordering::Relaxed)]
+    }
+
+    pub fn output(&self) -> &[ChannelLevels] {
+        &self.output[..self.num_channels.load(Ordering::Relaxed)]
+    }
+
+    pub fn publish(&self, input: &BlockLevels, output: &BlockLevels) {
+        let num_channels = input.num_channels.max(output.num_channels);
+        for (levels, block) in [(&self.input, input), (&self.output, output)] {
+            for (channel, level) in levels.iter().enumerate().take(num_channels) {
+                level
+                    .peak
+                    .store(block.peaks[channel].to_bits(), Ordering::Relaxed);
+                level
+                    .rms
+                    .store(block.rms(channel).to_bits(), Ordering::Relaxed);
+            }
+        }
+        self.num_channels.store(num_channels, Ordering::Relaxed);
+
+        if output.peaks.iter().any(|&peak| peak >= 1.) {
+            self.clipped.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Levels accumulated over a block on the audio thread
+#[derive(Clone, Copy, Default)]
+pub struct BlockLevels {
+    peaks: [f32; MAX_METER_CHANNELS],
+    squares: [f32; MAX_METER_CHANNELS],
+    num_samples: usize,
+    num_channels: usize,
+}
+
+impl BlockLevels {
+    #[inline]
+    pub fn add(&mut self, channel: usize, sample: f32) {
+        if channel >= MAX_METER_CHANNELS {
+            return;
+        }
+
+        self.peaks[channel] = self.peaks[channel].max(sample.abs());
+        self.squares[channel] += sample * sample;
+        self.num_channels = self.num_channels.max(channel + 1);
+        if channel == 0 {
+            self.num_samples += 1;
+        }
+    }
+
+    fn rms(&self, channel: usize) -> f32 {
+        if self.num_samples == 0 {
+            0.
+        } else {
+            (self.squares[channel] / self.num_samples as f32).sqrt()
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::io;
+use std::path::Path;
+
+/// The tempo until the file sets one, in microseconds per quarter note (120 BPM)
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// A note on or note off from a Standard MIDI File
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileNote {
+    /// The time from the start of the file in seconds
+    pub time: f64,
+    pub channel: u8,
+    pub note: u8,
+    /// The velocity between 0 and 1 for a note on, `None` for a note off
+    pub velocity: Option<f32>,
+}
+
+/// Read the notes of a format 0 or format 1 Standard MIDI File, sorted by time. Tempo changes
+/// are applied across all tracks, everything other than notes and tempo changes is skipped.
+pub fn read(path: &Path) -> io::Result<Vec<FileNote>> {
+    let bytes = std::fs::read(path)?;
+    parse_smf(&bytes).ok_or_else(unsupported_file)
+}
+
+fn unsupported_file() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a supported MIDI file")
+}
+
+/// The events that matter for the notes' timing, at their position in ticks
+enum Event {
+    Tempo(u32),
+    Note {
+        channel: u8,
+        note: u8,
+        velocity: Option<f32>,
+    },
+}
+
+fn parse_smf(bytes: &[u8]) -> Option<Vec<FileNote>> {
+    let (header, mut rest) = read_chunk(bytes, b"MThd")?;
+    let format = read_u16(header.get(..2)?);
+    let division = read_u16(header.get(4..6)?);
+    // SMPTE time divisions aren't supported, only ticks per quarter note
+    if format > 1 || division == 0 || division & 0x8000 != 0 {
+        return None;
+    }
+
+    let mut events = Vec::new();
+    while !rest.is_empty() {
+        let (track, after) = read_chunk(rest, b"MTrk")?;
+        parse_track(track, &mut events)?;
+        rest = after;
+    }
+    // Tempo changes come before notes on the same tick
+    events.sort_by_key(|(tick, event)| (*tick, !matches!(event, Event::Tempo(_))));
+
+    let mut notes = Vec::new();
+    let (mut time, mut last_tick, mut tempo) = (0., 0, DEFAULT_TEMPO);
+    for (tick, event) in events {
+        time += (tick - last_tick) as f64 * tempo as f64 / 1_000_000. / division as f64;
+        last_tick = tick;
+        match event {
+            Event::Tempo(new_tempo) => tempo = new_tempo,
+            Event::Note {
+                channel,
+                note,
+                velocity,
+            } => notes.push(FileNote {
+                time,
+                channel,
+                note,
+                velocity,
+            }),
+        }
+    }
+
+    Some(notes)
+}
+
+/// Read a chunk with the type `id`. Returns the chunk's data and the bytes after it.
+fn read_chunk<'a>(bytes: &'a [u8], id: &[u8; 4]) -> Option<(&'a [u8], &'a [u8])> {
+    if bytes.get(..4)? != id {
+        return None;
+    }
+    let len = read_u32(bytes.get(4..8)?) as usize;
+    let data = bytes.get(8..8 + len)?;
+
+    Some((data, &bytes[8 + len..]))
+}
+
+fn parse_track(mut track: &[u8], events: &mut Vec<(u64, Event)>) -> Option<()> {
+    let mut tick = 0u64;
+    let mut running_status = None;
+    while !track.is_empty() {
+        let delta;
+        (delta, track) = read_variable_len(track)?;
+        tick += delta as u64;
+
+        let status = match *track.first()? {
+            status if status & 0x80 != 0 => {
+                track = &track[1..];
+                status
+            }
+            // Data bytes without a status byte repeat the previous channel message's status
+            _ => running_status?,
+        };
+        match status {
+            0xff => {
+                let meta_type = *track.first()?;
+                let len;
+                (len, track) = read_variable_len(&track[1..])?;
+                let data = track.get(..len as usize)?;
+                if meta_type == 0x51 && len == 3 {
+                    events.push((tick, Event::Tempo(read_u24(data))));
+                }
+                track = &track[len as usize..];
+            }
+            0xf0 | 0xf7 => {
+                let len;
+                (len, track) = read_variable_len(track)?;
+                track = track.get(len as usize..)?;
+            }
+            0x80..=0xef => {
+                running_status = Some(status);
+                let data_len = if matches!(status & 0xf0, 0xc0 | 0xd0) {
+                    1
+                } else {
+                    2
+                };
+                let data = track.get(..data_len)?;
+                let channel = status & 0x0f;
+                let velocity = match status & 0xf0 {
+                    0x80 => Some(None),
+                    // A note on with a velocity of zero is a note off
+                    0x90 if data[1] == 0 => Some(None),
+                    0x90 => Some(Some(data[1] as f32 / 127.)),
+                    _ => None,
+                };
+                if let Some(velocity) = velocity {
+                    events.push((
+                        tick,
+                        Event::Note {
+                            channel,
+                            note: data[0] & 0x7f,
+                            velocity,
+                        },
+                    ));
+                }
+                track = &track[data_len..];
+            }
+            _ => return None,
+        }
+    }
+
+    Some(())
+}
+
+/// Read a variable length quantity. Returns the value and the bytes after it.
+fn read_variable_len(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let mut value = 0u32;
+    for (i, &byte) in bytes.iter().enumerate().take(4) {
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[i + 1..]));
+        }
+    }
+
+    None
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u24(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A format 1 file with 96 ticks per quarter note, a tempo track that switches from
+    /// 120 BPM to 60 BPM after one beat, and a note track
+    fn test_file() -> Vec<u8> {
+        let mut file = b"MThd\0\0\0\x06\0\x01\0\x02\0\x60".to_vec();
+        let tempo_track: &[u8] = &[
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, // 120 BPM
+            0x60, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40, // 60 BPM after one beat
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        let note_track: &[u8] = &[
+            0x00, 0x90, 0x3c, 0x7f, // Note on at 0 s
+            0x60, 0x3c, 0x00, // Running status note off after one beat, at 0.5 s
+            0x00, 0xf0, 0x02, 0x7d, 0xf7, // A SysEx message that's skipped
+            0x81, 0x40, 0x80, 0x3e, 0x40, // Note off after 192 ticks at 60 BPM, at 2.5 s
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        for track in [tempo_track, note_track] {
+            file.extend_from_slice(b"MTrk");
+            file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            file.extend_from_slice(track);
+        }
+
+        file
+    }
+
+    #[test]
+    fn notes_follow_the_tempo_map() {
+        let notes = parse_smf(&test_file()).unwrap();
+        let times: Vec<(u8, Option<f32>, f64)> = notes
+            .iter()
+            .map(|note| (note.note, note.velocity, note.time))
+            .collect();
+
+        assert_eq!(
+            times,
+            [(0x3c, Some(1.), 0.), (0x3c, None, 0.5), (0x3e, None, 2.5)]
+        );
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert!(parse_smf(b"RIFF\0\0\0\0WAVE").is_none());
+        // SMPTE time division
+        assert!(parse_smf(b"MThd\0\0\0\x06\0\0\0\x01\xe7\x28").is_none());
+        // Truncated track
+        assert!(parse_smf(b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk\0\0\0\x08\0\x90").is_none());
+    }
+}
@@ -0,0 +1,265 @@
+//! A small OSC server for the standalone application. Messages are received on a background
+//! thread, which applies them through the editor's `GuiContext` like any other change made in
+//! the GUI. Messages that arrive before the editor has been opened are queued up until it is.
+//!
+//! Every parameter is addressed by its ID as `/winxpcrash/<id>`, for instance
+//! `/winxpcrash/freeze` or `/winxpcrash/buffer_size`. Float arguments are normalized values
+//! between 0 and 1, integer arguments are plain values like a buffer size in samples, and the
+//! `T` and `F` types switch parameters on or off. Unknown addresses are ignored.
+
+use std::any::Any;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use nih_plug::prelude::*;
+
+/// The prefix of every address
+const ADDRESS_PREFIX: &str = "/winxpcrash/";
+/// How often the server thread checks whether it should stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Messages that arrive while there's no editor to apply them are dropped beyond this
+const MAX_PENDING_MESSAGES: usize = 1024;
+
+/// Messages that arrived while no editor was open. There's only one plugin instance in the
+/// standalone application, so this is shared by everything.
+static PENDING: Mutex<Vec<Message>> = Mutex::new(Vec::new());
+/// The context and parameters of the open editor, which the messages are applied to
+static TARGET: Mutex<Option<Target>> = Mutex::new(None);
+
+struct Target {
+    context: Arc<dyn GuiContext>,
+    params: Arc<dyn Params>,
+}
+
+/// Wraps the plugin's editor to hand its `GuiContext` to the OSC server for as long as the
+/// editor is open
+struct OscEditor {
+    editor: Box<dyn Editor>,
+    params: Arc<dyn Params>,
+}
+
+/// Clears the OSC server's target when the editor closes
+struct Attachment;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    param_id: String,
+    value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Normalized(f32),
+    Plain(i32),
+    Bool(bool),
+}
+
+/// The OSC server's thread. It's stopped and joined when this is dropped.
+pub struct OscServer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscServer {
+    /// Listen for OSC messages on `port` on all interfaces
+    pub fn start(port: u16) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("osc-server".to_owned())
+            .spawn({
+                let stop = stop.clone();
+                move || receive(socket, &stop)
+            })?;
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Editor for OscEditor {
+    fn spawn(
+        &self,
+        parent: ParentWindowHandle,
+        context: Arc<dyn GuiContext>,
+    ) -> Box<dyn Any + Send> {
+        let handle = self.editor.spawn(parent, context.clone());
+        if let Ok(mut target) = TARGET.lock() {
+            let messages = PENDING.lock().map(|mut pending| std::mem::take(&mut *pending));
+            apply(&*context, &*self.params, messages.unwrap_or_default());
+            *target = Some(Target {
+                context,
+                params: self.params.clone(),
+            });
+        }
+
+        // The attachment is dropped before the editor's own handle, so no messages are applied
+        // to an editor that's closing
+        Box::new((Attachment, handle))
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.editor.size()
+    }
+
+    fn set_scale_factor(&self, factor: f32) -> bool {
+        self.editor.set_scale_factor(factor)
+    }
+
+    fn param_value_changed(&self, id: &str, normalized_value: f32) {
+        self.editor.param_value_changed(id, normalized_value)
+    }
+
+    fn param_modulation_changed(&self, id: &str, modulation_offset: f32) {
+        self.editor.param_modulation_changed(id, modulation_offset)
+    }
+
+    fn param_values_changed(&self) {
+        self.editor.param_values_changed()
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        if let Ok(mut target) = TARGET.lock() {
+            *target = None;
+        }
+    }
+}
+
+/// Let the OSC server apply its messages through `editor`'s `GuiContext` while it's open
+pub fn attach(editor: Box<dyn Editor>, params: Arc<dyn Params>) -> Box<dyn Editor> {
+    Box::new(OscEditor { editor, params })
+}
+
+/// Apply `messages` to `params` through `context`. Values that aren't finite are ignored.
+fn apply(context: &dyn GuiContext, params: &dyn Params, messages: Vec<Message>) {
+    if messages.is_empty() {
+        return;
+    }
+
+    let param_map = params.param_map();
+    for message in messages {
+        let Some((_, param_ptr, _)) = param_map.iter().find(|(id, _, _)| *id == message.param_id)
+        else {
+            continue;
+        };
+
+        // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+        unsafe {
+            let normalized = match message.value {
+                Value::Normalized(value) if !value.is_finite() => continue,
+                Value::Normalized(value) => value.clamp(0., 1.),
+                Value::Plain(value) => param_ptr.preview_normalized(value as f32),
+                Value::Bool(value) => value as u8 as f32,
+            };
+            crate::presets::set_normalized(context, *param_ptr, normalized);
+        }
+    }
+}
+
+fn receive(socket: UdpSocket, stop: &AtomicBool) {
+    let mut packet = [0; 1536];
+    while !stop.load(Ordering::Relaxed) {
+        let len = match socket.recv(&mut packet) {
+            Ok(len) => len,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(err) => {
+                nih_error!("The OSC server stopped: {err}");
+                return;
+            }
+        };
+
+        let mut messages = Vec::new();
+        parse_packet(&packet[..len], &mut messages);
+        let Ok(target) = TARGET.lock() else {
+            continue;
+        };
+        if let Some(target) = &*target {
+            apply(&*target.context, &*target.params, messages);
+        } else if let Ok(mut pending) = PENDING.lock() {
+            let room = MAX_PENDING_MESSAGES.saturating_sub(pending.len());
+            pending.extend(messages.into_iter().take(room));
+        }
+    }
+}
+
+/// Decode an OSC message or bundle. Only the first argument of every message is used, and
+/// anything that can't be decoded is skipped.
+fn parse_packet(packet: &[u8], messages: &mut Vec<Message>) {
+    if let Some(mut contents) = packet.strip_prefix(b"#bundle\0") {
+        // The time tag is ignored, messages are applied as soon as they arrive
+        contents = contents.get(8..).unwrap_or_default();
+        while let Some(len) = contents.get(..4).map(read_u32) {
+            let Some(element) = contents.get(4..4 + len as usize) else {
+                return;
+            };
+            parse_packet(element, messages);
+            contents = &contents[4 + len as usize..];
+        }
+        return;
+    }
+
+    let Some((address, rest)) = read_string(packet) else {
+        return;
+    };
+    let Some(param_id) = address.strip_prefix(ADDRESS_PREFIX) else {
+        return;
+    };
+    let Some((type_tags, arguments)) = read_string(rest) else {
+        return;
+    };
+
+    let argument = arguments.get(..4).map(read_u32);
+    let value = match (type_tags.strip_prefix(','), argument) {
+        (Some(tags), Some(argument)) if tags.starts_with('f') => {
+            Value::Normalized(f32::from_bits(argument))
+        }
+        (Some(tags), Some(argument)) if tags.starts_with('i') => Value::Plain(argument as i32),
+        (Some(tags), _) if tags.starts_with('T') => Value::Bool(true),
+        (Some(tags), _) if tags.starts_with('F') => Value::Bool(false),
+        _ => return,
+    };
+    messages.push(Message {
+        param_id: param_id.to_owned(),
+        value,
+    });
+}
+
+/// Read a null terminated string padded to a multiple of four bytes. Returns the string and
+/// the data after it.
+fn read_string(data: &[u8]) -> Option<(&str, &[u8])> {
+    let len = data.iter().position(|&byte| byte == 0)?;
+    let string = std::str::from_utf8(&data[..len]).ok()?;
+    let padded_len = (len + 4) & !3;
+
+    Some((string, data.get(padded_len..).unwrap_or_default()))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
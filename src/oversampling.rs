@@ -0,0 +1,210 @@
+use nih_plug::prelude::*;
+
+use crate::interpolation::{blackman, sinc};
+
+/// The number of nonzero taps on either side of a halfband filter's center. Every other tap of a
+/// halfband filter is zero, which the polyphase decimator skips.
+const HALFBAND_SIDE_TAPS: usize = 8;
+/// The halfband filter's length in input samples
+const HALFBAND_LEN: usize = 4 * HALFBAND_SIDE_TAPS - 1;
+/// The inputs a decimator remembers. The newest one isn't used until the next output so the
+/// filter's delay is a whole number of output samples.
+const HALFBAND_HISTORY: usize = HALFBAND_LEN + 1;
+/// How many input samples a halfband decimator delays its input by
+const HALFBAND_DELAY: usize = HALFBAND_HISTORY / 2;
+/// The longest latency any oversampling factor adds, in samples at the host's sample rate
+pub const MAX_LATENCY: usize = Oversampling::X4.latency();
+
+/// How many samples the variable-rate read head reads per output sample. The extra samples are
+/// filtered and decimated again, which removes most of the aliasing from pitching a loop up.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling {
+    #[name = "Off"]
+    Off,
+    #[name = "2x"]
+    X2,
+    #[name = "4x"]
+    X4,
+}
+
+impl Oversampling {
+    pub fn factor(self) -> usize {
+        match self {
+            Oversampling::Off => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+        }
+    }
+
+    /// The delay the decimation filters add, in samples at the host's sample rate
+    pub const fn latency(self) -> usize {
+        match self {
+            Oversampling::Off => 0,
+            Oversampling::X2 => HALFBAND_DELAY / 2,
+            // The first stage runs at twice the second stage's rate
+            Oversampling::X4 => HALFBAND_DELAY / 2 + HALFBAND_DELAY / 4,
+        }
+    }
+}
+
+/// Decimates an oversampled signal back down to the host's sample rate with one or two polyphase
+/// halfband stages. The filters only hold fixed size arrays, so changing the factor doesn't
+/// allocate.
+#[derive(Clone, Debug)]
+pub struct Oversampler {
+    oversampling: Oversampling,
+    /// Only used at 4x, from four to two times the host's sample rate
+    first_stage: Halfband,
+    second_stage: Halfband,
+}
+
+/// A linear phase FIR halfband lowpass that halves the sample rate
+#[derive(Clone, Debug)]
+struct Halfband {
+    /// The nonzero taps on one side of the center, starting next to it
+    coefficients: [f32; HALFBAND_SIDE_TAPS],
+    /// The last inputs, stored twice so they can be read without wrapping around
+    history: [f32; 2 * HALFBAND_HISTORY],
+    position: usize,
+}
+
+impl Default for Oversampler {
+    fn default() -> Self {
+        Self {
+            oversampling: Oversampling::Off,
+            first_stage: Halfband::new(),
+            second_stage: Halfband::new(),
+        }
+    }
+}
+
+impl Oversampler {
+    pub fn oversampling(&self) -> Oversampling {
+        self.oversampling
+    }
+
+    /// Change the factor. The filters start out silent again when it changes.
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        if oversampling != self.oversampling {
+            self.oversampling = oversampling;
+            self.reset();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.first_stage.reset();
+        self.second_stage.reset();
+    }
+
+    /// Compute the next output sample from the next `factor()` samples returned by `read`
+    #[inline]
+    pub fn process(&mut self, mut read: impl FnMut() -> f32) -> f32 {
+        match self.oversampling {
+            Oversampling::Off => read(),
+            Oversampling::X2 => {
+                let (first, second) = (read(), read());
+                self.second_stage.decimate(first, second)
+            }
+            Oversampling::X4 => {
+                let (first, second) = (read(), read());
+                let first = self.first_stage.decimate(first, second);
+                let (third, fourth) = (read(), read());
+                let second = self.first_stage.decimate(third, fourth);
+                self.second_stage.decimate(first, second)
+            }
+        }
+    }
+}
+
+impl Halfband {
+    fn new() -> Self {
+        let half_width = HALFBAND_HISTORY as f64 / 2.;
+        let mut coefficients: [f32; HALFBAND_SIDE_TAPS] = std::array::from_fn(|tap| {
+            let offset = (2 * tap + 1) as f64;
+            (0.5 * sinc(offset / 2.) * blackman(offset, half_width)) as f32
+        });
+        // The center tap is 0.5, so both sides need to add up to the other half for unity gain
+        let sum: f32 = coefficients.iter().sum();
+        coefficients.iter_mut().for_each(|c| *c *= 0.25 / sum);
+
+        Self {
+            coefficients,
+            history: [0.; 2 * HALFBAND_HISTORY],
+            position: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history = [0.; 2 * HALFBAND_HISTORY];
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.position = (self.position + 1) % HALFBAND_HISTORY;
+        self.history[self.position] = sample;
+        self.history[self.position + HALFBAND_HISTORY] = sample;
+    }
+
+    /// Add two input samples and compute the next output sample
+    #[inline]
+    fn decimate(&mut self, first: f32, second: f32) -> f32 {
+        self.push(first);
+        self.push(second);
+
+        // From the oldest to the newest input
+        let window = &self.history[self.position + 1..self.position + 1 + HALFBAND_HISTORY];
+        let center = HALFBAND_DELAY - 1;
+        self.coefficients.iter().enumerate().fold(
+            0.5 * window[center],
+            |output, (tap, coefficient)| {
+                let offset = 2 * tap + 1;
+                output + coefficient * (window[center - offset] + window[center + offset])
+            },
+        )
+    }
+}
+
+/// Delays the dry signal by the oversampling's latency so it lines up with the oversampled
+/// playback again once the host compensates for it
+#[derive(Clone, Debug)]
+pub struct LatencyDelay {
+    samples: [f32; MAX_LATENCY],
+    len: usize,
+    position: usize,
+}
+
+impl Default for LatencyDelay {
+    fn default() -> Self {
+        Self {
+            samples: [0.; MAX_LATENCY],
+            len: 0,
+            position: 0,
+        }
+    }
+}
+
+impl LatencyDelay {
+    /// Change the delay in samples. The delay starts out silent again when it changes.
+    pub fn set_len(&mut self, len: usize) {
+        let len = len.min(MAX_LATENCY);
+        if len != self.len {
+            self.len = len;
+            self.samples = [0.; MAX_LATENCY];
+            self.position = 0;
+        }
+    }
+
+    /// Delay `samples` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.len == 0 {
+            return;
+        }
+
+        for sample in samples {
+            std::mem::swap(&mut self.samples[self.position], sample);
+            self.position += 1;
+            if self.position >= self.len {
+                self.position = 0;
+            }
+        }
+    }
+}
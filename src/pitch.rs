@@ -0,0 +1,167 @@
+use crate::buffer::ChannelRef;
+
+/// The autocorrelation compares at least this many samples of the loop, and at least two of
+/// the longest periods
+const MIN_WINDOW: usize = 2048;
+/// The lowest detected frequency in Hz, which sets the longest period that's searched for
+const MIN_FREQUENCY: f32 = 40.;
+/// The highest detected frequency in Hz
+const MAX_FREQUENCY: f32 = 2000.;
+/// How many lags are compared per block, which spreads the analysis over a few blocks
+const LAGS_PER_BLOCK: usize = 128;
+/// A peak within this fraction of the highest peak counts, so the shortest period wins over
+/// its multiples
+const PEAK_THRESHOLD: f32 = 0.9;
+/// Loops whose highest peak is below this are treated as unpitched
+const MIN_CLARITY: f32 = 0.5;
+
+/// Estimates the dominant pitch of a frozen loop with the normalized square difference
+/// function, the autocorrelation of McLeod's pitch method. Loops shorter than the longest
+/// period just have their loop rate as their pitch.
+#[derive(Debug, Default)]
+pub struct PitchDetector {
+    sample_rate: f32,
+    /// The samples being analyzed, and how many of them are used
+    window: Vec<f32>,
+    len: usize,
+    /// Whether the window holds the whole loop, so it's compared with itself circularly
+    circular: bool,
+    /// The normalized square difference for every lag computed so far
+    nsdf: Vec<f32>,
+    state: State,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Idle,
+    /// The next lag to compare
+    Running(usize),
+    /// A loop this short repeats at its loop rate, which is reported by the next step
+    Short(usize),
+    /// The analysis is finished until the next `reset()`
+    Done,
+}
+
+impl PitchDetector {
+    /// Allocate the analysis buffers for a sample rate. This allocates.
+    pub fn prepare(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.window.resize((2 * self.max_lag()).max(MIN_WINDOW), 0.);
+        self.nsdf.resize(self.max_lag() + 1, 0.);
+        self.reset();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    /// Stop analyzing, so the next freeze can be analyzed
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Start analyzing the loop in `buffer`, using its most recent samples
+    pub fn start(&mut self, buffer: &ChannelRef<'_>) {
+        let loop_len = buffer.loop_len();
+        if loop_len <= self.max_lag() {
+            self.state = if loop_len > 0 {
+                State::Short(loop_len)
+            } else {
+                State::Done
+            };
+            return;
+        }
+
+        self.len = loop_len.min(self.window.len());
+        self.circular = self.len == loop_len;
+        let newest = buffer.loop_samples().skip(loop_len - self.len);
+        for (target, sample) in self.window.iter_mut().zip(newest) {
+            *target = sample;
+        }
+        self.state = State::Running(self.min_lag());
+    }
+
+    /// Compare the next few lags. Returns the pitch in Hz once the analysis is finished and
+    /// found one, and nothing otherwise.
+    pub fn step(&mut self) -> Option<f32> {
+        let first_lag = match self.state {
+            State::Idle | State::Done => return None,
+            State::Short(loop_len) => {
+                self.state = State::Done;
+                return Some(self.sample_rate / loop_len as f32);
+            }
+            State::Running(lag) => lag,
+        };
+
+        let max_lag = self.max_lag();
+        let last_lag = (first_lag + LAGS_PER_BLOCK).min(max_lag + 1);
+        for lag in first_lag..last_lag {
+            self.nsdf[lag] = self.nsdf_at(lag);
+        }
+        if last_lag <= max_lag {
+            self.state = State::Running(last_lag);
+            return None;
+        }
+
+        self.state = State::Done;
+        let period = self.pick_period()?;
+        Some(self.sample_rate / period)
+    }
+
+    /// The normalized square difference at one lag, between -1 and 1
+    fn nsdf_at(&self, lag: usize) -> f32 {
+        let window = &self.window[..self.len];
+        let overlap = if self.circular {
+            self.len
+        } else {
+            self.len - lag
+        };
+        let (mut correlation, mut energy) = (0., 0.);
+        for i in 0..overlap {
+            let (a, b) = (window[i], window[(i + lag) % self.len]);
+            correlation += a * b;
+            energy += a * a + b * b;
+        }
+
+        if energy > 0. {
+            2. * correlation / energy
+        } else {
+            0.
+        }
+    }
+
+    /// The period in samples of the first peak that comes close to the highest peak, refined
+    /// with a parabola through its neighbors. Peaks only count once the function has dropped
+    /// below zero, before that it's still falling from the lag of zero.
+    fn pick_period(&self) -> Option<f32> {
+        let nsdf = &self.nsdf;
+        let max_lag = self.max_lag();
+        let start = (self.min_lag()..=max_lag).find(|&lag| nsdf[lag] < 0.)?;
+        let is_peak = |lag: &usize| nsdf[*lag] > nsdf[lag - 1] && nsdf[*lag] >= nsdf[lag + 1];
+        let peaks = || (start + 1..max_lag).filter(is_peak);
+        let highest = peaks().map(|lag| nsdf[lag]).fold(0f32, f32::max);
+        if highest < MIN_CLARITY {
+            return None;
+        }
+
+        let lag = peaks().find(|&lag| nsdf[lag] >= highest * PEAK_THRESHOLD)?;
+        let (before, peak, after) = (nsdf[lag - 1], nsdf[lag], nsdf[lag + 1]);
+        let curvature = before - 2. * peak + after;
+        let offset = if curvature < 0. {
+            0.5 * (before - after) / curvature
+        } else {
+            0.
+        };
+
+        Some(lag as f32 + offset)
+    }
+
+    fn min_lag(&self) -> usize {
+        ((self.sample_rate / MAX_FREQUENCY) as usize).max(1)
+    }
+
+    fn max_lag(&self) -> usize {
+        (self.sample_rate / MIN_FREQUENCY).ceil() as usize
+    }
+}
@@ -0,0 +1,387 @@
+//! CLAP's preset discovery factory, so hosts can list the factory presets in their own preset
+//! browsers. There's a single provider with a single location inside the plugin, and the presets
+//! are loaded by their names through the preset load extension in `clap_entry`.
+
+use clap_sys::factory::preset_discovery::*;
+use clap_sys::timestamp::CLAP_TIMESTAMP_UNKNOWN;
+use clap_sys::universal_plugin_id::clap_universal_plugin_id;
+use clap_sys::version::CLAP_VERSION;
+use nih_plug::prelude::*;
+use std::ffi::{c_char, CStr, CString};
+use std::sync::OnceLock;
+
+use crate::presets::{Preset, FACTORY_PRESETS, FACTORY_PRESET_CREATOR};
+use crate::WinXpCrash;
+
+pub(crate) static FACTORY: clap_preset_discovery_factory = clap_preset_discovery_factory {
+    count: Some(count),
+    get_descriptor: Some(get_descriptor),
+    create: Some(create),
+};
+
+/// A provider handed to the host, which needs to keep the indexer around until it's initialized
+#[repr(C)]
+struct Provider {
+    /// The host only ever sees this, the struct starts with it so the pointer can be cast back
+    provider: clap_preset_discovery_provider,
+    indexer: *const clap_preset_discovery_indexer,
+}
+
+/// The C strings the host reads. Those can't be built from the plugin's constants at compile
+/// time, so they're created the first time the host asks for them.
+struct Strings {
+    provider_id: CString,
+    provider_name: CString,
+    vendor: CString,
+    location_name: CString,
+    soundpack_id: CString,
+    soundpack_name: CString,
+    soundpack_description: CString,
+    url: CString,
+    plugin_id: CString,
+    creator: CString,
+    /// Every factory preset's name, which is also its load key, and its features
+    presets: Vec<(CString, Vec<CString>)>,
+}
+
+// SAFETY: The descriptor only points into `STRINGS`, which lives forever and is never changed
+struct Descriptor(clap_preset_discovery_provider_descriptor);
+unsafe impl Send for Descriptor {}
+unsafe impl Sync for Descriptor {}
+
+static STRINGS: OnceLock<Strings> = OnceLock::new();
+static DESCRIPTOR: OnceLock<Descriptor> = OnceLock::new();
+
+fn c_string(string: &str) -> CString {
+    CString::new(string).expect("No NUL bytes in the plugin's strings")
+}
+
+fn strings() -> &'static Strings {
+    STRINGS.get_or_init(|| Strings {
+        provider_id: c_string(&format!("{}.factory-presets", WinXpCrash::CLAP_ID)),
+        provider_name: c_string(&format!("{} Factory Presets", WinXpCrash::NAME)),
+        vendor: c_string(WinXpCrash::VENDOR),
+        location_name: c_string("Factory Presets"),
+        soundpack_id: c_string(&format!("{}.factory", WinXpCrash::CLAP_ID)),
+        soundpack_name: c_string(&format!("{} Factory", WinXpCrash::NAME)),
+        soundpack_description: c_string("The presets that come with the plugin"),
+        url: c_string(WinXpCrash::URL),
+        plugin_id: c_string(WinXpCrash::CLAP_ID),
+        creator: c_string(FACTORY_PRESET_CREATOR),
+        presets: FACTORY_PRESETS
+            .iter()
+            .map(|preset| {
+                let features = preset.features.iter().map(|feature| c_string(feature));
+                (c_string(preset.name), features.collect())
+            })
+            .collect(),
+    })
+}
+
+fn descriptor() -> &'static clap_preset_discovery_provider_descriptor {
+    let strings = strings();
+    &DESCRIPTOR
+        .get_or_init(|| {
+            Descriptor(clap_preset_discovery_provider_descriptor {
+                clap_version: CLAP_VERSION,
+                id: strings.provider_id.as_ptr(),
+                name: strings.provider_name.as_ptr(),
+                vendor: strings.vendor.as_ptr(),
+            })
+        })
+        .0
+}
+
+/// The factory preset a host indexed by its load key, which is the preset's name
+pub(crate) unsafe fn preset(
+    location_kind: clap_preset_discovery_location_kind,
+    load_key: *const c_char,
+) -> Option<&'static Preset> {
+    if location_kind != CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN || load_key.is_null() {
+        return None;
+    }
+
+    Preset::factory(CStr::from_ptr(load_key).to_str().ok()?)
+}
+
+unsafe extern "C" fn count(_factory: *const clap_preset_discovery_factory) -> u32 {
+    1
+}
+
+unsafe extern "C" fn get_descriptor(
+    _factory: *const clap_preset_discovery_factory,
+    index: u32,
+) -> *const clap_preset_discovery_provider_descriptor {
+    match index {
+        0 => descriptor(),
+        _ => std::ptr::null(),
+    }
+}
+
+unsafe extern "C" fn create(
+    _factory: *const clap_preset_discovery_factory,
+    indexer: *const clap_preset_discovery_indexer,
+    provider_id: *const c_char,
+) -> *const clap_preset_discovery_provider {
+    if indexer.is_null()
+        || provider_id.is_null()
+        || CStr::from_ptr(provider_id) != strings().provider_id.as_c_str()
+    {
+        return std::ptr::null();
+    }
+
+    let provider = Box::new(Provider {
+        provider: clap_preset_discovery_provider {
+            desc: descriptor(),
+            provider_data: std::ptr::null_mut(),
+            init: Some(init),
+            destroy: Some(destroy),
+            get_metadata: Some(get_metadata),
+            get_extension: Some(get_extension),
+        },
+        indexer,
+    });
+
+    Box::into_raw(provider).cast()
+}
+
+unsafe extern "C" fn init(provider: *const clap_preset_discovery_provider) -> bool {
+    let indexer = &*(*provider.cast::<Provider>()).indexer;
+    let strings = strings();
+    let location = clap_preset_discovery_location {
+        flags: CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT,
+        name: strings.location_name.as_ptr(),
+        kind: CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN,
+        location: std::ptr::null(),
+    };
+    let soundpack = clap_preset_discovery_soundpack {
+        flags: CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT,
+        id: strings.soundpack_id.as_ptr(),
+        name: strings.soundpack_name.as_ptr(),
+        description: strings.soundpack_description.as_ptr(),
+        homepage_url: strings.url.as_ptr(),
+        vendor: strings.vendor.as_ptr(),
+        image_path: std::ptr::null(),
+        release_timestamp: CLAP_TIMESTAMP_UNKNOWN,
+    };
+
+    // The soundpack is optional, hosts that don't know about them still get the presets
+    let Some(declare_location) = indexer.declare_location else {
+        return false;
+    };
+    if let Some(declare_soundpack) = indexer.declare_soundpack {
+        declare_soundpack(indexer, &soundpack);
+    }
+
+    declare_location(indexer, &location)
+}
+
+unsafe extern "C" fn destroy(provider: *const clap_preset_discovery_provider) {
+    drop(Box::from_raw(provider.cast_mut().cast::<Provider>()));
+}
+
+unsafe extern "C" fn get_metadata(
+    _provider: *const clap_preset_discovery_provider,
+    location_kind: clap_preset_discovery_location_kind,
+    _location: *const c_char,
+    metadata_receiver: *const clap_preset_discovery_metadata_receiver,
+) -> bool {
+    if location_kind != CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN || metadata_receiver.is_null() {
+        return false;
+    }
+
+    let receiver = &*metadata_receiver;
+    let strings = strings();
+    let plugin_id = clap_universal_plugin_id {
+        abi: c"clap".as_ptr(),
+        id: strings.plugin_id.as_ptr(),
+    };
+    let Some(begin_preset) = receiver.begin_preset else {
+        return false;
+    };
+    for (name, features) in &strings.presets {
+        // The name doubles as the load key
+        if !begin_preset(receiver, name.as_ptr(), name.as_ptr()) {
+            break;
+        }
+
+        if let Some(add_plugin_id) = receiver.add_plugin_id {
+            add_plugin_id(receiver, &plugin_id);
+        }
+        if let Some(set_soundpack_id) = receiver.set_soundpack_id {
+            set_soundpack_id(receiver, strings.soundpack_id.as_ptr());
+        }
+        if let Some(set_flags) = receiver.set_flags {
+            set_flags(receiver, CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT);
+        }
+        if let Some(add_creator) = receiver.add_creator {
+            add_creator(receiver, strings.creator.as_ptr());
+        }
+        if let Some(add_feature) = receiver.add_feature {
+            for feature in features {
+                add_feature(receiver, feature.as_ptr());
+            }
+        }
+    }
+
+    true
+}
+
+unsafe extern "C" fn get_extension(
+    _provider: *const clap_preset_discovery_provider,
+    _extension_id: *const c_char,
+) -> *const std::ffi::c_void {
+    std::ptr::null()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+
+    use super::*;
+
+    /// What the test indexer and metadata receiver were told
+    #[derive(Default)]
+    struct Index {
+        locations: Vec<(String, u32)>,
+        soundpacks: Vec<String>,
+        /// Every preset's name, load key, plugin ID, soundpack, creator and features
+        presets: Vec<(String, String, String, String, String, Vec<String>)>,
+    }
+
+    unsafe fn string(string: *const c_char) -> String {
+        CStr::from_ptr(string).to_str().unwrap().to_owned()
+    }
+
+    unsafe fn index(data: *mut c_void) -> &'static mut Index {
+        &mut *data.cast::<Index>()
+    }
+
+    unsafe extern "C" fn declare_location(
+        indexer: *const clap_preset_discovery_indexer,
+        location: *const clap_preset_discovery_location,
+    ) -> bool {
+        let location = &*location;
+        assert!(location.location.is_null());
+        index((*indexer).indexer_data).locations.push((string(location.name), location.kind));
+        true
+    }
+
+    unsafe extern "C" fn declare_soundpack(
+        indexer: *const clap_preset_discovery_indexer,
+        soundpack: *const clap_preset_discovery_soundpack,
+    ) -> bool {
+        index((*indexer).indexer_data).soundpacks.push(string((*soundpack).id));
+        true
+    }
+
+    unsafe extern "C" fn begin_preset(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        name: *const c_char,
+        load_key: *const c_char,
+    ) -> bool {
+        let (name, load_key) = (string(name), string(load_key));
+        let presets = &mut index((*receiver).receiver_data).presets;
+        presets.push((name, load_key, String::new(), String::new(), String::new(), Vec::new()));
+        true
+    }
+
+    unsafe extern "C" fn add_plugin_id(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        plugin_id: *const clap_universal_plugin_id,
+    ) {
+        assert_eq!(string((*plugin_id).abi), "clap");
+        let preset = index((*receiver).receiver_data).presets.last_mut().unwrap();
+        preset.2 = string((*plugin_id).id);
+    }
+
+    unsafe extern "C" fn set_soundpack_id(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        soundpack_id: *const c_char,
+    ) {
+        index((*receiver).receiver_data).presets.last_mut().unwrap().3 = string(soundpack_id);
+    }
+
+    unsafe extern "C" fn add_creator(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        creator: *const c_char,
+    ) {
+        index((*receiver).receiver_data).presets.last_mut().unwrap().4 = string(creator);
+    }
+
+    unsafe extern "C" fn add_feature(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        feature: *const c_char,
+    ) {
+        let preset = index((*receiver).receiver_data).presets.last_mut().unwrap();
+        preset.5.push(string(feature));
+    }
+
+    #[test]
+    fn indexes_the_factory_presets() {
+        let mut index = Index::default();
+        let data = std::ptr::from_mut(&mut index).cast::<c_void>();
+        let indexer = clap_preset_discovery_indexer {
+            clap_version: CLAP_VERSION,
+            name: c"Test".as_ptr(),
+            vendor: std::ptr::null(),
+            url: std::ptr::null(),
+            version: std::ptr::null(),
+            indexer_data: data,
+            declare_filetype: None,
+            declare_location: Some(declare_location),
+            declare_soundpack: Some(declare_soundpack),
+            get_extension: None,
+        };
+        let receiver = clap_preset_discovery_metadata_receiver {
+            receiver_data: data,
+            on_error: None,
+            begin_preset: Some(begin_preset),
+            add_plugin_id: Some(add_plugin_id),
+            set_soundpack_id: Some(set_soundpack_id),
+            set_flags: None,
+            add_creator: Some(add_creator),
+            set_description: None,
+            set_timestamps: None,
+            add_feature: Some(add_feature),
+            add_extra_info: None,
+        };
+
+        // SAFETY: The indexer and the receiver outlive the provider
+        unsafe {
+            assert_eq!(count(&FACTORY), 1);
+            assert!(get_descriptor(&FACTORY, 1).is_null());
+            let descriptor = &*get_descriptor(&FACTORY, 0);
+            assert!(create(&FACTORY, &indexer, c"other".as_ptr()).is_null());
+            let provider = create(&FACTORY, &indexer, descriptor.id);
+            assert!((*provider).init.unwrap()(provider));
+            let get_metadata = (*provider).get_metadata.unwrap();
+            let file = CLAP_PRESET_DISCOVERY_LOCATION_FILE;
+            assert!(!get_metadata(provider, file, c"".as_ptr(), &receiver));
+            let plugin = CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN;
+            assert!(get_metadata(provider, plugin, std::ptr::null(), &receiver));
+            (*provider).destroy.unwrap()(provider);
+        }
+
+        let soundpack = format!("{}.factory", WinXpCrash::CLAP_ID);
+        assert_eq!(
+            index.locations,
+            [("Factory Presets".to_owned(), CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN)]
+        );
+        assert_eq!(index.soundpacks, [soundpack.as_str()]);
+        assert_eq!(index.presets.len(), FACTORY_PRESETS.len());
+        for (indexed, preset) in index.presets.iter().zip(FACTORY_PRESETS) {
+            let (name, load_key, plugin_id, soundpack_id, creator, features) = indexed;
+            assert_eq!(name, preset.name);
+            assert_eq!(plugin_id, WinXpCrash::CLAP_ID);
+            assert_eq!(*soundpack_id, soundpack);
+            assert_eq!(creator, FACTORY_PRESET_CREATOR);
+            assert_eq!(*features, preset.features);
+            let load_key = c_string(load_key);
+            // SAFETY: The load key is a valid C string
+            let loaded =
+                unsafe { super::preset(CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN, load_key.as_ptr()) };
+            assert_eq!(loaded.map(|preset| preset.name), Some(preset.name));
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use nih_plug::params::internals::ParamPtr;
+use nih_plug::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::rng::Rng;
+use crate::state::{self, BufferState};
+use crate::wav;
+
+/// User presets and exported presets are stored in files with this extension
+const USER_PRESET_EXTENSION: &str = "json";
+/// Parameters that presets and A/B compare slots never touch. Freeze and Freeze Amount are left
+/// alone so loading a preset doesn't release a frozen buffer, the WAV buttons and Trigger One
+/// Loop would start an export, an import or a freeze, and the link group belongs to the session
+/// rather than to a sound.
+const UNSTORED_PARAMS: &[&str] = &[
+    "freeze",
+    "freeze_amount",
+    "trigger_one_loop",
+    "export_wav",
+    "import_wav",
+    "link_group",
+];
+/// The parameters the randomizer changes and the range of plain values it picks from. The MIDI
+/// and sidechain setup is left alone, and the ranges stay clear of extremes like tiny buffers or
+/// very long releases.
+const RANDOM_RANGES: &[(&str, f32, f32)] = &[
+    ("buffer_size", 1024., 32768.),
+    ("key_tracking", 0., 1.),
+    ("note_behavior", 0., 1.),
+    ("glide", 0., 500.),
+    ("glide_buffer_size", 0., 1.),
+    ("interpolation", 0., 3.),
+    // Off to 1/32
+    ("division", 0., 6.),
+    ("velocity_division", 0., 1.),
+    ("attack", 0., 20.),
+    ("release", 0., 1000.),
+    ("key_pan", 0., 1.),
+    ("random_pan", 0., 1.),
+    ("loop_start", 0., 1.),
+    ("loop_length", 0.1, 1.),
+    ("spread", 0., 1.),
+    ("stretch", 0.5, 2.),
+    ("crackle", 0., 0.5),
+    ("hitch_rate", 0., 2.),
+    ("hitch_depth", 0., 1.),
+    ("bit_depth", 4., 24.),
+];
+
+/// The creator CLAP hosts show for the factory presets
+pub const FACTORY_PRESET_CREATOR: &str = <crate::WinXpCrash as Plugin>::VENDOR;
+
+/// A curated set of parameter values. Parameters that aren't listed are set to their defaults,
+/// except for the ones in `UNSTORED_PARAMS`.
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub name: &'static str,
+    /// Tags for a host's preset browser, in the style of CLAP's plugin features
+    pub features: &'static [&'static str],
+    /// Plain values by parameter ID. Booleans are 0 or 1 and enums use their variant index.
+    pub values: &'static [(&'static str, f32)],
+}
+
+pub const FACTORY_PRESETS: &[Preset] = &[
+    Preset {
+        name: "Clean Hold",
+        features: &["audio-effect", "freeze", "ambient"],
+        values: &[("buffer_size", 8192.), ("release", 50.)],
+    },
+    Preset {
+        name: "Stutter 1/16",
+        features: &["audio-effect", "glitch", "stutter"],
+        values: &[
+            ("buffer_size", 4096.),
+            // 1/16
+            ("division", 5.),
+            ("quantize_trigger", 1.),
+            ("release", 20.),
+        ],
+    },
+    Preset {
+        name: "Tuned Keys",
+        features: &["audio-effect", "freeze", "melodic"],
+        values: &[
+            ("key_tracking", 1.),
+            // Length Tuned
+            ("note_behavior", 0.),
+            ("glide", 30.),
+            ("release", 200.),
+        ],
+    },
+    Preset {
+        name: "Total Crash",
+        features: &["audio-effect", "glitch", "stutter"],
+        values: &[
+            ("buffer_size", 2048.),
+            // 1/32
+            ("division", 6.),
+            ("velocity_division", 1.),
+            ("spread", 1.),
+        ],
+    },
+    Preset {
+        name: "Tape Death",
+        features: &["audio-effect", "glitch", "tape"],
+        values: &[
+            ("buffer_size", 32768.),
+            ("key_tracking", 1.),
+            // Repitch
+            ("note_behavior", 1.),
+            ("glide", 800.),
+            ("glide_buffer_size", 1.),
+            ("release", 1500.),
+        ],
+    },
+    Preset {
+        name: "Driver Crash",
+        features: &["audio-effect", "glitch", "lo-fi"],
+        values: &[
+            // Authentic with 1024 sample chunks
+            ("mode", 1.),
+            ("chunk_size", 2.),
+        ],
+    },
+];
+
+impl Preset {
+    /// The factory preset a host indexed by its load key, which is the preset's name
+    pub fn factory(load_key: &str) -> Option<&'static Preset> {
+        FACTORY_PRESETS
+            .iter()
+            .find(|preset| preset.name == load_key)
+    }
+
+    /// Set all parameters to this preset's values through the GUI context, so the host records
+    /// the changes like any other parameter change.
+    pub fn apply(&self, context: &dyn GuiContext, params: &dyn Params) {
+        apply_values(context, params, |id| self.value(id));
+    }
+
+    /// This preset as a plugin state, for loading it without the editor. Restoring the state
+    /// sets the parameters like `apply()` does and leaves everything else alone. `default_params`
+    /// provide the parameters that aren't part of the preset.
+    pub fn state(&self, default_params: &dyn Params) -> PluginState {
+        let params = default_params
+            .param_map()
+            .into_iter()
+            .filter(|(id, _, _)| !UNSTORED_PARAMS.contains(&id.as_str()))
+            .map(|(id, param_ptr, _)| {
+                // SAFETY: The parameter pointers stay valid for as long as `default_params` is
+                //         borrowed
+                let value = match self.value(&id) {
+                    Some(plain) => unsafe { state::stored_value(param_ptr, plain) },
+                    None => unsafe { state::default_value(param_ptr) },
+                };
+                (id, value)
+            })
+            .collect();
+
+        PluginState {
+            version: <crate::WinXpCrash as Plugin>::VERSION.to_owned(),
+            params,
+            // Without a version the state would be migrated as one from before the presets
+            fields: BTreeMap::from([(
+                state::STATE_VERSION_KEY.to_owned(),
+                state::STATE_VERSION.to_string(),
+            )]),
+        }
+    }
+
+    /// The plain value this preset sets the parameter with the ID `id` to, if it sets it at all
+    fn value(&self, id: &str) -> Option<f32> {
+        self.values
+            .iter()
+            .find(|(preset_id, _)| *preset_id == id)
+            .map(|(_, plain)| *plain)
+    }
+}
+
+/// A preset saved from the editor. Every user preset is a JSON file in `user_directory()`, and
+/// presets can be exported to JSON files to share them. Every field has a default so presets
+/// from other versions of the plugin can still be read, and unknown fields are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserPreset {
+    #[serde(default)]
+    pub name: String,
+    /// The version of the plugin that saved the preset
+    #[serde(default)]
+    pub plugin_version: String,
+    /// Plain values by parameter ID, like `Preset::values`
+    #[serde(default)]
+    pub values: BTreeMap<String, f32>,
+    /// The frozen loops, if they were saved with the preset
+    #[serde(default)]
+    pub buffer: Option<BufferState>,
+    /// The name of the WAV file next to an exported preset that contains its loops, see
+    /// `export()`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_file: Option<String>,
+}
+
+impl UserPreset {
+    /// Store the current parameter values
+    pub fn capture(name: String, params: &dyn Params, buffer: Option<BufferState>) -> Self {
+        Self {
+            name,
+            plugin_version: env!("CARGO_PKG_VERSION").to_owned(),
+            values: capture_values(params),
+            buffer,
+            buffer_file: None,
+        }
+    }
+
+    /// Set all parameters to this preset's values, see `Preset::apply()`. Parameters that were
+    /// added after the preset was saved are set to their defaults.
+    pub fn apply(&self, context: &dyn GuiContext, params: &dyn Params) {
+        apply_values(context, params, |id| self.values.get(id).copied());
+    }
+
+    /// Write the preset to `path`, creating the preset directory if it doesn't exist yet
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let json = serde_json::to_vec_pretty(self)?;
+        fs::write(path, json)
+    }
+
+    /// Write the preset to `path` to share it. The loops are written to a WAV file with the same
+    /// name instead of into the JSON file, so they can be opened in other software too.
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        let mut exported = UserPreset {
+            name: self.name.clone(),
+            plugin_version: self.plugin_version.clone(),
+            values: self.values.clone(),
+            buffer: None,
+            buffer_file: None,
+        };
+        if let Some(buffer) = &self.buffer {
+            let wav_path = path.with_extension("wav");
+            wav::write(&wav_path, &buffer.channels, buffer.sample_rate)?;
+            exported.buffer_file = wav_path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned());
+        }
+
+        exported.save(path)
+    }
+
+    /// Read a preset file written by `save()` or `export()`. The loops of exported presets are
+    /// read from their WAV file and resampled to `sample_rate`. A missing WAV file only drops
+    /// the loops, and they're skipped while `sample_rate` isn't known yet.
+    pub fn import(path: &Path, sample_rate: f32) -> io::Result<Self> {
+        let json = fs::read(path)?;
+        let mut preset: UserPreset = serde_json::from_slice(&json)?;
+
+        // Only files next to the preset are read
+        let buffer_file = preset.buffer_file.take();
+        let wav_path = buffer_file
+            .as_deref()
+            .and_then(|buffer_file| Path::new(buffer_file).file_name())
+            .map(|file_name| path.with_file_name(file_name));
+        if let Some(wav_path) = wav_path.filter(|_| sample_rate > 0.) {
+            match wav::import(&wav_path, sample_rate) {
+                Ok(channels) => {
+                    preset.buffer = Some(BufferState {
+                        sample_rate,
+                        frozen: true,
+                        channels,
+                    })
+                }
+                Err(err) => nih_warn!("Could not read '{}': {err}", wav_path.display()),
+            }
+        }
+
+        Ok(preset)
+    }
+}
+
+/// One of the two A/B compare slots
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AbSlot {
+    #[default]
+    A,
+    B,
+}
+
+/// Two sets of parameter values to flip between. The active slot's values are the current
+/// parameter values, so only the other slot needs to be stored. The frozen loops are never part
+/// of a slot.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AbCompare {
+    pub active: AbSlot,
+    /// The inactive slot's plain values by parameter ID. This is `None` until the slots have
+    /// been switched or copied for the first time.
+    pub other: Option<BTreeMap<String, f32>>,
+}
+
+impl AbCompare {
+    /// Store the current values in the active slot and apply the other slot's values. A slot
+    /// that's still empty starts out as a copy of the active one.
+    pub fn switch(&mut self, context: &dyn GuiContext, params: &dyn Params) {
+        let current = capture_values(params);
+        if let Some(other) = &self.other {
+            apply_values(context, params, |id| other.get(id).copied());
+        }
+
+        self.other = Some(current);
+        self.active = match self.active {
+            AbSlot::A => AbSlot::B,
+            AbSlot::B => AbSlot::A,
+        };
+    }
+
+    /// Overwrite slot B with slot A's values
+    pub fn copy_a_to_b(&mut self, context: &dyn GuiContext, params: &dyn Params) {
+        match (self.active, &self.other) {
+            (AbSlot::A, _) => self.other = Some(capture_values(params)),
+            (AbSlot::B, Some(a)) => apply_values(context, params, |id| a.get(id).copied()),
+            // Both slots are still the same
+            (AbSlot::B, None) => (),
+        }
+    }
+}
+
+/// The per-user directory the user presets are stored in, or `None` if the platform's config
+/// directory can't be determined
+pub fn user_directory() -> Option<PathBuf> {
+    let config_directory = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+        })
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|directory| !directory.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    config_directory.map(|directory| directory.join("Windows XP Crash").join("Presets"))
+}
+
+/// The file a preset called `name` is saved to in `directory`. Characters that aren't allowed in
+/// file names on every platform are replaced.
+pub fn preset_path(directory: &Path, name: &str) -> PathBuf {
+    let file_name: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || " -_()".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    directory.join(format!("{file_name}.{USER_PRESET_EXTENSION}"))
+}
+
+/// Read all user presets in `directory` together with their paths, sorted by name. A directory
+/// that doesn't exist yet doesn't contain any presets, and files that can't be parsed are
+/// skipped.
+pub fn load_user_presets(directory: &Path) -> io::Result<Vec<(PathBuf, UserPreset)>> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut presets = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if !is_preset_file(&path) {
+            continue;
+        }
+
+        let preset = fs::read(&path).and_then(|json| Ok(serde_json::from_slice(&json)?));
+        match preset {
+            Ok(preset) => presets.push((path, preset)),
+            Err(err) => nih_warn!("Skipping the preset '{}': {err}", path.display()),
+        }
+    }
+    presets.sort_by_cached_key(|(_, preset): &(PathBuf, UserPreset)| preset.name.to_lowercase());
+
+    Ok(presets)
+}
+
+/// The current plain values by parameter ID, leaving out `UNSTORED_PARAMS`
+fn capture_values(params: &dyn Params) -> BTreeMap<String, f32> {
+    params
+        .param_map()
+        .into_iter()
+        .filter(|(id, _, _)| !UNSTORED_PARAMS.contains(&id.as_str()))
+        // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+        .map(|(id, param_ptr, _)| (id, unsafe { param_ptr.unmodulated_plain_value() }))
+        .collect()
+}
+
+/// Whether `path` looks like a preset file, going by its extension
+pub fn is_preset_file(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new(USER_PRESET_EXTENSION))
+}
+
+/// Set every parameter but the ones in `UNSTORED_PARAMS` to the plain value `value()` returns
+/// for its ID, or to its default if it returns `None`
+fn apply_values(
+    context: &dyn GuiContext,
+    params: &dyn Params,
+    value: impl Fn(&str) -> Option<f32>,
+) {
+    for (id, param_ptr, _) in params.param_map() {
+        if UNSTORED_PARAMS.contains(&id.as_str()) {
+            continue;
+        }
+
+        // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+        unsafe {
+            let normalized = match value(&id) {
+                Some(plain) => param_ptr.preview_normalized(plain),
+                None => param_ptr.default_normalized_value(),
+            };
+            set_normalized(context, param_ptr, normalized);
+        }
+    }
+}
+
+/// The IDs and names of the parameters the randomizer can change, so they can be locked
+pub fn randomizable_params(params: &dyn Params) -> Vec<(String, String)> {
+    params
+        .param_map()
+        .into_iter()
+        .filter(|(id, _, _)| {
+            RANDOM_RANGES
+                .iter()
+                .any(|(random_id, _, _)| random_id == id)
+        })
+        // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+        .map(|(id, param_ptr, _)| (id, unsafe { param_ptr.name() }.to_owned()))
+        .collect()
+}
+
+/// Set every parameter in `RANDOM_RANGES` that isn't locked to a random value. Returns their
+/// plain values from before, which `restore_values()` can bring back.
+pub fn randomize(
+    context: &dyn GuiContext,
+    params: &dyn Params,
+    locked: &BTreeSet<String>,
+    rng: &mut Rng,
+) -> BTreeMap<String, f32> {
+    let mut previous_values = BTreeMap::new();
+    for (id, param_ptr, _) in params.param_map() {
+        let Some(&(_, min, max)) = RANDOM_RANGES
+            .iter()
+            .find(|(random_id, _, _)| *random_id == id)
+        else {
+            continue;
+        };
+        if locked.contains(&id) {
+            continue;
+        }
+
+        // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+        unsafe {
+            previous_values.insert(id, param_ptr.unmodulated_plain_value());
+            let min = param_ptr.preview_normalized(min);
+            let max = param_ptr.preview_normalized(max);
+            set_normalized(context, param_ptr, min + (max - min) * rng.next_f32());
+        }
+    }
+
+    previous_values
+}
+
+/// Set the parameters in `values` to their plain values, leaving all other parameters alone
+pub fn restore_values(
+    context: &dyn GuiContext,
+    params: &dyn Params,
+    values: &BTreeMap<String, f32>,
+) {
+    for (id, param_ptr, _) in params.param_map() {
+        if let Some(&plain) = values.get(&id) {
+            // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+            unsafe { set_normalized(context, param_ptr, param_ptr.preview_normalized(plain)) };
+        }
+    }
+}
+
+/// Change a parameter in a gesture of its own, so the host records it like any other change
+pub(crate) unsafe fn set_normalized(
+    context: &dyn GuiContext,
+    param_ptr: ParamPtr,
+    normalized: f32,
+) {
+    context.raw_begin_set_parameter(param_ptr);
+    context.raw_set_parameter_normalized(param_ptr, normalized);
+    context.raw_end_set_parameter(param_ptr);
+}
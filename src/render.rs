@@ -0,0 +1,443 @@
+//! Offline rendering for the standalone application. `--input in.wav --output out.wav` runs a
+//! WAV file through the plugin's own `process()` in fixed blocks, as fast as possible and
+//! without an audio device, for batch sound design work.
+//!
+//! `--freeze 1.5s-3s,5s-6s` sets the ranges where the buffer is frozen, in seconds or with an
+//! `ms` suffix in milliseconds. `--midi notes.mid` plays a MIDI file's notes into the plugin,
+//! where they freeze the buffer like notes from a host do. `--buffer-size` sets the buffer size
+//! in samples. The other parameters keep their defaults, as nih-plug only lets hosts change
+//! them. The plugin is initialized at the file's sample rate, and the transport plays from the
+//! start of the file at 120 BPM like in the standalone application.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+
+use nih_plug::params::internals::ParamPtr;
+use nih_plug::prelude::*;
+
+use crate::freeze::TransportInfo;
+use crate::host::Host;
+use crate::midi_file::{self, FileNote};
+use crate::{wav, Task, WinXpCrash, DEFAULT_TEMPO};
+
+/// The file is processed in blocks of this many samples, like a host would
+const BLOCK_SIZE: usize = 512;
+/// Enough room for the events of a block, so queueing them doesn't allocate
+const EVENT_CAPACITY: usize = 1024;
+
+/// What to render, parsed from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// The ranges where the buffer is frozen, in seconds
+    pub freezes: Vec<(f64, f64)>,
+    /// A MIDI file whose notes are sent to the plugin
+    pub midi: Option<PathBuf>,
+    /// The buffer size in samples, instead of the Buffer Size parameter's default
+    pub buffer_size: Option<usize>,
+}
+
+impl RenderOptions {
+    /// Parse the command line arguments after the program's name. Returns `Ok(None)` without
+    /// an `--input` argument, so the standalone application starts as usual.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, String> {
+        if !args.iter().any(|arg| arg == "--input") {
+            return Ok(None);
+        }
+
+        let mut input = None;
+        let mut output = None;
+        let mut options = Self {
+            input: PathBuf::new(),
+            output: PathBuf::new(),
+            freezes: Vec::new(),
+            midi: None,
+            buffer_size: None,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--input" => input = Some(PathBuf::from(value()?)),
+                "--output" => output = Some(PathBuf::from(value()?)),
+                "--freeze" => options.freezes = parse_schedule(value()?)?,
+                "--midi" => options.midi = Some(PathBuf::from(value()?)),
+                "--buffer-size" => {
+                    let size = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&size| size > 0)
+                        .ok_or_else(|| format!("{arg} needs a number of samples"))?;
+                    options.buffer_size = Some(size);
+                }
+                _ => return Err(format!("Unknown argument {arg} for offline rendering")),
+            }
+        }
+
+        options.input = input.ok_or("--input needs a value")?;
+        options.output = output.ok_or("Offline rendering needs an --output file")?;
+        Ok(Some(options))
+    }
+}
+
+/// Render the input file to the output file. The blocks end early wherever the freeze
+/// schedule switches, so the freeze engages on the exact sample.
+pub fn render(options: &RenderOptions) -> io::Result<()> {
+    let (mut channels, sample_rate) = wav::read(&options.input)?;
+    let notes = match &options.midi {
+        Some(path) => midi_file::read(path)?,
+        None => Vec::new(),
+    };
+
+    let mut renderer = Renderer::new(channels.len(), sample_rate)?;
+    if let Some(size) = options.buffer_size {
+        renderer.plugin.engine.set_length(size as f32);
+    }
+
+    let frozen_at = |sample: usize| {
+        let time = sample as f64 / sample_rate as f64;
+        options
+            .freezes
+            .iter()
+            .any(|&(start, end)| time >= start && time < end)
+    };
+    let note_sample = |note: &FileNote| (note.time * sample_rate as f64).round() as usize;
+    let mut notes = notes.iter().peekable();
+    let len = channels.first().map_or(0, Vec::len);
+    let mut start = 0;
+    while start < len {
+        let frozen = frozen_at(start);
+        let end = (start + 1..(start + BLOCK_SIZE).min(len))
+            .find(|&sample| frozen_at(sample) != frozen)
+            .unwrap_or((start + BLOCK_SIZE).min(len));
+        renderer.plugin.engine.set_freeze(frozen);
+        while let Some(note) = notes.next_if(|note| note_sample(note) < end) {
+            let timing = note_sample(note).saturating_sub(start) as u32;
+            renderer.send_event(note_event(note, timing));
+        }
+
+        let mut block: Vec<&mut [f32]> = channels
+            .iter_mut()
+            .map(|channel| &mut channel[start..end])
+            .collect();
+        renderer.process(&mut block);
+        start = end;
+    }
+
+    wav::write(&options.output, &channels, sample_rate)
+}
+
+fn note_event(note: &FileNote, timing: u32) -> PluginNoteEvent<WinXpCrash> {
+    match note.velocity {
+        Some(velocity) => NoteEvent::NoteOn {
+            timing,
+            voice_id: None,
+            channel: note.channel,
+            note: note.note,
+            velocity,
+        },
+        None => NoteEvent::NoteOff {
+            timing,
+            voice_id: None,
+            channel: note.channel,
+            note: note.note,
+            velocity: 0.,
+        },
+    }
+}
+
+/// Runs the plugin without a plugin host, initialized like a host would
+pub(crate) struct Renderer {
+    pub plugin: WinXpCrash,
+    host: OfflineHost,
+    task_executor: TaskExecutor<WinXpCrash>,
+}
+
+/// The host the plugin sees while rendering. The transport is playing from the first rendered
+/// sample on, and the plugin's own events are discarded.
+struct OfflineHost {
+    sample_rate: f32,
+    sample_position: usize,
+    /// The events for the next block, timed relative to the block's start
+    events: VecDeque<PluginNoteEvent<WinXpCrash>>,
+    /// Background tasks, which are run after the block that requested them
+    tasks: RefCell<Vec<Task>>,
+}
+
+impl Renderer {
+    /// Initialize and reset a plugin for `num_channels` channels, which needs to be one of its
+    /// main output layouts
+    pub(crate) fn new(num_channels: usize, sample_rate: f32) -> io::Result<Self> {
+        let num_channels = NonZeroU32::new(num_channels as u32);
+        let layout = WinXpCrash::AUDIO_IO_LAYOUTS
+            .iter()
+            .find(|layout| {
+                layout.main_input_channels == num_channels
+                    && layout.main_output_channels == num_channels
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "only mono and stereo files are supported",
+                )
+            })?;
+        let buffer_config = BufferConfig {
+            sample_rate,
+            min_buffer_size: None,
+            max_buffer_size: BLOCK_SIZE as u32,
+            process_mode: ProcessMode::Offline,
+        };
+
+        let mut plugin = WinXpCrash::default();
+        reset_smoothers(&*plugin.params());
+        let mut host = OfflineHost {
+            sample_rate,
+            sample_position: 0,
+            events: VecDeque::with_capacity(EVENT_CAPACITY),
+            tasks: RefCell::new(Vec::with_capacity(EVENT_CAPACITY)),
+        };
+        if !plugin.initialize(layout, &buffer_config, &mut host) {
+            return Err(io::Error::other("the plugin could not be initialized"));
+        }
+        plugin.reset();
+
+        let mut renderer = Self {
+            task_executor: plugin.task_executor(),
+            plugin,
+            host,
+        };
+        renderer.run_tasks();
+        Ok(renderer)
+    }
+
+    /// Send an event to the plugin with the next block, timed relative to the block's start
+    pub(crate) fn send_event(&mut self, event: PluginNoteEvent<WinXpCrash>) {
+        self.host.events.push_back(event);
+    }
+
+    /// Process the next `block` of samples in place and run the tasks it requested
+    pub(crate) fn process(&mut self, block: &mut [&mut [f32]]) -> ProcessStatus {
+        let len = block.first().map_or(0, |channel| channel.len());
+        let mut buffer = Buffer::default();
+        // SAFETY: All channels are `len` samples long and outlive the buffer
+        unsafe {
+            buffer.set_slices(len, |slices| {
+                slices.extend(block.iter_mut().map(|channel| &mut **channel));
+            });
+        }
+
+        let status = self.process_buffer(&mut buffer);
+        self.run_tasks();
+        status
+    }
+
+    /// Process `buffer` exactly like a host would, without running any background tasks
+    pub(crate) fn process_buffer(&mut self, buffer: &mut Buffer) -> ProcessStatus {
+        let mut aux = AuxiliaryBuffers {
+            inputs: &mut [],
+            outputs: &mut [],
+        };
+        let status = self.plugin.process_block(buffer, &mut aux, &mut self.host);
+        self.host.events.clear();
+        self.host.sample_position += buffer.samples();
+        status
+    }
+
+    /// Run the background tasks the processed blocks requested
+    pub(crate) fn run_tasks(&mut self) {
+        let tasks = std::mem::take(&mut *self.host.tasks.borrow_mut());
+        for task in tasks {
+            (self.task_executor)(task);
+        }
+    }
+}
+
+/// Move all smoothers to their parameter's value, like nih-plug does before initializing a
+/// plugin. The parameters never change while rendering, so they don't need to be smoothed.
+fn reset_smoothers(params: &dyn Params) {
+    for (_, param_ptr, _) in params.param_map() {
+        // SAFETY: The parameter pointers stay valid for as long as `params` is borrowed
+        unsafe {
+            match param_ptr {
+                ParamPtr::FloatParam(param) => (*param).smoothed.reset((*param).value()),
+                ParamPtr::IntParam(param) => (*param).smoothed.reset((*param).value()),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Host for OfflineHost {
+    fn transport(&self) -> TransportInfo {
+        let pos_beats = self.sample_position as f64 / self.sample_rate as f64 / 60. * DEFAULT_TEMPO;
+        TransportInfo {
+            playing: true,
+            tempo: Some(DEFAULT_TEMPO),
+            time_sig_numerator: Some(4),
+            time_sig_denominator: Some(4),
+            pos_beats: Some(pos_beats),
+            bar_start_pos_beats: Some((pos_beats / 4.).floor() * 4.),
+        }
+    }
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<WinXpCrash>> {
+        self.events.pop_front()
+    }
+
+    fn send_event(&mut self, _event: PluginNoteEvent<WinXpCrash>) {}
+
+    fn execute_background(&self, task: Task) {
+        self.tasks.borrow_mut().push(task);
+    }
+
+    fn set_latency_samples(&self, _samples: u32) {}
+
+    fn set_current_voice_capacity(&self, _capacity: u32) {}
+}
+
+impl InitContext<WinXpCrash> for OfflineHost {
+    fn plugin_api(&self) -> PluginApi {
+        PluginApi::Standalone
+    }
+
+    fn execute(&self, task: Task) {
+        self.tasks.borrow_mut().push(task);
+    }
+
+    fn set_latency_samples(&self, _samples: u32) {}
+
+    fn set_current_voice_capacity(&self, _capacity: u32) {}
+}
+
+/// Parse a freeze schedule like `1.5s-3s,5s-6s` into ranges in seconds
+fn parse_schedule(schedule: &str) -> Result<Vec<(f64, f64)>, String> {
+    schedule
+        .split(',')
+        .map(|range| {
+            let invalid = || format!("{range} is not a range like 1.5s-3s");
+            let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+            let (start, end) = (parse_time(start), parse_time(end));
+            match (start, end) {
+                (Some(start), Some(end)) if start < end => Ok((start, end)),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+/// A time in seconds, with an optional `s` or `ms` suffix
+fn parse_time(time: &str) -> Option<f64> {
+    let time = time.trim();
+    let seconds = match time.strip_suffix("ms") {
+        Some(ms) => ms.trim_end().parse::<f64>().ok()? / 1000.,
+        None => time.trim_end_matches('s').trim_end().parse().ok()?,
+    };
+
+    (seconds.is_finite() && seconds >= 0.).then_some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_freeze_schedule() {
+        assert_eq!(
+            parse_schedule("1.5s-3s,500ms-6").unwrap(),
+            [(1.5, 3.), (0.5, 6.)]
+        );
+        assert!(parse_schedule("3s-1s").is_err());
+        assert!(parse_schedule("1s").is_err());
+    }
+
+    #[test]
+    fn parse_arguments() {
+        let args = |args: &str| args.split(' ').map(str::to_owned).collect::<Vec<_>>();
+        assert_eq!(RenderOptions::from_args(&args("--osc-port 9000")), Ok(None));
+        assert!(RenderOptions::from_args(&args("--input in.wav")).is_err());
+        assert!(
+            RenderOptions::from_args(&args("--input in.wav --output out.wav --attack 5")).is_err()
+        );
+
+        let options =
+            RenderOptions::from_args(&args("--input in.wav --output out.wav --midi notes.mid"))
+                .unwrap()
+                .unwrap();
+        assert_eq!(options.midi, Some(PathBuf::from("notes.mid")));
+        assert_eq!(options.buffer_size, None);
+    }
+
+    #[test]
+    fn unsupported_channel_counts() {
+        assert!(Renderer::new(3, 44100.).is_err());
+        assert!(Renderer::new(0, 44100.).is_err());
+    }
+
+    /// A second of a sine that doesn't repeat within a loop
+    fn sine() -> Vec<f32> {
+        (0..48000).map(|i| (i as f32 * 0.05).sin() * 0.5).collect()
+    }
+
+    /// How far the output strays from repeating the frozen loop, well after the attack
+    fn loop_error(renderer: &Renderer, output: &[f32]) -> f32 {
+        let loop_len = renderer.plugin.engine.loop_len();
+        let tail = &output[30000..];
+        tail.iter()
+            .zip(&tail[loop_len..])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0., f32::max)
+    }
+
+    /// The freeze from the plugin's `process()` holds the loop that was recorded before it
+    #[test]
+    fn offline_freeze_holds_the_loop() {
+        let mut renderer = Renderer::new(1, 48000.).unwrap();
+        let mut output = sine();
+        for (i, block) in output.chunks_mut(BLOCK_SIZE).enumerate() {
+            renderer.plugin.engine.set_freeze(i >= 40);
+            renderer.process(&mut [block]);
+        }
+
+        assert!(loop_error(&renderer, &output) < 1e-4);
+        assert!(output[30000..].iter().any(|&sample| sample.abs() > 0.4));
+        // Before the freeze the input passes through
+        assert_eq!(output[..20000], sine()[..20000]);
+    }
+
+    /// Notes from a MIDI file freeze the buffer like the schedule does
+    #[test]
+    fn notes_freeze_the_buffer() {
+        let note = |velocity| FileNote {
+            time: 0.,
+            channel: 0,
+            note: 60,
+            velocity,
+        };
+        let mut renderer = Renderer::new(2, 48000.).unwrap();
+        renderer.plugin.engine.set_length(2000.);
+        let mut left = sine();
+        let mut right = sine();
+        for (i, (left, right)) in left
+            .chunks_mut(BLOCK_SIZE)
+            .zip(right.chunks_mut(BLOCK_SIZE))
+            .enumerate()
+        {
+            if i == 40 {
+                renderer.send_event(note_event(&note(Some(1.)), 100));
+            }
+            renderer.process(&mut [left, right]);
+        }
+
+        assert_eq!(
+            renderer.plugin.engine.loop_len(),
+            1999
+        );
+        assert!(renderer.plugin.engine.is_frozen());
+        assert!(left[30000..].iter().any(|&sample| sample.abs() > 0.4));
+        assert_eq!(left, right);
+    }
+}
@@ -0,0 +1,27 @@
+/// A xorshift random number generator. It always produces the same sequence for the same seed,
+/// so anything random can be reproduced.
+#[derive(Debug, Clone, Copy)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    /// Xorshift gets stuck on zero, so a zero seed is replaced
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// A random value between 0 and 1
+    pub fn next_f32(&mut self) -> f32 {
+        self.next_u32() as f32 / u32::MAX as f32
+    }
+}
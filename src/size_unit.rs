@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use nih_plug::prelude::*;
+
+/// A parameter's callback that parses an entered value
+type StringToValue = Arc<dyn Fn(&str) -> Option<i32> + Send + Sync>;
+
+/// The whole note fractions the sync unit shows by name
+const SYNC_DENOMINATORS: [u32; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+/// How the Buffer Size parameter is shown and entered. The parameter always stores a length in
+/// samples, so switching units keeps the length.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeUnit {
+    #[name = "Samples"]
+    Samples,
+    #[name = "ms"]
+    Milliseconds,
+    /// Note lengths relative to the tempo when this unit was selected. The length follows the
+    /// host's tempo from then on.
+    #[name = "Sync"]
+    Sync,
+    /// The frequency of a loop that's one period long, and the closest note
+    #[name = "Pitch"]
+    Pitch,
+}
+
+/// What the Buffer Size parameter's formatters need to know, shared with the audio thread
+#[derive(Debug)]
+pub struct SizeDisplay {
+    unit: AtomicUsize,
+    /// The sample rate as `f32` bits
+    sample_rate: AtomicU32,
+    /// The tempo the sync unit's lengths are relative to as `f64` bits
+    sync_tempo: AtomicU64,
+}
+
+impl Default for SizeDisplay {
+    fn default() -> Self {
+        Self {
+            unit: AtomicUsize::new(SizeUnit::Samples.to_index()),
+            sample_rate: AtomicU32::new(44100f32.to_bits()),
+            sync_tempo: AtomicU64::new(crate::DEFAULT_TEMPO.to_bits()),
+        }
+    }
+}
+
+impl SizeDisplay {
+    /// Update what the formatters show, called by the audio thread for every block
+    pub fn set(&self, unit: SizeUnit, sample_rate: f32, sync_tempo: f64) {
+        self.unit.store(unit.to_index(), Ordering::Relaxed);
+        self.sample_rate
+            .store(sample_rate.to_bits(), Ordering::Relaxed);
+        self.sync_tempo
+            .store(sync_tempo.to_bits(), Ordering::Relaxed);
+    }
+
+    fn unit(&self) -> SizeUnit {
+        SizeUnit::from_index(self.unit.load(Ordering::Relaxed))
+    }
+
+    fn sample_rate(&self) -> f32 {
+        f32::from_bits(self.sample_rate.load(Ordering::Relaxed))
+    }
+
+    /// The number of samples in a beat at the sync unit's tempo
+    fn beat_samples(&self) -> f64 {
+        let tempo = f64::from_bits(self.sync_tempo.load(Ordering::Relaxed));
+        self.sample_rate() as f64 * 60. / tempo
+    }
+}
+
+pub fn v2s_buffer_size(display: Arc<SizeDisplay>) -> Arc<dyn Fn(i32) -> String + Send + Sync> {
+    let note_name = formatters::v2s_i32_note_formatter();
+    Arc::new(move |samples| match display.unit() {
+        SizeUnit::Samples => samples.to_string(),
+        SizeUnit::Milliseconds => {
+            format!("{:.1} ms", samples as f32 / display.sample_rate() * 1000.)
+        }
+        SizeUnit::Sync => {
+            let beats = samples as f64 / display.beat_samples();
+            sync_name(beats).unwrap_or_else(|| format!("{beats:.2} beats"))
+        }
+        SizeUnit::Pitch => {
+            let frequency = display.sample_rate() / samples as f32;
+            let note = util::freq_to_midi_note(frequency).round() as i32;
+            format!("{frequency:.1} Hz ({})", note_name(note))
+        }
+    })
+}
+
+pub fn s2v_buffer_size(display: Arc<SizeDisplay>) -> StringToValue {
+    let parse_note = formatters::s2v_i32_note_formatter();
+    Arc::new(move |string| {
+        let string = string.trim();
+        let samples = match display.unit() {
+            SizeUnit::Samples => string.parse::<f32>().ok()?,
+            SizeUnit::Milliseconds => {
+                let ms = string
+                    .trim_end_matches("ms")
+                    .trim_end()
+                    .parse::<f32>()
+                    .ok()?;
+                ms / 1000. * display.sample_rate()
+            }
+            SizeUnit::Sync => {
+                let beats = match string.split_once('/') {
+                    Some((numerator, denominator)) => {
+                        let numerator = numerator.trim().parse::<f64>().ok()?;
+                        let denominator = denominator.trim().parse::<f64>().ok()?;
+                        numerator / denominator * 4.
+                    }
+                    None => string
+                        .trim_end_matches("beats")
+                        .trim_end()
+                        .parse::<f64>()
+                        .ok()?,
+                };
+                (beats * display.beat_samples()) as f32
+            }
+            // Either a frequency or a note name
+            SizeUnit::Pitch => {
+                let frequency = match string.trim_end_matches("Hz").trim_end().parse::<f32>() {
+                    Ok(frequency) => frequency,
+                    Err(_) => util::midi_note_to_freq(parse_note(string)?.clamp(0, 127) as u8),
+                };
+                display.sample_rate() / frequency
+            }
+        };
+
+        samples.is_finite().then(|| {
+            (samples.round() as i32)
+                .clamp(crate::MIN_BUFFER_SIZE as i32, crate::MAX_BUFFER_SIZE as i32)
+        })
+    })
+}
+
+/// The name of a length in beats that's close to a fraction of a whole note, like `3/8`
+fn sync_name(beats: f64) -> Option<String> {
+    let whole_notes = beats / 4.;
+    SYNC_DENOMINATORS.iter().find_map(|&denominator| {
+        let numerator = (whole_notes * denominator as f64).round();
+        let close = numerator >= 1. && (whole_notes * denominator as f64 - numerator).abs() < 0.01;
+        close.then(|| format!("{numerator:.0}/{denominator}"))
+    })
+}
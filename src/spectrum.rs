@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// The number of output samples the FIFO holds. This needs to be at least the editor's FFT size.
+pub const SPECTRUM_FIFO_SIZE: usize = 4096;
+
+/// A lock-free FIFO the audio thread writes the output into for the editor's spectrum analyzer.
+/// The audio thread only ever writes and the editor only ever reads the most recent samples, so
+/// old samples are simply overwritten.
+pub struct SpectrumFifo {
+    /// The samples as `f32` bits
+    samples: [AtomicU32; SPECTRUM_FIFO_SIZE],
+    /// The total number of samples that have been written
+    written: AtomicUsize,
+}
+
+impl Default for SpectrumFifo {
+    fn default() -> Self {
+        Self {
+            samples: std::array::from_fn(|_| AtomicU32::new(0)),
+            written: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl SpectrumFifo {
+    pub fn push(&self, sample: f32) {
+        let written = self.written.load(Ordering::Relaxed);
+        self.samples[written % SPECTRUM_FIFO_SIZE].store(sample.to_bits(), Ordering::Relaxed);
+        self.written
+            .store(written.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Copy the most recent samples into `target`, oldest first. Returns the total number of
+    /// samples that have been written so far, which tells the reader whether anything changed.
+    pub fn read_latest(&self, target: &mut [f32]) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        let start = written.wrapping_sub(target.len());
+        for (i, sample) in target.iter_mut().enumerate() {
+            let index = start.wrapping_add(i) % SPECTRUM_FIFO_SIZE;
+            *sample = f32::from_bits(self.samples[index].load(Ordering::Relaxed));
+        }
+
+        written
+    }
+}
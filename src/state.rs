@@ -0,0 +1,202 @@
+use nih_plug::params::internals::ParamPtr;
+use nih_plug::prelude::*;
+use nih_plug::wrapper::state::ParamValue;
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{resample, ChannelBuffers};
+
+/// The version of the plugin's state. Bump this and add a migration to `migrate()` whenever
+/// stored parameters or fields change in a way older states need to be adjusted for.
+pub const STATE_VERSION: u32 = 2;
+/// The persistent field storing the state version
+pub const STATE_VERSION_KEY: &str = "state-version";
+
+/// Bring a state saved by an older version of the plugin up to date. States from before the
+/// version field was added are version 1. `default_params` are used for parameters the state
+/// didn't contain yet.
+pub fn migrate(state: &mut PluginState, default_params: &dyn Params) {
+    let version = state
+        .fields
+        .get(STATE_VERSION_KEY)
+        .and_then(|version| version.parse::<u32>().ok())
+        .unwrap_or(1);
+
+    if version < 2 {
+        // Version 1 states only knew about the buffer size and the freeze. Anything that's
+        // missing would otherwise keep whatever value the instance had before loading the
+        // state, so it's set to its default instead. The buffer size is in samples in both
+        // versions and is kept as is.
+        for (id, param_ptr, _) in default_params.param_map() {
+            // SAFETY: The parameter pointers stay valid for as long as `default_params` is
+            //         borrowed
+            state
+                .params
+                .entry(id)
+                .or_insert_with(|| unsafe { default_value(param_ptr) });
+        }
+    }
+
+    state
+        .fields
+        .insert(STATE_VERSION_KEY.to_owned(), STATE_VERSION.to_string());
+}
+
+/// Switch off the parameters that are only meant for tuning other settings, so they're never
+/// silently active after reopening a session
+pub fn disable_debug_params(state: &mut PluginState) {
+    state
+        .params
+        .insert("sidechain_listen".to_owned(), ParamValue::Bool(false));
+}
+
+/// A parameter's default value in the format it is stored in the state
+pub(crate) unsafe fn default_value(param_ptr: ParamPtr) -> ParamValue {
+    match param_ptr {
+        ParamPtr::FloatParam(p) => ParamValue::F32((*p).default_plain_value()),
+        ParamPtr::IntParam(p) => ParamValue::I32((*p).default_plain_value()),
+        ParamPtr::BoolParam(p) => ParamValue::Bool((*p).default_plain_value()),
+        // The enums don't have stable IDs, so they're stored by their index
+        ParamPtr::EnumParam(p) => ParamValue::I32((*p).default_plain_value()),
+    }
+}
+
+/// A plain value in the format a parameter like `param_ptr` is stored in the state. Booleans
+/// are 0 or 1 and enums use their variant index, like in the presets.
+pub(crate) unsafe fn stored_value(param_ptr: ParamPtr, plain: f32) -> ParamValue {
+    match param_ptr {
+        ParamPtr::FloatParam(_) => ParamValue::F32(plain),
+        ParamPtr::IntParam(_) | ParamPtr::EnumParam(_) => ParamValue::I32(plain.round() as i32),
+        ParamPtr::BoolParam(_) => ParamValue::Bool(plain >= 0.5),
+    }
+}
+
+/// The frozen loops as they're stored in the plugin's state, so a freeze survives saving and
+/// reopening a project
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BufferState {
+    /// The sample rate the loops were captured at
+    pub sample_rate: f32,
+    /// Whether the loops were frozen when the state was saved. Loops are only restored if they
+    /// were.
+    pub frozen: bool,
+    /// Every channel's loop in playback order
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl BufferState {
+    /// Allocate enough space to capture this many channels without allocating. This must be
+    /// called outside of the audio thread.
+    pub fn reserve(&mut self, num_channels: usize) {
+        self.channels.resize_with(num_channels, Vec::new);
+        for channel in &mut self.channels {
+            channel.reserve_exact(crate::MAX_BUFFER_SIZE.saturating_sub(channel.len()));
+        }
+    }
+
+    /// Free the space `reserve()` allocated, keeping the captured loops
+    pub fn shrink(&mut self) {
+        for channel in &mut self.channels {
+            channel.shrink_to_fit();
+        }
+    }
+
+    /// Copy the buffers' loops into the state. This does not allocate as long as `reserve()` has
+    /// been called for the current channel count.
+    pub fn capture(&mut self, buffers: &ChannelBuffers, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        for (channel, buffer) in self.channels.iter_mut().zip(buffers.iter()) {
+            channel.resize(crate::MAX_BUFFER_SIZE, 0.);
+            let len = buffer.copy_loop(channel);
+            channel.truncate(len);
+        }
+    }
+
+    /// Copy the stored loops into the buffers, resampling them if the sample rate changed.
+    /// States with fewer channels than there are buffers repeat their channels. Returns the
+    /// length of the restored loop, or `None` if there was nothing to restore. Resampling
+    /// allocates, so this may only be called on the audio thread if the sample rates match.
+    pub fn restore(&self, buffers: &mut ChannelBuffers, sample_rate: f32) -> Option<usize> {
+        // Invalid states are ignored
+        if !self.frozen
+            || self.sample_rate <= 0.
+            || self
+                .channels
+                .iter()
+                .any(|channel| channel.is_empty() || channel.len() >= crate::MAX_BUFFER_SIZE)
+        {
+            return None;
+        }
+
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        let mut restored = None;
+        for (mut buffer, channel) in buffers.iter_mut().zip(self.channels.iter().cycle()) {
+            if self.sample_rate == sample_rate {
+                buffer.load(channel);
+                restored = Some(channel.len());
+            } else {
+                let loop_samples = resample(channel, ratio);
+                buffer.load(&loop_samples);
+                restored = Some(loop_samples.len());
+            }
+        }
+
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WinXpCrashParams;
+
+    /// A state saved by version 1 of the plugin
+    const V1_STATE: &str = concat!(
+        r#"{"version":"0.1.0","#,
+        r#""params":{"buffer_size":{"i32":12345},"freeze":{"bool":true}},"fields":{}}"#
+    );
+
+    fn migrated(json: &str) -> PluginState {
+        let mut state: PluginState = serde_json::from_str(json).unwrap();
+        migrate(&mut state, &WinXpCrashParams::default());
+        state
+    }
+
+    #[test]
+    fn migrate_v1_state() {
+        let state = migrated(V1_STATE);
+
+        assert!(matches!(
+            state.params["buffer_size"],
+            ParamValue::I32(12345)
+        ));
+        assert!(matches!(state.params["freeze"], ParamValue::Bool(true)));
+        // Parameters that didn't exist yet get their default values
+        assert!(matches!(state.params["attack"], ParamValue::F32(attack) if attack == 0.));
+        assert_eq!(
+            state.params.len(),
+            WinXpCrashParams::default().param_map().len()
+        );
+        assert_eq!(state.fields[STATE_VERSION_KEY], STATE_VERSION.to_string());
+    }
+
+    #[test]
+    fn migration_round_trip() {
+        let state = migrated(&serde_json::to_string(&migrated(V1_STATE)).unwrap());
+
+        assert!(matches!(
+            state.params["buffer_size"],
+            ParamValue::I32(12345)
+        ));
+        assert_eq!(state.fields[STATE_VERSION_KEY], STATE_VERSION.to_string());
+    }
+
+    #[test]
+    fn current_states_are_left_alone() {
+        let state = migrated(concat!(
+            r#"{"version":"0.1.0","params":{"buffer_size":{"i32":12345}},"#,
+            r#""fields":{"state-version":"2"}}"#
+        ));
+
+        assert_eq!(state.params.len(), 1);
+    }
+}
@@ -0,0 +1,413 @@
+use nih_plug::prelude::*;
+
+use crate::buffer::ChannelBuffers;
+use crate::freeze::{Host, OutputEvent};
+
+/// The number of samples in a single sample data message
+pub const CHUNK_SAMPLES: usize = 32;
+/// The number of sample data messages sent per processing block while dumping
+const CHUNKS_PER_BLOCK: usize = 8;
+
+/// Set in a sample data message's flags to freeze the buffer once the channel has been loaded
+pub const FLAG_FREEZE: u8 = 0x01;
+
+/// The non-commercial manufacturer ID
+const MANUFACTURER_ID: u8 = 0x7d;
+const DUMP_REQUEST: u8 = 0x01;
+const SAMPLE_DATA: u8 = 0x02;
+
+/// Start, manufacturer ID, message type, channel, offset, total length, chunk length, flags
+const SAMPLE_DATA_HEADER_LEN: usize = 12;
+/// Every sample is packed into five 7-bit bytes
+const BYTES_PER_SAMPLE: usize = 5;
+const MAX_MESSAGE_LEN: usize = SAMPLE_DATA_HEADER_LEN + CHUNK_SAMPLES * BYTES_PER_SAMPLE + 1;
+
+/// The SysEx messages used to transfer the ring buffer contents.
+///
+/// A dump request looks like `F0 7D 01 F7`. Sample data messages look like
+/// `F0 7D 02 <channel> <offset: 3 bytes> <total length: 3 bytes> <length> <flags> <samples> F7`
+/// where all multi-byte numbers are little endian 7-bit groups and every sample is an `f32`
+/// split into five 7-bit groups, least significant bits first. The samples of a channel are
+/// sent in playback order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SysEx {
+    /// Ask the plugin to send the current buffer contents
+    DumpRequest,
+    /// A chunk of samples for one channel
+    SampleData {
+        channel: u8,
+        /// The position of the first sample in this chunk within the channel's loop
+        offset: u32,
+        /// The length of the channel's entire loop
+        total_len: u32,
+        /// The number of valid samples in `samples`
+        len: u8,
+        flags: u8,
+        samples: [f32; CHUNK_SAMPLES],
+    },
+}
+
+impl SysExMessage for SysEx {
+    type Buffer = [u8; MAX_MESSAGE_LEN];
+
+    fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        match buffer {
+            [0xf0, MANUFACTURER_ID, DUMP_REQUEST, 0xf7] => Some(SysEx::DumpRequest),
+            [0xf0, MANUFACTURER_ID, SAMPLE_DATA, payload @ .., 0xf7] => {
+                // Everything in between the start and end bytes needs to be 7-bit data
+                if payload.len() < SAMPLE_DATA_HEADER_LEN - 3
+                    || payload.iter().any(|b| b & 0x80 != 0)
+                {
+                    return None;
+                }
+
+                let channel = payload[0];
+                let offset = decode_u21(&payload[1..4]);
+                let total_len = decode_u21(&payload[4..7]);
+                let len = payload[7];
+                let flags = payload[8];
+                let data = &payload[9..];
+                if len as usize > CHUNK_SAMPLES || data.len() != len as usize * BYTES_PER_SAMPLE {
+                    return None;
+                }
+
+                let mut samples = [0.; CHUNK_SAMPLES];
+                for (sample, bytes) in samples.iter_mut().zip(data.chunks_exact(BYTES_PER_SAMPLE)) {
+                    *sample = decode_f32(bytes);
+                }
+
+                Some(SysEx::SampleData {
+                    channel,
+                    offset,
+                    total_len,
+                    len,
+                    flags,
+                    samples,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn to_buffer(self) -> (Self::Buffer, usize) {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        buffer[0] = 0xf0;
+        buffer[1] = MANUFACTURER_ID;
+
+        let len = match self {
+            SysEx::DumpRequest => {
+                buffer[2] = DUMP_REQUEST;
+                buffer[3] = 0xf7;
+                4
+            }
+            SysEx::SampleData {
+                channel,
+                offset,
+                total_len,
+                len,
+                flags,
+                samples,
+            } => {
+                buffer[2] = SAMPLE_DATA;
+                buffer[3] = channel & 0x7f;
+                encode_u21(offset, &mut buffer[4..7]);
+                encode_u21(total_len, &mut buffer[7..10]);
+                buffer[10] = len & 0x7f;
+                buffer[11] = flags & 0x7f;
+
+                let data = &mut buffer[SAMPLE_DATA_HEADER_LEN..];
+                for (sample, bytes) in samples
+                    .iter()
+                    .take(len as usize)
+                    .zip(data.chunks_exact_mut(BYTES_PER_SAMPLE))
+                {
+                    encode_f32(*sample, bytes);
+                }
+
+                let end = SAMPLE_DATA_HEADER_LEN + len as usize * BYTES_PER_SAMPLE;
+                buffer[end] = 0xf7;
+                end + 1
+            }
+        };
+
+        (buffer, len)
+    }
+}
+
+fn encode_u21(value: u32, bytes: &mut [u8]) {
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = ((value >> (i * 7)) & 0x7f) as u8;
+    }
+}
+
+fn decode_u21(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .enumerate()
+        .fold(0, |value, (i, byte)| value | (*byte as u32) << (i * 7))
+}
+
+fn encode_f32(sample: f32, bytes: &mut [u8]) {
+    let bits = sample.to_bits();
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = ((bits >> (i * 7)) & 0x7f) as u8;
+    }
+}
+
+fn decode_f32(bytes: &[u8]) -> f32 {
+    let bits = bytes
+        .iter()
+        .enumerate()
+        .fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (i * 7));
+    f32::from_bits(bits)
+}
+
+/// Streams a snapshot of the ring buffers out as SysEx sample data messages, a couple of chunks
+/// per processing block.
+#[derive(Debug, Default)]
+pub struct BufferDump {
+    /// A copy of every channel's loop taken when the dump was requested
+    staging: Vec<Vec<f32>>,
+    lengths: Vec<usize>,
+    /// The channel and offset of the next chunk. `None` when no dump is in progress.
+    next_chunk: Option<(usize, usize)>,
+}
+
+impl BufferDump {
+    /// Allocate the staging buffers. This must be called outside of the audio thread.
+    pub fn new(num_channels: usize) -> Self {
+        Self {
+            staging: vec![vec![0.; crate::MAX_BUFFER_SIZE]; num_channels],
+            lengths: vec![0; num_channels],
+            next_chunk: None,
+        }
+    }
+
+    /// Take a snapshot of the buffers and start sending it. A dump that is still in progress
+    /// starts over.
+    pub fn start(&mut self, buffers: &ChannelBuffers) {
+        for ((staging, length), buffer) in
+            self.staging.iter_mut().zip(&mut self.lengths).zip(buffers.iter())
+        {
+            *length = buffer.copy_loop(staging);
+        }
+
+        self.next_chunk = if self.staging.is_empty() {
+            None
+        } else {
+            Some((0, 0))
+        };
+    }
+
+    /// Send the next couple of chunks of the current dump, if there is one.
+    pub fn send_chunks(&mut self, context: &mut impl Host) {
+        for _ in 0..CHUNKS_PER_BLOCK {
+            let Some((channel, offset)) = self.next_chunk else {
+                return;
+            };
+
+            let total_len = self.lengths[channel];
+            let len = CHUNK_SAMPLES.min(total_len - offset);
+            let mut samples = [0.; CHUNK_SAMPLES];
+            samples[..len].copy_from_slice(&self.staging[channel][offset..offset + len]);
+
+            context.send_event(OutputEvent::SysEx {
+                timing: 0,
+                message: SysEx::SampleData {
+                    channel: channel as u8,
+                    offset: offset as u32,
+                    total_len: total_len as u32,
+                    len: len as u8,
+                    flags: 0,
+                    samples,
+                },
+            });
+
+            self.next_chunk = if offset + len < total_len {
+                Some((channel, offset + len))
+            } else if channel + 1 < self.staging.len() {
+                Some((channel + 1, 0))
+            } else {
+                None
+            };
+        }
+    }
+}
+
+/// The progress of loading a single channel
+#[derive(Debug, Default, Clone, Copy)]
+struct ChannelLoad {
+    total_len: usize,
+    received: usize,
+    flags: u8,
+}
+
+/// Collects sample data messages into staging buffers. Complete channels are copied into the
+/// ring buffers with `apply()` at the start of a block.
+#[derive(Debug, Default)]
+pub struct BufferLoad {
+    staging: Vec<Vec<f32>>,
+    channels: Vec<ChannelLoad>,
+}
+
+impl BufferLoad {
+    /// Allocate the staging buffers. This must be called outside of the audio thread.
+    pub fn new(num_channels: usize) -> Self {
+        Self {
+            staging: vec![vec![0.; crate::MAX_BUFFER_SIZE]; num_channels],
+            channels: vec![ChannelLoad::default(); num_channels],
+        }
+    }
+
+    /// Stage a sample data message. The chunks for a channel need to arrive in order, starting
+    /// at offset 0. Chunks that don't fit the buffer or that don't continue the current load
+    /// are ignored.
+    pub fn receive(&mut self, message: &SysEx) {
+        let SysEx::SampleData {
+            channel,
+            offset,
+            total_len,
+            len,
+            flags,
+            samples,
+        } = *message
+        else {
+            return;
+        };
+
+        let channel = channel as usize;
+        let offset = offset as usize;
+        let total_len = total_len as usize;
+        let len = len as usize;
+        let Some(load) = self.channels.get_mut(channel) else {
+            return;
+        };
+        // The ring buffer needs one extra sample on top of the loop
+        if total_len == 0
+            || total_len >= crate::MAX_BUFFER_SIZE
+            || len > CHUNK_SAMPLES
+            || offset + len > total_len
+        {
+            return;
+        }
+
+        if offset == 0 {
+            *load = ChannelLoad {
+                total_len,
+                received: 0,
+                flags,
+            };
+        } else if offset != load.received || total_len != load.total_len {
+            return;
+        }
+
+        self.staging[channel][offset..offset + len].copy_from_slice(&samples[..len]);
+        load.received += len;
+    }
+
+    /// Copy all channels that have been received completely into their ring buffers. Returns
+    /// the length of the loaded loop and whether the buffer should be frozen if any channel has
+    /// been loaded.
+    pub fn apply(&mut self, buffers: &mut ChannelBuffers) -> Option<(usize, bool)> {
+        let mut loaded = None;
+        for ((load, staging), mut buffer) in self
+            .channels
+            .iter_mut()
+            .zip(&self.staging)
+            .zip(buffers.iter_mut())
+        {
+            if load.total_len == 0 || load.received < load.total_len {
+                continue;
+            }
+
+            buffer.load(&staging[..load.total_len]);
+            loaded = Some((load.total_len, load.flags & FLAG_FREEZE != 0));
+            *load = ChannelLoad::default();
+        }
+
+        loaded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The sample data messages for a loop, like a dump would send them
+    fn chunks(channel: u8, samples: &[f32], flags: u8) -> Vec<SysEx> {
+        samples
+            .chunks(CHUNK_SAMPLES)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut chunk_samples = [0.; CHUNK_SAMPLES];
+                chunk_samples[..chunk.len()].copy_from_slice(chunk);
+                SysEx::SampleData {
+                    channel,
+                    offset: (i * CHUNK_SAMPLES) as u32,
+                    total_len: samples.len() as u32,
+                    len: chunk.len() as u8,
+                    flags,
+                    samples: chunk_samples,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let samples: Vec<f32> = (0..40).map(|i| i as f32 * -0.37).collect();
+        for message in [SysEx::DumpRequest]
+            .into_iter()
+            .chain(chunks(1, &samples, FLAG_FREEZE))
+        {
+            let (buffer, len) = message.to_buffer();
+            assert!(buffer[1..len - 1].iter().all(|byte| byte & 0x80 == 0));
+            assert_eq!(SysEx::from_buffer(&buffer[..len]), Some(message));
+        }
+    }
+
+    #[test]
+    fn malformed_messages_are_ignored() {
+        let (mut buffer, len) = chunks(0, &[1., 2.], 0)[0].to_buffer();
+        assert_eq!(SysEx::from_buffer(&buffer[..len - 2]), None);
+        buffer[12] = 0x80;
+        assert_eq!(SysEx::from_buffer(&buffer[..len]), None);
+        assert_eq!(SysEx::from_buffer(&[0xf0, 0x7e, 0x01, 0xf7]), None);
+    }
+
+    #[test]
+    fn chunks_are_reassembled() {
+        let samples: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let mut buffers = ChannelBuffers::new(2, 129);
+        let mut load = BufferLoad::new(2);
+        let messages = chunks(1, &samples, FLAG_FREEZE);
+        for message in &messages[..messages.len() - 1] {
+            load.receive(message);
+        }
+        assert_eq!(load.apply(&mut buffers), None);
+
+        load.receive(messages.last().unwrap());
+        assert_eq!(load.apply(&mut buffers), Some((100, true)));
+        let loaded: Vec<f32> = buffers.iter().nth(1).unwrap().loop_samples().collect();
+        assert_eq!(loaded, samples);
+        // A load is only applied once
+        assert_eq!(load.apply(&mut buffers), None);
+    }
+
+    #[test]
+    fn chunks_out_of_order_are_ignored() {
+        let samples: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let mut buffers = ChannelBuffers::new(1, 129);
+        let mut load = BufferLoad::new(1);
+        let messages = chunks(0, &samples, 0);
+        for message in [&messages[0], &messages[2], &messages[1], &messages[3]] {
+            load.receive(message);
+        }
+        assert_eq!(load.apply(&mut buffers), None);
+
+        // Neither are chunks for channels that don't exist or loops that don't fit the buffer
+        load.receive(&chunks(1, &samples, 0)[0]);
+        load.receive(&chunks(0, &vec![0.; crate::MAX_BUFFER_SIZE], 0)[0]);
+        assert_eq!(load.apply(&mut buffers), None);
+    }
+}
@@ -0,0 +1,36 @@
+/// The number of intervals that are averaged to get the tapped length
+const TAP_HISTORY: usize = 4;
+
+/// Measures the average interval between repeated taps
+#[derive(Clone, Debug, Default)]
+pub struct TapTempo {
+    /// The sample position of the previous tap
+    last_tap: Option<u64>,
+    intervals: [u64; TAP_HISTORY],
+    num_intervals: usize,
+    next_interval: usize,
+}
+
+impl TapTempo {
+    /// Register a tap at `position` in samples. Returns the average interval between the taps
+    /// in samples once there are at least two taps. Taps further apart than `timeout` samples
+    /// start a new tap sequence.
+    pub fn tap(&mut self, position: u64, timeout: u64) -> Option<f32> {
+        let last_tap = self.last_tap.replace(position);
+        let interval = match last_tap.and_then(|last_tap| position.checked_sub(last_tap)) {
+            Some(interval) if interval > 0 && interval <= timeout => interval,
+            _ => {
+                self.num_intervals = 0;
+                self.next_interval = 0;
+                return None;
+            }
+        };
+
+        self.intervals[self.next_interval] = interval;
+        self.next_interval = (self.next_interval + 1) % TAP_HISTORY;
+        self.num_intervals = (self.num_intervals + 1).min(TAP_HISTORY);
+
+        let total: u64 = self.intervals.iter().take(self.num_intervals).sum();
+        Some(total as f32 / self.num_intervals as f32)
+    }
+}
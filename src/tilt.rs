@@ -0,0 +1,37 @@
+use nih_plug::prelude::*;
+
+use crate::ftz::flush_denormal;
+
+/// The frequency the tilt pivots around, in Hz
+const PIVOT_FREQUENCY: f32 = 700.;
+
+/// Complementary low and high shelves around `PIVOT_FREQUENCY` for one channel. The signal is
+/// split into a one-pole lowpass and the rest, so both halves add up to the input again when
+/// they get the same gain.
+#[derive(Clone, Debug)]
+pub struct Tilt {
+    coefficient: f32,
+    lowpass_state: f32,
+}
+
+impl Tilt {
+    /// The filter for a sample rate. This needs to be created again when the sample rate
+    /// changes.
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            coefficient: 1. - (-std::f32::consts::TAU * PIVOT_FREQUENCY / sample_rate).exp(),
+            lowpass_state: 0.,
+        }
+    }
+
+    /// Tilt the next sample by `db`, raising the highs and lowering the lows by that much.
+    /// Negative values darken the sample instead.
+    pub fn process(&mut self, sample: f32, db: f32) -> f32 {
+        let low =
+            flush_denormal(self.lowpass_state + (sample - self.lowpass_state) * self.coefficient);
+        self.lowpass_state = low;
+        let high = sample - low;
+
+        low * util::db_to_gain(-db) + high * util::db_to_gain(db)
+    }
+}
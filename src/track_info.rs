@@ -0,0 +1,53 @@
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+/// The track's name and color for the editor, as the host last reported them. This stays `None`
+/// in hosts that don't report them, which is every host but CLAP hosts with the track info
+/// extension.
+pub type SharedTrackInfo = Arc<Mutex<Option<TrackInfo>>>;
+
+/// What the host told the plugin about the track it's on
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub name: Option<String>,
+    /// The opaque RGB color
+    pub color: Option<[u8; 3]>,
+}
+
+thread_local! {
+    /// The track info the plugin created next on this thread shares with the CLAP entry point
+    static PENDING: RefCell<Option<SharedTrackInfo>> = const { RefCell::new(None) };
+}
+
+/// Create a plugin with `create` that shares `track_info` with the caller. nih-plug creates the
+/// plugin inside its wrapper without a way to pass anything to it, but it does so on the calling
+/// thread.
+pub fn create_with<T>(track_info: SharedTrackInfo, create: impl FnOnce() -> T) -> T {
+    PENDING.with_borrow_mut(|pending| *pending = Some(track_info));
+    let created = create();
+    PENDING.with_borrow_mut(|pending| *pending = None);
+
+    created
+}
+
+/// The track info for a new plugin's parameters. That's the one `create_with()` shares, or an
+/// empty one nobody updates.
+pub fn take_pending() -> SharedTrackInfo {
+    PENDING
+        .with_borrow_mut(|pending| pending.take())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_created_plugin_shares_the_track_info() {
+        let track_info = SharedTrackInfo::default();
+        let (shared, second) = create_with(track_info.clone(), || (take_pending(), take_pending()));
+        assert!(Arc::ptr_eq(&shared, &track_info));
+        assert!(!Arc::ptr_eq(&second, &track_info));
+        assert!(!Arc::ptr_eq(&take_pending(), &track_info));
+    }
+}
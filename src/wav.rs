@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::buffer::resample;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+/// The WAVE format tag for 32-bit float samples
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// The actual format is stored in the sub format GUID for these
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// The directory exports are written to when no export directory has been configured
+pub fn default_directory() -> PathBuf {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Write the loops to a new WAV file in `directory`, one channel per loop. Returns the path of
+/// the written file.
+pub fn export(directory: &Path, channels: &[Vec<f32>], sample_rate: f32) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let path = directory.join(format!("Windows XP Crash {timestamp}.wav"));
+    write(&path, channels, sample_rate)?;
+
+    Ok(path)
+}
+
+/// Write the loops to a WAV file at `path`, replacing the file if it already exists
+pub fn write(path: &Path, channels: &[Vec<f32>], sample_rate: f32) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_wav(&mut writer, channels, sample_rate)?;
+    writer.flush()
+}
+
+/// Write a 32-bit float WAV file with the channels interleaved
+fn write_wav(writer: &mut impl Write, channels: &[Vec<f32>], sample_rate: f32) -> io::Result<()> {
+    let num_channels = channels.len() as u16;
+    let num_frames = channels.iter().map(Vec::len).min().unwrap_or(0) as u32;
+    let block_align = num_channels as u32 * 4;
+    let data_len = num_frames * block_align;
+    let sample_rate = sample_rate.round() as u32;
+
+    writer.write_all(b"RIFF")?;
+    // The fmt chunk, the fact chunk and the data chunk
+    writer.write_all(&(4 + 26 + 12 + 8 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&18u32.to_le_bytes())?;
+    writer.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+    writer.write_all(&num_channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align).to_le_bytes())?;
+    writer.write_all(&(block_align as u16).to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?;
+
+    // Non-PCM formats are supposed to include the number of frames
+    writer.write_all(b"fact")?;
+    writer.write_all(&4u32.to_le_bytes())?;
+    writer.write_all(&num_frames.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for frame in 0..num_frames as usize {
+        for channel in channels {
+            writer.write_all(&channel[frame].to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a WAV file and resample it to `sample_rate`. Only the start of files that don't fit
+/// into the ring buffer is read. Returns one loop per channel.
+pub fn import(path: &Path, sample_rate: f32) -> io::Result<Vec<Vec<f32>>> {
+    let bytes = std::fs::read(path)?;
+    let (channels, file_sample_rate) =
+        parse_wav(&bytes, Some(sample_rate)).ok_or_else(unsupported_file)?;
+
+    if file_sample_rate == sample_rate {
+        return Ok(channels);
+    }
+
+    let ratio = sample_rate as f64 / file_sample_rate as f64;
+    Ok(channels
+        .iter()
+        .map(|channel| resample(channel, ratio))
+        .collect())
+}
+
+/// Read all of a WAV file at its own sample rate. Returns one channel per channel in the file
+/// and the sample rate.
+pub fn read(path: &Path) -> io::Result<(Vec<Vec<f32>>, f32)> {
+    let bytes = std::fs::read(path)?;
+    parse_wav(&bytes, None).ok_or_else(unsupported_file)
+}
+
+fn unsupported_file() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a supported WAV file")
+}
+
+/// Decode a 16, 24 or 32-bit integer or a 32-bit float WAV file. With a `target_sample_rate`
+/// the number of frames is limited to what fits into the ring buffer after resampling to it.
+fn parse_wav(bytes: &[u8], target_sample_rate: Option<f32>) -> Option<(Vec<Vec<f32>>, f32)> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().ok()?) as usize;
+        // Truncated files still contain usable audio
+        let chunk = &bytes[pos + 8..(pos + 8).saturating_add(len).min(bytes.len())];
+        match id {
+            b"fmt " => format = Some(chunk),
+            b"data" => data = Some(chunk),
+            _ => (),
+        }
+        // Chunks are padded to an even length
+        pos = pos.saturating_add(8 + len + (len & 1));
+    }
+
+    let (format, data) = (format?, data?);
+    let read_u16 = |offset: usize| {
+        Some(u16::from_le_bytes(
+            format.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let mut format_tag = read_u16(0)?;
+    let num_channels = read_u16(2)? as usize;
+    let sample_rate = u32::from_le_bytes(format.get(4..8)?.try_into().ok()?) as f32;
+    let bits_per_sample = read_u16(14)?;
+    if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        format_tag = read_u16(24)?;
+    }
+    if num_channels == 0 || sample_rate <= 0. {
+        return None;
+    }
+
+    let decode: fn(&[u8]) -> f32 = match (format_tag, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.,
+        (WAVE_FORMAT_PCM, 24) => {
+            |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2_147_483_648.
+        }
+        (WAVE_FORMAT_PCM, 32) => {
+            |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        _ => return None,
+    };
+
+    let frame_len = num_channels * bits_per_sample as usize / 8;
+    let max_frames = target_sample_rate.map_or(usize::MAX, |target_sample_rate| {
+        ((crate::MAX_BUFFER_SIZE - 1) as f32 * sample_rate / target_sample_rate).ceil() as usize
+    });
+    let mut channels = vec![Vec::new(); num_channels];
+    for frame in data.chunks_exact(frame_len).take(max_frames) {
+        for (channel, sample) in channels
+            .iter_mut()
+            .zip(frame.chunks_exact(bits_per_sample as usize / 8))
+        {
+            channel.push(decode(sample));
+        }
+    }
+
+    if channels[0].is_empty() {
+        return None;
+    }
+
+    Some((channels, sample_rate))
+}
@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::buffer::{ChannelBuffers, PlayRegion};
+
+/// The most min/max pairs every channel's waveform can have, which is also the resolution the
+/// editor's waveform display uses
+pub const WAVEFORM_POINTS: usize = 256;
+/// The most channels that get their own peaks. Further channels are left out.
+pub const MAX_WAVEFORM_CHANNELS: usize = 8;
+/// How many points are recomputed per processed block. The whole waveform is refreshed every
+/// `resolution / POINTS_PER_BLOCK` blocks, which keeps the work per block small.
+const POINTS_PER_BLOCK: usize = 32;
+
+/// Decimated peaks of every channel's recorded loop, together with the part of the loop that's
+/// being played, the write head and whether the freeze is engaged. The audio thread publishes
+/// these through atomics once per block so the editor, or anything else holding the plugin's
+/// parameters, can poll them at its own rate without any locking.
+///
+/// Nothing is published while neither the editor is open nor an observer enabled publishing
+/// with `set_enabled()`, so running headless costs nothing.
+pub struct Waveform {
+    /// The lowest and highest sample for every channel's points as `f32` bits
+    min: [[AtomicU32; WAVEFORM_POINTS]; MAX_WAVEFORM_CHANNELS],
+    max: [[AtomicU32; WAVEFORM_POINTS]; MAX_WAVEFORM_CHANNELS],
+    /// The number of points per channel that are in use, set in `prepare()`
+    resolution: AtomicUsize,
+    num_channels: AtomicUsize,
+    /// Whether an observer other than the editor wants the waveform to be published
+    enabled: AtomicBool,
+    /// The loop's length in samples
+    loop_len: AtomicUsize,
+    /// The position of the loop's oldest sample in the buffer
+    oldest: AtomicUsize,
+    /// The position of the newest recorded sample in the buffer
+    head: AtomicUsize,
+    frozen: AtomicBool,
+    /// The first channel's `PlayRegion` as `f32` bits
+    region_start: AtomicU32,
+    region_len: AtomicU32,
+    position: AtomicU32,
+    rate: AtomicU32,
+    /// Incremented with every publish, so the editor knows when the position is new
+    publishes: AtomicU32,
+    /// The next point the audio thread recomputes
+    next_point: AtomicUsize,
+}
+
+/// The buffer's state at the last publish, without the peaks
+#[derive(Debug, Clone, Copy)]
+pub struct BufferState {
+    pub loop_len: usize,
+    pub oldest: usize,
+    pub head: usize,
+    pub frozen: bool,
+    pub region: PlayRegion,
+    /// The publish's number, which only changes when the state is new
+    pub publishes: u32,
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        let points = || std::array::from_fn(|_| std::array::from_fn(|_| AtomicU32::new(0)));
+        Self {
+            min: points(),
+            max: points(),
+            resolution: AtomicUsize::new(WAVEFORM_POINTS),
+            num_channels: AtomicUsize::new(0),
+            enabled: AtomicBool::new(false),
+            loop_len: AtomicUsize::new(0),
+            oldest: AtomicUsize::new(0),
+            head: AtomicUsize::new(0),
+            frozen: AtomicBool::new(false),
+            region_start: AtomicU32::new(0),
+            region_len: AtomicU32::new(0),
+            position: AtomicU32::new(0),
+            rate: AtomicU32::new(0),
+            publishes: AtomicU32::new(0),
+            next_point: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Waveform {
+    /// Set the number of points per channel, clamped to `WAVEFORM_POINTS`, and the number of
+    /// channels. This is called from `initialize()` and clears the waveform.
+    pub fn prepare(&self, resolution: usize, num_channels: usize) {
+        self.resolution
+            .store(resolution.clamp(1, WAVEFORM_POINTS), Ordering::Relaxed);
+        self.num_channels
+            .store(num_channels.min(MAX_WAVEFORM_CHANNELS), Ordering::Relaxed);
+        self.clear();
+    }
+
+    /// Publish the waveform even while the editor is closed, for observers like a
+    /// visualization that polls it from another thread
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Recompute the next few points from the buffers and publish the play region, the head
+    /// and the freeze. This doesn't allocate.
+    pub fn publish(&self, buffers: &ChannelBuffers, frozen: bool) {
+        let Some(first_buffer) = buffers.first() else {
+            return;
+        };
+        let loop_len = first_buffer.loop_len();
+        let region = first_buffer.play_region();
+        self.loop_len.store(loop_len, Ordering::Relaxed);
+        self.oldest.store(first_buffer.oldest(), Ordering::Relaxed);
+        self.head.store(first_buffer.head(), Ordering::Relaxed);
+        self.frozen.store(frozen, Ordering::Relaxed);
+        self.region_start
+            .store(region.start.to_bits(), Ordering::Relaxed);
+        self.region_len
+            .store(region.len.to_bits(), Ordering::Relaxed);
+        self.position
+            .store(region.position.to_bits(), Ordering::Relaxed);
+        self.rate.store(region.rate.to_bits(), Ordering::Relaxed);
+        self.publishes.fetch_add(1, Ordering::Release);
+
+        let resolution = self.resolution.load(Ordering::Relaxed);
+        let first_point = self.next_point.load(Ordering::Relaxed) % resolution;
+        let last_point = (first_point + POINTS_PER_BLOCK).min(resolution);
+        for point in first_point..last_point {
+            let range = point * loop_len / resolution..(point + 1) * loop_len / resolution;
+            let channels = buffers.iter().zip(self.min.iter().zip(&self.max));
+            for (buffer, (channel_min, channel_max)) in channels {
+                let (min, max) = buffer.peak(range.clone());
+                channel_min[point].store(min.to_bits(), Ordering::Relaxed);
+                channel_max[point].store(max.to_bits(), Ordering::Relaxed);
+            }
+        }
+        self.next_point
+            .store(last_point % resolution, Ordering::Relaxed);
+    }
+
+    /// Show an empty buffer until the next publish
+    pub fn clear(&self) {
+        for value in self.min.iter().chain(&self.max).flatten() {
+            value.store(0, Ordering::Relaxed);
+        }
+        self.loop_len.store(0, Ordering::Relaxed);
+        self.frozen.store(false, Ordering::Relaxed);
+        self.next_point.store(0, Ordering::Relaxed);
+    }
+
+    /// The number of points per channel
+    pub fn resolution(&self) -> usize {
+        self.resolution.load(Ordering::Relaxed)
+    }
+
+    /// The number of channels with their own peaks
+    pub fn num_channels(&self) -> usize {
+        self.num_channels.load(Ordering::Relaxed)
+    }
+
+    /// The lowest and highest sample of all channels for every point, from the start to the
+    /// end of the buffer
+    pub fn points(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let num_channels = self.num_channels();
+        (0..self.resolution()).map(move |point| {
+            (0..num_channels).fold((0f32, 0f32), |(min, max), channel| {
+                let (channel_min, channel_max) = self.point(channel, point);
+                (min.min(channel_min), max.max(channel_max))
+            })
+        })
+    }
+
+    /// The lowest and highest sample of one channel for every point, or nothing for channels
+    /// without their own peaks
+    pub fn channel_points(&self, channel: usize) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let points = if channel < self.num_channels() {
+            0..self.resolution()
+        } else {
+            0..0
+        };
+        points.map(move |point| self.point(channel, point))
+    }
+
+    fn point(&self, channel: usize, point: usize) -> (f32, f32) {
+        (
+            f32::from_bits(self.min[channel][point].load(Ordering::Relaxed)),
+            f32::from_bits(self.max[channel][point].load(Ordering::Relaxed)),
+        )
+    }
+
+    pub fn loop_len(&self) -> usize {
+        self.loop_len.load(Ordering::Relaxed)
+    }
+
+    pub fn oldest(&self) -> usize {
+        self.oldest.load(Ordering::Relaxed)
+    }
+
+    /// The play region at the last publish, and that publish's number
+    pub fn play_region(&self) -> (PlayRegion, u32) {
+        let load = |value: &AtomicU32| f32::from_bits(value.load(Ordering::Relaxed));
+        let publishes = self.publishes.load(Ordering::Acquire);
+        let region = PlayRegion {
+            start: load(&self.region_start),
+            len: load(&self.region_len),
+            position: load(&self.position),
+            rate: load(&self.rate),
+        };
+
+        (region, publishes)
+    }
+
+    /// Everything but the peaks at the last publish
+    pub fn state(&self) -> BufferState {
+        let (region, publishes) = self.play_region();
+        BufferState {
+            loop_len: self.loop_len(),
+            oldest: self.oldest(),
+            head: self.head.load(Ordering::Relaxed),
+            frozen: self.frozen.load(Ordering::Relaxed),
+            region,
+            publishes,
+        }
+    }
+}